@@ -1,7 +1,7 @@
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    prost_build::compile_protos(&["proto/messages.proto"], &["proto/"])?;
+    prost_build::compile_protos(&["proto/messages.proto", "proto/storage.proto"], &["proto/"])?;
 
     Ok(())
 }
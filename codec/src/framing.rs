@@ -0,0 +1,155 @@
+//! COBS framing with a CRC-16, as spoken by the server's serial transport
+//!
+//! A frame is the payload followed by its CRC-16/CCITT-FALSE (big endian),
+//! COBS encoded and terminated by a zero byte.
+
+use crate::Error;
+
+/// Byte terminating every frame
+pub const DELIMITER: u8 = 0;
+
+/// Largest encoded size of a frame carrying `payload_len` bytes, delimiter included
+pub const fn max_frame_len(payload_len: usize) -> usize {
+    let data_len = payload_len + 2;
+    data_len + data_len / 254 + 2
+}
+
+/// Computes the CRC-16/CCITT-FALSE of `data`
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Encodes `payload` into a frame in `out`, returning the frame length
+pub fn encode_frame(payload: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let crc = crc16(payload).to_be_bytes();
+    let mut len = 0;
+    let mut put = |byte: u8, at: usize| -> Result<(), Error> {
+        *out.get_mut(at).ok_or(Error::BufferTooSmall)? = byte;
+        Ok(())
+    };
+
+    //index of the code byte of the current block
+    let mut code_index = len;
+    len += 1;
+    let mut code: u8 = 1;
+    for &byte in payload.iter().chain(crc.iter()) {
+        if byte == 0 {
+            put(code, code_index)?;
+            code_index = len;
+            len += 1;
+            code = 1;
+        } else {
+            put(byte, len)?;
+            len += 1;
+            code += 1;
+            if code == 0xFF {
+                put(code, code_index)?;
+                code_index = len;
+                len += 1;
+                code = 1;
+            }
+        }
+    }
+    put(code, code_index)?;
+    put(DELIMITER, len)?;
+    Ok(len + 1)
+}
+
+/// Decodes a COBS block sequence in place, returning the decoded length
+fn cobs_decode_in_place(buf: &mut [u8]) -> Result<usize, Error> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < buf.len() {
+        let code = buf[read] as usize;
+        if code == 0 || read + code > buf.len() {
+            return Err(Error::InvalidFrame);
+        }
+        //the decoded data never overtakes the encoded data
+        buf.copy_within(read + 1..read + code, write);
+        write += code - 1;
+        read += code;
+        if code < 0xFF && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Ok(write)
+}
+
+/// Reassembles frames from received bytes in a fixed buffer of `N` bytes
+///
+/// Size `N` with [`max_frame_len`] of the largest expected payload.
+pub struct FrameDecoder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    //set while skipping the rest of a frame that does not fit
+    overflowed: bool,
+}
+
+impl<const N: usize> Default for FrameDecoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FrameDecoder<N> {
+    pub const fn new() -> Self {
+        FrameDecoder {
+            buf: [0; N],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Feeds one received byte, returning the payload whenever a frame is complete
+    ///
+    /// The payload borrows the decoder's buffer until the next call. Corrupted
+    /// frames and frames too large for the buffer are reported as errors;
+    /// decoding carries on with the next frame.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&[u8], Error>> {
+        if byte != DELIMITER {
+            if self.len == N {
+                self.overflowed = true;
+                self.len = 0;
+            }
+            if !self.overflowed {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            }
+            return None;
+        }
+
+        let len = core::mem::replace(&mut self.len, 0);
+        if core::mem::replace(&mut self.overflowed, false) {
+            return Some(Err(Error::BufferTooSmall));
+        }
+        //back to back delimiters carry no frame
+        if len == 0 {
+            return None;
+        }
+        Some(self.finish(len))
+    }
+
+    fn finish(&mut self, len: usize) -> Result<&[u8], Error> {
+        let data_len = cobs_decode_in_place(&mut self.buf[..len])?;
+        if data_len < 2 {
+            return Err(Error::InvalidFrame);
+        }
+        let (payload, crc) = self.buf[..data_len].split_at(data_len - 2);
+        if crc16(payload).to_be_bytes() != crc {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(payload)
+    }
+}
@@ -0,0 +1,135 @@
+//! Length-delimited framing, as spoken by the server's stream sockets when it
+//! is configured to or a client asked for `length-delimited` in its `Hello`
+//!
+//! A frame is the payload prefixed with its length as a protobuf varint, the
+//! same bytes prost's `encode_length_delimited` produces.
+
+use crate::{wire, Error};
+
+/// Longest length prefix, a varint of a `u64`
+pub const MAX_PREFIX_LEN: usize = 10;
+
+/// Largest encoded size of a frame carrying `payload_len` bytes, prefix included
+pub const fn max_frame_len(payload_len: usize) -> usize {
+    let mut prefix_len = 1;
+    let mut len = payload_len;
+    while len >= 0x80 {
+        len >>= 7;
+        prefix_len += 1;
+    }
+    prefix_len + payload_len
+}
+
+/// Encodes `payload` into a frame in `out`, returning the frame length
+pub fn encode_frame(payload: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let mut w = wire::Writer::new(out);
+    w.varint(payload.len() as u64)?;
+    w.put_slice(payload)?;
+    Ok(w.position())
+}
+
+/// Decodes the frame at the start of `buf`, returning its payload and the
+/// number of bytes the frame takes up
+///
+/// Fails with [`Error::Truncated`] as long as `buf` does not hold all of it.
+pub fn decode_frame(buf: &[u8]) -> Result<(&[u8], usize), Error> {
+    let mut r = wire::Reader::new(buf);
+    let len = r.varint()?;
+    let prefix_len = buf.len() - r.remaining().len();
+    let payload = usize::try_from(len)
+        .ok()
+        .and_then(|len| r.remaining().get(..len))
+        .ok_or(Error::Truncated)?;
+    Ok((payload, prefix_len + payload.len()))
+}
+
+/// Reassembles frames from received bytes in a fixed buffer of `N` bytes
+///
+/// `N` is the largest payload accepted, unlike the COBS decoder the prefix
+/// does not need room in the buffer.
+pub struct FrameDecoder<const N: usize> {
+    buf: [u8; N],
+    //payload bytes of the current frame received so far
+    len: usize,
+    //length of the current frame, once its prefix is complete
+    frame_len: Option<usize>,
+    prefix: u64,
+    prefix_len: usize,
+    //bytes of a frame too large for the buffer still to be dropped
+    skipping: u64,
+}
+
+impl<const N: usize> Default for FrameDecoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FrameDecoder<N> {
+    pub const fn new() -> Self {
+        FrameDecoder {
+            buf: [0; N],
+            len: 0,
+            frame_len: None,
+            prefix: 0,
+            prefix_len: 0,
+            skipping: 0,
+        }
+    }
+
+    /// Forgets the frame being received, e.g. after reconnecting
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Feeds one received byte, returning the payload whenever a frame is complete
+    ///
+    /// The payload borrows the decoder's buffer until the next call. A frame
+    /// too large for the buffer is reported as [`Error::BufferTooSmall`] as
+    /// soon as its prefix is in and its payload is dropped. After an
+    /// [`Error::InvalidVarint`] prefix there is no telling where the next
+    /// frame starts, so the connection should be reset.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&[u8], Error>> {
+        if self.skipping > 0 {
+            self.skipping -= 1;
+            return None;
+        }
+        let Some(frame_len) = self.frame_len else {
+            return self.push_prefix(byte);
+        };
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < frame_len {
+            return None;
+        }
+        self.len = 0;
+        self.frame_len = None;
+        Some(Ok(&self.buf[..frame_len]))
+    }
+
+    fn push_prefix(&mut self, byte: u8) -> Option<Result<&[u8], Error>> {
+        self.prefix |= ((byte & 0x7F) as u64) << (7 * self.prefix_len);
+        self.prefix_len += 1;
+        if byte & 0x80 != 0 {
+            if self.prefix_len == MAX_PREFIX_LEN {
+                self.prefix = 0;
+                self.prefix_len = 0;
+                return Some(Err(Error::InvalidVarint));
+            }
+            return None;
+        }
+
+        let len = core::mem::replace(&mut self.prefix, 0);
+        self.prefix_len = 0;
+        if len > N as u64 {
+            self.skipping = len;
+            return Some(Err(Error::BufferTooSmall));
+        }
+        //an empty frame is complete with its prefix
+        if len == 0 {
+            return Some(Ok(&[]));
+        }
+        self.frame_len = Some(len as usize);
+        None
+    }
+}
@@ -0,0 +1,59 @@
+//! Allocation-free codec for the messages of `proto/messages.proto`
+//!
+//! Meant for firmware talking to the server: messages are encoded into and
+//! decoded from caller provided buffers, decoded strings borrow from the input,
+//! and frames use either the same COBS + CRC-16 format as the server's serial
+//! transport ([`framing`]) or the varint length prefix its stream sockets
+//! can switch to ([`length_delimited`]). The encoding is byte-for-byte what prost produces on
+//! the server.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod framing;
+pub mod length_delimited;
+pub mod messages;
+mod wire;
+
+pub use messages::{
+    batch_item, batch_result, client_message, error_code, room_event_kind, server_message,
+    AddRequest, AddResponse, AuthRequest, AuthResponse, BatchItem, BatchRequest, BatchResponse,
+    BatchResult, CancelRequest, ClientMessage, EchoMessage, ErrorResponse, Event, Hello, JoinRoom,
+    KvCompareAndSwap, KvDelete, KvGet, KvIncrement, KvResponse, KvSet, LeaveRoom, Message,
+    Notification, Ping, Pong, Publish, PublishResponse, RangeSumRequest, RepeatedMessage,
+    RepeatedStr, RoomEvent, RoomMessage, ServerMessage, Signature, StreamCredit, StreamEnd,
+    StreamItem, StreamRequest, Subscribe, SubscribeCounter, SubscriptionResponse, Unsubscribe,
+};
+
+/// Errors of the codec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer cannot hold the encoded data
+    BufferTooSmall,
+    /// The input ended in the middle of a value
+    Truncated,
+    /// A varint is longer than 10 bytes
+    InvalidVarint,
+    /// A field uses a wire type that is not allowed for it
+    InvalidWireType,
+    /// A string field is not valid UTF-8
+    InvalidUtf8,
+    /// A frame is not valid COBS or has no room for its CRC
+    InvalidFrame,
+    /// The CRC of a frame does not match its payload
+    CrcMismatch,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let description = match self {
+            Error::BufferTooSmall => "buffer too small",
+            Error::Truncated => "input truncated",
+            Error::InvalidVarint => "invalid varint",
+            Error::InvalidWireType => "invalid wire type",
+            Error::InvalidUtf8 => "invalid UTF-8 in string field",
+            Error::InvalidFrame => "invalid frame",
+            Error::CrcMismatch => "CRC mismatch",
+        };
+        f.write_str(description)
+    }
+}
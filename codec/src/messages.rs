@@ -0,0 +1,2285 @@
+//! Message types of `proto/messages.proto`
+//!
+//! The types mirror the ones prost generates for the server, except that
+//! strings borrow from the decoded buffer. Unknown fields are skipped, so
+//! firmware keeps working when the server learns new fields; a `oneof` variant
+//! this codec does not know decodes as `message: None`.
+
+use crate::{
+    wire::{self, Reader, Writer, WIRE_LEN, WIRE_VARINT},
+    Error,
+};
+
+/// Encoding and decoding of a message
+pub trait Message<'a>: Sized {
+    /// Number of bytes [`Message::encode`] writes
+    fn encoded_len(&self) -> usize;
+
+    /// Encodes the message into `buf`, returning the number of bytes written
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, Error>;
+
+    /// Decodes a message, borrowing strings from `buf`
+    fn decode(buf: &'a [u8]) -> Result<Self, Error>;
+}
+
+fn int32_field_len(field: u32, value: i32) -> usize {
+    if value == 0 {
+        0
+    } else {
+        wire::key_len(field) + wire::varint_len(wire::int32_to_varint(value))
+    }
+}
+
+fn write_int32_field(w: &mut Writer, field: u32, value: i32) -> Result<(), Error> {
+    if value != 0 {
+        w.key(field, WIRE_VARINT)?;
+        w.varint(wire::int32_to_varint(value))?;
+    }
+    Ok(())
+}
+
+fn int64_field_len(field: u32, value: i64) -> usize {
+    uint64_field_len(field, value as u64)
+}
+
+fn write_int64_field(w: &mut Writer, field: u32, value: i64) -> Result<(), Error> {
+    write_uint64_field(w, field, value as u64)
+}
+
+fn uint64_field_len(field: u32, value: u64) -> usize {
+    if value == 0 {
+        0
+    } else {
+        wire::key_len(field) + wire::varint_len(value)
+    }
+}
+
+fn write_uint64_field(w: &mut Writer, field: u32, value: u64) -> Result<(), Error> {
+    if value != 0 {
+        w.key(field, WIRE_VARINT)?;
+        w.varint(value)?;
+    }
+    Ok(())
+}
+
+fn bool_field_len(field: u32, value: bool) -> usize {
+    if value {
+        wire::key_len(field) + 1
+    } else {
+        0
+    }
+}
+
+fn write_bool_field(w: &mut Writer, field: u32, value: bool) -> Result<(), Error> {
+    if value {
+        w.key(field, WIRE_VARINT)?;
+        w.put(1)?;
+    }
+    Ok(())
+}
+
+fn bytes_field_len(field: u32, value: &[u8]) -> usize {
+    if value.is_empty() {
+        0
+    } else {
+        wire::key_len(field) + wire::varint_len(value.len() as u64) + value.len()
+    }
+}
+
+fn write_bytes_field(w: &mut Writer, field: u32, value: &[u8]) -> Result<(), Error> {
+    if !value.is_empty() {
+        w.key(field, WIRE_LEN)?;
+        w.varint(value.len() as u64)?;
+        w.put_slice(value)?;
+    }
+    Ok(())
+}
+
+fn string_field_len(field: u32, value: &str) -> usize {
+    bytes_field_len(field, value.as_bytes())
+}
+
+fn write_string_field(w: &mut Writer, field: u32, value: &str) -> Result<(), Error> {
+    write_bytes_field(w, field, value.as_bytes())
+}
+
+/// Length of one element of a repeated string field, empty ones included
+fn repeated_string_len(field: u32, value: &str) -> usize {
+    wire::key_len(field) + wire::varint_len(value.len() as u64) + value.len()
+}
+
+fn write_repeated_string(w: &mut Writer, field: u32, value: &str) -> Result<(), Error> {
+    w.key(field, WIRE_LEN)?;
+    w.varint(value.len() as u64)?;
+    w.put_slice(value.as_bytes())
+}
+
+/// Values of a repeated string field
+///
+/// Messages to encode refer to a slice of strings. Decoded messages keep the
+/// encoded bytes and read the values from them while iterating, so decoding
+/// needs no storage for them.
+#[derive(Debug, Clone, Copy)]
+pub enum RepeatedStr<'a> {
+    Slice(&'a [&'a str]),
+    /// Encoded message holding the values in `field`, already checked to be valid
+    Encoded {
+        field: u32,
+        message: &'a [u8],
+    },
+}
+
+impl<'a> RepeatedStr<'a> {
+    pub fn iter(&self) -> RepeatedStrIter<'a> {
+        match *self {
+            RepeatedStr::Slice(values) => RepeatedStrIter::Slice(values.iter()),
+            RepeatedStr::Encoded { field, message } => RepeatedStrIter::Encoded {
+                field,
+                reader: Reader::new(message),
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+impl Default for RepeatedStr<'_> {
+    fn default() -> Self {
+        RepeatedStr::Slice(&[])
+    }
+}
+
+impl PartialEq for RepeatedStr<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for RepeatedStr<'_> {}
+
+impl<'a> IntoIterator for RepeatedStr<'a> {
+    type Item = &'a str;
+    type IntoIter = RepeatedStrIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the values of a [`RepeatedStr`]
+#[derive(Debug, Clone)]
+pub enum RepeatedStrIter<'a> {
+    #[doc(hidden)]
+    Slice(core::slice::Iter<'a, &'a str>),
+    #[doc(hidden)]
+    Encoded { field: u32, reader: Reader<'a> },
+}
+
+impl<'a> Iterator for RepeatedStrIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        match self {
+            RepeatedStrIter::Slice(values) => values.next().copied(),
+            RepeatedStrIter::Encoded { field, reader } => {
+                //the message was validated when it was decoded
+                while !reader.is_empty() {
+                    match reader.key().ok()? {
+                        (number, wire_type) if number == *field => {
+                            return reader.string(wire_type).ok()
+                        }
+                        (_, wire_type) => reader.skip(wire_type).ok()?,
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Values of a repeated message field, like [`RepeatedStr`]
+#[derive(Debug, Clone, Copy)]
+pub enum RepeatedMessage<'a, T> {
+    Slice(&'a [T]),
+    /// Encoded message holding the values in `field`, already checked to be valid
+    Encoded {
+        field: u32,
+        message: &'a [u8],
+    },
+}
+
+impl<'a, T: Message<'a> + Copy> RepeatedMessage<'a, T> {
+    pub fn iter(&self) -> RepeatedMessageIter<'a, T> {
+        match *self {
+            RepeatedMessage::Slice(values) => RepeatedMessageIter::Slice(values.iter()),
+            RepeatedMessage::Encoded { field, message } => RepeatedMessageIter::Encoded {
+                field,
+                reader: Reader::new(message),
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    fn encoded_len(&self, field: u32) -> usize {
+        self.iter()
+            .map(|value| message_field_len(field, value.encoded_len()))
+            .sum()
+    }
+
+    fn write(&self, w: &mut Writer, field: u32) -> Result<(), Error> {
+        for value in self.iter() {
+            write_message_header(w, field, value.encoded_len())?;
+            w.put_with(|buf| value.encode(buf))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> Default for RepeatedMessage<'_, T> {
+    fn default() -> Self {
+        RepeatedMessage::Slice(&[])
+    }
+}
+
+impl<'a, T: Message<'a> + Copy + PartialEq> PartialEq for RepeatedMessage<'a, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<'a, T: Message<'a> + Copy + Eq> Eq for RepeatedMessage<'a, T> {}
+
+impl<'a, T: Message<'a> + Copy> IntoIterator for RepeatedMessage<'a, T> {
+    type Item = T;
+    type IntoIter = RepeatedMessageIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the values of a [`RepeatedMessage`]
+#[derive(Debug, Clone)]
+pub enum RepeatedMessageIter<'a, T> {
+    #[doc(hidden)]
+    Slice(core::slice::Iter<'a, T>),
+    #[doc(hidden)]
+    Encoded { field: u32, reader: Reader<'a> },
+}
+
+impl<'a, T: Message<'a> + Copy> Iterator for RepeatedMessageIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            RepeatedMessageIter::Slice(values) => values.next().copied(),
+            RepeatedMessageIter::Encoded { field, reader } => {
+                //the message was validated when it was decoded
+                while !reader.is_empty() {
+                    match reader.key().ok()? {
+                        (number, wire_type) if number == *field => {
+                            return T::decode(reader.message(wire_type).ok()?).ok()
+                        }
+                        (_, wire_type) => reader.skip(wire_type).ok()?,
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Length of an embedded message field, always present inside a `oneof`
+fn message_field_len(field: u32, len: usize) -> usize {
+    wire::key_len(field) + wire::varint_len(len as u64) + len
+}
+
+fn write_message_header(w: &mut Writer, field: u32, len: usize) -> Result<(), Error> {
+    w.key(field, WIRE_LEN)?;
+    w.varint(len as u64)
+}
+
+/// Implements [`Message`] on top of the inherent `write` and `read` of a type
+macro_rules! impl_message {
+    ($name:ident $(<$lt:lifetime>)?) => {
+        impl<'a> Message<'a> for $name $(<$lt>)? {
+            fn encoded_len(&self) -> usize {
+                $name::encoded_len(self)
+            }
+
+            fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+                let mut w = Writer::new(buf);
+                self.write(&mut w)?;
+                Ok(w.position())
+            }
+
+            fn decode(buf: &'a [u8]) -> Result<Self, Error> {
+                $name::read(Reader::new(buf))
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EchoMessage<'a> {
+    pub content: &'a str,
+}
+
+impl<'a> EchoMessage<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.content)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.content)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.content = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(EchoMessage<'a>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AddRequest {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl AddRequest {
+    fn encoded_len(&self) -> usize {
+        int32_field_len(1, self.a) + int32_field_len(2, self.b)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_int32_field(w, 1, self.a)?;
+        write_int32_field(w, 2, self.b)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.a = r.int32(wire_type)?,
+                (2, wire_type) => message.b = r.int32(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(AddRequest);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AddResponse {
+    pub result: i32,
+}
+
+impl AddResponse {
+    fn encoded_len(&self) -> usize {
+        int32_field_len(1, self.result)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_int32_field(w, 1, self.result)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.result = r.int32(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(AddResponse);
+
+/// Authenticates the connection with a token or a challenge response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AuthRequest<'a> {
+    pub token: &'a str,
+    pub client_id: &'a str,
+    /// HMAC-SHA256 of the challenge, keyed with the client's secret
+    pub challenge_response: &'a [u8],
+}
+
+impl<'a> AuthRequest<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.token)
+            + string_field_len(2, self.client_id)
+            + bytes_field_len(3, self.challenge_response)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.token)?;
+        write_string_field(w, 2, self.client_id)?;
+        write_bytes_field(w, 3, self.challenge_response)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.token = r.string(wire_type)?,
+                (2, wire_type) => message.client_id = r.string(wire_type)?,
+                (3, wire_type) => message.challenge_response = r.bytes_field(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(AuthRequest<'a>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AuthResponse<'a> {
+    pub authenticated: bool,
+    /// Set when a challenge response is expected next
+    pub challenge: &'a [u8],
+}
+
+impl<'a> AuthResponse<'a> {
+    fn encoded_len(&self) -> usize {
+        bool_field_len(1, self.authenticated) + bytes_field_len(2, self.challenge)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_bool_field(w, 1, self.authenticated)?;
+        write_bytes_field(w, 2, self.challenge)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.authenticated = r.bool(wire_type)?,
+                (2, wire_type) => message.challenge = r.bytes_field(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(AuthResponse<'a>);
+
+/// Opens a connection in both directions, see `handshake` in the server crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Hello<'a> {
+    pub protocol_version: u32,
+    pub features: RepeatedStr<'a>,
+    pub name: &'a str,
+}
+
+impl<'a> Hello<'a> {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.protocol_version as u64)
+            + self
+                .features
+                .iter()
+                .map(|feature| repeated_string_len(2, feature))
+                .sum::<usize>()
+            + string_field_len(3, self.name)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.protocol_version as u64)?;
+        for feature in self.features {
+            write_repeated_string(w, 2, feature)?;
+        }
+        write_string_field(w, 3, self.name)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self {
+            features: RepeatedStr::Encoded {
+                field: 2,
+                message: r.remaining(),
+            },
+            ..Self::default()
+        };
+        while !r.is_empty() {
+            match r.key()? {
+                //uint32 values are truncated like prost does
+                (1, wire_type) => message.protocol_version = r.uint64(wire_type)? as u32,
+                //checked here, read again when iterating over the features
+                (2, wire_type) => r.string(wire_type).map(|_| ())?,
+                (3, wire_type) => message.name = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Hello<'a>);
+
+/// Heartbeat of the server, to be answered with a [`Pong`] of the same sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Ping {
+    pub sequence: u64,
+}
+
+impl Ping {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.sequence)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.sequence)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.sequence = r.uint64(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Ping);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pong {
+    pub sequence: u64,
+}
+
+impl Pong {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.sequence)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.sequence)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.sequence = r.uint64(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Pong);
+
+/// Signature of an envelope, see `signing` in the server crate
+///
+/// The MAC is HMAC-SHA256 over the big endian nonce, the big endian timestamp
+/// and the envelope as sent, with its signature field cut out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Signature<'a> {
+    pub nonce: u64,
+    pub timestamp_ms: u64,
+    pub mac: &'a [u8],
+}
+
+impl<'a> Signature<'a> {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.nonce)
+            + uint64_field_len(2, self.timestamp_ms)
+            + bytes_field_len(3, self.mac)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.nonce)?;
+        write_uint64_field(w, 2, self.timestamp_ms)?;
+        write_bytes_field(w, 3, self.mac)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.nonce = r.uint64(wire_type)?,
+                (2, wire_type) => message.timestamp_ms = r.uint64(wire_type)?,
+                (3, wire_type) => message.mac = r.bytes_field(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Signature<'a>);
+
+fn signature_field_len(signature: &Option<Signature>) -> usize {
+    signature.map_or(0, |signature| {
+        message_field_len(SIGNATURE_FIELD, signature.encoded_len())
+    })
+}
+
+fn write_signature_field(w: &mut Writer, signature: &Option<Signature>) -> Result<(), Error> {
+    if let Some(signature) = signature {
+        write_message_header(w, SIGNATURE_FIELD, signature.encoded_len())?;
+        signature.write(w)?;
+    }
+    Ok(())
+}
+
+/// Field number of the signature in both envelopes
+const SIGNATURE_FIELD: u32 = 15;
+
+/// Field number of the deadline in the `ClientMessage` envelope
+const DEADLINE_FIELD: u32 = 14;
+
+/// Values of the `ErrorCode` enum, as carried in [`ErrorResponse::code`]
+pub mod error_code {
+    pub const UNSPECIFIED: i32 = 0;
+    pub const PERMISSION_DENIED: i32 = 1;
+    pub const UNAUTHENTICATED: i32 = 2;
+    pub const INVALID_SIGNATURE: i32 = 3;
+    pub const REPLAY_DETECTED: i32 = 4;
+    pub const RATE_LIMITED: i32 = 5;
+    pub const SERVER_BUSY: i32 = 6;
+    pub const UNSUPPORTED_VERSION: i32 = 7;
+    pub const FRAME_TOO_LARGE: i32 = 8;
+    pub const BATCH_TOO_LARGE: i32 = 9;
+    pub const INVALID_REQUEST: i32 = 10;
+    pub const TOO_MANY_STREAMS: i32 = 11;
+    pub const UNAVAILABLE: i32 = 12;
+    pub const CANCELLED: i32 = 13;
+    pub const DEADLINE_EXCEEDED: i32 = 14;
+    pub const SLOW_SUBSCRIBER: i32 = 15;
+    pub const STORAGE_FAILED: i32 = 16;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ErrorResponse<'a> {
+    /// One of [`error_code`], codes this codec does not know are kept as is
+    pub code: i32,
+    pub message: &'a str,
+}
+
+impl<'a> ErrorResponse<'a> {
+    fn encoded_len(&self) -> usize {
+        int32_field_len(1, self.code) + string_field_len(2, self.message)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_int32_field(w, 1, self.code)?;
+        write_string_field(w, 2, self.message)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.code = r.int32(wire_type)?,
+                (2, wire_type) => message.message = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(ErrorResponse<'a>);
+
+pub mod batch_item {
+    /// Variants of the `request` oneof of a `BatchItem`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Request<'a> {
+        EchoMessage(super::EchoMessage<'a>),
+        AddRequest(super::AddRequest),
+    }
+}
+
+/// One request of a [`BatchRequest`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchItem<'a> {
+    pub request: Option<batch_item::Request<'a>>,
+}
+
+impl<'a> BatchItem<'a> {
+    fn encoded_len(&self) -> usize {
+        match &self.request {
+            Some(batch_item::Request::EchoMessage(echo)) => {
+                message_field_len(1, echo.encoded_len())
+            }
+            Some(batch_item::Request::AddRequest(add)) => message_field_len(2, add.encoded_len()),
+            None => 0,
+        }
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        match &self.request {
+            Some(batch_item::Request::EchoMessage(echo)) => {
+                write_message_header(w, 1, echo.encoded_len())?;
+                echo.write(w)
+            }
+            Some(batch_item::Request::AddRequest(add)) => {
+                write_message_header(w, 2, add.encoded_len())?;
+                add.write(w)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => {
+                    let echo = EchoMessage::read(Reader::new(r.message(wire_type)?))?;
+                    message.request = Some(batch_item::Request::EchoMessage(echo));
+                }
+                (2, wire_type) => {
+                    let add = AddRequest::read(Reader::new(r.message(wire_type)?))?;
+                    message.request = Some(batch_item::Request::AddRequest(add));
+                }
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(BatchItem<'a>);
+
+/// Requests the server processes in order, answered by one [`BatchResponse`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchRequest<'a> {
+    pub requests: RepeatedMessage<'a, BatchItem<'a>>,
+}
+
+impl<'a> BatchRequest<'a> {
+    fn encoded_len(&self) -> usize {
+        self.requests.encoded_len(1)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        self.requests.write(w, 1)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let message = Self {
+            requests: RepeatedMessage::Encoded {
+                field: 1,
+                message: r.remaining(),
+            },
+        };
+        while !r.is_empty() {
+            match r.key()? {
+                //checked here, read again when iterating over the requests
+                (1, wire_type) => {
+                    BatchItem::read(Reader::new(r.message(wire_type)?)).map(|_| ())?
+                }
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(BatchRequest<'a>);
+
+pub mod batch_result {
+    /// Variants of the `result` oneof of a `BatchResult`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Result<'a> {
+        EchoMessage(super::EchoMessage<'a>),
+        AddResponse(super::AddResponse),
+        ErrorResponse(super::ErrorResponse<'a>),
+    }
+}
+
+/// Outcome of one request of a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchResult<'a> {
+    pub result: Option<batch_result::Result<'a>>,
+}
+
+impl<'a> BatchResult<'a> {
+    fn encoded_len(&self) -> usize {
+        match &self.result {
+            Some(batch_result::Result::EchoMessage(echo)) => {
+                message_field_len(1, echo.encoded_len())
+            }
+            Some(batch_result::Result::AddResponse(add)) => message_field_len(2, add.encoded_len()),
+            Some(batch_result::Result::ErrorResponse(error)) => {
+                message_field_len(3, error.encoded_len())
+            }
+            None => 0,
+        }
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        match &self.result {
+            Some(batch_result::Result::EchoMessage(echo)) => {
+                write_message_header(w, 1, echo.encoded_len())?;
+                echo.write(w)
+            }
+            Some(batch_result::Result::AddResponse(add)) => {
+                write_message_header(w, 2, add.encoded_len())?;
+                add.write(w)
+            }
+            Some(batch_result::Result::ErrorResponse(error)) => {
+                write_message_header(w, 3, error.encoded_len())?;
+                error.write(w)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => {
+                    let echo = EchoMessage::read(Reader::new(r.message(wire_type)?))?;
+                    message.result = Some(batch_result::Result::EchoMessage(echo));
+                }
+                (2, wire_type) => {
+                    let add = AddResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.result = Some(batch_result::Result::AddResponse(add));
+                }
+                (3, wire_type) => {
+                    let error = ErrorResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.result = Some(batch_result::Result::ErrorResponse(error));
+                }
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(BatchResult<'a>);
+
+/// One result per request of a [`BatchRequest`], in the order of the requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchResponse<'a> {
+    pub results: RepeatedMessage<'a, BatchResult<'a>>,
+}
+
+impl<'a> BatchResponse<'a> {
+    fn encoded_len(&self) -> usize {
+        self.results.encoded_len(1)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        self.results.write(w, 1)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let message = Self {
+            results: RepeatedMessage::Encoded {
+                field: 1,
+                message: r.remaining(),
+            },
+        };
+        while !r.is_empty() {
+            match r.key()? {
+                //checked here, read again when iterating over the results
+                (1, wire_type) => {
+                    BatchResult::read(Reader::new(r.message(wire_type)?)).map(|_| ())?
+                }
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(BatchResponse<'a>);
+
+/// Streams the running sums of `start..end`, see `streaming` in the server crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RangeSumRequest {
+    pub request_id: u64,
+    pub start: i64,
+    pub end: i64,
+    /// Items the server may send before it waits for a [`StreamCredit`]
+    pub credits: u32,
+}
+
+impl RangeSumRequest {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.request_id)
+            + int64_field_len(2, self.start)
+            + int64_field_len(3, self.end)
+            + uint64_field_len(4, self.credits as u64)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.request_id)?;
+        write_int64_field(w, 2, self.start)?;
+        write_int64_field(w, 3, self.end)?;
+        write_uint64_field(w, 4, self.credits as u64)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.request_id = r.uint64(wire_type)?,
+                (2, wire_type) => message.start = r.int64(wire_type)?,
+                (3, wire_type) => message.end = r.int64(wire_type)?,
+                (4, wire_type) => message.credits = r.uint64(wire_type)? as u32,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(RangeSumRequest);
+
+/// Streams the values of a counter, one every `interval_ms`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubscribeCounter {
+    pub request_id: u64,
+    /// Last value to send, 0 for no end
+    pub count: u64,
+    pub interval_ms: u32,
+    pub credits: u32,
+}
+
+impl SubscribeCounter {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.request_id)
+            + uint64_field_len(2, self.count)
+            + uint64_field_len(3, self.interval_ms as u64)
+            + uint64_field_len(4, self.credits as u64)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.request_id)?;
+        write_uint64_field(w, 2, self.count)?;
+        write_uint64_field(w, 3, self.interval_ms as u64)?;
+        write_uint64_field(w, 4, self.credits as u64)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.request_id = r.uint64(wire_type)?,
+                (2, wire_type) => message.count = r.uint64(wire_type)?,
+                (3, wire_type) => message.interval_ms = r.uint64(wire_type)? as u32,
+                (4, wire_type) => message.credits = r.uint64(wire_type)? as u32,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(SubscribeCounter);
+
+/// Lets the server send `credits` more items of a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamCredit {
+    pub request_id: u64,
+    pub credits: u32,
+}
+
+impl StreamCredit {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.request_id) + uint64_field_len(2, self.credits as u64)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.request_id)?;
+        write_uint64_field(w, 2, self.credits as u64)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.request_id = r.uint64(wire_type)?,
+                (2, wire_type) => message.credits = r.uint64(wire_type)? as u32,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(StreamCredit);
+
+/// Opens a stream of the handler the server registered as `method`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamRequest<'a> {
+    pub request_id: u64,
+    pub method: &'a str,
+    /// Arguments of the handler, in a format of its choosing
+    pub payload: &'a [u8],
+    pub credits: u32,
+}
+
+impl<'a> StreamRequest<'a> {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.request_id)
+            + string_field_len(2, self.method)
+            + bytes_field_len(3, self.payload)
+            + uint64_field_len(4, self.credits as u64)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.request_id)?;
+        write_string_field(w, 2, self.method)?;
+        write_bytes_field(w, 3, self.payload)?;
+        write_uint64_field(w, 4, self.credits as u64)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.request_id = r.uint64(wire_type)?,
+                (2, wire_type) => message.method = r.string(wire_type)?,
+                (3, wire_type) => message.payload = r.bytes_field(wire_type)?,
+                (4, wire_type) => message.credits = r.uint64(wire_type)? as u32,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(StreamRequest<'a>);
+
+/// Stops the request with this id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CancelRequest {
+    pub request_id: u64,
+}
+
+impl CancelRequest {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.request_id)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.request_id)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.request_id = r.uint64(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(CancelRequest);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamItem {
+    pub request_id: u64,
+    pub value: i64,
+}
+
+impl StreamItem {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.request_id) + int64_field_len(2, self.value)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.request_id)?;
+        write_int64_field(w, 2, self.value)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.request_id = r.uint64(wire_type)?,
+                (2, wire_type) => message.value = r.int64(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(StreamItem);
+
+/// Last message of a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamEnd<'a> {
+    pub request_id: u64,
+    /// Set if the stream ended early
+    pub error: Option<ErrorResponse<'a>>,
+}
+
+impl<'a> StreamEnd<'a> {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.request_id)
+            + self
+                .error
+                .map_or(0, |error| message_field_len(2, error.encoded_len()))
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.request_id)?;
+        if let Some(error) = &self.error {
+            write_message_header(w, 2, error.encoded_len())?;
+            error.write(w)?;
+        }
+        Ok(())
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.request_id = r.uint64(wire_type)?,
+                (2, wire_type) => {
+                    message.error = Some(ErrorResponse::read(Reader::new(r.message(wire_type)?))?)
+                }
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(StreamEnd<'a>);
+
+/// Subscribes the connection to a topic pattern, see `pubsub` in the server crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Subscribe<'a> {
+    pub topic: &'a str,
+}
+
+impl<'a> Subscribe<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.topic)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.topic)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.topic = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Subscribe<'a>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Unsubscribe<'a> {
+    pub topic: &'a str,
+}
+
+impl<'a> Unsubscribe<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.topic)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.topic)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.topic = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Unsubscribe<'a>);
+
+/// Sends an event to every connection subscribed to the topic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Publish<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Publish<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.topic) + bytes_field_len(2, self.payload)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.topic)?;
+        write_bytes_field(w, 2, self.payload)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.topic = r.string(wire_type)?,
+                (2, wire_type) => message.payload = r.bytes_field(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Publish<'a>);
+
+/// Answer to `Subscribe` and `Unsubscribe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubscriptionResponse<'a> {
+    pub topic: &'a str,
+    pub subscribed: bool,
+}
+
+impl<'a> SubscriptionResponse<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.topic) + bool_field_len(2, self.subscribed)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.topic)?;
+        write_bool_field(w, 2, self.subscribed)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.topic = r.string(wire_type)?,
+                (2, wire_type) => message.subscribed = r.bool(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(SubscriptionResponse<'a>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PublishResponse {
+    /// Connections the event was queued for
+    pub receivers: u32,
+}
+
+impl PublishResponse {
+    fn encoded_len(&self) -> usize {
+        uint64_field_len(1, self.receivers as u64)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_uint64_field(w, 1, self.receivers as u64)
+    }
+
+    fn read(mut r: Reader) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.receivers = r.uint64(wire_type)? as u32,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(PublishResponse);
+
+/// Payload published on a topic the connection subscribed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Event<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Event<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.topic) + bytes_field_len(2, self.payload)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.topic)?;
+        write_bytes_field(w, 2, self.payload)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.topic = r.string(wire_type)?,
+                (2, wire_type) => message.payload = r.bytes_field(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Event<'a>);
+
+/// Unsolicited message of the server application to this connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Notification<'a> {
+    /// Tells the client how to read the payload
+    pub kind: &'a str,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Notification<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.kind) + bytes_field_len(2, self.payload)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.kind)?;
+        write_bytes_field(w, 2, self.payload)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.kind = r.string(wire_type)?,
+                (2, wire_type) => message.payload = r.bytes_field(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(Notification<'a>);
+
+/// Joins the connection to a chat room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JoinRoom<'a> {
+    pub room: &'a str,
+    /// Shown to the other members unless the connection authenticated
+    pub name: &'a str,
+}
+
+impl<'a> JoinRoom<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.room) + string_field_len(2, self.name)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.room)?;
+        write_string_field(w, 2, self.name)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.room = r.string(wire_type)?,
+                (2, wire_type) => message.name = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(JoinRoom<'a>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LeaveRoom<'a> {
+    pub room: &'a str,
+}
+
+impl<'a> LeaveRoom<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.room)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.room)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.room = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(LeaveRoom<'a>);
+
+/// Posts a message to a room the connection is a member of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RoomMessage<'a> {
+    pub room: &'a str,
+    pub content: &'a str,
+}
+
+impl<'a> RoomMessage<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.room) + string_field_len(2, self.content)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.room)?;
+        write_string_field(w, 2, self.content)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.room = r.string(wire_type)?,
+                (2, wire_type) => message.content = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(RoomMessage<'a>);
+
+/// Values of the `RoomEventKind` enum, as carried in [`RoomEvent::kind`]
+pub mod room_event_kind {
+    pub const UNSPECIFIED: i32 = 0;
+    pub const MEMBER_JOINED: i32 = 1;
+    pub const MEMBER_LEFT: i32 = 2;
+    pub const MESSAGE_POSTED: i32 = 3;
+}
+
+/// Something that happened in a room the connection is a member of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RoomEvent<'a> {
+    pub room: &'a str,
+    /// One of [`room_event_kind`]
+    pub kind: i32,
+    pub member: &'a str,
+    pub content: &'a str,
+    /// Set for messages posted before the connection joined
+    pub replayed: bool,
+}
+
+impl<'a> RoomEvent<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.room)
+            + int32_field_len(2, self.kind)
+            + string_field_len(3, self.member)
+            + string_field_len(4, self.content)
+            + bool_field_len(5, self.replayed)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.room)?;
+        write_int32_field(w, 2, self.kind)?;
+        write_string_field(w, 3, self.member)?;
+        write_string_field(w, 4, self.content)?;
+        write_bool_field(w, 5, self.replayed)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.room = r.string(wire_type)?,
+                (2, wire_type) => message.kind = r.int32(wire_type)?,
+                (3, wire_type) => message.member = r.string(wire_type)?,
+                (4, wire_type) => message.content = r.string(wire_type)?,
+                (5, wire_type) => message.replayed = r.bool(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(RoomEvent<'a>);
+
+/// Reads a key of the server's key-value store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KvGet<'a> {
+    pub key: &'a str,
+}
+
+impl<'a> KvGet<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.key)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.key)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.key = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(KvGet<'a>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KvSet<'a> {
+    pub key: &'a str,
+    pub value: &'a [u8],
+    /// Milliseconds until the key expires, 0 for never
+    pub ttl_ms: u64,
+}
+
+impl<'a> KvSet<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.key)
+            + bytes_field_len(2, self.value)
+            + uint64_field_len(3, self.ttl_ms)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.key)?;
+        write_bytes_field(w, 2, self.value)?;
+        write_uint64_field(w, 3, self.ttl_ms)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.key = r.string(wire_type)?,
+                (2, wire_type) => message.value = r.bytes_field(wire_type)?,
+                (3, wire_type) => message.ttl_ms = r.uint64(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(KvSet<'a>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KvDelete<'a> {
+    pub key: &'a str,
+}
+
+impl<'a> KvDelete<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.key)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.key)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.key = r.string(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(KvDelete<'a>);
+
+/// Sets a key only if it still has the expected version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KvCompareAndSwap<'a> {
+    pub key: &'a str,
+    /// 0 for a key that does not exist yet
+    pub expected_version: u64,
+    pub value: &'a [u8],
+    pub ttl_ms: u64,
+}
+
+impl<'a> KvCompareAndSwap<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.key)
+            + uint64_field_len(2, self.expected_version)
+            + bytes_field_len(3, self.value)
+            + uint64_field_len(4, self.ttl_ms)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.key)?;
+        write_uint64_field(w, 2, self.expected_version)?;
+        write_bytes_field(w, 3, self.value)?;
+        write_uint64_field(w, 4, self.ttl_ms)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.key = r.string(wire_type)?,
+                (2, wire_type) => message.expected_version = r.uint64(wire_type)?,
+                (3, wire_type) => message.value = r.bytes_field(wire_type)?,
+                (4, wire_type) => message.ttl_ms = r.uint64(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(KvCompareAndSwap<'a>);
+
+/// Adds to the integer stored in a key as decimal text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KvIncrement<'a> {
+    pub key: &'a str,
+    pub delta: i64,
+}
+
+impl<'a> KvIncrement<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.key) + int64_field_len(2, self.delta)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.key)?;
+        write_int64_field(w, 2, self.delta)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.key = r.string(wire_type)?,
+                (2, wire_type) => message.delta = r.int64(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(KvIncrement<'a>);
+
+/// Entry of a key after a key-value operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KvResponse<'a> {
+    pub key: &'a str,
+    pub found: bool,
+    pub value: &'a [u8],
+    /// 0 if the key is not found
+    pub version: u64,
+    /// Whether the operation changed the store
+    pub applied: bool,
+}
+
+impl<'a> KvResponse<'a> {
+    fn encoded_len(&self) -> usize {
+        string_field_len(1, self.key)
+            + bool_field_len(2, self.found)
+            + bytes_field_len(3, self.value)
+            + uint64_field_len(4, self.version)
+            + bool_field_len(5, self.applied)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        write_string_field(w, 1, self.key)?;
+        write_bool_field(w, 2, self.found)?;
+        write_bytes_field(w, 3, self.value)?;
+        write_uint64_field(w, 4, self.version)?;
+        write_bool_field(w, 5, self.applied)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => message.key = r.string(wire_type)?,
+                (2, wire_type) => message.found = r.bool(wire_type)?,
+                (3, wire_type) => message.value = r.bytes_field(wire_type)?,
+                (4, wire_type) => message.version = r.uint64(wire_type)?,
+                (5, wire_type) => message.applied = r.bool(wire_type)?,
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(KvResponse<'a>);
+
+pub mod client_message {
+    /// Variants of the `message` oneof of a `ClientMessage`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Message<'a> {
+        EchoMessage(super::EchoMessage<'a>),
+        AddRequest(super::AddRequest),
+        AuthRequest(super::AuthRequest<'a>),
+        Pong(super::Pong),
+        Hello(super::Hello<'a>),
+        BatchRequest(super::BatchRequest<'a>),
+        RangeSumRequest(super::RangeSumRequest),
+        SubscribeCounter(super::SubscribeCounter),
+        StreamCredit(super::StreamCredit),
+        CancelRequest(super::CancelRequest),
+        Subscribe(super::Subscribe<'a>),
+        Unsubscribe(super::Unsubscribe<'a>),
+        Publish(super::Publish<'a>),
+        JoinRoom(super::JoinRoom<'a>),
+        LeaveRoom(super::LeaveRoom<'a>),
+        RoomMessage(super::RoomMessage<'a>),
+        KvGet(super::KvGet<'a>),
+        KvSet(super::KvSet<'a>),
+        KvDelete(super::KvDelete<'a>),
+        KvCompareAndSwap(super::KvCompareAndSwap<'a>),
+        KvIncrement(super::KvIncrement<'a>),
+        StreamRequest(super::StreamRequest<'a>),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClientMessage<'a> {
+    pub message: Option<client_message::Message<'a>>,
+    /// Milliseconds since the unix epoch after which the client no longer
+    /// waits for the response, 0 for no deadline
+    pub deadline_ms: u64,
+    pub signature: Option<Signature<'a>>,
+}
+
+impl<'a> ClientMessage<'a> {
+    fn encoded_len(&self) -> usize {
+        let message_len = match &self.message {
+            Some(client_message::Message::EchoMessage(echo)) => {
+                message_field_len(1, echo.encoded_len())
+            }
+            Some(client_message::Message::AddRequest(add)) => {
+                message_field_len(2, add.encoded_len())
+            }
+            Some(client_message::Message::AuthRequest(auth)) => {
+                message_field_len(3, auth.encoded_len())
+            }
+            Some(client_message::Message::Pong(pong)) => message_field_len(4, pong.encoded_len()),
+            Some(client_message::Message::Hello(hello)) => {
+                message_field_len(5, hello.encoded_len())
+            }
+            Some(client_message::Message::BatchRequest(batch)) => {
+                message_field_len(6, batch.encoded_len())
+            }
+            Some(client_message::Message::RangeSumRequest(range_sum)) => {
+                message_field_len(7, range_sum.encoded_len())
+            }
+            Some(client_message::Message::SubscribeCounter(subscribe)) => {
+                message_field_len(8, subscribe.encoded_len())
+            }
+            Some(client_message::Message::StreamCredit(credit)) => {
+                message_field_len(9, credit.encoded_len())
+            }
+            Some(client_message::Message::CancelRequest(cancel)) => {
+                message_field_len(10, cancel.encoded_len())
+            }
+            Some(client_message::Message::Subscribe(subscribe)) => {
+                message_field_len(11, subscribe.encoded_len())
+            }
+            Some(client_message::Message::Unsubscribe(unsubscribe)) => {
+                message_field_len(12, unsubscribe.encoded_len())
+            }
+            Some(client_message::Message::Publish(publish)) => {
+                message_field_len(13, publish.encoded_len())
+            }
+            Some(client_message::Message::JoinRoom(join)) => {
+                message_field_len(16, join.encoded_len())
+            }
+            Some(client_message::Message::LeaveRoom(leave)) => {
+                message_field_len(17, leave.encoded_len())
+            }
+            Some(client_message::Message::RoomMessage(room_message)) => {
+                message_field_len(18, room_message.encoded_len())
+            }
+            Some(client_message::Message::KvGet(get)) => message_field_len(19, get.encoded_len()),
+            Some(client_message::Message::KvSet(set)) => message_field_len(20, set.encoded_len()),
+            Some(client_message::Message::KvDelete(delete)) => {
+                message_field_len(21, delete.encoded_len())
+            }
+            Some(client_message::Message::KvCompareAndSwap(swap)) => {
+                message_field_len(22, swap.encoded_len())
+            }
+            Some(client_message::Message::KvIncrement(increment)) => {
+                message_field_len(23, increment.encoded_len())
+            }
+            Some(client_message::Message::StreamRequest(stream)) => {
+                message_field_len(24, stream.encoded_len())
+            }
+            None => 0,
+        };
+        message_len
+            + uint64_field_len(DEADLINE_FIELD, self.deadline_ms)
+            + signature_field_len(&self.signature)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        //fields in order of their numbers, like prost writes them
+        match &self.message {
+            Some(client_message::Message::EchoMessage(echo)) => {
+                write_message_header(w, 1, echo.encoded_len())?;
+                echo.write(w)
+            }
+            Some(client_message::Message::AddRequest(add)) => {
+                write_message_header(w, 2, add.encoded_len())?;
+                add.write(w)
+            }
+            Some(client_message::Message::AuthRequest(auth)) => {
+                write_message_header(w, 3, auth.encoded_len())?;
+                auth.write(w)
+            }
+            Some(client_message::Message::Pong(pong)) => {
+                write_message_header(w, 4, pong.encoded_len())?;
+                pong.write(w)
+            }
+            Some(client_message::Message::Hello(hello)) => {
+                write_message_header(w, 5, hello.encoded_len())?;
+                hello.write(w)
+            }
+            Some(client_message::Message::BatchRequest(batch)) => {
+                write_message_header(w, 6, batch.encoded_len())?;
+                batch.write(w)
+            }
+            Some(client_message::Message::RangeSumRequest(range_sum)) => {
+                write_message_header(w, 7, range_sum.encoded_len())?;
+                range_sum.write(w)
+            }
+            Some(client_message::Message::SubscribeCounter(subscribe)) => {
+                write_message_header(w, 8, subscribe.encoded_len())?;
+                subscribe.write(w)
+            }
+            Some(client_message::Message::StreamCredit(credit)) => {
+                write_message_header(w, 9, credit.encoded_len())?;
+                credit.write(w)
+            }
+            Some(client_message::Message::CancelRequest(cancel)) => {
+                write_message_header(w, 10, cancel.encoded_len())?;
+                cancel.write(w)
+            }
+            Some(client_message::Message::Subscribe(subscribe)) => {
+                write_message_header(w, 11, subscribe.encoded_len())?;
+                subscribe.write(w)
+            }
+            Some(client_message::Message::Unsubscribe(unsubscribe)) => {
+                write_message_header(w, 12, unsubscribe.encoded_len())?;
+                unsubscribe.write(w)
+            }
+            Some(client_message::Message::Publish(publish)) => {
+                write_message_header(w, 13, publish.encoded_len())?;
+                publish.write(w)
+            }
+            Some(client_message::Message::JoinRoom(join)) => {
+                write_message_header(w, 16, join.encoded_len())?;
+                join.write(w)
+            }
+            Some(client_message::Message::LeaveRoom(leave)) => {
+                write_message_header(w, 17, leave.encoded_len())?;
+                leave.write(w)
+            }
+            Some(client_message::Message::RoomMessage(room_message)) => {
+                write_message_header(w, 18, room_message.encoded_len())?;
+                room_message.write(w)
+            }
+            Some(client_message::Message::KvGet(get)) => {
+                write_message_header(w, 19, get.encoded_len())?;
+                get.write(w)
+            }
+            Some(client_message::Message::KvSet(set)) => {
+                write_message_header(w, 20, set.encoded_len())?;
+                set.write(w)
+            }
+            Some(client_message::Message::KvDelete(delete)) => {
+                write_message_header(w, 21, delete.encoded_len())?;
+                delete.write(w)
+            }
+            Some(client_message::Message::KvCompareAndSwap(swap)) => {
+                write_message_header(w, 22, swap.encoded_len())?;
+                swap.write(w)
+            }
+            Some(client_message::Message::KvIncrement(increment)) => {
+                write_message_header(w, 23, increment.encoded_len())?;
+                increment.write(w)
+            }
+            Some(client_message::Message::StreamRequest(stream)) => {
+                write_message_header(w, 24, stream.encoded_len())?;
+                stream.write(w)
+            }
+            None => Ok(()),
+        }?;
+        write_uint64_field(w, DEADLINE_FIELD, self.deadline_ms)?;
+        write_signature_field(w, &self.signature)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => {
+                    let echo = EchoMessage::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::EchoMessage(echo));
+                }
+                (2, wire_type) => {
+                    let add = AddRequest::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::AddRequest(add));
+                }
+                (3, wire_type) => {
+                    let auth = AuthRequest::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::AuthRequest(auth));
+                }
+                (4, wire_type) => {
+                    let pong = Pong::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::Pong(pong));
+                }
+                (5, wire_type) => {
+                    let hello = Hello::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::Hello(hello));
+                }
+                (6, wire_type) => {
+                    let batch = BatchRequest::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::BatchRequest(batch));
+                }
+                (7, wire_type) => {
+                    let range_sum = RangeSumRequest::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::RangeSumRequest(range_sum));
+                }
+                (8, wire_type) => {
+                    let subscribe = SubscribeCounter::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::SubscribeCounter(subscribe));
+                }
+                (9, wire_type) => {
+                    let credit = StreamCredit::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::StreamCredit(credit));
+                }
+                (10, wire_type) => {
+                    let cancel = CancelRequest::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::CancelRequest(cancel));
+                }
+                (11, wire_type) => {
+                    let subscribe = Subscribe::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::Subscribe(subscribe));
+                }
+                (12, wire_type) => {
+                    let unsubscribe = Unsubscribe::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::Unsubscribe(unsubscribe));
+                }
+                (13, wire_type) => {
+                    let publish = Publish::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::Publish(publish));
+                }
+                (16, wire_type) => {
+                    let join = JoinRoom::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::JoinRoom(join));
+                }
+                (17, wire_type) => {
+                    let leave = LeaveRoom::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::LeaveRoom(leave));
+                }
+                (18, wire_type) => {
+                    let room_message = RoomMessage::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::RoomMessage(room_message));
+                }
+                (19, wire_type) => {
+                    let get = KvGet::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::KvGet(get));
+                }
+                (20, wire_type) => {
+                    let set = KvSet::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::KvSet(set));
+                }
+                (21, wire_type) => {
+                    let delete = KvDelete::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::KvDelete(delete));
+                }
+                (22, wire_type) => {
+                    let swap = KvCompareAndSwap::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::KvCompareAndSwap(swap));
+                }
+                (23, wire_type) => {
+                    let increment = KvIncrement::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::KvIncrement(increment));
+                }
+                (24, wire_type) => {
+                    let stream = StreamRequest::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(client_message::Message::StreamRequest(stream));
+                }
+                (DEADLINE_FIELD, wire_type) => message.deadline_ms = r.uint64(wire_type)?,
+                (SIGNATURE_FIELD, wire_type) => {
+                    message.signature = Some(Signature::read(Reader::new(r.message(wire_type)?))?);
+                }
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(ClientMessage<'a>);
+
+pub mod server_message {
+    /// Variants of the `message` oneof of a `ServerMessage`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Message<'a> {
+        EchoMessage(super::EchoMessage<'a>),
+        AddResponse(super::AddResponse),
+        ErrorResponse(super::ErrorResponse<'a>),
+        AuthResponse(super::AuthResponse<'a>),
+        Ping(super::Ping),
+        Hello(super::Hello<'a>),
+        BatchResponse(super::BatchResponse<'a>),
+        StreamItem(super::StreamItem),
+        StreamEnd(super::StreamEnd<'a>),
+        SubscriptionResponse(super::SubscriptionResponse<'a>),
+        PublishResponse(super::PublishResponse),
+        Event(super::Event<'a>),
+        Notification(super::Notification<'a>),
+        RoomEvent(super::RoomEvent<'a>),
+        KvResponse(super::KvResponse<'a>),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ServerMessage<'a> {
+    pub message: Option<server_message::Message<'a>>,
+    pub signature: Option<Signature<'a>>,
+}
+
+impl<'a> ServerMessage<'a> {
+    fn encoded_len(&self) -> usize {
+        let message_len = match &self.message {
+            Some(server_message::Message::EchoMessage(echo)) => {
+                message_field_len(1, echo.encoded_len())
+            }
+            Some(server_message::Message::AddResponse(add)) => {
+                message_field_len(2, add.encoded_len())
+            }
+            Some(server_message::Message::ErrorResponse(error)) => {
+                message_field_len(3, error.encoded_len())
+            }
+            Some(server_message::Message::AuthResponse(auth)) => {
+                message_field_len(4, auth.encoded_len())
+            }
+            Some(server_message::Message::Ping(ping)) => message_field_len(5, ping.encoded_len()),
+            Some(server_message::Message::Hello(hello)) => {
+                message_field_len(6, hello.encoded_len())
+            }
+            Some(server_message::Message::BatchResponse(batch)) => {
+                message_field_len(7, batch.encoded_len())
+            }
+            Some(server_message::Message::StreamItem(item)) => {
+                message_field_len(8, item.encoded_len())
+            }
+            Some(server_message::Message::StreamEnd(end)) => {
+                message_field_len(9, end.encoded_len())
+            }
+            Some(server_message::Message::SubscriptionResponse(subscription)) => {
+                message_field_len(10, subscription.encoded_len())
+            }
+            Some(server_message::Message::PublishResponse(publish)) => {
+                message_field_len(11, publish.encoded_len())
+            }
+            Some(server_message::Message::Event(event)) => {
+                message_field_len(12, event.encoded_len())
+            }
+            Some(server_message::Message::Notification(notification)) => {
+                message_field_len(13, notification.encoded_len())
+            }
+            Some(server_message::Message::RoomEvent(room_event)) => {
+                message_field_len(14, room_event.encoded_len())
+            }
+            Some(server_message::Message::KvResponse(kv)) => {
+                message_field_len(16, kv.encoded_len())
+            }
+            None => 0,
+        };
+        message_len + signature_field_len(&self.signature)
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        //fields in order of their numbers, like prost writes them
+        match &self.message {
+            Some(server_message::Message::EchoMessage(echo)) => {
+                write_message_header(w, 1, echo.encoded_len())?;
+                echo.write(w)
+            }
+            Some(server_message::Message::AddResponse(add)) => {
+                write_message_header(w, 2, add.encoded_len())?;
+                add.write(w)
+            }
+            Some(server_message::Message::ErrorResponse(error)) => {
+                write_message_header(w, 3, error.encoded_len())?;
+                error.write(w)
+            }
+            Some(server_message::Message::AuthResponse(auth)) => {
+                write_message_header(w, 4, auth.encoded_len())?;
+                auth.write(w)
+            }
+            Some(server_message::Message::Ping(ping)) => {
+                write_message_header(w, 5, ping.encoded_len())?;
+                ping.write(w)
+            }
+            Some(server_message::Message::Hello(hello)) => {
+                write_message_header(w, 6, hello.encoded_len())?;
+                hello.write(w)
+            }
+            Some(server_message::Message::BatchResponse(batch)) => {
+                write_message_header(w, 7, batch.encoded_len())?;
+                batch.write(w)
+            }
+            Some(server_message::Message::StreamItem(item)) => {
+                write_message_header(w, 8, item.encoded_len())?;
+                item.write(w)
+            }
+            Some(server_message::Message::StreamEnd(end)) => {
+                write_message_header(w, 9, end.encoded_len())?;
+                end.write(w)
+            }
+            Some(server_message::Message::SubscriptionResponse(subscription)) => {
+                write_message_header(w, 10, subscription.encoded_len())?;
+                subscription.write(w)
+            }
+            Some(server_message::Message::PublishResponse(publish)) => {
+                write_message_header(w, 11, publish.encoded_len())?;
+                publish.write(w)
+            }
+            Some(server_message::Message::Event(event)) => {
+                write_message_header(w, 12, event.encoded_len())?;
+                event.write(w)
+            }
+            Some(server_message::Message::Notification(notification)) => {
+                write_message_header(w, 13, notification.encoded_len())?;
+                notification.write(w)
+            }
+            Some(server_message::Message::RoomEvent(room_event)) => {
+                write_message_header(w, 14, room_event.encoded_len())?;
+                room_event.write(w)
+            }
+            Some(server_message::Message::KvResponse(kv)) => {
+                write_message_header(w, 16, kv.encoded_len())?;
+                kv.write(w)
+            }
+            None => Ok(()),
+        }?;
+        write_signature_field(w, &self.signature)
+    }
+
+    fn read(mut r: Reader<'a>) -> Result<Self, Error> {
+        let mut message = Self::default();
+        while !r.is_empty() {
+            match r.key()? {
+                (1, wire_type) => {
+                    let echo = EchoMessage::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::EchoMessage(echo));
+                }
+                (2, wire_type) => {
+                    let add = AddResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::AddResponse(add));
+                }
+                (3, wire_type) => {
+                    let error = ErrorResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::ErrorResponse(error));
+                }
+                (4, wire_type) => {
+                    let auth = AuthResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::AuthResponse(auth));
+                }
+                (5, wire_type) => {
+                    let ping = Ping::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::Ping(ping));
+                }
+                (6, wire_type) => {
+                    let hello = Hello::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::Hello(hello));
+                }
+                (7, wire_type) => {
+                    let batch = BatchResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::BatchResponse(batch));
+                }
+                (8, wire_type) => {
+                    let item = StreamItem::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::StreamItem(item));
+                }
+                (9, wire_type) => {
+                    let end = StreamEnd::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::StreamEnd(end));
+                }
+                (10, wire_type) => {
+                    let subscription =
+                        SubscriptionResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.message =
+                        Some(server_message::Message::SubscriptionResponse(subscription));
+                }
+                (11, wire_type) => {
+                    let publish = PublishResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::PublishResponse(publish));
+                }
+                (12, wire_type) => {
+                    let event = Event::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::Event(event));
+                }
+                (13, wire_type) => {
+                    let notification = Notification::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::Notification(notification));
+                }
+                (14, wire_type) => {
+                    let room_event = RoomEvent::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::RoomEvent(room_event));
+                }
+                (16, wire_type) => {
+                    let kv = KvResponse::read(Reader::new(r.message(wire_type)?))?;
+                    message.message = Some(server_message::Message::KvResponse(kv));
+                }
+                (SIGNATURE_FIELD, wire_type) => {
+                    message.signature = Some(Signature::read(Reader::new(r.message(wire_type)?))?);
+                }
+                (_, wire_type) => r.skip(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl_message!(ServerMessage<'a>);
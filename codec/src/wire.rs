@@ -0,0 +1,193 @@
+//! Protobuf wire format primitives
+
+use crate::Error;
+
+pub const WIRE_VARINT: u8 = 0;
+pub const WIRE_FIXED64: u8 = 1;
+pub const WIRE_LEN: u8 = 2;
+pub const WIRE_FIXED32: u8 = 5;
+
+/// Number of bytes `value` takes as a varint
+pub fn varint_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// Varint value of an `int32`, negative numbers are sign extended to 64 bits
+pub fn int32_to_varint(value: i32) -> u64 {
+    value as i64 as u64
+}
+
+pub fn key_len(field: u32) -> usize {
+    varint_len((field as u64) << 3)
+}
+
+/// Writes into a fixed buffer
+pub struct Writer<'b> {
+    buf: &'b mut [u8],
+    pos: usize,
+}
+
+impl<'b> Writer<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Writer { buf, pos: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn put(&mut self, byte: u8) -> Result<(), Error> {
+        let slot = self.buf.get_mut(self.pos).ok_or(Error::BufferTooSmall)?;
+        *slot = byte;
+        self.pos += 1;
+        Ok(())
+    }
+
+    pub fn put_slice(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.pos + bytes.len();
+        let slot = self
+            .buf
+            .get_mut(self.pos..end)
+            .ok_or(Error::BufferTooSmall)?;
+        slot.copy_from_slice(bytes);
+        self.pos = end;
+        Ok(())
+    }
+
+    pub fn varint(&mut self, mut value: u64) -> Result<(), Error> {
+        while value >= 0x80 {
+            self.put((value as u8) | 0x80)?;
+            value >>= 7;
+        }
+        self.put(value as u8)
+    }
+
+    pub fn key(&mut self, field: u32, wire_type: u8) -> Result<(), Error> {
+        self.varint(((field as u64) << 3) | wire_type as u64)
+    }
+
+    /// Lets `encode` write into the rest of the buffer, then moves past the
+    /// number of bytes it returns
+    pub fn put_with(
+        &mut self,
+        encode: impl FnOnce(&mut [u8]) -> Result<usize, Error>,
+    ) -> Result<(), Error> {
+        self.pos += encode(&mut self.buf[self.pos..])?;
+        Ok(())
+    }
+}
+
+/// Reads from an input buffer, handing out slices that borrow from it
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Input not read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    pub fn varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        for i in 0..10 {
+            let (&byte, rest) = self.buf.split_first().ok_or(Error::Truncated)?;
+            self.buf = rest;
+            value |= ((byte & 0x7F) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidVarint)
+    }
+
+    /// Reads a field key, returning the field number and wire type
+    pub fn key(&mut self) -> Result<(u32, u8), Error> {
+        let key = self.varint()?;
+        Ok(((key >> 3) as u32, (key & 0x7) as u8))
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.buf.len() {
+            return Err(Error::Truncated);
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    pub fn len_delimited(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.varint()?;
+        let len = usize::try_from(len).map_err(|_| Error::Truncated)?;
+        self.bytes(len)
+    }
+
+    pub fn int32(&mut self, wire_type: u8) -> Result<i32, Error> {
+        expect_wire_type(wire_type, WIRE_VARINT)?;
+        //int32 values are truncated like prost does
+        Ok(self.varint()? as i32)
+    }
+
+    pub fn int64(&mut self, wire_type: u8) -> Result<i64, Error> {
+        expect_wire_type(wire_type, WIRE_VARINT)?;
+        Ok(self.varint()? as i64)
+    }
+
+    pub fn uint64(&mut self, wire_type: u8) -> Result<u64, Error> {
+        expect_wire_type(wire_type, WIRE_VARINT)?;
+        self.varint()
+    }
+
+    pub fn bool(&mut self, wire_type: u8) -> Result<bool, Error> {
+        expect_wire_type(wire_type, WIRE_VARINT)?;
+        Ok(self.varint()? != 0)
+    }
+
+    pub fn bytes_field(&mut self, wire_type: u8) -> Result<&'a [u8], Error> {
+        expect_wire_type(wire_type, WIRE_LEN)?;
+        self.len_delimited()
+    }
+
+    pub fn string(&mut self, wire_type: u8) -> Result<&'a str, Error> {
+        expect_wire_type(wire_type, WIRE_LEN)?;
+        core::str::from_utf8(self.len_delimited()?).map_err(|_| Error::InvalidUtf8)
+    }
+
+    pub fn message(&mut self, wire_type: u8) -> Result<&'a [u8], Error> {
+        expect_wire_type(wire_type, WIRE_LEN)?;
+        self.len_delimited()
+    }
+
+    /// Skips a field this codec does not know, for forward compatibility
+    pub fn skip(&mut self, wire_type: u8) -> Result<(), Error> {
+        match wire_type {
+            WIRE_VARINT => self.varint().map(|_| ()),
+            WIRE_FIXED64 => self.bytes(8).map(|_| ()),
+            WIRE_LEN => self.len_delimited().map(|_| ()),
+            WIRE_FIXED32 => self.bytes(4).map(|_| ()),
+            _ => Err(Error::InvalidWireType),
+        }
+    }
+}
+
+fn expect_wire_type(actual: u8, expected: u8) -> Result<(), Error> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(Error::InvalidWireType)
+    }
+}
@@ -0,0 +1,338 @@
+//! Client identities and what they are allowed to do
+//!
+//! Every connection carries a [`ConnectionContext`]. Once a client proved who
+//! it is, with a TLS client certificate or a token from a [`TokenStore`], the
+//! context holds its [`Identity`], and an [`Authorize`] implementation such as
+//! [`AccessPolicy`] decides which message types it may send.
+
+use crate::{
+    handshake::Protocol,
+    message::{batch_item, client_message},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt, fs,
+    io::{self, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+/// Who is on the other end of a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// Distinguished name of the subject, e.g. `CN=sensor-17, O=Acme`
+    pub subject: String,
+    /// Common name of the subject, if it has one
+    pub common_name: Option<String>,
+    /// Subject alternative names: DNS names, email addresses, URIs and IP addresses
+    pub alt_names: Vec<String>,
+}
+
+impl Identity {
+    /// Identity known only by a name, e.g. a client that authenticated with a token
+    pub fn named(name: impl Into<String>) -> Self {
+        let name = name.into();
+        Identity {
+            subject: name.clone(),
+            common_name: Some(name),
+            alt_names: Vec::new(),
+        }
+    }
+
+    /// Common name followed by the subject alternative names
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.common_name.iter().chain(&self.alt_names).map(String::as_str)
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.subject)
+    }
+}
+
+/// What the server knows about a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionContext {
+    /// Printable address of the peer
+    pub peer_addr: String,
+    /// IP address of the peer, `None` for peers on unix sockets and serial ports
+    pub peer_ip: Option<IpAddr>,
+    /// Identity the client authenticated with, `None` for anonymous clients
+    pub identity: Option<Identity>,
+    /// Protocol agreed on in the `Hello` handshake, `None` until the client sent one
+    pub protocol: Option<Protocol>,
+}
+
+impl ConnectionContext {
+    pub fn new(peer_addr: impl Into<String>) -> Self {
+        let peer_addr = peer_addr.into();
+        let peer_ip = peer_addr.parse::<SocketAddr>().ok().map(|addr| addr.ip());
+        ConnectionContext {
+            peer_addr,
+            peer_ip,
+            identity: None,
+            protocol: None,
+        }
+    }
+}
+
+/// Type of a client request, what authorization decisions are made on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Echo,
+    Add,
+    Auth,
+    Pong,
+    Hello,
+    /// A batch, each of its requests is authorized as well
+    Batch,
+    RangeSum,
+    SubscribeCounter,
+    /// A stream of a handler the application registered
+    Stream,
+    StreamCredit,
+    Cancel,
+    Subscribe,
+    Unsubscribe,
+    Publish,
+    JoinRoom,
+    LeaveRoom,
+    RoomMessage,
+    KvGet,
+    KvSet,
+    KvDelete,
+    KvCompareAndSwap,
+    KvIncrement,
+}
+
+impl MessageKind {
+    pub fn of(message: &client_message::Message) -> Self {
+        match message {
+            client_message::Message::EchoMessage(_) => MessageKind::Echo,
+            client_message::Message::AddRequest(_) => MessageKind::Add,
+            client_message::Message::AuthRequest(_) => MessageKind::Auth,
+            client_message::Message::Pong(_) => MessageKind::Pong,
+            client_message::Message::Hello(_) => MessageKind::Hello,
+            client_message::Message::BatchRequest(_) => MessageKind::Batch,
+            client_message::Message::RangeSumRequest(_) => MessageKind::RangeSum,
+            client_message::Message::SubscribeCounter(_) => MessageKind::SubscribeCounter,
+            client_message::Message::StreamRequest(_) => MessageKind::Stream,
+            client_message::Message::StreamCredit(_) => MessageKind::StreamCredit,
+            client_message::Message::CancelRequest(_) => MessageKind::Cancel,
+            client_message::Message::Subscribe(_) => MessageKind::Subscribe,
+            client_message::Message::Unsubscribe(_) => MessageKind::Unsubscribe,
+            client_message::Message::Publish(_) => MessageKind::Publish,
+            client_message::Message::JoinRoom(_) => MessageKind::JoinRoom,
+            client_message::Message::LeaveRoom(_) => MessageKind::LeaveRoom,
+            client_message::Message::RoomMessage(_) => MessageKind::RoomMessage,
+            client_message::Message::KvGet(_) => MessageKind::KvGet,
+            client_message::Message::KvSet(_) => MessageKind::KvSet,
+            client_message::Message::KvDelete(_) => MessageKind::KvDelete,
+            client_message::Message::KvCompareAndSwap(_) => MessageKind::KvCompareAndSwap,
+            client_message::Message::KvIncrement(_) => MessageKind::KvIncrement,
+        }
+    }
+
+    /// Kind of a request inside a batch, each one is authorized on its own
+    pub fn of_batch_item(request: &batch_item::Request) -> Self {
+        match request {
+            batch_item::Request::EchoMessage(_) => MessageKind::Echo,
+            batch_item::Request::AddRequest(_) => MessageKind::Add,
+        }
+    }
+}
+
+impl fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MessageKind::Echo => "EchoMessage",
+            MessageKind::Add => "AddRequest",
+            MessageKind::Auth => "AuthRequest",
+            MessageKind::Pong => "Pong",
+            MessageKind::Hello => "Hello",
+            MessageKind::Batch => "BatchRequest",
+            MessageKind::RangeSum => "RangeSumRequest",
+            MessageKind::SubscribeCounter => "SubscribeCounter",
+            MessageKind::Stream => "StreamRequest",
+            MessageKind::StreamCredit => "StreamCredit",
+            MessageKind::Cancel => "CancelRequest",
+            MessageKind::Subscribe => "Subscribe",
+            MessageKind::Unsubscribe => "Unsubscribe",
+            MessageKind::Publish => "Publish",
+            MessageKind::JoinRoom => "JoinRoom",
+            MessageKind::LeaveRoom => "LeaveRoom",
+            MessageKind::RoomMessage => "RoomMessage",
+            MessageKind::KvGet => "KvGet",
+            MessageKind::KvSet => "KvSet",
+            MessageKind::KvDelete => "KvDelete",
+            MessageKind::KvCompareAndSwap => "KvCompareAndSwap",
+            MessageKind::KvIncrement => "KvIncrement",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Decides whether a connection may send a type of message
+///
+/// Implemented for closures, so simple policies need no type of their own.
+pub trait Authorize: Send + Sync {
+    fn authorize(&self, context: &ConnectionContext, kind: MessageKind) -> bool;
+}
+
+impl<F> Authorize for F
+where
+    F: Fn(&ConnectionContext, MessageKind) -> bool + Send + Sync,
+{
+    fn authorize(&self, context: &ConnectionContext, kind: MessageKind) -> bool {
+        self(context, kind)
+    }
+}
+
+/// Allow list of message types per identity, everything else is denied
+///
+/// Identities are matched by their common name or any of their subject
+/// alternative names.
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    identities: HashMap<String, HashSet<MessageKind>>,
+    anyone: HashSet<MessageKind>,
+}
+
+impl AccessPolicy {
+    /// Creates a policy that denies everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows the identity known as `name` to send `kinds`
+    pub fn allow(mut self, name: impl Into<String>, kinds: &[MessageKind]) -> Self {
+        self.identities.entry(name.into()).or_default().extend(kinds);
+        self
+    }
+
+    /// Allows every client to send `kinds`, including anonymous ones
+    pub fn allow_anyone(mut self, kinds: &[MessageKind]) -> Self {
+        self.anyone.extend(kinds);
+        self
+    }
+}
+
+impl Authorize for AccessPolicy {
+    fn authorize(&self, context: &ConnectionContext, kind: MessageKind) -> bool {
+        if self.anyone.contains(&kind) {
+            return true;
+        }
+        let Some(identity) = &context.identity else {
+            return false;
+        };
+        identity
+            .names()
+            .filter_map(|name| self.identities.get(name))
+            .any(|kinds| kinds.contains(&kind))
+    }
+}
+
+/// Length of the challenges sent to clients authenticating with an HMAC
+pub const CHALLENGE_LEN: usize = 32;
+
+/// Pre-shared secrets of the clients allowed to connect
+///
+/// A client authenticates either by sending its secret as a token, or by
+/// answering a challenge with HMAC-SHA256 keyed with its secret, which keeps
+/// the secret off the wire. Entries are written `name:secret`, the name
+/// becomes the client's [`Identity`].
+#[derive(Clone, Default)]
+pub struct TokenStore {
+    secrets: HashMap<String, Vec<u8>>,
+}
+
+impl TokenStore {
+    /// Creates an empty store, nobody can authenticate against it
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads one `name:secret` entry per line from the file at `path`
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let mut store = Self::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            store.insert_entry(line).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}:{}: {}", path.display(), number + 1, e))
+            })?;
+        }
+        Ok(store)
+    }
+
+    /// Reads comma separated `name:secret` entries from the environment variable `var`
+    pub fn from_env(var: &str) -> io::Result<Self> {
+        let value = env::var(var).map_err(|e| io::Error::new(ErrorKind::NotFound, format!("{}: {}", var, e)))?;
+        let mut store = Self::new();
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            store.insert_entry(entry)?;
+        }
+        Ok(store)
+    }
+
+    /// Adds or replaces the secret of the client `name`
+    pub fn insert(&mut self, name: impl Into<String>, secret: impl Into<Vec<u8>>) {
+        self.secrets.insert(name.into(), secret.into());
+    }
+
+    fn insert_entry(&mut self, entry: &str) -> io::Result<()> {
+        match entry.split_once(':') {
+            Some((name, secret)) if !name.is_empty() && !secret.is_empty() => {
+                self.insert(name, secret);
+                Ok(())
+            }
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "expected an entry of the form name:secret")),
+        }
+    }
+
+    /// Name of the client whose secret is `token`
+    pub fn authenticate_token(&self, token: &str) -> Option<&str> {
+        self.secrets
+            .iter()
+            .find(|(_, secret)| constant_time_eq(secret, token.as_bytes()))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Checks the answer of the client `name` to `challenge`
+    pub fn verify_challenge(&self, name: &str, challenge: &[u8], response: &[u8]) -> bool {
+        let Some(secret) = self.secrets.get(name) else {
+            return false;
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+        mac.update(challenge);
+        mac.verify_slice(response).is_ok()
+    }
+}
+
+impl fmt::Debug for TokenStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        //never print the secrets
+        f.debug_struct("TokenStore").field("clients", &self.secrets.keys()).finish()
+    }
+}
+
+/// Creates a random challenge for [`TokenStore::verify_challenge`]
+pub fn new_challenge() -> io::Result<Vec<u8>> {
+    let mut challenge = vec![0; CHALLENGE_LEN];
+    getrandom::getrandom(&mut challenge)?;
+    Ok(challenge)
+}
+
+/// Compares secrets in time independent of where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,86 @@
+//! Cancellation of requests the server is still working on
+//!
+//! A handler that keeps producing results after a request arrived, like a
+//! stream, holds a [`CancellationToken`] and checks it before every step.
+//! When the client sends a `CancelRequest` with the id of the request, the
+//! server cancels the token and the handler stops, answering with a
+//! `CANCELLED` error instead of its remaining results. The token also carries
+//! the request's deadline, after which the handler stops with a
+//! `DEADLINE_EXCEEDED` error. Handlers of the application get theirs in the
+//! [`crate::streaming::StreamContext`].
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Why a handler has to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Cancelled,
+    DeadlineExceeded,
+}
+
+/// Shared flag telling a handler to stop, clones observe the same flag
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Token that also stops the handler once `deadline` passed
+    pub fn with_deadline(deadline: Option<Instant>) -> Self {
+        CancellationToken {
+            deadline,
+            ..Self::default()
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Whether the handler has to stop at `now`, and why
+    pub fn stop_reason(&self, now: Instant) -> Option<StopReason> {
+        if self.is_cancelled() {
+            Some(StopReason::Cancelled)
+        } else if self.deadline.is_some_and(|deadline| now >= deadline) {
+            Some(StopReason::DeadlineExceeded)
+        } else {
+            None
+        }
+    }
+}
+
+/// Deadline of a request, given in milliseconds since the unix epoch as in
+/// `ClientMessage::deadline_ms`, or `None` for 0
+///
+/// Deadlines in the past map to the current instant.
+pub fn deadline_from_unix_ms(deadline_ms: u64) -> Option<Instant> {
+    if deadline_ms == 0 {
+        return None;
+    }
+    let deadline = UNIX_EPOCH + Duration::from_millis(deadline_ms);
+    let now = Instant::now();
+    match deadline.duration_since(SystemTime::now()) {
+        //too far ahead to be represented, which is no deadline at all
+        Ok(remaining) => now.checked_add(remaining),
+        Err(_) => Some(now),
+    }
+}
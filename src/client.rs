@@ -0,0 +1,487 @@
+//! Client of the server over TCP, TLS or a unix domain socket
+//!
+//! Besides sending requests and receiving responses, the client takes care of
+//! what the protocol expects of every client:
+//!
+//! - it stamps each request with a deadline, [`Client::new`]'s timeout from now;
+//! - it answers the server's `Ping`s while waiting for a response;
+//! - given a [`MessageSigner`], see [`Client::with_signer`], it signs its
+//!   requests and those answers, for servers that require signatures;
+//! - [`Client::handshake`] offers the compression algorithms compiled in and
+//!   switches to what the server agreed on, compression and length-delimited
+//!   framing;
+//! - notifications pushed by the server can be received on a channel of their
+//!   own, see [`Client::notifications`].
+
+use crate::{
+    compression::{self, Algorithm},
+    handshake::FEATURE_LENGTH_DELIMITED,
+    message::{client_message, server_message, ClientMessage, Hello, Notification, Ping, Pong, ServerMessage},
+    server::FrameFormat,
+    signing::MessageSigner,
+};
+use log::{debug, error, info};
+use prost::Message;
+#[cfg(feature = "tls")]
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+#[cfg(feature = "tls")]
+use std::{path::Path, sync::Arc};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::PathBuf};
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::mpsc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// address of the server
+enum Endpoint {
+    Tcp { ip: String, port: u32 },
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+// connection to the server
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => {
+                stream.conn.send_close_notify();
+                stream.flush()?;
+                stream.sock.shutdown(how)
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// TCP/IP or unix domain socket client
+pub struct Client {
+    endpoint: Endpoint,
+    timeout: Duration,
+    stream: Option<Stream>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<ClientConfig>>,
+    // payloads from this size on are compressed once an algorithm is agreed on
+    compression_threshold: Option<usize>,
+    compression: Option<Algorithm>,
+    // where notifications go instead of being returned by `receive`
+    notifications: Option<mpsc::Sender<Notification>>,
+    // how messages are delimited, raw unless the server is configured or
+    // asked otherwise
+    frame_format: FrameFormat,
+    // signs every message sent, except envelopes sent as they are
+    signer: Option<MessageSigner>,
+    // nonce of the last signed message
+    nonce: u64,
+}
+
+// most bytes taken as one raw message
+const RAW_READ_LEN: usize = 64 * 1024;
+
+impl Client {
+    /// Client for a server listening on `ip` and `port`, giving up on
+    /// connecting and on requests after `timeout_ms` milliseconds
+    pub fn new(ip: &str, port: u32, timeout_ms: u64) -> Self {
+        Client {
+            endpoint: Endpoint::Tcp {
+                ip: ip.to_string(),
+                port,
+            },
+            timeout: Duration::from_millis(timeout_ms),
+            stream: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            compression_threshold: None,
+            compression: None,
+            notifications: None,
+            frame_format: FrameFormat::Raw,
+            signer: None,
+            nonce: 0,
+        }
+    }
+
+    /// Client for a server listening on a unix domain socket
+    #[cfg(unix)]
+    pub fn new_unix(path: impl Into<PathBuf>, timeout_ms: u64) -> Self {
+        Client {
+            endpoint: Endpoint::Unix(path.into()),
+            timeout: Duration::from_millis(timeout_ms),
+            stream: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            compression_threshold: None,
+            compression: None,
+            notifications: None,
+            frame_format: FrameFormat::Raw,
+            signer: None,
+            nonce: 0,
+        }
+    }
+
+    /// Uses TLS, trusting the certificate authorities in the PEM file `ca_bundle`
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, ca_bundle: impl AsRef<Path>) -> io::Result<Self> {
+        let config = ClientConfig::builder()
+            .with_root_certificates(root_store(ca_bundle.as_ref())?)
+            .with_no_client_auth();
+        self.tls = Some(Arc::new(config));
+        Ok(self)
+    }
+
+    /// Uses TLS like [`Client::with_tls`], authenticating with the client
+    /// certificate chain and private key in the PEM files `cert` and `key`
+    #[cfg(feature = "tls")]
+    pub fn with_tls_identity(
+        mut self,
+        ca_bundle: impl AsRef<Path>,
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        let certs = CertificateDer::pem_file_iter(cert.as_ref())
+            .map_err(invalid_data)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid_data)?;
+        let key = PrivateKeyDer::from_pem_file(key.as_ref()).map_err(invalid_data)?;
+        let config = ClientConfig::builder()
+            .with_root_certificates(root_store(ca_bundle.as_ref())?)
+            .with_client_auth_cert(certs, key)
+            .map_err(invalid_data)?;
+        self.tls = Some(Arc::new(config));
+        Ok(self)
+    }
+
+    /// Offers the compression algorithms compiled in when shaking hands,
+    /// compressing payloads from `threshold` bytes on once one is agreed on
+    pub fn with_compression(mut self, threshold: usize) -> Self {
+        self.compression_threshold = Some(threshold);
+        self
+    }
+
+    /// Frames messages the way the server is configured to
+    pub fn with_frame_format(mut self, format: FrameFormat) -> Self {
+        self.frame_format = format;
+        self
+    }
+
+    /// Signs the requests and the answers to the server's `Ping`s with
+    /// `signer`, with nonces counting up from 1
+    ///
+    /// Envelopes passed to [`Client::send_envelope`] are sent as they are.
+    pub fn with_signer(mut self, signer: MessageSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Agrees on the protocol with the server, compressing the following
+    /// frames if it picked one of the offered algorithms and prefixing them
+    /// with their length if it agreed to [`FEATURE_LENGTH_DELIMITED`]
+    ///
+    /// Returns the server's `Hello` with all the features it agreed to.
+    pub fn handshake(&mut self, mut hello: Hello) -> io::Result<Hello> {
+        if self.compression_threshold.is_some() {
+            hello
+                .features
+                .extend(Algorithm::SUPPORTED.iter().map(|algorithm| algorithm.feature().to_string()));
+        }
+        self.send(client_message::Message::Hello(hello))?;
+        match self.receive()?.message {
+            Some(server_message::Message::Hello(mut response)) => {
+                let features = response.features.clone();
+                self.compression = compression::select(&mut response.features);
+                if features.iter().any(|feature| feature == FEATURE_LENGTH_DELIMITED) {
+                    self.frame_format = FrameFormat::LengthDelimited;
+                }
+                response.features = features;
+                Ok(response)
+            }
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected Hello, but received {:?}", other),
+            )),
+        }
+    }
+
+    /// Channel receiving the notifications the server pushes from now on,
+    /// [`Client::receive`] passes them on as they arrive between other messages
+    pub fn notifications(&mut self) -> mpsc::Receiver<Notification> {
+        let (sender, receiver) = mpsc::channel();
+        self.notifications = Some(sender);
+        receiver
+    }
+
+    /// Connects to the server
+    pub fn connect(&mut self) -> io::Result<()> {
+        let stream = match &self.endpoint {
+            #[cfg(feature = "tls")]
+            Endpoint::Tcp { ip, port } if self.tls.is_some() => {
+                let config = Arc::clone(self.tls.as_ref().unwrap());
+                let sock = Self::connect_tcp(ip, *port, self.timeout)?;
+                let server_name = ServerName::try_from(ip.clone()).map_err(invalid_data)?;
+                let conn = ClientConnection::new(config, server_name).map_err(invalid_data)?;
+                let mut stream = StreamOwned::new(conn, sock);
+                // complete the handshake now so certificate errors surface here
+                while stream.conn.is_handshaking() {
+                    stream.conn.complete_io(&mut stream.sock)?;
+                }
+                Stream::Tls(Box::new(stream))
+            }
+            Endpoint::Tcp { ip, port } => Stream::Tcp(Self::connect_tcp(ip, *port, self.timeout)?),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                info!("Connecting to {}", path.display());
+                Stream::Unix(UnixStream::connect(path)?)
+            }
+        };
+        self.stream = Some(stream);
+
+        info!("Connected to the server");
+        Ok(())
+    }
+
+    fn connect_tcp(ip: &str, port: u32, timeout: Duration) -> io::Result<TcpStream> {
+        info!("Connecting to {}:{}", ip, port);
+
+        // Resolve the address
+        let address = format!("{}:{}", ip, port);
+        let socket_addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+
+        if socket_addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid IP or port",
+            ));
+        }
+
+        // Connect to the server with a timeout
+        TcpStream::connect_timeout(&socket_addrs[0], timeout)
+    }
+
+    /// Disconnects from the server
+    pub fn disconnect(&mut self) -> io::Result<()> {
+        if let Some(mut stream) = self.stream.take() {
+            stream.shutdown(Shutdown::Both)?;
+        }
+
+        info!("Disconnected from the server");
+        Ok(())
+    }
+
+    /// Sends a request, which the server gives up on once the client's
+    /// timeout passed
+    pub fn send(&mut self, message: client_message::Message) -> io::Result<()> {
+        let deadline = SystemTime::now() + self.timeout;
+        let mut envelope = ClientMessage {
+            message: Some(message),
+            deadline_ms: deadline.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64),
+            signature: None,
+        };
+        self.sign(&mut envelope);
+        self.send_envelope(&envelope)
+    }
+
+    // signs `envelope` with the next nonce, if the client has a signer
+    fn sign(&mut self, envelope: &mut ClientMessage) {
+        if let Some(signer) = &self.signer {
+            self.nonce += 1;
+            signer.sign(envelope, self.nonce);
+        }
+    }
+
+    /// Sends bytes as they are, e.g. a frame built by hand
+    pub fn send_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(ref mut stream) = self.stream {
+            stream.write_all(bytes)?;
+            stream.flush()
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No active connection",
+            ))
+        }
+    }
+
+    /// Sends a complete envelope, e.g. one carrying a signature
+    pub fn send_envelope(&mut self, message: &ClientMessage) -> io::Result<()> {
+        self.write_frame(message.encode_to_vec())?;
+        debug!("Sent message: {:?}", message);
+        Ok(())
+    }
+
+    // send `payload`, compressed if agreed on and prefixed with its length
+    // if the messages are length delimited
+    fn write_frame(&mut self, mut payload: Vec<u8>) -> io::Result<()> {
+        if let Some(ref mut stream) = self.stream {
+            if let Some(algorithm) = self.compression {
+                let threshold = self.compression_threshold.unwrap_or(usize::MAX);
+                payload = compression::compress(algorithm, &payload, threshold);
+            }
+            let mut buffer = Vec::new();
+            if self.frame_format == FrameFormat::LengthDelimited {
+                prost::encode_length_delimiter(payload.len(), &mut buffer)?;
+            }
+            buffer.extend_from_slice(&payload);
+
+            // Send the buffer to the server
+            stream.write_all(&buffer)?;
+            stream.flush()
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No active connection",
+            ))
+        }
+    }
+
+    /// Receives the next message, answering the server's heartbeats on the way
+    pub fn receive(&mut self) -> io::Result<ServerMessage> {
+        loop {
+            let message = self.receive_frame()?;
+            match message.message {
+                Some(server_message::Message::Ping(Ping { sequence })) => {
+                    info!("Answering heartbeat {}", sequence);
+                    let mut pong = ClientMessage {
+                        message: Some(client_message::Message::Pong(Pong { sequence })),
+                        deadline_ms: 0,
+                        signature: None,
+                    };
+                    self.sign(&mut pong);
+                    self.send_envelope(&pong)?;
+                }
+                Some(server_message::Message::Notification(notification)) if self.notifications.is_some() => {
+                    info!("Passing on notification {}", notification.kind);
+                    let sender = self.notifications.as_ref().unwrap();
+                    if sender.send(notification).is_err() {
+                        // nobody listens anymore
+                        self.notifications = None;
+                    }
+                }
+                _ => return Ok(message),
+            }
+        }
+    }
+
+    fn receive_frame(&mut self) -> io::Result<ServerMessage> {
+        if let Some(ref mut stream) = self.stream {
+            debug!("Receiving message from the server");
+            let mut buffer = match self.frame_format {
+                // a raw message is whatever one read returns
+                FrameFormat::Raw => {
+                    let mut buffer = vec![0u8; RAW_READ_LEN];
+                    let bytes_read = stream.read(&mut buffer)?;
+                    if bytes_read == 0 {
+                        return Err(server_disconnected());
+                    }
+                    buffer.truncate(bytes_read);
+                    buffer
+                }
+                // Read the length prefix one byte at a time, then the message
+                FrameFormat::LengthDelimited => {
+                    let mut header = Vec::new();
+                    loop {
+                        let mut byte = [0u8; 1];
+                        if stream.read(&mut byte)? == 0 {
+                            return Err(server_disconnected());
+                        }
+                        header.push(byte[0]);
+                        if byte[0] & 0x80 == 0 {
+                            break;
+                        }
+                    }
+                    let len = prost::decode_length_delimiter(header.as_slice())?;
+                    let mut buffer = vec![0u8; len];
+                    stream.read_exact(&mut buffer)?;
+                    buffer
+                }
+            };
+
+            debug!("Received {} bytes from the server", buffer.len());
+            if self.compression.is_some() {
+                buffer = compression::decompress(&buffer, usize::MAX)?;
+            }
+
+            // Decode the received message
+            ServerMessage::decode(buffer.as_slice()).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Failed to decode ServerMessage: {}", e),
+                )
+            })
+        } else {
+            error!("No active connection");
+            Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No active connection",
+            ))
+        }
+    }
+}
+
+fn server_disconnected() -> io::Error {
+    info!("Server disconnected.");
+    io::Error::new(io::ErrorKind::ConnectionAborted, "Server disconnected")
+}
+
+// certificate authorities trusted by a TLS client
+#[cfg(feature = "tls")]
+fn root_store(ca_bundle: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_bundle).map_err(invalid_data)? {
+        roots.add(cert.map_err(invalid_data)?).map_err(invalid_data)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(feature = "tls")]
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
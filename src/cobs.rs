@@ -0,0 +1,144 @@
+//! COBS framing with a CRC for links without message boundaries, e.g. UARTs
+//!
+//! A frame is the payload followed by its CRC-16/CCITT-FALSE (big endian),
+//! encoded with Consistent Overhead Byte Stuffing and terminated by a zero
+//! byte. Zero never occurs inside an encoded frame, so a receiver that lost
+//! bytes resynchronizes at the next delimiter.
+
+use std::io::{self, ErrorKind};
+
+/// Byte terminating every frame
+pub const DELIMITER: u8 = 0;
+
+/// Largest payload accepted by [`FrameDecoder`]
+pub const MAX_PAYLOAD_LEN: usize = 4096;
+
+/// Computes the CRC-16/CCITT-FALSE of `data`
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Encodes `payload` into a complete frame, including the trailing delimiter
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(payload.len() + 2);
+    data.extend_from_slice(payload);
+    data.extend_from_slice(&crc16(payload).to_be_bytes());
+
+    let mut frame = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    //index of the code byte of the current block
+    let mut code_index = frame.len();
+    frame.push(0);
+    let mut code: u8 = 1;
+    for &byte in &data {
+        if byte == 0 {
+            frame[code_index] = code;
+            code_index = frame.len();
+            frame.push(0);
+            code = 1;
+        } else {
+            frame.push(byte);
+            code += 1;
+            if code == 0xFF {
+                frame[code_index] = code;
+                code_index = frame.len();
+                frame.push(0);
+                code = 1;
+            }
+        }
+    }
+    frame[code_index] = code;
+    frame.push(DELIMITER);
+    frame
+}
+
+/// Decodes the COBS encoded bytes of one frame, without the delimiter,
+/// verifies the CRC and returns the payload
+pub fn decode_frame(encoded: &[u8]) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let code = encoded[i] as usize;
+        if code == 0 || i + code > encoded.len() {
+            return Err(invalid_frame("malformed COBS block"));
+        }
+        data.extend_from_slice(&encoded[i + 1..i + code]);
+        i += code;
+        //a block shorter than 254 bytes stands for a zero, except at the end
+        if code < 0xFF && i < encoded.len() {
+            data.push(0);
+        }
+    }
+
+    if data.len() < 2 {
+        return Err(invalid_frame("frame too short"));
+    }
+    let (payload, crc) = data.split_at(data.len() - 2);
+    if crc16(payload).to_be_bytes() != crc {
+        return Err(invalid_frame("CRC mismatch"));
+    }
+    Ok(payload.to_vec())
+}
+
+fn invalid_frame(reason: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("invalid frame: {}", reason))
+}
+
+/// Reassembles frames from bytes arriving in arbitrary chunks
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    //set while skipping the rest of an oversized frame
+    overflowed: bool,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether bytes of an unfinished frame have been received
+    pub fn in_frame(&self) -> bool {
+        !self.buffer.is_empty() || self.overflowed
+    }
+
+    /// Feeds one received byte, returning a result whenever a frame is complete
+    ///
+    /// Corrupted and oversized frames are reported as errors; decoding carries
+    /// on with the next frame.
+    pub fn push(&mut self, byte: u8) -> Option<io::Result<Vec<u8>>> {
+        if byte != DELIMITER {
+            //largest possible encoding of a maximum size payload and its CRC
+            if self.buffer.len() > MAX_PAYLOAD_LEN + 2 + (MAX_PAYLOAD_LEN + 2) / 254 + 1 {
+                self.overflowed = true;
+                self.buffer.clear();
+            }
+            if !self.overflowed {
+                self.buffer.push(byte);
+            }
+            return None;
+        }
+
+        if self.overflowed {
+            self.overflowed = false;
+            return Some(Err(invalid_frame("frame too large")));
+        }
+        //back to back delimiters carry no frame
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let result = decode_frame(&self.buffer);
+        self.buffer.clear();
+        Some(result)
+    }
+}
@@ -0,0 +1,135 @@
+//! Per-frame payload compression, negotiated in the `Hello` handshake
+//!
+//! Each algorithm compiled in (cargo features `zstd` and `lz4`) is offered as
+//! a handshake feature of the same name; the first one of the server's list
+//! that the client offers too is used in both directions. From the frame after
+//! the handshake response on, every payload starts with a flag byte telling
+//! how the rest is compressed. Payloads shorter than the threshold are sent
+//! raw, as compressing them rarely pays off.
+
+#[cfg(feature = "zstd")]
+use std::io::Read;
+use std::io::{self, ErrorKind};
+
+/// Flag of a payload sent as it is
+pub const FLAG_RAW: u8 = 0;
+/// Flag of a zstd compressed payload
+pub const FLAG_ZSTD: u8 = 1;
+/// Flag of an LZ4 block, prefixed with its uncompressed size
+pub const FLAG_LZ4: u8 = 2;
+
+/// Compression algorithm of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Algorithm {
+    /// Algorithms compiled in, in order of preference
+    pub const SUPPORTED: &'static [Algorithm] = &[
+        #[cfg(feature = "zstd")]
+        Algorithm::Zstd,
+        #[cfg(feature = "lz4")]
+        Algorithm::Lz4,
+    ];
+
+    /// Name of the algorithm as a handshake feature
+    pub fn feature(self) -> &'static str {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => "zstd",
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => "lz4",
+        }
+    }
+
+    fn flag(self) -> u8 {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => FLAG_ZSTD,
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => FLAG_LZ4,
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+    fn compress(self, payload: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => zstd::bulk::compress(payload, zstd::DEFAULT_COMPRESSION_LEVEL).ok(),
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => Some(lz4_flex::compress_prepend_size(payload)),
+        }
+    }
+
+    fn from_feature(feature: &str) -> Option<Self> {
+        Self::SUPPORTED.iter().copied().find(|algorithm| algorithm.feature() == feature)
+    }
+}
+
+/// Picks the algorithm to use from negotiated `features`, removing the other
+/// compression features from the list
+pub fn select(features: &mut Vec<String>) -> Option<Algorithm> {
+    let selected = features.iter().find_map(|feature| Algorithm::from_feature(feature));
+    features.retain(|feature| match Algorithm::from_feature(feature) {
+        Some(algorithm) => Some(algorithm) == selected,
+        None => true,
+    });
+    selected
+}
+
+/// Flag byte and body of `payload`, compressed with `algorithm` unless it is
+/// shorter than `threshold` or does not get any smaller
+pub fn compress(algorithm: Algorithm, payload: &[u8], threshold: usize) -> Vec<u8> {
+    if payload.len() >= threshold {
+        let compressed = algorithm.compress(payload);
+        if let Some(compressed) = compressed.filter(|compressed| compressed.len() < payload.len()) {
+            let mut frame = Vec::with_capacity(compressed.len() + 1);
+            frame.push(algorithm.flag());
+            frame.extend_from_slice(&compressed);
+            return frame;
+        }
+    }
+    let mut frame = Vec::with_capacity(payload.len() + 1);
+    frame.push(FLAG_RAW);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Payload of a frame made by [`compress`], refusing to inflate it beyond
+/// `max_len` bytes
+pub fn decompress(frame: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    let (&flag, body) = frame.split_first().ok_or_else(|| invalid("frame has no compression flag"))?;
+    let payload = match flag {
+        FLAG_RAW => body.to_vec(),
+        #[cfg(feature = "zstd")]
+        FLAG_ZSTD => {
+            //streamed, so memory grows with the actual output rather than the limit
+            let mut payload = Vec::new();
+            zstd::stream::read::Decoder::new(body)
+                .and_then(|decoder| decoder.take((max_len as u64).saturating_add(1)).read_to_end(&mut payload))
+                .map_err(|e| invalid(&e.to_string()))?;
+            payload
+        }
+        #[cfg(feature = "lz4")]
+        FLAG_LZ4 => {
+            let (len, block) = lz4_flex::block::uncompressed_size(body).map_err(|e| invalid(&e.to_string()))?;
+            if len > max_len {
+                return Err(invalid(&format!("{} bytes uncompressed exceed the limit of {} bytes", len, max_len)));
+            }
+            lz4_flex::decompress(block, len).map_err(|e| invalid(&e.to_string()))?
+        }
+        flag => return Err(invalid(&format!("unsupported compression flag {}", flag))),
+    };
+    if payload.len() > max_len {
+        return Err(invalid(&format!("{} bytes exceed the limit of {} bytes", payload.len(), max_len)));
+    }
+    Ok(payload)
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("invalid compressed frame: {}", reason))
+}
@@ -0,0 +1,261 @@
+//! Splitting the byte stream of a connection into messages
+
+use crate::{cobs, server::FrameFormat, transport::Stream};
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind},
+};
+
+/// Result of trying to read one message from a connection
+pub(crate) enum ReadFrame {
+    /// A complete message payload
+    Frame(Vec<u8>),
+    /// A frame arrived but could not be decoded; it has been discarded
+    Invalid(io::Error),
+    /// The next frame is over the limit, nothing of it has been buffered; its
+    /// bytes are discarded as they arrive. Carries the length the frame
+    /// declares, if the framing has one
+    TooLarge(Option<usize>),
+    /// No complete message is available yet
+    Pending,
+    /// The peer closed the connection
+    Closed,
+}
+
+/// How messages are delimited on a connection
+pub(crate) trait Framing: Send {
+    fn read_frame(&mut self, stream: &mut dyn Stream) -> io::Result<ReadFrame>;
+
+    /// Bytes to send for a message with `payload`
+    fn encode_frame(&mut self, payload: &[u8]) -> Vec<u8>;
+
+    /// Whether part of a frame has been received and the rest is still missing
+    fn has_partial_frame(&self) -> bool {
+        false
+    }
+
+    /// Format of the framing if it is one stream sockets speak, `None` for
+    /// framings a client cannot ask for in its `Hello`
+    fn format(&self) -> Option<FrameFormat> {
+        None
+    }
+}
+
+/// Framing of a stream socket connection starting out in `format`, taking
+/// payloads of up to `max_len` bytes
+pub(crate) fn stream_framing(format: FrameFormat, max_len: usize) -> Box<dyn Framing> {
+    match format {
+        FrameFormat::Raw => Box::new(RawFraming::new(max_len)),
+        FrameFormat::LengthDelimited => Box::new(LengthDelimitedFraming::new(max_len)),
+    }
+}
+
+/// Messages sent without delimiters, each read is taken as one message
+///
+/// This is what clients on stream sockets speak unless the server is
+/// configured otherwise or they ask for [`LengthDelimitedFraming`] in their
+/// `Hello`. A message has to arrive in one read.
+///
+/// A read of more than the limit is reported as too large. The rest of such a
+/// message can only be told by its arrival right after, so the bytes already
+/// available are discarded with it, before the client is answered.
+pub(crate) struct RawFraming {
+    //one byte over the limit, a read filling it is too large
+    buffer: Vec<u8>,
+    //discarding the rest of a message over the limit
+    skipping: bool,
+}
+
+/// Most reads discarded at a time, a client that never pauses is left to the
+/// read timeout
+const MAX_SKIPPED_READS: usize = 64;
+
+impl RawFraming {
+    /// Framing accepting messages of up to `max_len` bytes
+    pub fn new(max_len: usize) -> Self {
+        RawFraming {
+            buffer: vec![0; max_len.saturating_add(1)],
+            skipping: false,
+        }
+    }
+
+    /// Discards the bytes available on `stream`, until there are none left
+    fn skip_available(&mut self, stream: &mut dyn Stream) -> io::Result<()> {
+        for _ in 0..MAX_SKIPPED_READS {
+            match stream.read(&mut self.buffer) {
+                //the next read reports it
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.skipping = false;
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Framing for RawFraming {
+    fn read_frame(&mut self, stream: &mut dyn Stream) -> io::Result<ReadFrame> {
+        if self.skipping {
+            self.skip_available(stream)?;
+            if self.skipping {
+                return Ok(ReadFrame::Pending);
+            }
+        }
+        match stream.read(&mut self.buffer) {
+            Ok(0) => Ok(ReadFrame::Closed),
+            Ok(bytes_read) if bytes_read == self.buffer.len() => {
+                self.skipping = true;
+                self.skip_available(stream)?;
+                Ok(ReadFrame::TooLarge(None))
+            }
+            Ok(bytes_read) => Ok(ReadFrame::Frame(self.buffer[..bytes_read].to_vec())),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(ReadFrame::Pending),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn encode_frame(&mut self, payload: &[u8]) -> Vec<u8> {
+        payload.to_vec()
+    }
+
+    fn has_partial_frame(&self) -> bool {
+        self.skipping
+    }
+
+    fn format(&self) -> Option<FrameFormat> {
+        Some(FrameFormat::Raw)
+    }
+}
+
+/// Messages prefixed with their length as a protobuf varint, like prost's
+/// `encode_length_delimited`
+///
+/// Stream socket clients speak this when the server is configured to, see
+/// [`FrameFormat`], or after asking for it in their `Hello`.
+pub(crate) struct LengthDelimitedFraming {
+    //bytes read from the stream that do not form a complete frame yet
+    received: Vec<u8>,
+    max_len: usize,
+    //bytes of an oversized frame still to be discarded
+    skipping: usize,
+}
+
+/// Longest varint encoding of a `u64`
+const MAX_VARINT_LEN: usize = 10;
+
+impl LengthDelimitedFraming {
+    /// Framing accepting payloads of up to `max_len` bytes
+    pub fn new(max_len: usize) -> Self {
+        LengthDelimitedFraming {
+            received: Vec::new(),
+            max_len,
+            skipping: 0,
+        }
+    }
+
+    /// Takes the first complete frame out of the received bytes
+    fn take_frame(&mut self) -> io::Result<Option<ReadFrame>> {
+        if self.skipping > 0 {
+            let skipped = self.skipping.min(self.received.len());
+            self.received.drain(..skipped);
+            self.skipping -= skipped;
+            if self.skipping > 0 {
+                return Ok(None);
+            }
+        }
+        let Some(header_len) = self.received.iter().position(|byte| byte & 0x80 == 0).map(|i| i + 1) else {
+            if self.received.len() >= MAX_VARINT_LEN {
+                return Err(io::Error::new(ErrorKind::InvalidData, "invalid frame length"));
+            }
+            return Ok(None);
+        };
+        let len = prost::decode_length_delimiter(&self.received[..header_len])
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        if len > self.max_len {
+            self.received.drain(..header_len);
+            self.skipping = len;
+            return Ok(Some(ReadFrame::TooLarge(Some(len))));
+        }
+        if self.received.len() - header_len < len {
+            return Ok(None);
+        }
+        let frame = self.received[header_len..header_len + len].to_vec();
+        self.received.drain(..header_len + len);
+        Ok(Some(ReadFrame::Frame(frame)))
+    }
+}
+
+impl Framing for LengthDelimitedFraming {
+    fn read_frame(&mut self, stream: &mut dyn Stream) -> io::Result<ReadFrame> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(frame);
+            }
+
+            let mut buffer = [0; 1024];
+            match stream.read(&mut buffer) {
+                Ok(0) => return Ok(ReadFrame::Closed),
+                Ok(bytes_read) => self.received.extend_from_slice(&buffer[..bytes_read]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(ReadFrame::Pending),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn encode_frame(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(payload.len() + prost::length_delimiter_len(payload.len()));
+        prost::encode_length_delimiter(payload.len(), &mut frame).expect("a Vec grows as needed");
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn has_partial_frame(&self) -> bool {
+        !self.received.is_empty() || self.skipping > 0
+    }
+
+    fn format(&self) -> Option<FrameFormat> {
+        Some(FrameFormat::LengthDelimited)
+    }
+}
+
+/// COBS frames with a CRC, see [`crate::cobs`]
+#[derive(Default)]
+pub(crate) struct CobsFraming {
+    decoder: cobs::FrameDecoder,
+    //bytes read from the stream that were not fed to the decoder yet
+    received: VecDeque<u8>,
+}
+
+impl Framing for CobsFraming {
+    fn read_frame(&mut self, stream: &mut dyn Stream) -> io::Result<ReadFrame> {
+        loop {
+            while let Some(byte) = self.received.pop_front() {
+                match self.decoder.push(byte) {
+                    Some(Ok(payload)) => return Ok(ReadFrame::Frame(payload)),
+                    Some(Err(e)) => return Ok(ReadFrame::Invalid(e)),
+                    None => {}
+                }
+            }
+
+            let mut buffer = [0; 1024];
+            match stream.read(&mut buffer) {
+                Ok(0) => return Ok(ReadFrame::Closed),
+                Ok(bytes_read) => self.received.extend(&buffer[..bytes_read]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(ReadFrame::Pending),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn encode_frame(&mut self, payload: &[u8]) -> Vec<u8> {
+        cobs::encode_frame(payload)
+    }
+
+    fn has_partial_frame(&self) -> bool {
+        !self.received.is_empty() || self.decoder.in_frame()
+    }
+}
@@ -0,0 +1,66 @@
+//! `Hello` handshake agreeing on the protocol version and optional features
+//!
+//! A client opens the connection with a [`Hello`] carrying the newest
+//! protocol version it speaks and the features it supports. The server
+//! answers with the version both speak and the features both support, or with
+//! an `UNSUPPORTED_VERSION` error if the client's version is older than
+//! [`MIN_PROTOCOL_VERSION`]. Clients that skip the handshake are served as
+//! [`MIN_PROTOCOL_VERSION`] clients without optional features.
+
+use crate::message::Hello;
+
+/// Newest protocol version the server speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version the server still accepts
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Name the server introduces itself with unless configured otherwise
+pub const DEFAULT_SERVER_NAME: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// The server sends `Ping`s, see [`crate::server::ServerBuilder::heartbeat`]
+pub const FEATURE_HEARTBEAT: &str = "heartbeat";
+/// The server verifies and signs messages, see [`crate::signing`]
+pub const FEATURE_SIGNING: &str = "signing";
+/// The server expects an `AuthRequest` first, see [`crate::auth::TokenStore`]
+pub const FEATURE_AUTH: &str = "auth";
+/// Messages are prefixed with their length from the next one on, see
+/// [`crate::server::FrameFormat`]; offered on stream sockets
+pub const FEATURE_LENGTH_DELIMITED: &str = "length-delimited";
+
+/// Outcome of the handshake of a connection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: u32,
+    /// Features both ends support, in the order the server lists them
+    pub features: Vec<String>,
+    /// Name the peer introduced itself with
+    pub peer_name: String,
+}
+
+impl Protocol {
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|supported| supported == feature)
+    }
+}
+
+/// Agrees on a protocol with a client that sent `hello`, given the features
+/// the server supports, or explains why the client cannot be served
+pub fn negotiate(hello: &Hello, server_features: &[String]) -> Result<Protocol, String> {
+    if hello.protocol_version < MIN_PROTOCOL_VERSION {
+        return Err(format!(
+            "protocol version {} is not supported, the server speaks versions {} to {}",
+            hello.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ));
+    }
+    Ok(Protocol {
+        version: hello.protocol_version.min(PROTOCOL_VERSION),
+        features: server_features
+            .iter()
+            .filter(|feature| hello.features.contains(feature))
+            .cloned()
+            .collect(),
+        peer_name: hello.name.clone(),
+    })
+}
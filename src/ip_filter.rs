@@ -0,0 +1,110 @@
+//! Allow and deny lists of IP address ranges, checked when a client connects
+
+use std::{
+    fmt,
+    io::{self, ErrorKind},
+    net::IpAddr,
+    str::FromStr,
+};
+
+/// Range of IP addresses in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Range of the addresses sharing the first `prefix_len` bits with `network`
+    pub fn new(network: IpAddr, prefix_len: u8) -> io::Result<Self> {
+        let max_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("prefix length {} is longer than {} bits", prefix_len, max_len),
+            ));
+        }
+        Ok(Cidr { network, prefix_len })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        //IPv4 clients of a dual stack socket show up as ::ffff:a.b.c.d
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(network).into(), u32::from(ip).into(), 32, self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(network), u128::from(ip), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Compares the first `prefix_len` of `bits` bits of two addresses
+fn prefix_matches(network: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = bits - prefix_len;
+    network >> shift == ip >> shift
+}
+
+impl FromStr for Cidr {
+    type Err = io::Error;
+
+    /// Parses `address/prefix_len`, a bare address is a range of one
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("invalid CIDR range {}", s));
+        let (network, prefix_len) = match s.split_once('/') {
+            Some((network, prefix_len)) => (network, Some(prefix_len.parse::<u8>().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let prefix_len = prefix_len.unwrap_or(if network.is_ipv4() { 32 } else { 128 });
+        Cidr::new(network, prefix_len)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Decides which peers may connect by their IP address
+///
+/// Denied ranges win over allowed ones. With no allowed ranges every address
+/// that is not denied may connect.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Creates a filter letting everyone in
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, range: Cidr) -> Self {
+        self.allow.push(range);
+        self
+    }
+
+    pub fn deny(mut self, range: Cidr) -> Self {
+        self.deny.push(range);
+        self
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|range| range.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip))
+    }
+}
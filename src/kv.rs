@@ -0,0 +1,362 @@
+//! Key-value store shared by the connections of a server
+//!
+//! A small scratchpad for devices: keys map to byte values, and every write
+//! gives the key a new version, so that clients can update a key without
+//! overwriting a change they have not seen (see [`Store::compare_and_swap`]).
+//! Keys may expire after a time to live; expired keys are no longer found and
+//! get removed in the background.
+//!
+//! The keys are spread over independently locked shards, so connections
+//! working on different keys rarely wait for each other. A store opened with
+//! [`Store::open`] survives restarts, see [`crate::persistence`]; its log
+//! also keeps the server's subscriptions and counters.
+
+use crate::persistence::{self, Change, Durable, Persistence, Wal};
+use log::error;
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fmt,
+    hash::BuildHasher,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+/// Number of independently locked parts of a store
+pub const SHARDS: usize = 16;
+
+/// Why a key-value operation is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvError {
+    EmptyKey,
+    /// The value to increment is not a decimal integer
+    NotANumber,
+    /// The result of an increment does not fit into an `i64`
+    Overflow,
+    /// The change could not be written to the log, the store is unchanged
+    Storage,
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::EmptyKey => write!(f, "key is empty"),
+            KvError::NotANumber => write!(f, "value is not a decimal integer"),
+            KvError::Overflow => write!(f, "result is out of range"),
+            KvError::Storage => write!(f, "change could not be stored"),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+/// Checks that `key` may be stored
+pub fn check_key(key: &str) -> Result<(), KvError> {
+    if key.is_empty() {
+        Err(KvError::EmptyKey)
+    } else {
+        Ok(())
+    }
+}
+
+/// Time to live given in milliseconds on the wire, where 0 stands for none
+pub fn ttl_from_ms(ttl_ms: u64) -> Option<Duration> {
+    (ttl_ms > 0).then(|| Duration::from_millis(ttl_ms))
+}
+
+/// Value of a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub value: Vec<u8>,
+    /// Taken from one sequence for the whole store, so a key that is deleted
+    /// and set again never gets a version it had before
+    pub version: u64,
+    pub expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+/// Outcome of an operation on a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    /// Whether the store was changed
+    pub applied: bool,
+    /// Entry of the key after the operation, or the one that made a
+    /// compare-and-swap fail
+    pub entry: Option<Entry>,
+}
+
+type Shard = HashMap<String, Entry>;
+
+#[derive(Debug)]
+pub struct Store {
+    shards: Vec<Mutex<Shard>>,
+    hasher: RandomState,
+    last_version: AtomicU64,
+    //changes are written ahead to it while a shard is locked, so in the order
+    //they are applied
+    wal: Option<Mutex<Wal>>,
+    //subscriptions and counters logged along with the keys, empty unless
+    //there is a log
+    durable: Mutex<Durable>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store {
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+            last_version: AtomicU64::new(0),
+            wal: None,
+            durable: Mutex::default(),
+        }
+    }
+}
+
+fn live<'a>(shard: &'a Shard, key: &str, now: Instant) -> Option<&'a Entry> {
+    shard.get(key).filter(|entry| !entry.is_expired(now))
+}
+
+impl Store {
+    /// Store kept in `persistence.dir`, with the keys a previous store left there
+    pub fn open(persistence: &Persistence) -> io::Result<Store> {
+        let (wal, recovered) = Wal::open(persistence)?;
+        let store = Store {
+            last_version: AtomicU64::new(recovered.last_version),
+            wal: Some(Mutex::new(wal)),
+            durable: Mutex::new(recovered.durable),
+            ..Store::default()
+        };
+        for (key, entry) in recovered.entries {
+            store.shard(&key).insert(key, entry);
+        }
+        Ok(store)
+    }
+
+    fn shard(&self, key: &str) -> MutexGuard<'_, Shard> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        self.shards[index].lock().unwrap()
+    }
+
+    /// Only called with the key's shard locked, so the versions of a key
+    /// grow in the order it is written
+    fn next_version(&self) -> u64 {
+        self.last_version.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn write_ahead(&self, change: Change) -> Result<(), KvError> {
+        self.append(change).map_err(|e| {
+            error!("Failed to log a change of the key-value store: {}", e);
+            KvError::Storage
+        })
+    }
+
+    fn append(&self, change: Change) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.lock().unwrap().append(change),
+            None => Ok(()),
+        }
+    }
+
+    /// Topic patterns `identity` subscribed to, if the store is persistent
+    pub(crate) fn subscriptions(&self, identity: &str) -> Vec<String> {
+        let durable = self.durable.lock().unwrap();
+        durable.subscriptions.get(identity).into_iter().flatten().cloned().collect()
+    }
+
+    /// Keeps the subscription of `identity` to `pattern`, if the store is persistent
+    pub(crate) fn subscribe(&self, identity: &str, pattern: &str) -> io::Result<()> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+        let mut durable = self.durable.lock().unwrap();
+        if durable.subscriptions.get(identity).is_some_and(|patterns| patterns.contains(pattern)) {
+            return Ok(());
+        }
+        self.append(Change::Subscribe(identity, pattern))?;
+        durable
+            .subscriptions
+            .entry(identity.to_string())
+            .or_default()
+            .insert(pattern.to_string());
+        Ok(())
+    }
+
+    /// Forgets the subscription of `identity` to `pattern`
+    pub(crate) fn unsubscribe(&self, identity: &str, pattern: &str) -> io::Result<()> {
+        let mut durable = self.durable.lock().unwrap();
+        let Some(patterns) = durable.subscriptions.get_mut(identity) else {
+            return Ok(());
+        };
+        if !patterns.contains(pattern) {
+            return Ok(());
+        }
+        self.append(Change::Unsubscribe(identity, pattern))?;
+        patterns.remove(pattern);
+        if patterns.is_empty() {
+            durable.subscriptions.remove(identity);
+        }
+        Ok(())
+    }
+
+    /// Counter values by name as they were last logged, e.g. before a restart
+    pub(crate) fn counters(&self) -> BTreeMap<String, u64> {
+        self.durable.lock().unwrap().counters.clone()
+    }
+
+    /// Logs the values of the counters if the store is persistent and they
+    /// changed since they were last logged
+    pub(crate) fn log_counters(&self, counters: BTreeMap<String, u64>) -> io::Result<()> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+        let mut durable = self.durable.lock().unwrap();
+        if durable.counters == counters {
+            return Ok(());
+        }
+        self.append(Change::Counters(&counters))?;
+        durable.counters = counters;
+        Ok(())
+    }
+
+    /// Entry of `key`, unless it is missing or expired
+    pub fn get(&self, key: &str, now: Instant) -> Option<Entry> {
+        live(&self.shard(key), key, now).cloned()
+    }
+
+    /// Sets `key` to `value` whatever its version, returning the new entry
+    pub fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>, now: Instant) -> Result<Entry, KvError> {
+        let mut shard = self.shard(key);
+        let entry = Entry {
+            value,
+            version: self.next_version(),
+            expires_at: ttl.map(|ttl| now + ttl),
+        };
+        self.write_ahead(Change::Put(key, &entry))?;
+        shard.insert(key.to_string(), entry.clone());
+        Ok(entry)
+    }
+
+    /// Removes `key`, returning whether it was there
+    pub fn delete(&self, key: &str, now: Instant) -> Result<bool, KvError> {
+        let mut shard = self.shard(key);
+        //expired keys are not found after a restart either
+        if live(&shard, key, now).is_some() {
+            self.write_ahead(Change::Delete(key))?;
+        }
+        Ok(shard.remove(key).is_some_and(|entry| !entry.is_expired(now)))
+    }
+
+    /// Sets `key` to `value` if it still has `expected_version`, 0 standing
+    /// for a key that does not exist yet
+    pub fn compare_and_swap(
+        &self,
+        key: &str,
+        expected_version: u64,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+        now: Instant,
+    ) -> Result<Update, KvError> {
+        let mut shard = self.shard(key);
+        let current = live(&shard, key, now);
+        if current.map_or(0, |entry| entry.version) != expected_version {
+            return Ok(Update {
+                applied: false,
+                entry: current.cloned(),
+            });
+        }
+        let entry = Entry {
+            value,
+            version: self.next_version(),
+            expires_at: ttl.map(|ttl| now + ttl),
+        };
+        self.write_ahead(Change::Put(key, &entry))?;
+        shard.insert(key.to_string(), entry.clone());
+        Ok(Update {
+            applied: true,
+            entry: Some(entry),
+        })
+    }
+
+    /// Adds `delta` to the integer stored in `key` as decimal text, a missing
+    /// key counting as 0, returning the new entry
+    ///
+    /// The key keeps its expiry, one created by the increment never expires.
+    pub fn increment(&self, key: &str, delta: i64, now: Instant) -> Result<Entry, KvError> {
+        let mut shard = self.shard(key);
+        let (number, expires_at) = match live(&shard, key, now) {
+            Some(entry) => {
+                let number = std::str::from_utf8(&entry.value)
+                    .ok()
+                    .and_then(|text| text.parse::<i64>().ok())
+                    .ok_or(KvError::NotANumber)?;
+                (number, entry.expires_at)
+            }
+            None => (0, None),
+        };
+        let number = number.checked_add(delta).ok_or(KvError::Overflow)?;
+        let entry = Entry {
+            value: number.to_string().into_bytes(),
+            version: self.next_version(),
+            expires_at,
+        };
+        self.write_ahead(Change::Put(key, &entry))?;
+        shard.insert(key.to_string(), entry.clone());
+        Ok(entry)
+    }
+
+    /// Removes the keys that expired by `now`, returning how many there were
+    pub fn remove_expired(&self, now: Instant) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut shard = shard.lock().unwrap();
+                let len = shard.len();
+                shard.retain(|_, entry| !entry.is_expired(now));
+                len - shard.len()
+            })
+            .sum()
+    }
+
+    /// Forces logged changes to disk once [`persistence::FsyncPolicy::Interval`] passed
+    pub fn sync(&self, now: Instant) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.lock().unwrap().sync_if_due(now),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the store out as a snapshot replacing the log, if it is
+    /// persistent and changed since the last snapshot
+    pub fn snapshot(&self) -> io::Result<()> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        //no change is half done while all shards and the subscriptions are locked
+        let shards: Vec<MutexGuard<'_, Shard>> = self.shards.iter().map(|shard| shard.lock().unwrap()).collect();
+        let durable = self.durable.lock().unwrap();
+        let mut wal = wal.lock().unwrap();
+        let Some(generation) = wal.rotate()? else {
+            return Ok(());
+        };
+        let dir = wal.dir().to_path_buf();
+        drop(wal);
+        let entries = shards
+            .iter()
+            .flat_map(|shard| shard.iter().map(|(key, entry)| (key.clone(), entry.clone())))
+            .collect();
+        let last_version = self.last_version.load(Ordering::Relaxed);
+        let kept = durable.clone();
+        //changes go on into the new log while the snapshot is written
+        drop(durable);
+        drop(shards);
+        persistence::write_snapshot(&dir, generation, entries, last_version, &kept)
+    }
+}
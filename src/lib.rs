@@ -1,4 +1,27 @@
+pub mod auth;
+pub mod cancel;
+pub mod client;
+pub mod cobs;
+pub mod compression;
+mod framing;
+pub mod handshake;
+pub mod ip_filter;
+pub mod kv;
+pub mod metrics;
+pub mod persistence;
+pub mod pubsub;
+pub mod rate_limit;
+mod rooms;
+#[cfg(unix)]
+pub mod serial;
 pub mod server;
+pub mod signing;
+pub mod streaming;
+#[cfg(unix)]
+pub mod systemd;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
 
 pub mod message {
     include!(concat!(env!("OUT_DIR"), "/messages.rs"));
@@ -1,6 +1,125 @@
-use embedded_recruitment_task::server::Server;
+use embedded_recruitment_task::{
+    auth::TokenStore,
+    persistence::{FsyncPolicy, Persistence},
+    server::{Server, ServerBuilder},
+};
 use log::info;
-use std::io;
+use std::{env, io, sync::Arc, thread, time::Duration};
+
+/// Installs SIGINT/SIGTERM handlers: the first signal stops the server
+/// gracefully, a second one exits immediately
+#[cfg(unix)]
+fn install_signal_handlers(server: Arc<Server>) -> io::Result<()> {
+    use log::warn;
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+    use std::process;
+
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        let mut received = 0;
+        for signal in signals.forever() {
+            received += 1;
+            if received == 1 {
+                info!("received signal {}, shutting down gracefully", signal);
+                server.stop();
+            } else {
+                warn!("received signal {} during shutdown, exiting immediately", signal);
+                process::exit(1);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Installs a Ctrl-C handler, the signal iterator is only there on unix: the
+/// first Ctrl-C stops the server gracefully, a second one exits immediately
+#[cfg(not(unix))]
+fn install_signal_handlers(server: Arc<Server>) -> io::Result<()> {
+    use signal_hook::{consts::SIGINT, flag};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    // registered first, so it sees the flag as the previous Ctrl-C left it
+    flag::register_conditional_shutdown(SIGINT, 1, Arc::clone(&interrupted))?;
+    flag::register(SIGINT, Arc::clone(&interrupted))?;
+    thread::spawn(move || {
+        while !interrupted.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        info!("received Ctrl-C, shutting down gracefully");
+        server.stop();
+    });
+    Ok(())
+}
+
+/// Tokens clients have to authenticate with, read from the file named by
+/// `SERVER_TOKEN_FILE` or from `SERVER_TOKENS`; without either clients are
+/// not asked to authenticate
+fn token_store()->io::Result<Option<TokenStore>>{
+    if let Some(path)=env::var_os("SERVER_TOKEN_FILE"){
+        return TokenStore::from_file(path).map(Some);
+    }
+    if env::var_os("SERVER_TOKENS").is_some(){
+        return TokenStore::from_env("SERVER_TOKENS").map(Some);
+    }
+    Ok(None)
+}
+
+fn invalid_setting(name:&str,value:&str)->io::Error{
+    io::Error::new(io::ErrorKind::InvalidInput,format!("{} has an invalid value {:?}",name,value))
+}
+
+/// Where the key-value store is kept, the directory named by
+/// `SERVER_DATA_DIR` with `SERVER_FSYNC` (`always`, `never` or an interval in
+/// milliseconds) and `SERVER_SNAPSHOT_INTERVAL_MS`; without a directory the
+/// store is lost when the server stops
+fn persistence()->io::Result<Option<Persistence>>{
+    let Some(dir)=env::var_os("SERVER_DATA_DIR") else{
+        return Ok(None);
+    };
+    let mut persistence=Persistence::new(dir);
+    if let Ok(fsync)=env::var("SERVER_FSYNC"){
+        let policy=match fsync.as_str(){
+            "always"=>FsyncPolicy::Always,
+            "never"=>FsyncPolicy::Never,
+            ms=>{
+                let ms=ms.parse().map_err(|_|invalid_setting("SERVER_FSYNC",ms))?;
+                FsyncPolicy::Interval(Duration::from_millis(ms))
+            }
+        };
+        persistence=persistence.fsync(policy);
+    }
+    if let Ok(interval)=env::var("SERVER_SNAPSHOT_INTERVAL_MS"){
+        let ms=interval.parse().map_err(|_|invalid_setting("SERVER_SNAPSHOT_INTERVAL_MS",&interval))?;
+        persistence=persistence.snapshot_interval(Duration::from_millis(ms));
+    }
+    Ok(Some(persistence))
+}
+
+/// Creates the server on the socket passed by systemd if we were socket
+/// activated, on localhost:8080 otherwise
+#[cfg(unix)]
+fn create_server(builder:ServerBuilder)->io::Result<Server>{
+    use embedded_recruitment_task::systemd;
+
+    match systemd::take_listener()?{
+        Some(listener)=>builder.listener(listener),
+        None=>{
+            info!("server starting on localhost:8080");
+            builder.bind("localhost:8080")
+        }
+    }
+}
+
+/// Creates the server on localhost:8080, there is no socket activation off unix
+#[cfg(not(unix))]
+fn create_server(builder:ServerBuilder)->io::Result<Server>{
+    info!("server starting on localhost:8080");
+    builder.bind("localhost:8080")
+}
 
 fn main()->io::Result<()>{
     //initialize logger
@@ -8,9 +127,19 @@ fn main()->io::Result<()>{
         .parse_filters("info")
         .init();
 
-    //create server
-    let server=Server::new("localhost:8080")?;
-    info!("server starting on localhost:8080");
+    let mut builder=Server::builder();
+    if let Some(tokens)=token_store()?{
+        info!("clients have to authenticate");
+        builder=builder.require_auth(tokens);
+    }
+    if let Some(persistence)=persistence()?{
+        info!("keeping the key-value store in {}",persistence.dir.display());
+        builder=builder.persistence(persistence);
+    }
+
+    let server=Arc::new(create_server(builder)?);
+
+    install_signal_handlers(Arc::clone(&server))?;
 
     server.run()
-}
\ No newline at end of file
+}
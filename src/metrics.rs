@@ -0,0 +1,131 @@
+//! Counters of noteworthy events, readable while the server runs
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Events the server counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    /// Requests held back until the rate limit allowed them
+    RequestsDelayed,
+    /// Requests answered with a rate limit error
+    RequestsRejected,
+    /// Connections closed for exceeding the rate limit
+    RateLimitDisconnects,
+    /// Connections turned away because of the connection limits
+    ConnectionsRejected,
+    /// Connections from addresses the IP filter does not allow
+    ConnectionsDenied,
+    /// Connections closed after sending no request for the idle timeout
+    IdleTimeouts,
+    /// Connections closed because a frame was not completed within the read timeout
+    ReadTimeouts,
+    /// Connections closed because a response could not be written within the write timeout
+    WriteTimeouts,
+    /// Connections closed because the client stopped answering heartbeats
+    HeartbeatTimeouts,
+    /// Clients turned away in the handshake for speaking an unsupported protocol version
+    HandshakesRejected,
+    /// Frames rejected for exceeding the frame size limit
+    FramesTooLarge,
+    /// Requests skipped or streams ended because their deadline passed
+    DeadlinesExceeded,
+    /// Published events dropped because a subscriber's queue was full
+    EventsDropped,
+    /// Connections closed because they did not keep up with their subscriptions
+    SlowSubscriberDisconnects,
+    /// Keys of the key-value store removed once their time to live passed
+    KeysExpired,
+    /// Connections closed after too many failed authentication attempts
+    AuthFailureDisconnects,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 16] = [
+        Counter::RequestsDelayed,
+        Counter::RequestsRejected,
+        Counter::RateLimitDisconnects,
+        Counter::ConnectionsRejected,
+        Counter::ConnectionsDenied,
+        Counter::IdleTimeouts,
+        Counter::ReadTimeouts,
+        Counter::WriteTimeouts,
+        Counter::HeartbeatTimeouts,
+        Counter::HandshakesRejected,
+        Counter::FramesTooLarge,
+        Counter::DeadlinesExceeded,
+        Counter::EventsDropped,
+        Counter::SlowSubscriberDisconnects,
+        Counter::KeysExpired,
+        Counter::AuthFailureDisconnects,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Counter::RequestsDelayed => "requests_delayed",
+            Counter::RequestsRejected => "requests_rejected",
+            Counter::RateLimitDisconnects => "rate_limit_disconnects",
+            Counter::ConnectionsRejected => "connections_rejected",
+            Counter::ConnectionsDenied => "connections_denied",
+            Counter::IdleTimeouts => "idle_timeouts",
+            Counter::ReadTimeouts => "read_timeouts",
+            Counter::WriteTimeouts => "write_timeouts",
+            Counter::HeartbeatTimeouts => "heartbeat_timeouts",
+            Counter::HandshakesRejected => "handshakes_rejected",
+            Counter::FramesTooLarge => "frames_too_large",
+            Counter::DeadlinesExceeded => "deadlines_exceeded",
+            Counter::EventsDropped => "events_dropped",
+            Counter::SlowSubscriberDisconnects => "slow_subscriber_disconnects",
+            Counter::KeysExpired => "keys_expired",
+            Counter::AuthFailureDisconnects => "auth_failure_disconnects",
+        }
+    }
+
+    /// The counter called `name`
+    pub fn named(name: &str) -> Option<Counter> {
+        Counter::ALL.into_iter().find(|counter| counter.name() == name)
+    }
+}
+
+/// Current values of all [`Counter`]s
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: [AtomicU64; Counter::ALL.len()],
+}
+
+impl Metrics {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn increment(&self, counter: Counter) {
+        self.add(counter, 1);
+    }
+
+    pub(crate) fn add(&self, counter: Counter, n: u64) {
+        self.counters[counter as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Values of all counters by name
+    pub(crate) fn values(&self) -> BTreeMap<String, u64> {
+        Counter::ALL
+            .into_iter()
+            .map(|counter| (counter.name().to_string(), self.get(counter)))
+            .collect()
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, counter) in Counter::ALL.into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}={}", counter.name(), self.get(counter))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,508 @@
+//! Durable server state through a write-ahead log and snapshots
+//!
+//! With [`crate::server::ServerBuilder::persistence`], every change of the
+//! key-value store is appended to a log in a data directory before it is
+//! applied and answered, and now and then the whole store is written out as a
+//! snapshot, so the log does not grow without end.
+//!
+//! The log and snapshots keep two more parts of the server's state:
+//!
+//! - the topic patterns clients authenticated as an identity subscribed to,
+//!   logged before the subscription is answered. They are back in place once
+//!   a client authenticates as the same identity again, after a restart as
+//!   well. Subscriptions of clients without an identity end with their
+//!   connection, as do chat rooms.
+//! - the values of the server's [`crate::metrics::Counter`]s, logged as they
+//!   change at most every 100 ms and when the server stops, so a crash may
+//!   lose the counts of its last moments.
+//!
+//! The directory holds generations of files: `snapshot-<n>` is the store as
+//! it was when the log `wal-<n>` was started. Taking a snapshot starts the log
+//! of the next generation first, then writes the store under a temporary name
+//! and renames it into place; only then are the files of older generations
+//! removed. Recovery loads the newest snapshot and replays the logs from its
+//! generation on, so a crash at any point loses no change that reached the
+//! disk. A log ending in a partly written record, as a crash in the middle of
+//! an append leaves it, is cut off after its last complete record.
+//!
+//! Every record is preceded by its length and CRC-32, both little endian, and
+//! holds a `LogRecord` or `Snapshot` of `proto/storage.proto`. Only one server
+//! may use a directory at a time.
+
+use crate::kv::Entry;
+use log::{error, info, warn};
+use prost::Message;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+mod record {
+    include!(concat!(env!("OUT_DIR"), "/storage.rs"));
+}
+
+use record::{log_record, LogRecord, Snapshot, StoredCounter, StoredCounters, StoredEntry, StoredSubscription};
+
+/// How often the store is written out as a snapshot unless configured otherwise
+pub const DEFAULT_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+const WAL_PREFIX: &str = "wal-";
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+//of a snapshot until it is complete
+const TEMPORARY_EXTENSION: &str = "tmp";
+//length and CRC-32 in front of every record
+const HEADER_LEN: usize = 8;
+
+/// When changes appended to the log are forced to disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Before each change is answered, so no answered change is ever lost
+    #[default]
+    Always,
+    /// At most this long after a change, if the machine fails changes answered
+    /// in the meantime are lost
+    Interval(Duration),
+    /// Whenever the operating system gets to it, which survives a crash of the
+    /// server but not of the machine
+    Never,
+}
+
+/// Where and how the key-value store is kept, see [`crate::server::ServerBuilder::persistence`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Persistence {
+    pub dir: PathBuf,
+    pub fsync: FsyncPolicy,
+    /// How often the store is written out as a snapshot, if it changed since the last one
+    pub snapshot_interval: Duration,
+}
+
+impl Persistence {
+    /// Keeps the store in `dir`, which is created if it does not exist
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Persistence {
+            dir: dir.into(),
+            fsync: FsyncPolicy::default(),
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+        }
+    }
+
+    pub fn fsync(mut self, policy: FsyncPolicy) -> Self {
+        self.fsync = policy;
+        self
+    }
+
+    pub fn snapshot_interval(mut self, interval: Duration) -> Self {
+        self.snapshot_interval = interval;
+        self
+    }
+}
+
+/// Change appended to the log before it is applied
+pub(crate) enum Change<'a> {
+    Put(&'a str, &'a Entry),
+    Delete(&'a str),
+    /// Identity and the pattern it subscribed to
+    Subscribe(&'a str, &'a str),
+    /// Identity and the pattern it unsubscribed from
+    Unsubscribe(&'a str, &'a str),
+    Counters(&'a BTreeMap<String, u64>),
+}
+
+/// State kept besides the keys
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Durable {
+    /// Topic patterns by the identity that subscribed to them
+    pub subscriptions: BTreeMap<String, BTreeSet<String>>,
+    /// Values by counter name, as last logged
+    pub counters: BTreeMap<String, u64>,
+}
+
+impl Durable {
+    fn subscribe(&mut self, subscription: StoredSubscription) {
+        self.subscriptions
+            .entry(subscription.identity)
+            .or_default()
+            .insert(subscription.pattern);
+    }
+
+    fn unsubscribe(&mut self, subscription: &StoredSubscription) {
+        if let Some(patterns) = self.subscriptions.get_mut(&subscription.identity) {
+            patterns.remove(&subscription.pattern);
+            if patterns.is_empty() {
+                self.subscriptions.remove(&subscription.identity);
+            }
+        }
+    }
+
+    fn set_counters(&mut self, counters: Vec<StoredCounter>) {
+        self.counters = counters.into_iter().map(|counter| (counter.name, counter.value)).collect();
+    }
+
+    fn stored_subscriptions(&self) -> Vec<StoredSubscription> {
+        self.subscriptions
+            .iter()
+            .flat_map(|(identity, patterns)| patterns.iter().map(|pattern| stored_subscription(identity, pattern)))
+            .collect()
+    }
+}
+
+/// Keys, versions, subscriptions and counters found in a data directory
+#[derive(Debug, Default)]
+pub(crate) struct Recovered {
+    pub entries: HashMap<String, Entry>,
+    pub last_version: u64,
+    pub durable: Durable,
+}
+
+/// CRC-32 (IEEE 802.3) of `data`
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32(payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Payloads of the intact records at the start of `data`, and how many bytes
+/// they take up
+fn read_frames(data: &[u8]) -> (Vec<&[u8]>, usize) {
+    let mut payloads = Vec::new();
+    let mut pos = 0;
+    while let Some(header) = data.get(pos..pos + HEADER_LEN) {
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let start = pos + HEADER_LEN;
+        match data.get(start..start + len) {
+            Some(payload) if crc32(payload) == crc => payloads.push(payload),
+            _ => break,
+        }
+        pos = start + len;
+    }
+    (payloads, pos)
+}
+
+fn file_path(dir: &Path, prefix: &str, generation: u64) -> PathBuf {
+    dir.join(format!("{}{}", prefix, generation))
+}
+
+/// Generations of the files in `dir` named `prefix` and a number, in ascending order
+fn generations(dir: &Path, prefix: &str) -> io::Result<Vec<u64>> {
+    let mut generations = Vec::new();
+    for file in fs::read_dir(dir)? {
+        let name = file?.file_name();
+        let generation = name.to_str().and_then(|name| name.strip_prefix(prefix)?.parse::<u64>().ok());
+        generations.extend(generation);
+    }
+    generations.sort_unstable();
+    Ok(generations)
+}
+
+/// Makes created, renamed and removed files of `dir` survive a crash
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+fn to_stored(key: &str, entry: &Entry, now: Instant, system_now: SystemTime) -> StoredEntry {
+    let expires_at_ms = entry.expires_at.map_or(0, |expires_at| {
+        let expires_at = system_now + expires_at.saturating_duration_since(now);
+        //0 would be never, a key already expired stays so
+        (expires_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64).max(1)
+    });
+    StoredEntry {
+        key: key.to_string(),
+        value: entry.value.clone(),
+        version: entry.version,
+        expires_at_ms,
+    }
+}
+
+fn stored_subscription(identity: &str, pattern: &str) -> StoredSubscription {
+    StoredSubscription {
+        identity: identity.to_string(),
+        pattern: pattern.to_string(),
+    }
+}
+
+fn stored_counters(counters: &BTreeMap<String, u64>) -> Vec<StoredCounter> {
+    counters
+        .iter()
+        .map(|(name, &value)| StoredCounter {
+            name: name.clone(),
+            value,
+        })
+        .collect()
+}
+
+/// Key and entry of `stored`, unless it expired by `system_now`
+fn from_stored(stored: StoredEntry, now: Instant, system_now: SystemTime) -> (String, Option<Entry>) {
+    let expires_at = match stored.expires_at_ms {
+        0 => None,
+        ms => match (UNIX_EPOCH + Duration::from_millis(ms)).duration_since(system_now) {
+            Ok(ttl) if !ttl.is_zero() => Some(now + ttl),
+            _ => return (stored.key, None),
+        },
+    };
+    let entry = Entry {
+        value: stored.value,
+        version: stored.version,
+        expires_at,
+    };
+    (stored.key, Some(entry))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Loads the snapshot of `generation`, which has to be intact
+fn read_snapshot(dir: &Path, generation: u64) -> io::Result<Snapshot> {
+    let data = fs::read(file_path(dir, SNAPSHOT_PREFIX, generation))?;
+    match read_frames(&data) {
+        (payloads, len) if payloads.len() == 1 && len == data.len() => Snapshot::decode(payloads[0])
+            .map_err(|e| invalid_data(format!("snapshot {} cannot be decoded: {}", generation, e))),
+        _ => Err(invalid_data(format!("snapshot {} is damaged", generation))),
+    }
+}
+
+/// Applies the intact records of the log of `generation` to `recovered`
+fn replay(dir: &Path, generation: u64, recovered: &mut Recovered, now: Instant, system_now: SystemTime) -> io::Result<()> {
+    let data = fs::read(file_path(dir, WAL_PREFIX, generation))?;
+    let (payloads, len) = read_frames(&data);
+    let intact = payloads.len();
+    let mut replayed = 0;
+    for payload in payloads {
+        let Ok(record) = LogRecord::decode(payload) else {
+            break;
+        };
+        match record.change {
+            Some(log_record::Change::Put(stored)) => {
+                recovered.last_version = recovered.last_version.max(stored.version);
+                match from_stored(stored, now, system_now) {
+                    (key, Some(entry)) => recovered.entries.insert(key, entry),
+                    (key, None) => recovered.entries.remove(&key),
+                };
+            }
+            Some(log_record::Change::Delete(key)) => {
+                recovered.entries.remove(&key);
+            }
+            Some(log_record::Change::Subscribe(subscription)) => recovered.durable.subscribe(subscription),
+            Some(log_record::Change::Unsubscribe(subscription)) => recovered.durable.unsubscribe(&subscription),
+            Some(log_record::Change::Counters(counters)) => recovered.durable.set_counters(counters.counters),
+            None => {}
+        }
+        replayed += 1;
+    }
+    if replayed < intact || len < data.len() {
+        warn!("Log {} ends in an incomplete or damaged record, recovered the {} records before it", generation, replayed);
+    }
+    Ok(())
+}
+
+/// Writes `entries` and `durable` as the snapshot of `generation`, then
+/// removes the files of older generations
+pub(crate) fn write_snapshot(
+    dir: &Path,
+    generation: u64,
+    entries: Vec<(String, Entry)>,
+    last_version: u64,
+    durable: &Durable,
+) -> io::Result<()> {
+    let now = Instant::now();
+    let system_now = SystemTime::now();
+    let snapshot = Snapshot {
+        entries: entries
+            .iter()
+            .map(|(key, entry)| to_stored(key, entry, now, system_now))
+            .collect(),
+        last_version,
+        subscriptions: durable.stored_subscriptions(),
+        counters: stored_counters(&durable.counters),
+    };
+    let path = file_path(dir, SNAPSHOT_PREFIX, generation);
+    let temporary = path.with_extension(TEMPORARY_EXTENSION);
+    let mut file = File::create(&temporary)?;
+    file.write_all(&frame(&snapshot.encode_to_vec()))?;
+    file.sync_all()?;
+    fs::rename(&temporary, &path)?;
+    sync_dir(dir)?;
+    info!("Wrote snapshot {} of {} keys", generation, entries.len());
+
+    for prefix in [WAL_PREFIX, SNAPSHOT_PREFIX] {
+        for older in generations(dir, prefix)?.into_iter().filter(|&older| older < generation) {
+            fs::remove_file(file_path(dir, prefix, older))?;
+        }
+    }
+    sync_dir(dir)
+}
+
+/// Log of the current generation, appended to by the store
+#[derive(Debug)]
+pub(crate) struct Wal {
+    dir: PathBuf,
+    fsync: FsyncPolicy,
+    generation: u64,
+    file: File,
+    len: u64,
+    //records appended in this generation
+    records: u64,
+    //since when appended records wait for FsyncPolicy::Interval
+    unsynced_since: Option<Instant>,
+    //set when a failed append could not be undone
+    broken: bool,
+}
+
+impl Wal {
+    /// Recovers the store kept in `persistence.dir` and starts a new
+    /// generation with a snapshot of it
+    pub(crate) fn open(persistence: &Persistence) -> io::Result<(Wal, Recovered)> {
+        let dir = &persistence.dir;
+        fs::create_dir_all(dir)?;
+        for file in fs::read_dir(dir)? {
+            let path = file?.path();
+            //left behind by a crash while a snapshot was written
+            if path.extension().is_some_and(|extension| extension == TEMPORARY_EXTENSION) {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let snapshots = generations(dir, SNAPSHOT_PREFIX)?;
+        let logs = generations(dir, WAL_PREFIX)?;
+        let mut recovered = Recovered::default();
+        let base = snapshots.last().copied();
+        if let Some(generation) = base {
+            let snapshot = read_snapshot(dir, generation)?;
+            recovered.last_version = snapshot.last_version;
+            for stored in snapshot.entries {
+                if let (key, Some(entry)) = from_stored(stored, now, system_now) {
+                    recovered.entries.insert(key, entry);
+                }
+            }
+            for subscription in snapshot.subscriptions {
+                recovered.durable.subscribe(subscription);
+            }
+            recovered.durable.set_counters(snapshot.counters);
+        }
+        for &generation in logs.iter().filter(|&&generation| base.is_none_or(|base| generation >= base)) {
+            replay(dir, generation, &mut recovered, now, system_now)?;
+        }
+
+        //a fresh generation leaves any damaged end of the old logs behind
+        let generation = snapshots.iter().chain(&logs).max().map_or(1, |latest| latest + 1);
+        let wal = Wal::create(dir, persistence.fsync, generation)?;
+        let entries = recovered.entries.iter().map(|(key, entry)| (key.clone(), entry.clone())).collect();
+        write_snapshot(dir, generation, entries, recovered.last_version, &recovered.durable)?;
+        info!(
+            "Recovered {} keys and the subscriptions of {} identities from {}",
+            recovered.entries.len(),
+            recovered.durable.subscriptions.len(),
+            dir.display()
+        );
+        Ok((wal, recovered))
+    }
+
+    fn create(dir: &Path, fsync: FsyncPolicy, generation: u64) -> io::Result<Wal> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path(dir, WAL_PREFIX, generation))?;
+        sync_dir(dir)?;
+        Ok(Wal {
+            dir: dir.to_path_buf(),
+            fsync,
+            generation,
+            len: file.metadata()?.len(),
+            file,
+            records: 0,
+            unsynced_since: None,
+            broken: false,
+        })
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Appends `change`, on disk before returning if the fsync policy says so
+    pub(crate) fn append(&mut self, change: Change) -> io::Result<()> {
+        if self.broken {
+            return Err(io::Error::other("log is damaged by an earlier failed write"));
+        }
+        let change = match change {
+            Change::Put(key, entry) => log_record::Change::Put(to_stored(key, entry, Instant::now(), SystemTime::now())),
+            Change::Delete(key) => log_record::Change::Delete(key.to_string()),
+            Change::Subscribe(identity, pattern) => log_record::Change::Subscribe(stored_subscription(identity, pattern)),
+            Change::Unsubscribe(identity, pattern) => {
+                log_record::Change::Unsubscribe(stored_subscription(identity, pattern))
+            }
+            Change::Counters(counters) => log_record::Change::Counters(StoredCounters {
+                counters: stored_counters(counters),
+            }),
+        };
+        let frame = frame(&LogRecord { change: Some(change) }.encode_to_vec());
+        let written = self.file.write_all(&frame).and_then(|()| match self.fsync {
+            FsyncPolicy::Always => self.file.sync_data(),
+            FsyncPolicy::Interval(_) | FsyncPolicy::Never => Ok(()),
+        });
+        if let Err(e) = written {
+            //a change that was refused must not show up after a restart
+            if let Err(truncate_error) = self.file.set_len(self.len) {
+                error!("Failed to undo a failed write of log {}: {}", self.generation, truncate_error);
+                self.broken = true;
+            }
+            return Err(e);
+        }
+        self.len += frame.len() as u64;
+        self.records += 1;
+        if let FsyncPolicy::Interval(_) = self.fsync {
+            self.unsynced_since.get_or_insert_with(Instant::now);
+        }
+        Ok(())
+    }
+
+    /// Forces appended records to disk once they waited as long as
+    /// [`FsyncPolicy::Interval`] allows
+    pub(crate) fn sync_if_due(&mut self, now: Instant) -> io::Result<()> {
+        match (self.fsync, self.unsynced_since) {
+            (FsyncPolicy::Interval(interval), Some(since)) if now.duration_since(since) >= interval => {
+                self.file.sync_data()?;
+                self.unsynced_since = None;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts the log of the next generation, unless nothing was appended in
+    /// this one, returning the new generation
+    pub(crate) fn rotate(&mut self) -> io::Result<Option<u64>> {
+        if self.records == 0 {
+            return Ok(None);
+        }
+        //replayed along with the new one until the snapshot is complete
+        if self.fsync != FsyncPolicy::Never {
+            self.file.sync_data()?;
+        }
+        *self = Wal::create(&self.dir, self.fsync, self.generation + 1)?;
+        Ok(Some(self.generation))
+    }
+}
@@ -0,0 +1,260 @@
+//! Publish/subscribe on named topics
+//!
+//! Topics consist of levels separated by `/`, e.g. `sensors/kitchen/temp`. A
+//! connection subscribes to topic patterns, in which `+` stands for exactly
+//! one level and `#`, as the last level, for any number of them (including
+//! none): `sensors/+/temp` and `sensors/#` both match the topic above.
+//!
+//! A published payload is queued as an `Event` for every subscribed
+//! connection, whose own thread sends it. The queues are bounded, a
+//! subscriber that does not keep up is handled as its
+//! [`SlowSubscriberAction`] says, so it never holds up the publisher.
+
+use crate::message::Event;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// Level separator of topics
+pub const SEPARATOR: char = '/';
+/// Pattern level matching exactly one topic level
+pub const SINGLE_LEVEL_WILDCARD: &str = "+";
+/// Last pattern level matching any number of topic levels
+pub const MULTI_LEVEL_WILDCARD: &str = "#";
+
+/// Why a topic or pattern is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicError {
+    Empty,
+    /// Topics of published events may not contain wildcards
+    Wildcard,
+    /// A wildcard is not a level of its own, or `#` is not the last level
+    MisplacedWildcard,
+}
+
+impl fmt::Display for TopicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopicError::Empty => write!(f, "topic is empty"),
+            TopicError::Wildcard => write!(f, "wildcards are only allowed when subscribing"),
+            TopicError::MisplacedWildcard => write!(f, "'+' has to be a whole level and '#' the whole last level"),
+        }
+    }
+}
+
+impl std::error::Error for TopicError {}
+
+/// Checks that events may be published on `topic`
+pub fn check_topic(topic: &str) -> Result<(), TopicError> {
+    if topic.is_empty() {
+        Err(TopicError::Empty)
+    } else if topic.contains(['+', '#']) {
+        Err(TopicError::Wildcard)
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that `pattern` may be subscribed to
+pub fn check_pattern(pattern: &str) -> Result<(), TopicError> {
+    if pattern.is_empty() {
+        return Err(TopicError::Empty);
+    }
+    let mut levels = pattern.split(SEPARATOR).peekable();
+    while let Some(level) = levels.next() {
+        let is_last = levels.peek().is_none();
+        let misplaced = match level {
+            SINGLE_LEVEL_WILDCARD => false,
+            MULTI_LEVEL_WILDCARD => !is_last,
+            _ => level.contains(['+', '#']),
+        };
+        if misplaced {
+            return Err(TopicError::MisplacedWildcard);
+        }
+    }
+    Ok(())
+}
+
+/// Whether events published on `topic` are delivered to subscribers of `pattern`
+pub fn matches(pattern: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split(SEPARATOR);
+    for level in pattern.split(SEPARATOR) {
+        if level == MULTI_LEVEL_WILDCARD {
+            return true;
+        }
+        match topic_levels.next() {
+            Some(topic_level) if level == SINGLE_LEVEL_WILDCARD || level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// What happens when an event is published for a subscriber whose queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SlowSubscriberAction {
+    /// Drop the oldest queued event to make room, the subscriber sees the latest ones
+    #[default]
+    DropOldest,
+    /// Drop the new event
+    DropNewest,
+    /// Answer with a `SLOW_SUBSCRIBER` error and close the connection
+    Disconnect,
+}
+
+/// Outcome of queuing an event for one subscriber
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Push {
+    Queued,
+    /// Queued, an older one was dropped for it
+    ReplacedOldest,
+    Dropped,
+    /// Not queued, the subscriber has to be disconnected
+    Overflowed,
+}
+
+#[derive(Debug, Default)]
+struct SubscriberState {
+    patterns: Vec<String>,
+    events: VecDeque<Arc<Event>>,
+    overflowed: bool,
+}
+
+/// Subscriptions and event queue of one connection
+#[derive(Debug)]
+pub struct Subscriber {
+    capacity: usize,
+    action: SlowSubscriberAction,
+    state: Mutex<SubscriberState>,
+}
+
+impl Subscriber {
+    fn new(capacity: usize, action: SlowSubscriberAction) -> Self {
+        Subscriber {
+            capacity,
+            action,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether the connection is subscribed to any pattern
+    pub fn is_subscribed(&self) -> bool {
+        !self.state.lock().unwrap().patterns.is_empty()
+    }
+
+    /// Whether events were lost while the action is [`SlowSubscriberAction::Disconnect`]
+    pub fn has_overflowed(&self) -> bool {
+        self.state.lock().unwrap().overflowed
+    }
+
+    /// Takes the oldest queued event
+    pub fn next_event(&self) -> Option<Arc<Event>> {
+        self.state.lock().unwrap().events.pop_front()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn push(&self, topic: &str, event: &Arc<Event>) -> Option<Push> {
+        let mut state = self.state.lock().unwrap();
+        if state.overflowed || !state.patterns.iter().any(|pattern| matches(pattern, topic)) {
+            return None;
+        }
+        if state.events.len() < self.capacity {
+            state.events.push_back(Arc::clone(event));
+            return Some(Push::Queued);
+        }
+        Some(match self.action {
+            SlowSubscriberAction::DropOldest => {
+                state.events.pop_front();
+                state.events.push_back(Arc::clone(event));
+                Push::ReplacedOldest
+            }
+            SlowSubscriberAction::DropNewest => Push::Dropped,
+            SlowSubscriberAction::Disconnect => {
+                //nothing more is delivered to a connection about to be closed
+                state.overflowed = true;
+                state.events.clear();
+                Push::Overflowed
+            }
+        })
+    }
+}
+
+/// What became of a published event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Delivery {
+    /// Subscribers the event was queued for
+    pub receivers: usize,
+    /// Events dropped from or not added to full queues
+    pub dropped: usize,
+}
+
+/// Subscribers of a server by connection id
+#[derive(Debug, Default)]
+pub struct Broker {
+    subscribers: Mutex<HashMap<u64, Arc<Subscriber>>>,
+}
+
+impl Broker {
+    /// Subscribes connection `id` to `pattern`, which has to pass [`check_pattern`]
+    ///
+    /// The connection's queue is created with its first subscription and
+    /// returned to be drained by the connection.
+    pub fn subscribe(&self, id: u64, pattern: &str, capacity: usize, action: SlowSubscriberAction) -> Arc<Subscriber> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let subscriber = subscribers
+            .entry(id)
+            .or_insert_with(|| Arc::new(Subscriber::new(capacity, action)));
+        let mut state = subscriber.state.lock().unwrap();
+        if !state.patterns.iter().any(|subscribed| subscribed == pattern) {
+            state.patterns.push(pattern.to_string());
+        }
+        drop(state);
+        Arc::clone(subscriber)
+    }
+
+    /// Unsubscribes connection `id` from `pattern`, returning false if it was not subscribed
+    ///
+    /// Events already queued are still delivered.
+    pub fn unsubscribe(&self, id: u64, pattern: &str) -> bool {
+        let subscribers = self.subscribers.lock().unwrap();
+        let Some(subscriber) = subscribers.get(&id) else {
+            return false;
+        };
+        let mut state = subscriber.state.lock().unwrap();
+        let len = state.patterns.len();
+        state.patterns.retain(|subscribed| subscribed != pattern);
+        state.patterns.len() != len
+    }
+
+    /// Removes all subscriptions of connection `id`, e.g. once it is closed
+    pub fn remove(&self, id: u64) {
+        self.subscribers.lock().unwrap().remove(&id);
+    }
+
+    /// Queues an event for every subscriber of a matching pattern, `topic`
+    /// has to pass [`check_topic`]
+    pub fn publish(&self, topic: &str, payload: Vec<u8>) -> Delivery {
+        let event = Arc::new(Event {
+            topic: topic.to_string(),
+            payload,
+        });
+        let mut delivery = Delivery::default();
+        for subscriber in self.subscribers.lock().unwrap().values() {
+            match subscriber.push(topic, &event) {
+                Some(Push::Queued) => delivery.receivers += 1,
+                Some(Push::ReplacedOldest) => {
+                    delivery.receivers += 1;
+                    delivery.dropped += 1;
+                }
+                Some(Push::Dropped | Push::Overflowed) => delivery.dropped += 1,
+                None => {}
+            }
+        }
+        delivery
+    }
+}
@@ -0,0 +1,214 @@
+//! Token bucket rate limits on client requests
+//!
+//! Every request takes a token from the bucket of its connection, of its
+//! peer's IP address and from the global bucket, whichever are configured.
+//! Buckets refill continuously up to their burst size. A request finding one
+//! of them empty is handled as the [`RateLimitAction`] says.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Rate and burst size of a token bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Tokens added per second
+    pub per_second: f64,
+    /// Tokens the bucket holds when full, i.e. requests allowed back to back
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        RateLimit { per_second, burst }
+    }
+
+    /// Fails unless tokens are added at a positive rate and the bucket holds
+    /// at least one, otherwise a delayed request would be held back forever
+    fn check(&self) -> io::Result<()> {
+        if self.per_second.is_nan() || self.per_second <= 0.0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("rate limit of {} requests per second is not positive", self.per_second),
+            ));
+        }
+        if self.burst == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "rate limit with a burst size of 0 allows no request",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// What happens to a request exceeding a rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitAction {
+    /// Hold the request back until tokens are available
+    #[default]
+    Delay,
+    /// Answer with a `RATE_LIMITED` error, the request is dropped
+    Reject,
+    /// Answer with a `RATE_LIMITED` error and close the connection
+    Disconnect,
+}
+
+/// Rate limits of a server, see [`crate::server::ServerBuilder::rate_limits`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+    pub per_connection: Option<RateLimit>,
+    pub per_ip: Option<RateLimit>,
+    pub global: Option<RateLimit>,
+    pub action: RateLimitAction,
+}
+
+impl RateLimits {
+    /// No limits yet, exceeding the ones added later triggers `action`
+    pub fn new(action: RateLimitAction) -> Self {
+        RateLimits {
+            action,
+            ..Default::default()
+        }
+    }
+
+    pub fn per_connection(mut self, limit: RateLimit) -> Self {
+        self.per_connection = Some(limit);
+        self
+    }
+
+    pub fn per_ip(mut self, limit: RateLimit) -> Self {
+        self.per_ip = Some(limit);
+        self
+    }
+
+    pub fn global(mut self, limit: RateLimit) -> Self {
+        self.global = Some(limit);
+        self
+    }
+
+    /// Fails if one of the limits has a rate that is not positive or a burst
+    /// size of 0
+    pub(crate) fn check(&self) -> io::Result<()> {
+        [self.per_connection, self.per_ip, self.global]
+            .iter()
+            .flatten()
+            .try_for_each(RateLimit::check)
+    }
+}
+
+/// Longest time until a token is available that is reported, a slower rate
+/// is as good as none
+const MAX_WAIT_TIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of per IP buckets kept before full ones are dropped
+const MAX_IDLE_IP_BUCKETS: usize = 1024;
+
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.refilled = now;
+    }
+
+    /// Time until a token is available, zero if there is one, at most [`MAX_WAIT_TIME`]
+    fn wait_time(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        //a tiny rate makes for a wait too long to represent
+        Duration::try_from_secs_f64((1.0 - self.tokens) / self.limit.per_second)
+            .map_or(MAX_WAIT_TIME, |wait| wait.min(MAX_WAIT_TIME))
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.limit.burst as f64
+    }
+}
+
+/// Buckets shared by all connections
+pub(crate) struct RateLimiter {
+    limits: RateLimits,
+    per_ip: Mutex<HashMap<IpAddr, TokenBucket>>,
+    global: Option<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            limits,
+            per_ip: Mutex::new(HashMap::new()),
+            global: limits.global.map(|limit| Mutex::new(TokenBucket::new(limit))),
+        }
+    }
+
+    pub fn action(&self) -> RateLimitAction {
+        self.limits.action
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Bucket of a new connection, if connections are limited
+    pub fn connection_bucket(&self) -> Option<TokenBucket> {
+        self.limits.per_connection.map(TokenBucket::new)
+    }
+
+    /// Takes a token from every bucket that applies to a request, or from none
+    /// of them, returning how long to wait for the next try in that case
+    pub fn acquire(&self, connection: Option<&mut TokenBucket>, ip: Option<IpAddr>) -> Result<(), Duration> {
+        let now = Instant::now();
+        //always locked in this order
+        let mut per_ip = self.per_ip.lock().unwrap();
+        let mut global = self.global.as_ref().map(|global| global.lock().unwrap());
+
+        let ip_bucket = match (self.limits.per_ip, ip) {
+            (Some(limit), Some(ip)) => {
+                if per_ip.len() >= MAX_IDLE_IP_BUCKETS && !per_ip.contains_key(&ip) {
+                    per_ip.retain(|_, bucket| {
+                        bucket.refill(now);
+                        !bucket.is_full()
+                    });
+                }
+                Some(per_ip.entry(ip).or_insert_with(|| TokenBucket::new(limit)))
+            }
+            _ => None,
+        };
+
+        let mut buckets: Vec<&mut TokenBucket> = [connection, ip_bucket, global.as_deref_mut()]
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut wait = Duration::ZERO;
+        for bucket in buckets.iter_mut() {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait_time());
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for bucket in buckets {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
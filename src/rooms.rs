@@ -0,0 +1,170 @@
+//! Chat rooms: membership and the recent messages of each room
+//!
+//! Rooms only decide who gets which `RoomEvent`, the server queues the
+//! events for the connections' threads to send. As all changes go through
+//! the server state one at a time, every member sees the events of a room in
+//! the same order.
+//!
+//! The history of a room is kept as [`HistoryLimits`] say, whether or not the
+//! room has members: a room nobody is in is only forgotten once its history
+//! expired.
+
+use crate::message::{RoomEvent, RoomEventKind};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Why a room operation is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomError {
+    EmptyName,
+    AlreadyMember,
+    NotAMember,
+}
+
+impl fmt::Display for RoomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomError::EmptyName => write!(f, "room name is empty"),
+            RoomError::AlreadyMember => write!(f, "already a member of the room"),
+            RoomError::NotAMember => write!(f, "not a member of the room"),
+        }
+    }
+}
+
+/// Events to send, each to the connection with the given id
+pub type Deliveries = Vec<(u64, RoomEvent)>;
+
+/// How much of the history of a room is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryLimits {
+    /// Messages kept at most
+    pub len: usize,
+    /// How long a message is kept after it was posted
+    pub retention: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Room {
+    //names of the members by connection id
+    members: BTreeMap<u64, String>,
+    //latest MESSAGE_POSTED events with the time they were posted, oldest first
+    history: VecDeque<(Instant, RoomEvent)>,
+}
+
+impl Room {
+    /// The same event for every member
+    fn broadcast(&self, event: RoomEvent) -> Deliveries {
+        self.members.keys().map(|&id| (id, event.clone())).collect()
+    }
+
+    /// Drops the messages the limits no longer allow to keep
+    fn trim_history(&mut self, limits: HistoryLimits, now: Instant) {
+        while self.history.len() > limits.len {
+            self.history.pop_front();
+        }
+        while self
+            .history
+            .front()
+            .is_some_and(|(posted, _)| now.saturating_duration_since(*posted) > limits.retention)
+        {
+            self.history.pop_front();
+        }
+    }
+
+    fn is_unused(&self) -> bool {
+        self.members.is_empty() && self.history.is_empty()
+    }
+}
+
+/// Rooms of a server by name
+#[derive(Debug, Default)]
+pub struct Rooms {
+    rooms: HashMap<String, Room>,
+}
+
+fn event(room: &str, kind: RoomEventKind, member: &str, content: String) -> RoomEvent {
+    RoomEvent {
+        room: room.to_string(),
+        kind: kind as i32,
+        member: member.to_string(),
+        content,
+        replayed: false,
+    }
+}
+
+impl Rooms {
+    /// Adds connection `id` to `room` as `member`, replaying the history to it
+    pub fn join(&mut self, id: u64, room: &str, member: &str, limits: HistoryLimits) -> Result<Deliveries, RoomError> {
+        if room.is_empty() {
+            return Err(RoomError::EmptyName);
+        }
+        self.expire(limits);
+        let state = self.rooms.entry(room.to_string()).or_default();
+        if state.members.contains_key(&id) {
+            return Err(RoomError::AlreadyMember);
+        }
+        state.members.insert(id, member.to_string());
+        let mut deliveries: Deliveries = state
+            .history
+            .iter()
+            .map(|(_, posted)| (id, RoomEvent { replayed: true, ..posted.clone() }))
+            .collect();
+        deliveries.extend(state.broadcast(event(room, RoomEventKind::MemberJoined, member, String::new())));
+        Ok(deliveries)
+    }
+
+    /// Removes connection `id` from `room`, which stays with its history
+    pub fn leave(&mut self, id: u64, room: &str) -> Result<Deliveries, RoomError> {
+        let state = self.rooms.get_mut(room).ok_or(RoomError::NotAMember)?;
+        let member = state.members.get(&id).cloned().ok_or(RoomError::NotAMember)?;
+        //the one leaving learns about it like everyone else
+        let deliveries = state.broadcast(event(room, RoomEventKind::MemberLeft, &member, String::new()));
+        state.members.remove(&id);
+        if state.is_unused() {
+            self.rooms.remove(room);
+        }
+        Ok(deliveries)
+    }
+
+    /// Posts `content` from connection `id`, keeping it in the history as `limits` allow
+    pub fn post(&mut self, id: u64, room: &str, content: String, limits: HistoryLimits) -> Result<Deliveries, RoomError> {
+        self.expire(limits);
+        let state = self.rooms.get_mut(room).ok_or(RoomError::NotAMember)?;
+        let member = state.members.get(&id).ok_or(RoomError::NotAMember)?;
+        let posted = event(room, RoomEventKind::MessagePosted, member, content);
+        let now = Instant::now();
+        state.history.push_back((now, posted.clone()));
+        state.trim_history(limits, now);
+        Ok(state.broadcast(posted))
+    }
+
+    /// Drops the messages past their retention, and the rooms left with
+    /// neither members nor history
+    fn expire(&mut self, limits: HistoryLimits) {
+        let now = Instant::now();
+        self.rooms.retain(|_, state| {
+            state.trim_history(limits, now);
+            !state.is_unused()
+        });
+    }
+
+    /// Removes connection `id` from all its rooms, e.g. once it is closed
+    pub fn leave_all(&mut self, id: u64) -> Deliveries {
+        let mut joined: Vec<String> = self
+            .rooms
+            .iter()
+            .filter(|(_, state)| state.members.contains_key(&id))
+            .map(|(room, _)| room.clone())
+            .collect();
+        joined.sort();
+        joined
+            .iter()
+            .flat_map(|room| self.leave(id, room).unwrap_or_default())
+            //a closed connection has nobody to tell
+            .filter(|(member, _)| *member != id)
+            .collect()
+    }
+}
@@ -0,0 +1,181 @@
+//! Serial (UART) transport for devices attached to a tty
+//!
+//! Serial links have no message boundaries, so the server exchanges COBS
+//! frames with a CRC on them, see [`crate::cobs`].
+
+use crate::transport::Stream;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, RawFd},
+    },
+    path::Path,
+};
+
+/// How long a write may wait for the UART to accept more data
+const WRITE_TIMEOUT_MS: libc::c_int = 1000;
+
+/// Parity bit added to every character
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+/// Line settings of a serial port, always 8 data bits and 1 stop bit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub parity: Parity,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            baud_rate: 115200,
+            parity: Parity::None,
+        }
+    }
+}
+
+/// Tty device opened in raw, non-blocking mode
+pub struct SerialPort {
+    file: File,
+}
+
+impl SerialPort {
+    /// Opens the tty at `path` and applies `config`
+    pub fn open(path: &Path, config: &SerialConfig) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)?;
+        configure(file.as_raw_fd(), config)?;
+        Ok(SerialPort { file })
+    }
+}
+
+impl Read for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for SerialPort {
+    /// Waits for room in the output buffer instead of failing with `WouldBlock`
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.file.write(buf) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => wait_writable(self.file.as_raw_fd())?,
+                result => return result,
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Stream for SerialPort {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let fd = self.file.as_raw_fd();
+        // SAFETY: fcntl on a descriptor owned by `self.file`
+        let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        let flags = if nonblocking {
+            flags | libc::O_NONBLOCK
+        } else {
+            flags & !libc::O_NONBLOCK
+        };
+        check(unsafe { libc::fcntl(fd, libc::F_SETFL, flags) })?;
+        Ok(())
+    }
+}
+
+fn wait_writable(fd: RawFd) -> io::Result<()> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+    // SAFETY: `pollfd` is a valid array of one element
+    match check(unsafe { libc::poll(&mut pollfd, 1, WRITE_TIMEOUT_MS) }) {
+        Ok(0) => Err(io::Error::new(ErrorKind::TimedOut, "serial port write timed out")),
+        Ok(_) => Ok(()),
+        Err(ref e) if e.kind() == ErrorKind::Interrupted => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Puts the tty into raw mode with the line settings of `config`
+fn configure(fd: RawFd, config: &SerialConfig) -> io::Result<()> {
+    let speed = baud_constant(config.baud_rate)?;
+
+    // SAFETY: termios is plain data, filled in by tcgetattr before use
+    let mut tio: libc::termios = unsafe { mem::zeroed() };
+    check(unsafe { libc::tcgetattr(fd, &mut tio) })?;
+    unsafe { libc::cfmakeraw(&mut tio) };
+
+    tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+    tio.c_cflag &= !(libc::PARENB | libc::PARODD | libc::CSTOPB);
+    tio.c_iflag &= !libc::INPCK;
+    match config.parity {
+        Parity::None => {}
+        Parity::Even => {
+            tio.c_cflag |= libc::PARENB;
+            tio.c_iflag |= libc::INPCK;
+        }
+        Parity::Odd => {
+            tio.c_cflag |= libc::PARENB | libc::PARODD;
+            tio.c_iflag |= libc::INPCK;
+        }
+    }
+    //reads return whatever is available; with VMIN at 0 an empty read would
+    //return 0 like end of file instead of failing with WouldBlock
+    tio.c_cc[libc::VMIN] = 1;
+    tio.c_cc[libc::VTIME] = 0;
+
+    check(unsafe { libc::cfsetispeed(&mut tio, speed) })?;
+    check(unsafe { libc::cfsetospeed(&mut tio, speed) })?;
+    check(unsafe { libc::tcsetattr(fd, libc::TCSANOW, &tio) })?;
+    Ok(())
+}
+
+fn baud_constant(baud_rate: u32) -> io::Result<libc::speed_t> {
+    let speed = match baud_rate {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        #[cfg(target_os = "linux")]
+        460800 => libc::B460800,
+        #[cfg(target_os = "linux")]
+        921600 => libc::B921600,
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported baud rate {}", baud_rate),
+            ))
+        }
+    };
+    Ok(speed)
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
@@ -1,168 +1,1912 @@
-use crate::message::{client_message, server_message, AddRequest, AddResponse, ClientMessage, EchoMessage, ServerMessage};
-use log::{error, info, warn};
+use crate::auth::{self, Authorize, ConnectionContext, Identity, MessageKind, TokenStore};
+use crate::cancel::{self, StopReason};
+use crate::message::{batch_item, batch_result, client_message, server_message, AddRequest, AddResponse, AuthRequest, AuthResponse, BatchItem, BatchRequest, BatchResponse, BatchResult, ClientMessage, EchoMessage, ErrorCode, ErrorResponse, Event, Hello, JoinRoom, KvResponse, Ping, Publish, PublishResponse, RoomMessage, ServerMessage, StreamEnd, StreamItem, StreamRequest, Subscribe, SubscriptionResponse, Unsubscribe};
+use crate::kv::{self, KvError, Store, Update};
+use crate::metrics::{Counter, Metrics};
+use crate::persistence::Persistence;
+use crate::pubsub::{self, Broker, SlowSubscriberAction, Subscriber};
+use crate::rooms::{Deliveries, HistoryLimits, RoomError, Rooms};
+use crate::rate_limit::{RateLimitAction, RateLimiter, RateLimits, TokenBucket};
+use crate::signing::{MessageSigner, ReplayGuard, ReplayWindow, SignatureError};
+use crate::streaming::{OpenStream, OpenStreamHandler, StreamEvent, Streams};
+use log::{debug, error, info, warn};
 use prost::Message;
+use crate::compression::{self, Algorithm};
+use crate::framing::{self, Framing, LengthDelimitedFraming, ReadFrame};
+use crate::handshake::{self, DEFAULT_SERVER_NAME, FEATURE_AUTH, FEATURE_HEARTBEAT, FEATURE_LENGTH_DELIMITED, FEATURE_SIGNING};
+use crate::ip_filter::IpFilter;
+#[cfg(unix)]
+use crate::{
+    framing::CobsFraming,
+    serial::{SerialConfig, SerialPort},
+};
+#[cfg(feature = "tls")]
+use crate::tls::TlsAcceptor;
+use crate::transport::{self, Listener, Stream};
+#[cfg(unix)]
+use crate::transport::UnixSocket;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(feature = "tls")]
+use std::path::PathBuf;
 use std::{
-    io::{self, ErrorKind, Read, Write},
-    net::{TcpListener, TcpStream},
+    collections::{HashMap, VecDeque},
+    io::{self, ErrorKind},
+    net::{IpAddr, TcpListener},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,Mutex,
+        Arc,Mutex,PoisonError,
     },
-    thread,
-    time::Duration,
+    fmt,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+/// Outcome of polling a client connection once
+#[derive(Debug, PartialEq, Eq)]
+enum ClientStatus {
+    /// A message was read and handled
+    Served,
+    /// No data was available on the socket
+    Idle,
+    /// The client closed the connection
+    Disconnected,
+    /// The client exceeded a timeout, the connection has to be closed
+    TimedOut(Timeout),
+}
+
+/// Timeouts closing connections of clients that stall, see [`ServerBuilder::timeouts`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// How long a client may go without sending a request
+    pub idle: Option<Duration>,
+    /// How long receiving the rest of a partially received frame may take
+    pub read: Option<Duration>,
+    /// How long writing a response may take while the client is not reading
+    pub write: Option<Duration>,
+}
+
+impl Timeouts {
+    /// No timeouts, connections stay open as long as the client wants
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn idle(mut self, timeout: Duration) -> Self {
+        self.idle = Some(timeout);
+        self
+    }
+
+    pub fn read(mut self, timeout: Duration) -> Self {
+        self.read = Some(timeout);
+        self
+    }
+
+    pub fn write(mut self, timeout: Duration) -> Self {
+        self.write = Some(timeout);
+        self
+    }
+}
+
+/// Largest frame payload accepted from socket clients unless configured otherwise
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Most requests a batch may hold unless configured otherwise
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 64;
+
+/// Most streams a connection may have open at a time unless configured otherwise
+pub const DEFAULT_MAX_STREAMS: usize = 8;
+
+/// Failed authentication attempts after which a connection is closed, so a
+/// client cannot go on guessing credentials
+pub const MAX_FAILED_AUTHS: u32 = 3;
+
+//stream items sent per poll of a connection, so its requests keep being read
+const STREAM_ITEMS_PER_POLL: usize = 32;
+
+/// Events queued per subscribed connection unless configured otherwise
+pub const DEFAULT_SUBSCRIBER_QUEUE_LEN: usize = 256;
+
+//events, and messages of Server::send_to, sent per poll of a connection, like STREAM_ITEMS_PER_POLL
+const EVENTS_PER_POLL: usize = 32;
+
+/// Messages [`Server::send_to`] queues per connection before refusing more
+pub const MAX_PENDING_MESSAGES: usize = 256;
+
+/// Messages kept per chat room for members joining later unless configured otherwise
+pub const DEFAULT_ROOM_HISTORY_LEN: usize = 32;
+
+/// How long a chat room keeps a message unless configured otherwise
+pub const DEFAULT_ROOM_HISTORY_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+//how often expired keys are removed from the key-value store, and its log synced as its fsync policy says
+const STORE_MAINTENANCE_INTERVAL: Duration = Duration::from_millis(100);
+
+//how long a connection is still served once the server stops, so a client that
+//keeps sending cannot hold up the shutdown
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+//messages queued by Server::send_to, sent by the thread of the connection
+type Outbox = Mutex<VecDeque<ServerMessage>>;
+
+/// What happens to a connection that announces a frame over the size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizedFrameAction {
+    /// Skip the frame's bytes as they arrive and keep serving the client
+    #[default]
+    Drain,
+    /// Close the connection
+    Close,
+}
+
+/// How messages are delimited on stream sockets, see [`ServerBuilder::frame_format`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameFormat {
+    /// No delimiters, every read is taken as one message, as clients have
+    /// always sent them; a read of more than [`ServerBuilder::max_frame_size`]
+    /// is rejected
+    ///
+    /// Two messages sent back to back could arrive in one read, so the server
+    /// sends nothing the client did not ask for: no pings, streams,
+    /// subscriptions and rooms are refused with a `FRAMING_REQUIRED` error,
+    /// and [`Server::send_to`] fails.
+    #[default]
+    Raw,
+    /// Every message is prefixed with its length as a protobuf varint, like
+    /// prost's `encode_length_delimited`
+    LengthDelimited,
+}
+
+/// Size limit of incoming frames, see [`ServerBuilder::max_frame_size`]
+#[derive(Debug, Clone, Copy)]
+struct FrameLimit {
+    max_len: usize,
+    action: OversizedFrameAction,
+}
+
+impl Default for FrameLimit {
+    fn default() -> Self {
+        FrameLimit {
+            max_len: DEFAULT_MAX_FRAME_SIZE,
+            action: OversizedFrameAction::default(),
+        }
+    }
+}
+
+/// Which of the [`Timeouts`] a client exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Timeout {
+    Idle(Duration),
+    Read(Duration),
+    Write(Duration),
+    /// Number of heartbeats in a row the client did not answer
+    Heartbeat(u32),
+}
+
+impl Timeout {
+    fn counter(self) -> Counter {
+        match self {
+            Timeout::Idle(_) => Counter::IdleTimeouts,
+            Timeout::Read(_) => Counter::ReadTimeouts,
+            Timeout::Write(_) => Counter::WriteTimeouts,
+            Timeout::Heartbeat(_) => Counter::HeartbeatTimeouts,
+        }
+    }
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timeout::Idle(timeout) => write!(f, "no request within the idle timeout of {:?}", timeout),
+            Timeout::Read(timeout) => write!(f, "frame not completed within the read timeout of {:?}", timeout),
+            Timeout::Write(timeout) => write!(f, "response not written within the write timeout of {:?}", timeout),
+            Timeout::Heartbeat(missed) => write!(f, "{} heartbeats not answered", missed),
+        }
+    }
+}
+
+/// Heartbeats sent to every client, see [`ServerBuilder::heartbeat`]
+#[derive(Debug, Clone, Copy)]
+struct HeartbeatSettings {
+    interval: Duration,
+    max_missed: u32,
+}
+
+/// Configuration shared by all client connections of a server
+#[derive(Default)]
+struct ClientSettings {
+    authorizer: Option<Arc<dyn Authorize>>,
+    //clients have to authenticate against these before anything else
+    tokens: Option<TokenStore>,
+    //verifies signed requests and signs the responses
+    signer: Option<MessageSigner>,
+    require_signatures: bool,
+    //signatures accepted on any connection, so a request cannot be replayed on a new one
+    replay_window: ReplayWindow,
+    rate_limiter: Option<RateLimiter>,
+    timeouts: Timeouts,
+    heartbeat: Option<HeartbeatSettings>,
+    //framing stream socket connections start out with
+    frame_format: FrameFormat,
+    frame_limit: FrameLimit,
+    //name and application defined features announced in the handshake
+    server_name: Option<String>,
+    extra_features: Vec<String>,
+    //payloads from this size on are compressed, if the client agrees to an algorithm
+    compression_threshold: Option<usize>,
+    //DEFAULT_MAX_BATCH_SIZE if not set
+    max_batch_size: Option<usize>,
+    //DEFAULT_MAX_STREAMS if not set
+    max_streams: Option<usize>,
+    //streams of the application, by the method a StreamRequest names
+    stream_handlers: HashMap<String, Arc<dyn OpenStreamHandler>>,
+    //DEFAULT_SUBSCRIBER_QUEUE_LEN if not set
+    subscriber_queue_len: Option<usize>,
+    slow_subscriber_action: SlowSubscriberAction,
+    //topic subscriptions of all connections
+    broker: Broker,
+    //DEFAULT_ROOM_HISTORY_LEN if not set
+    room_history_len: Option<usize>,
+    //DEFAULT_ROOM_HISTORY_RETENTION if not set
+    room_history_retention: Option<Duration>,
+    //where the key-value store is kept across restarts, in memory only if not set
+    persistence: Option<Persistence>,
+    //counters updated by the client threads
+    metrics: Metrics,
+}
+
+impl ClientSettings {
+    /// Features the server offers in the handshake
+    fn features(&self) -> Vec<String> {
+        let mut features = Vec::new();
+        if self.heartbeat.is_some() {
+            features.push(FEATURE_HEARTBEAT.to_string());
+        }
+        if self.signer.is_some() {
+            features.push(FEATURE_SIGNING.to_string());
+        }
+        if self.tokens.is_some() {
+            features.push(FEATURE_AUTH.to_string());
+        }
+        if self.compression_threshold.is_some() {
+            features.extend(Algorithm::SUPPORTED.iter().map(|algorithm| algorithm.feature().to_string()));
+        }
+        features.extend(self.extra_features.iter().cloned());
+        features
+    }
+}
+
 struct Client {
-    stream: TcpStream,
+    stream: Box<dyn Stream>,
+    framing: Box<dyn Framing>,
+    context: ConnectionContext,
+    settings: Arc<ClientSettings>,
+    //client id and challenge of a pending challenge response authentication
+    challenge: Option<(String, Vec<u8>)>,
+    //authentication attempts that failed in a row, see MAX_FAILED_AUTHS
+    failed_auths: u32,
+    replay_guard: ReplayGuard,
+    //nonce of the last signed response
+    response_nonce: u64,
+    rate_bucket: Option<TokenBucket>,
+    timeouts: Timeouts,
+    //when the last complete frame arrived, or the connection was set up
+    last_request: Instant,
+    //since when part of a frame is waiting for the rest
+    partial_since: Option<Instant>,
+    //entry of the connection in the server's connection list
+    info: Arc<Mutex<ConnectionInfo>>,
+    ping_sequence: u64,
+    //sequence number and send time of the last ping, until it is answered
+    ping_sent: Option<(u64, Instant)>,
+    missed_pings: u32,
+    next_ping: Instant,
+    //algorithm agreed on in the handshake, payloads carry a compression flag once set
+    compression: Option<Algorithm>,
+    //server-streaming responses in progress
+    streams: Streams,
+    //id of the connection in the server's connection list
+    id: u64,
+    //queue of published events, once the client subscribed to a topic
+    subscriber: Option<Arc<Subscriber>>,
+    outbox: Arc<Outbox>,
+    //state shared with the other connections, e.g. the chat rooms
+    state: Arc<Mutex<ServerState>>,
+    //cleared when the server stops
+    is_running: Arc<AtomicBool>,
 }
 
 impl Client {
-    pub fn new(stream: TcpStream) -> Self {
-        Client { stream }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(stream: Box<dyn Stream>, framing: Box<dyn Framing>, context: ConnectionContext, settings: Arc<ClientSettings>, info: Arc<Mutex<ConnectionInfo>>, outbox: Arc<Outbox>, state: Arc<Mutex<ServerState>>, is_running: Arc<AtomicBool>) -> Self {
+        let rate_bucket = settings.rate_limiter.as_ref().and_then(RateLimiter::connection_bucket);
+        let timeouts = settings.timeouts;
+        let now = Instant::now();
+        let next_ping = now + settings.heartbeat.map_or(Duration::ZERO, |heartbeat| heartbeat.interval);
+        let id = info.lock().unwrap().id;
+        Client { stream, framing, context, settings, challenge: None, failed_auths: 0, replay_guard: ReplayGuard::new(), response_nonce: 0, rate_bucket, timeouts, last_request: now, partial_since: None, info, ping_sequence: 0, ping_sent: None, missed_pings: 0, next_ping, compression: None, streams: Streams::default(), id, subscriber: None, outbox, state, is_running }
     }
 
-    pub fn handle(&mut self) -> io::Result<bool> {  //changed return type to include connection status
-        let mut buffer = [0; 1024];
+    pub fn handle(&mut self) -> io::Result<ClientStatus> {
+        if let Some(status)=self.heartbeat()?{
+            return Ok(status);
+        }
+        if self.subscriber.as_ref().is_some_and(|subscriber|subscriber.has_overflowed()){
+            return self.disconnect_slow_subscriber();
+        }
+        let streamed=self.pump_streams()?|self.deliver_events()?|self.deliver_pushed()?;
+        // Read a message from the client
+        let payload = match self.framing.read_frame(self.stream.as_mut())? {
+            ReadFrame::Frame(payload) => payload,
+            ReadFrame::Invalid(e) => {
+                error!("Discarding corrupted frame: {}", e);
+                return Ok(ClientStatus::Served);
+            }
+            ReadFrame::TooLarge(len) => {
+                self.partial_since=None;
+                return self.reject_oversized(len);
+            }
+            ReadFrame::Pending => {
+                // no data available, but the connection is not idle while it streams
+                let status=self.check_timeouts();
+                return Ok(if streamed&&status==ClientStatus::Idle {ClientStatus::Served} else {status});
+            }
+            ReadFrame::Closed => return Ok(ClientStatus::Disconnected), //connection closed by the client
+        };
+        self.partial_since=None;
+        let payload=match self.compression{
+            Some(_)=>match compression::decompress(&payload,self.settings.frame_limit.max_len){
+                Ok(payload)=>payload,
+                Err(e)=>{
+                    //the frame may not even hold a request id, so the error cannot name one
+                    warn!("Discarding frame of client {} that does not decompress: {}",self.context.peer_addr,e);
+                    self.send_error(ErrorCode::InvalidRequest,format!("frame does not decompress: {}",e))?;
+                    return Ok(ClientStatus::Served);
+                }
+            },
+            None=>payload,
+        };
+        //the TLS handshake is complete once a message got through
+        if self.context.identity.is_none(){
+            self.context.identity=self.stream.peer_identity();
+            if let Some(identity)=&self.context.identity{
+                info!("Client {} authenticated as {}",self.context.peer_addr,identity);
+            }
+            self.restore_subscriptions();
+        }
         // Try to decode as a ClientMessage
-        
-        // Read data from the client
-        match self.stream.read(&mut buffer){
-            Ok(0)=>return Ok(false),//connection closed by the client
-
-            Ok(bytes_read)=>{
-                match ClientMessage::decode(&buffer[..bytes_read]){
-                    Ok(client_msg)=>{
-                        match client_msg.message{
-                            Some(client_message::Message::EchoMessage(echo))=>{
-                                info!("Received Echo: {}", echo.content);
-                                // Send Echo response
-                                self.handle_echo(echo)
-                            }
-                            Some(client_message::Message::AddRequest(add))=>{
-                                info!("recieved add request:{} + {}",add.a,add.b);
-                                //calculate result and create response
-                                self.handle_add(add)
-                                
-                            }
+        let decoded=ClientMessage::decode(payload.as_slice());
+        //heartbeats neither count as requests nor against the rate limits
+        let is_pong=matches!(&decoded,Ok(ClientMessage{message:Some(client_message::Message::Pong(_)),..}));
+        if !is_pong{
+            self.last_request=Instant::now();
+            if let Some(status)=self.throttle()?{
+                return Ok(status);
+            }
+        }
+
+        match decoded{
+            Ok(client_msg)=>{
+                if let Err(e)=self.check_signature(&client_msg,&payload){
+                    warn!("Rejected message from client {}: {}",self.context.peer_addr,e);
+                    let code=match e{
+                        SignatureError::Replayed|SignatureError::Expired=>ErrorCode::ReplayDetected,
+                        SignatureError::Missing|SignatureError::Invalid=>ErrorCode::InvalidSignature,
+                    };
+                    self.send_error(code,e.to_string())?;
+                    return Ok(ClientStatus::Served);
+                }
+                //also covers the time the request was held back by the rate limits
+                let deadline=cancel::deadline_from_unix_ms(client_msg.deadline_ms);
+                if deadline.is_some_and(|deadline|Instant::now()>=deadline){
+                    warn!("Skipping expired request of client {}",self.context.peer_addr);
+                    self.settings.metrics.increment(Counter::DeadlinesExceeded);
+                    self.send_error(ErrorCode::DeadlineExceeded,"deadline passed before the request was handled".to_string())?;
+                    return Ok(ClientStatus::Served);
+                }
+                if let Some(client_message::Message::Pong(pong))=&client_msg.message{
+                    self.handle_pong(pong.sequence);
+                    return Ok(ClientStatus::Served);
+                }
+                if let Some(client_message::Message::Hello(hello))=&client_msg.message{
+                    return self.handle_hello(hello);
+                }
+                //credits and cancellations only concern requests the client was allowed to send
+                if let Some(client_message::Message::StreamCredit(credit))=&client_msg.message{
+                    if !self.streams.grant(credit.request_id,credit.credits){
+                        debug!("Ignoring credit for closed stream {} of client {}",credit.request_id,self.context.peer_addr);
+                    }
+                    return Ok(ClientStatus::Served);
+                }
+                if let Some(client_message::Message::CancelRequest(cancel))=&client_msg.message{
+                    //a request that already completed has nothing left to cancel
+                    if self.streams.cancel(cancel.request_id){
+                        info!("Client {} cancelled request {}",self.context.peer_addr,cancel.request_id);
+                    }else{
+                        debug!("Ignoring cancellation of finished request {} of client {}",cancel.request_id,self.context.peer_addr);
+                    }
+                    return Ok(ClientStatus::Served);
+                }
+                if let Some(client_message::Message::AuthRequest(auth))=client_msg.message{
+                    return self.handle_auth(auth);
+                }
+                if self.settings.tokens.is_some()&&self.context.identity.is_none(){
+                    warn!("Rejected message from unauthenticated client {}",self.context.peer_addr);
+                    self.send_error(ErrorCode::Unauthenticated,"authentication required".to_string())?;
+                    return Ok(ClientStatus::Served);
+                }
+                if let Some(message)=&client_msg.message{
+                    let kind=MessageKind::of(message);
+                    if !self.is_authorized(kind){
+                        warn!("Denied {} to client {}",kind,self.context.peer_addr);
+                        self.send_error(ErrorCode::PermissionDenied,format!("{} is not allowed",kind))?;
+                        return Ok(ClientStatus::Served);
+                    }
+                    if causes_pushes(message)&&!self.can_push(){
+                        warn!("Refused {} to client {} with raw framing",kind,self.context.peer_addr);
+                        self.send_error(ErrorCode::FramingRequired,format!("{} needs length-delimited framing, ask for it in the Hello",kind))?;
+                        return Ok(ClientStatus::Served);
+                    }
+                }
+                match client_msg.message{
+                    Some(client_message::Message::EchoMessage(echo))=>{
+                        info!("Received Echo: {}", echo.content);
+                        // Send Echo response
+                        self.handle_echo(echo)?;
+                    }
+                    Some(client_message::Message::AddRequest(add))=>{
+                        info!("recieved add request:{} + {}",add.a,add.b);
+                        //calculate result and create response
+                        self.handle_add(add)?;
                         
-                            None =>{
-                                error!("Received empty message");
-                                Ok(true)
-                            }
-                        }
                     }
-                    Err(e)=>{
-                        error!("Failed to decode message:{}",e);
-                        Ok(true)
+                    Some(client_message::Message::BatchRequest(batch))=>{
+                        info!("Received batch of {} requests",batch.requests.len());
+                        self.handle_batch(batch)?;
+                    }
+                    Some(client_message::Message::RangeSumRequest(request))=>{
+                        info!("Received range sum {}: {}..{}",request.request_id,request.start,request.end);
+                        self.open_stream(OpenStream::range_sum(&request).with_deadline(deadline))?;
+                    }
+                    Some(client_message::Message::SubscribeCounter(request))=>{
+                        info!("Received counter subscription {}",request.request_id);
+                        self.open_stream(OpenStream::counter(&request,Instant::now()).with_deadline(deadline))?;
+                    }
+                    Some(client_message::Message::StreamRequest(request))=>{
+                        info!("Received stream {} of {}",request.request_id,request.method);
+                        self.handle_stream_request(request,deadline)?;
+                    }
+                    Some(client_message::Message::Subscribe(subscribe))=>{
+                        info!("Client {} subscribes to {}",self.context.peer_addr,subscribe.topic);
+                        self.handle_subscribe(subscribe)?;
+                    }
+                    Some(client_message::Message::Unsubscribe(unsubscribe))=>{
+                        info!("Client {} unsubscribes from {}",self.context.peer_addr,unsubscribe.topic);
+                        self.handle_unsubscribe(unsubscribe)?;
+                    }
+                    Some(client_message::Message::Publish(publish))=>{
+                        debug!("Client {} publishes {} bytes on {}",self.context.peer_addr,publish.payload.len(),publish.topic);
+                        self.handle_publish(publish)?;
+                    }
+                    Some(client_message::Message::JoinRoom(join))=>{
+                        info!("Client {} joins room {}",self.context.peer_addr,join.room);
+                        self.handle_join_room(join)?;
+                    }
+                    Some(client_message::Message::LeaveRoom(leave))=>{
+                        info!("Client {} leaves room {}",self.context.peer_addr,leave.room);
+                        let id=self.id;
+                        self.update_room(&leave.room,|rooms|rooms.leave(id,&leave.room))?;
+                    }
+                    Some(client_message::Message::RoomMessage(message))=>{
+                        info!("Client {} posts to room {}: {}",self.context.peer_addr,message.room,message.content);
+                        self.handle_room_message(message)?;
+                    }
+                    Some(client_message::Message::KvGet(get))=>{
+                        debug!("Client {} reads key {}",self.context.peer_addr,get.key);
+                        self.update_kv(get.key,|store,key,now|Ok(Update{applied:false,entry:store.get(key,now)}))?;
+                    }
+                    Some(client_message::Message::KvSet(set))=>{
+                        debug!("Client {} sets key {} to {} bytes",self.context.peer_addr,set.key,set.value.len());
+                        let ttl=kv::ttl_from_ms(set.ttl_ms);
+                        self.update_kv(set.key,|store,key,now|store.set(key,set.value,ttl,now).map(|entry|Update{applied:true,entry:Some(entry)}))?;
+                    }
+                    Some(client_message::Message::KvDelete(delete))=>{
+                        debug!("Client {} deletes key {}",self.context.peer_addr,delete.key);
+                        self.update_kv(delete.key,|store,key,now|store.delete(key,now).map(|applied|Update{applied,entry:None}))?;
+                    }
+                    Some(client_message::Message::KvCompareAndSwap(swap))=>{
+                        debug!("Client {} swaps key {} at version {}",self.context.peer_addr,swap.key,swap.expected_version);
+                        let ttl=kv::ttl_from_ms(swap.ttl_ms);
+                        self.update_kv(swap.key,|store,key,now|store.compare_and_swap(key,swap.expected_version,swap.value,ttl,now))?;
+                    }
+                    Some(client_message::Message::KvIncrement(increment))=>{
+                        debug!("Client {} adds {} to key {}",self.context.peer_addr,increment.delta,increment.key);
+                        self.update_kv(increment.key,|store,key,now|{
+                            store.increment(key,increment.delta,now).map(|entry|Update{applied:true,entry:Some(entry)})
+                        })?;
+                    }
+                    Some(client_message::Message::AuthRequest(_)|client_message::Message::Pong(_)|client_message::Message::Hello(_)|client_message::Message::StreamCredit(_)|client_message::Message::CancelRequest(_))=>unreachable!("handled before authorization"),
+                
+                    None =>{
+                        error!("Received empty message");
                     }
-                    
                 }
             }
-                    
-            Err(ref e)if e.kind()==ErrorKind::WouldBlock=>Ok(true),// no data availabel
-            Err(e)=>Err(e),//other errors
+            Err(e)=>{
+                error!("Failed to decode message:{}",e);
+            }
+            
+        }
+        Ok(ClientStatus::Served)
+    }
+    /// Sends a ping when the heartbeat interval has passed, returning the
+    /// status to report instead of reading if too many went unanswered
+    fn heartbeat(&mut self)->io::Result<Option<ClientStatus>>{
+        let Some(heartbeat)=self.settings.heartbeat else{
+            return Ok(None);
+        };
+        if !self.can_push(){
+            return Ok(None);
+        }
+        let now=Instant::now();
+        if now<self.next_ping{
+            return Ok(None);
+        }
+        if self.ping_sent.is_some(){
+            self.missed_pings+=1;
+            if self.missed_pings>=heartbeat.max_missed{
+                return Ok(Some(ClientStatus::TimedOut(Timeout::Heartbeat(self.missed_pings))));
+            }
+        }
+        self.ping_sequence+=1;
+        self.ping_sent=Some((self.ping_sequence,now));
+        self.next_ping=now+heartbeat.interval;
+        self.send_response(ServerMessage{
+            message:Some(server_message::Message::Ping(Ping{sequence:self.ping_sequence})),
+            signature:None,
+        })?;
+        Ok(None)
+    }
+    /// Whether the server may send messages the client did not ask for: with
+    /// raw framing they would end up in the same read as a response
+    fn can_push(&self)->bool{
+        self.framing.format()!=Some(FrameFormat::Raw)
+    }
+    /// Records the round trip time of the last ping if `sequence` answers it
+    fn handle_pong(&mut self,sequence:u64){
+        match self.ping_sent{
+            Some((sent_sequence,sent)) if sent_sequence==sequence=>{
+                let round_trip_time=sent.elapsed();
+                debug!("Heartbeat round trip time of client {}: {:?}",self.context.peer_addr,round_trip_time);
+                self.ping_sent=None;
+                self.missed_pings=0;
+                self.info.lock().unwrap().round_trip_time=Some(round_trip_time);
+            }
+            //answers a ping that was already counted as missed
+            _=>debug!("Ignoring stale pong {} of client {}",sequence,self.context.peer_addr),
+        }
+    }
+    /// Tells the client that its frame, of `len` bytes if known, is over the limit, then
+    /// drops the frame or the connection
+    fn reject_oversized(&mut self,len:Option<usize>)->io::Result<ClientStatus>{
+        let limit=self.settings.frame_limit;
+        let message=match len{
+            Some(len)=>format!("frame of {} bytes exceeds the limit of {} bytes",len,limit.max_len),
+            None=>format!("frame exceeds the limit of {} bytes",limit.max_len),
+        };
+        warn!("Rejecting frame of client {}: {}",self.context.peer_addr,message);
+        self.settings.metrics.increment(Counter::FramesTooLarge);
+        self.send_error(ErrorCode::FrameTooLarge,message)?;
+        match limit.action{
+            OversizedFrameAction::Drain=>Ok(ClientStatus::Served),
+            OversizedFrameAction::Close=>Ok(ClientStatus::Disconnected),
+        }
+    }
+    /// Status of a connection without a complete frame to handle, according
+    /// to the idle and read timeouts
+    fn check_timeouts(&mut self)->ClientStatus{
+        let now=Instant::now();
+        if self.framing.has_partial_frame(){
+            let since=*self.partial_since.get_or_insert(now);
+            match self.timeouts.read{
+                Some(timeout) if now.duration_since(since)>=timeout=>return ClientStatus::TimedOut(Timeout::Read(timeout)),
+                //a client in the middle of sending a frame is not idle
+                _=>return ClientStatus::Idle,
+            }
+        }
+        self.partial_since=None;
+        match self.timeouts.idle{
+            //nor while it waits for streams or events
+            Some(timeout) if self.streams.is_empty()&&!self.is_subscribed()&&now.duration_since(self.last_request)>=timeout=>ClientStatus::TimedOut(Timeout::Idle(timeout)),
+            _=>ClientStatus::Idle,
+        }
+    }
+    /// Applies the rate limits to a request, returning the status to report
+    /// instead of handling it if it exceeds them
+    fn throttle(&mut self)->io::Result<Option<ClientStatus>>{
+        let settings=Arc::clone(&self.settings);
+        let Some(limiter)=&settings.rate_limiter else{
+            return Ok(None);
+        };
+        let mut delayed=false;
+        loop{
+            let wait=match limiter.acquire(self.rate_bucket.as_mut(),self.context.peer_ip){
+                Ok(())=>return Ok(None),
+                Err(wait)=>wait,
+            };
+            let message=format!("rate limit exceeded, retry in {} ms",wait.as_millis());
+            match limiter.action(){
+                RateLimitAction::Delay if !self.is_running.load(Ordering::SeqCst)=>{
+                    //the shutdown does not wait for the tokens
+                    warn!("Rejecting delayed request of client {}, the server is stopping",self.context.peer_addr);
+                    settings.metrics.increment(Counter::RequestsRejected);
+                    self.send_error(ErrorCode::RateLimited,message)?;
+                    return Ok(Some(ClientStatus::Served));
+                }
+                RateLimitAction::Delay=>{
+                    if !delayed{
+                        delayed=true;
+                        settings.metrics.increment(Counter::RequestsDelayed);
+                    }
+                    //try again in a while, in case another connection's request went first
+                    thread::sleep(wait.min(Duration::from_millis(100)));
+                }
+                RateLimitAction::Reject=>{
+                    warn!("Rejecting request of client {}: {}",self.context.peer_addr,message);
+                    settings.metrics.increment(Counter::RequestsRejected);
+                    self.send_error(ErrorCode::RateLimited,message)?;
+                    return Ok(Some(ClientStatus::Served));
+                }
+                RateLimitAction::Disconnect=>{
+                    warn!("Disconnecting client {}: {}",self.context.peer_addr,message);
+                    settings.metrics.increment(Counter::RateLimitDisconnects);
+                    self.send_error(ErrorCode::RateLimited,message)?;
+                    return Ok(Some(ClientStatus::Disconnected));
+                }
+            }
+        }
+    }
+    /// Verifies the signature of a request decoded from `payload`, unsigned
+    /// ones pass unless signatures are required
+    fn check_signature(&mut self,message:&ClientMessage,payload:&[u8])->Result<(),SignatureError>{
+        let Some(signer)=&self.settings.signer else{
+            return Ok(());
+        };
+        if message.signature.is_none()&&!self.settings.require_signatures{
+            return Ok(());
+        }
+        let signature=signer.verify_encoded(message,payload)?;
+        //a replay from another connection must not move this connection's nonce on
+        self.settings.replay_window.check(&signature)?;
+        self.replay_guard.check(&signature)
+    }
+    /// Asks the authorization policy, if there is one, whether the client may send `kind`
+    fn is_authorized(&self,kind:MessageKind)->bool{
+        match &self.settings.authorizer{
+            Some(authorizer)=>authorizer.authorize(&self.context,kind),
+            None=>true,
+        }
+    }
+    /// Agrees on the protocol with the client, closing the connection if its
+    /// version is not supported
+    fn handle_hello(&mut self,hello:&Hello)->io::Result<ClientStatus>{
+        let mut features=self.settings.features();
+        //serial ports stay with their framing
+        let format=self.framing.format();
+        if format.is_some(){
+            features.push(FEATURE_LENGTH_DELIMITED.to_string());
+        }
+        match handshake::negotiate(hello,&features){
+            Ok(mut protocol)=>{
+                let algorithm=compression::select(&mut protocol.features);
+                info!("Client {} ({}) speaks protocol version {} with features {:?}",self.context.peer_addr,protocol.peer_name,protocol.version,protocol.features);
+                let response=Hello{
+                    protocol_version:protocol.version,
+                    features:protocol.features.clone(),
+                    name:self.settings.server_name.as_deref().unwrap_or(DEFAULT_SERVER_NAME).to_string(),
+                };
+                self.context.protocol=Some(protocol);
+                self.send_response(ServerMessage{
+                    message:Some(server_message::Message::Hello(response)),
+                    signature:None,
+                })?;
+                //the response itself still goes out uncompressed and unprefixed
+                self.compression=algorithm;
+                let length_delimited=self.context.protocol.as_ref().is_some_and(|protocol|protocol.supports(FEATURE_LENGTH_DELIMITED));
+                if length_delimited&&format==Some(FrameFormat::Raw){
+                    self.framing=Box::new(LengthDelimitedFraming::new(self.settings.frame_limit.max_len));
+                    self.info.lock().unwrap().frame_format=Some(FrameFormat::LengthDelimited);
+                    //kept back while the connection could not take events
+                    self.restore_subscriptions();
+                }
+                Ok(ClientStatus::Served)
+            }
+            Err(reason)=>{
+                warn!("Rejecting client {}: {}",self.context.peer_addr,reason);
+                self.settings.metrics.increment(Counter::HandshakesRejected);
+                self.send_error(ErrorCode::UnsupportedVersion,reason)?;
+                Ok(ClientStatus::Disconnected)
+            }
+        }
+    }
+    /// Authenticates the connection with a token, or runs a step of the
+    /// challenge response authentication
+    /// Authenticates the client, closing the connection after
+    /// [`MAX_FAILED_AUTHS`] failed attempts
+    fn handle_auth(&mut self,auth:AuthRequest)->io::Result<ClientStatus>{
+        let Some(tokens)=&self.settings.tokens else{
+            self.send_error(ErrorCode::Unauthenticated,"authentication is not enabled".to_string())?;
+            return Ok(ClientStatus::Served);
+        };
+        let name=if !auth.token.is_empty(){
+            tokens.authenticate_token(&auth.token).map(str::to_string)
+        }else if auth.challenge_response.is_empty(){
+            //first step, unknown client ids get a challenge too so they cannot be told apart
+            let challenge=auth::new_challenge()?;
+            self.challenge=Some((auth.client_id,challenge.clone()));
+            self.send_response(ServerMessage{
+                message:Some(server_message::Message::AuthResponse(AuthResponse{
+                    authenticated:false,
+                    challenge,
+                })),
+                signature:None,
+            })?;
+            return Ok(ClientStatus::Served);
+        }else{
+            //a challenge is only good for one answer
+            match self.challenge.take(){
+                Some((client_id,challenge)) if client_id==auth.client_id
+                    && tokens.verify_challenge(&client_id,&challenge,&auth.challenge_response)=>Some(client_id),
+                _=>None,
+            }
+        };
 
+        match name{
+            Some(name)=>{
+                info!("Client {} authenticated as {}",self.context.peer_addr,name);
+                self.context.identity=Some(Identity::named(name));
+                self.failed_auths=0;
+                self.restore_subscriptions();
+                self.send_response(ServerMessage{
+                    message:Some(server_message::Message::AuthResponse(AuthResponse{
+                        authenticated:true,
+                        challenge:Vec::new(),
+                    })),
+                    signature:None,
+                })?;
+                Ok(ClientStatus::Served)
+            }
+            None if self.failed_auths+1>=MAX_FAILED_AUTHS=>{
+                warn!("Disconnecting client {} after {} failed authentication attempts",self.context.peer_addr,MAX_FAILED_AUTHS);
+                self.settings.metrics.increment(Counter::AuthFailureDisconnects);
+                self.send_error(ErrorCode::Unauthenticated,format!("invalid credentials, closing the connection after {} failed attempts",MAX_FAILED_AUTHS))?;
+                Ok(ClientStatus::Disconnected)
+            }
+            None=>{
+                warn!("Authentication of client {} failed",self.context.peer_addr);
+                self.failed_auths+=1;
+                self.send_error(ErrorCode::Unauthenticated,"invalid credentials".to_string())?;
+                Ok(ClientStatus::Served)
+            }
         }
     }
-    fn handle_echo(&mut self,echo:EchoMessage)->io::Result<bool>{
+    fn handle_echo(&mut self,echo:EchoMessage)->io::Result<()>{
         let response=ServerMessage{
             message:Some(server_message::Message::EchoMessage(echo)),
+            signature:None,
         };
         self.send_response(response)
     }
-    fn handle_add(&mut self,add:AddRequest)->io::Result<bool>{
-        let result=add.a+add.b;
+    fn handle_add(&mut self,add:AddRequest)->io::Result<()>{
          let response=ServerMessage{
-            message: Some(server_message::Message::AddResponse(AddResponse{
-              result
-             })),                              
+            message: Some(server_message::Message::AddResponse(add_response(&add))),                              
+            signature:None,
          };
           self.send_response(response)  
     }
-    fn send_response(&mut self,response:ServerMessage)->io::Result<bool>{
-        let payload = response.encode_to_vec();
-        self.stream.write_all(&payload)?;
-        self.stream.flush()?;
-        Ok(true)
+    /// Answers the requests of a batch in order with one response, unless
+    /// there are more than the server accepts
+    fn handle_batch(&mut self,batch:BatchRequest)->io::Result<()>{
+        let max=self.settings.max_batch_size.unwrap_or(DEFAULT_MAX_BATCH_SIZE);
+        if batch.requests.len()>max{
+            warn!("Rejecting batch of {} requests from client {}",batch.requests.len(),self.context.peer_addr);
+            return self.send_error(ErrorCode::BatchTooLarge,format!("batch of {} requests exceeds the limit of {}",batch.requests.len(),max));
+        }
+        let results=batch.requests.into_iter().map(|item|self.batch_result(item)).collect();
+        let response=ServerMessage{
+            message:Some(server_message::Message::BatchResponse(BatchResponse{results})),
+            signature:None,
+        };
+        self.send_response(response)
+    }
+    fn batch_result(&self,item:BatchItem)->BatchResult{
+        let error=|code:ErrorCode,message:String|batch_result::Result::ErrorResponse(ErrorResponse{code:code as i32,message});
+        let result=match item.request{
+            Some(request)=>{
+                let kind=MessageKind::of_batch_item(&request);
+                if !self.is_authorized(kind){
+                    warn!("Denied {} in a batch to client {}",kind,self.context.peer_addr);
+                    error(ErrorCode::PermissionDenied,format!("{} is not allowed",kind))
+                }else{
+                    match request{
+                        batch_item::Request::EchoMessage(echo)=>batch_result::Result::EchoMessage(echo),
+                        batch_item::Request::AddRequest(add)=>batch_result::Result::AddResponse(add_response(&add)),
+                    }
+                }
+            }
+            None=>error(ErrorCode::InvalidRequest,"empty request".to_string()),
+        };
+        BatchResult{result:Some(result)}
+    }
+    /// Starts streaming the response to a request, unless its id is taken or
+    /// the connection has as many streams open as it may
+    /// Opens the stream of a handler the application registered
+    fn handle_stream_request(&mut self,request:StreamRequest,deadline:Option<Instant>)->io::Result<()>{
+        let opened=match self.settings.stream_handlers.get(&request.method){
+            Some(handler)=>handler.open(&self.context,&request.payload),
+            None=>Err(format!("no stream named {:?}",request.method)),
+        };
+        match opened{
+            Ok(handler)=>self.open_stream(OpenStream::new(request.request_id,handler,request.credits).with_deadline(deadline)),
+            Err(message)=>{
+                warn!("Refusing stream {} of client {}: {}",request.request_id,self.context.peer_addr,message);
+                self.end_stream(request.request_id,Some(ErrorResponse{code:ErrorCode::InvalidRequest as i32,message}))
+            }
+        }
+    }
+    fn open_stream(&mut self,stream:OpenStream)->io::Result<()>{
+        let max=self.settings.max_streams.unwrap_or(DEFAULT_MAX_STREAMS);
+        let request_id=stream.request_id;
+        let refusal=if self.streams.contains(request_id){
+            Some((ErrorCode::InvalidRequest,format!("request id {} is already streaming",request_id)))
+        }else if self.streams.len()>=max{
+            Some((ErrorCode::TooManyStreams,format!("no more than {} streams may be open at a time",max)))
+        }else{
+            None
+        };
+        match refusal{
+            Some((code,message))=>{
+                warn!("Refusing stream {} of client {}: {}",request_id,self.context.peer_addr,message);
+                self.end_stream(request_id,Some(ErrorResponse{code:code as i32,message}))
+            }
+            None=>{
+                self.streams.open(stream);
+                Ok(())
+            }
+        }
+    }
+    /// Sends the stream items that are due and have credit, returning whether
+    /// anything was sent
+    fn pump_streams(&mut self)->io::Result<bool>{
+        let now=Instant::now();
+        let mut sent=false;
+        for _ in 0..STREAM_ITEMS_PER_POLL{
+            match self.streams.poll(now){
+                Some(StreamEvent::Item{request_id,value})=>self.send_response(ServerMessage{
+                    message:Some(server_message::Message::StreamItem(StreamItem{request_id,value})),
+                    signature:None,
+                })?,
+                Some(StreamEvent::End{request_id})=>self.end_stream(request_id,None)?,
+                Some(StreamEvent::Stopped{request_id,reason})=>{
+                    let (code,message)=match reason{
+                        StopReason::Cancelled=>(ErrorCode::Cancelled,"request was cancelled"),
+                        StopReason::DeadlineExceeded=>{
+                            self.settings.metrics.increment(Counter::DeadlinesExceeded);
+                            (ErrorCode::DeadlineExceeded,"deadline passed before the stream was complete")
+                        }
+                    };
+                    self.end_stream(request_id,Some(ErrorResponse{code:code as i32,message:message.to_string()}))?
+                }
+                None=>break,
+            }
+            sent=true;
+        }
+        Ok(sent)
+    }
+    fn handle_subscribe(&mut self,subscribe:Subscribe)->io::Result<()>{
+        if let Err(e)=pubsub::check_pattern(&subscribe.topic){
+            return self.send_error(ErrorCode::InvalidRequest,format!("invalid topic {:?}: {}",subscribe.topic,e));
+        }
+        //kept for the identity before it is answered, like a key-value change
+        if let Some(identity)=self.context.identity.as_ref().map(ToString::to_string){
+            let store=Arc::clone(&self.state.lock().unwrap().kv);
+            if let Err(e)=store.subscribe(&identity,&subscribe.topic){
+                error!("Failed to log the subscription of client {} to {}: {}",self.context.peer_addr,subscribe.topic,e);
+                return self.send_error(ErrorCode::StorageFailed,format!("subscription to {:?} could not be stored",subscribe.topic));
+            }
+        }
+        self.subscribe(&subscribe.topic);
+        self.send_subscription_response(subscribe.topic,true)
+    }
+    fn subscribe(&mut self,pattern:&str){
+        let capacity=self.settings.subscriber_queue_len.unwrap_or(DEFAULT_SUBSCRIBER_QUEUE_LEN);
+        self.subscriber=Some(self.settings.broker.subscribe(self.id,pattern,capacity,self.settings.slow_subscriber_action));
+    }
+    /// Subscribes a connection that just authenticated to the patterns its
+    /// identity subscribed to before, see [`crate::persistence`]
+    fn restore_subscriptions(&mut self){
+        let Some(identity)=self.context.identity.as_ref().map(ToString::to_string) else{
+            return;
+        };
+        //until the client switches to length-delimited framing
+        if !self.can_push(){
+            return;
+        }
+        let patterns=self.state.lock().unwrap().kv.subscriptions(&identity);
+        if !patterns.is_empty(){
+            info!("Restoring {} subscription(s) of client {} authenticated as {}",patterns.len(),self.context.peer_addr,identity);
+        }
+        for pattern in patterns{
+            self.subscribe(&pattern);
+        }
+    }
+    fn handle_unsubscribe(&mut self,unsubscribe:Unsubscribe)->io::Result<()>{
+        if let Some(identity)=self.context.identity.as_ref().map(ToString::to_string){
+            let store=Arc::clone(&self.state.lock().unwrap().kv);
+            if let Err(e)=store.unsubscribe(&identity,&unsubscribe.topic){
+                error!("Failed to log the unsubscription of client {} from {}: {}",self.context.peer_addr,unsubscribe.topic,e);
+                return self.send_error(ErrorCode::StorageFailed,format!("unsubscription from {:?} could not be stored",unsubscribe.topic));
+            }
+        }
+        if !self.settings.broker.unsubscribe(self.id,&unsubscribe.topic){
+            debug!("Client {} was not subscribed to {}",self.context.peer_addr,unsubscribe.topic);
+        }
+        self.send_subscription_response(unsubscribe.topic,false)
+    }
+    fn send_subscription_response(&mut self,topic:String,subscribed:bool)->io::Result<()>{
+        self.send_response(ServerMessage{
+            message:Some(server_message::Message::SubscriptionResponse(SubscriptionResponse{topic,subscribed})),
+            signature:None,
+        })
+    }
+    fn handle_publish(&mut self,publish:Publish)->io::Result<()>{
+        if let Err(e)=pubsub::check_topic(&publish.topic){
+            return self.send_error(ErrorCode::InvalidRequest,format!("invalid topic {:?}: {}",publish.topic,e));
+        }
+        let delivery=self.settings.broker.publish(&publish.topic,publish.payload);
+        if delivery.dropped>0{
+            debug!("Dropped {} events on {} for slow subscribers",delivery.dropped,publish.topic);
+            self.settings.metrics.add(Counter::EventsDropped,delivery.dropped as u64);
+        }
+        self.send_response(ServerMessage{
+            message:Some(server_message::Message::PublishResponse(PublishResponse{receivers:delivery.receivers as u32})),
+            signature:None,
+        })
+    }
+    fn is_subscribed(&self)->bool{
+        self.subscriber.as_ref().is_some_and(|subscriber|subscriber.is_subscribed())
+    }
+    /// Sends queued events of the client's subscriptions, returning whether
+    /// anything was sent
+    fn deliver_events(&mut self)->io::Result<bool>{
+        let Some(subscriber)=self.subscriber.clone() else{
+            return Ok(false);
+        };
+        let mut sent=false;
+        for _ in 0..EVENTS_PER_POLL{
+            let Some(event)=subscriber.next_event() else{
+                break;
+            };
+            self.send_response(ServerMessage{
+                message:Some(server_message::Message::Event(Event::clone(&event))),
+                signature:None,
+            })?;
+            sent=true;
+        }
+        Ok(sent)
+    }
+    /// Sends the messages queued by [`Server::send_to`], returning whether
+    /// anything was sent
+    fn deliver_pushed(&mut self)->io::Result<bool>{
+        let mut sent=false;
+        for _ in 0..EVENTS_PER_POLL{
+            //not held while writing, so send_to never waits for a slow client
+            let message=self.outbox.lock().unwrap().pop_front();
+            let Some(message)=message else{
+                break;
+            };
+            self.send_response(message)?;
+            sent=true;
+        }
+        Ok(sent)
+    }
+    fn handle_join_room(&mut self,join:JoinRoom)->io::Result<()>{
+        //only names nobody can prove are up to the client
+        let member=match &self.context.identity{
+            Some(identity) if join.name.is_empty()||join.name==identity.subject||identity.names().any(|name|name==join.name)=>identity.to_string(),
+            Some(identity)=>{
+                warn!("Refusing to let client {} authenticated as {} join as {:?}",self.context.peer_addr,identity,join.name);
+                return self.send_error(ErrorCode::PermissionDenied,format!("room {:?}: {:?} is not the name of the connection",join.room,join.name));
+            }
+            None if !join.name.is_empty()=>join.name,
+            None=>self.context.peer_addr.clone(),
+        };
+        let id=self.id;
+        let limits=self.room_history_limits();
+        self.update_room(&join.room,|rooms|rooms.join(id,&join.room,&member,limits))
+    }
+    fn handle_room_message(&mut self,message:RoomMessage)->io::Result<()>{
+        let id=self.id;
+        let limits=self.room_history_limits();
+        self.update_room(&message.room,|rooms|rooms.post(id,&message.room,message.content,limits))
+    }
+    fn room_history_limits(&self)->HistoryLimits{
+        HistoryLimits{
+            len:self.settings.room_history_len.unwrap_or(DEFAULT_ROOM_HISTORY_LEN),
+            retention:self.settings.room_history_retention.unwrap_or(DEFAULT_ROOM_HISTORY_RETENTION),
+        }
+    }
+    /// Applies an operation on the chat rooms, queuing its events for the
+    /// members or telling the client why it was refused
+    fn update_room(&mut self,room:&str,operation:impl FnOnce(&mut Rooms)->Result<Deliveries,RoomError>)->io::Result<()>{
+        let result={
+            let mut state=self.state.lock().unwrap();
+            operation(&mut state.rooms).map(|deliveries|state.deliver(deliveries))
+        };
+        match result{
+            Ok(())=>Ok(()),
+            Err(e)=>{
+                warn!("Refusing room operation of client {}: {}",self.context.peer_addr,e);
+                self.send_error(ErrorCode::InvalidRequest,format!("room {:?}: {}",room,e))
+            }
+        }
+    }
+    /// Applies an operation on the key-value store, answering with the entry
+    /// of `key` or why it was refused
+    fn update_kv(&mut self,key:String,operation:impl FnOnce(&Store,&str,Instant)->Result<Update,KvError>)->io::Result<()>{
+        let store=Arc::clone(&self.state.lock().unwrap().kv);
+        let result=kv::check_key(&key).and_then(|()|operation(&store,&key,Instant::now()));
+        let update=match result{
+            Ok(update)=>update,
+            Err(e)=>{
+                warn!("Refusing key-value operation of client {}: {}",self.context.peer_addr,e);
+                let code=match e{
+                    KvError::Storage=>ErrorCode::StorageFailed,
+                    KvError::EmptyKey|KvError::NotANumber|KvError::Overflow=>ErrorCode::InvalidRequest,
+                };
+                return self.send_error(code,format!("key {:?}: {}",key,e));
+            }
+        };
+        let response=match update.entry{
+            Some(entry)=>KvResponse{key,found:true,value:entry.value,version:entry.version,applied:update.applied},
+            None=>KvResponse{key,found:false,value:Vec::new(),version:0,applied:update.applied},
+        };
+        self.send_response(ServerMessage{
+            message:Some(server_message::Message::KvResponse(response)),
+            signature:None,
+        })
+    }
+    /// Closes the connection of a client whose event queue overflowed
+    fn disconnect_slow_subscriber(&mut self)->io::Result<ClientStatus>{
+        let capacity=self.subscriber.as_ref().map_or(0,|subscriber|subscriber.capacity());
+        let message=format!("more than {} events waiting to be delivered",capacity);
+        warn!("Disconnecting client {}: {}",self.context.peer_addr,message);
+        self.settings.metrics.increment(Counter::SlowSubscriberDisconnects);
+        self.send_error(ErrorCode::SlowSubscriber,message)?;
+        Ok(ClientStatus::Disconnected)
+    }
+    fn end_stream(&mut self,request_id:u64,error:Option<ErrorResponse>)->io::Result<()>{
+        self.send_response(ServerMessage{
+            message:Some(server_message::Message::StreamEnd(StreamEnd{request_id,error})),
+            signature:None,
+        })
+    }
+    /// Ends all open streams early, telling the client why
+    fn close_streams(&mut self,code:ErrorCode,message:&str)->io::Result<()>{
+        for request_id in self.streams.close_all(){
+            self.end_stream(request_id,Some(ErrorResponse{code:code as i32,message:message.to_string()}))?;
+        }
+        Ok(())
+    }
+    fn send_error(&mut self,code:ErrorCode,message:String)->io::Result<()>{
+        let response=ServerMessage{
+            message:Some(server_message::Message::ErrorResponse(ErrorResponse{
+                code:code as i32,
+                message,
+            })),
+            signature:None,
+        };
+        self.send_response(response)
+    }
+    fn send_response(&mut self,mut response:ServerMessage)->io::Result<()>{
+        if let Some(signer)=&self.settings.signer{
+            self.response_nonce+=1;
+            signer.sign(&mut response,self.response_nonce);
+        }
+        let mut payload=response.encode_to_vec();
+        if let Some(algorithm)=self.compression{
+            let threshold=self.settings.compression_threshold.unwrap_or(usize::MAX);
+            payload=compression::compress(algorithm,&payload,threshold);
+        }
+        let frame = self.framing.encode_frame(&payload);
+        transport::write_all_within(self.stream.as_mut(), &frame, self.timeouts.write)
+    }
+
+    /// Serves whatever the client already sent before a shutdown was requested,
+    /// so requests that are in flight still get a complete response
+    ///
+    /// Gives up after [`DRAIN_TIMEOUT`] if the client keeps sending, or once a
+    /// response could not be written for that long.
+    fn drain(&mut self) -> io::Result<()> {
+        //streams are not run to completion, also those opened while draining
+        //nor can one that stopped reading
+        self.timeouts.write = Some(self.timeouts.write.map_or(DRAIN_TIMEOUT, |timeout| timeout.min(DRAIN_TIMEOUT)));
+        self.close_streams(ErrorCode::Unavailable, "server is shutting down")?;
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        while self.handle()? == ClientStatus::Served {
+            if Instant::now() >= deadline {
+                warn!("Client {} is still sending after {:?}, closing the connection", self.context.peer_addr, DRAIN_TIMEOUT);
+                break;
+            }
+        }
+        self.close_streams(ErrorCode::Unavailable, "server is shutting down")
+    }
+}
+
+fn add_response(add:&AddRequest)->AddResponse{
+    AddResponse{result:add.a+add.b}
+}
+
+/// Serves `client` until it disconnects or the server is stopped
+fn serve(mut client: Client, addr: &str, is_running: &AtomicBool) {
+    let mut connected=true;
+    while is_running.load(Ordering::SeqCst) {
+        match client.handle(){
+            Ok(ClientStatus::Served)=>{}
+            Ok(ClientStatus::Idle)=>{
+                //connection still alive
+                thread::sleep(Duration::from_millis(10));
+            }
+            Ok(ClientStatus::Disconnected)=>{
+                //client disconnected
+                info!("Client disconnected");
+                connected=false;
+                break;
+            }
+            Ok(ClientStatus::TimedOut(timeout))=>{
+                close_timed_out(&client,addr,timeout);
+                connected=false;
+                break;
+            }
+            Err(e) if e.kind()==ErrorKind::TimedOut=>{
+                let timeout=Timeout::Write(client.timeouts.write.unwrap_or_default());
+                close_timed_out(&client,addr,timeout);
+                connected=false;
+                break;
+            }
+            Err(e)=>{
+                error!("Error handling client {}: {}",addr,e);
+                connected=false;
+                break;
+            }
+        }
+    }
+    //answer requests that arrived before the shutdown
+    if connected{
+        if let Err(e)=client.drain(){
+            error!("Error draining client {}: {}",addr,e);
+        }
+    }
+    client.settings.broker.remove(client.id);
+}
+
+/// Whether `message` makes the server send messages of its own later: stream
+/// items, events or room events
+fn causes_pushes(message:&client_message::Message)->bool{
+    matches!(message,client_message::Message::RangeSumRequest(_)|client_message::Message::SubscribeCounter(_)|client_message::Message::StreamRequest(_)|client_message::Message::Subscribe(_)|client_message::Message::JoinRoom(_))
+}
+
+/// Queues `message` in `outbox` unless [`MAX_PENDING_MESSAGES`] are waiting already
+fn push(outbox:&Outbox,message:ServerMessage)->bool{
+    let mut messages=outbox.lock().unwrap();
+    if messages.len()>=MAX_PENDING_MESSAGES{
+        return false;
+    }
+    messages.push_back(message);
+    true
+}
+
+/// Logs and counts a connection about to be closed for exceeding `timeout`
+fn close_timed_out(client:&Client,addr:&str,timeout:Timeout){
+    warn!("Closing connection to {}: {}",addr,timeout);
+    client.settings.metrics.increment(timeout.counter());
+}
+
+/// Entry points of a server, see [`Server::stop`]
+#[derive(Debug, Default)]
+struct Lifecycle {
+    //run() and serve_serial() calls serving connections
+    active: usize,
+    //set by a stop() while nothing was serving, every entry point returns
+    //right away until a run() returned
+    stop_pending: bool,
+}
+
+/// Entry point serving connections, leaving the lifecycle once dropped
+struct Serving<'a> {
+    lifecycle: &'a Mutex<Lifecycle>,
+    started: bool,
+    //run() ends a pending stop, the server may be run again after it
+    ends_stop: bool,
+}
+
+impl Drop for Serving<'_> {
+    fn drop(&mut self) {
+        let mut lifecycle=self.lifecycle.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.started{
+            lifecycle.active-=1;
+        }
+        if self.ends_stop{
+            lifecycle.stop_pending=false;
+        }
     }
 }
 
 pub struct Server {
-    listener: TcpListener,
+    listener: Listener,
     is_running: Arc<AtomicBool>,
+    //entry points serving, and a stop() that came before them
+    lifecycle: Mutex<Lifecycle>,
     state: Arc<Mutex<ServerState>>,//add shared state for data consistancy and race conditions
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    settings: Arc<ClientSettings>,
+    admission: Admission,
 }
 pub struct ServerState{
     connection_count:i32,
+    connections_per_ip:HashMap<IpAddr,usize>,
+    next_connection_id:u64,
+    //open connections by id, each updated by its client thread
+    connections:HashMap<u64,Arc<Mutex<ConnectionInfo>>>,
+    //messages for the open connections, see Server::send_to
+    outboxes:HashMap<u64,Arc<Outbox>>,
+    //chat rooms and their members
+    rooms:Rooms,
+    //locked on its own, the state's lock is only held to get it
+    kv:Arc<Store>,
+}
+
+/// Open connection of a server, see [`Server::connections`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Unique among the connections of the server
+    pub id: u64,
+    pub peer_addr: String,
+    /// Round trip time of the last answered heartbeat
+    pub round_trip_time: Option<Duration>,
+    /// How messages are delimited, `None` for serial devices, which use COBS
+    /// frames
+    pub frame_format: Option<FrameFormat>,
+}
+
+/// Which connections the server accepts
+#[derive(Default)]
+struct Admission {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    ip_filter: Option<IpFilter>,
+}
+
+impl ServerState{
+    /// Counts a new connection from `ip`, unless it would exceed a limit of
+    /// `admission`, in which case the limit is returned
+    fn admit(&mut self,ip:Option<IpAddr>,admission:&Admission)->Result<(),String>{
+        if let Some(max)=admission.max_connections{
+            if self.connection_count as usize>=max{
+                return Err(format!("{} connections open",max));
+            }
+        }
+        if let (Some(max),Some(ip))=(admission.max_connections_per_ip,ip){
+            if self.connections_per_ip.get(&ip).copied().unwrap_or(0)>=max{
+                return Err(format!("{} connections open from {}",max,ip));
+            }
+        }
+        self.connection_count+=1;
+        if let Some(ip)=ip{
+            *self.connections_per_ip.entry(ip).or_default()+=1;
+        }
+        Ok(())
+    }
+
+    /// Adds a connection to the list of open connections, returning its entry
+    /// and the outbox of messages pushed to it
+    fn register(&mut self,peer_addr:&str,frame_format:Option<FrameFormat>)->(Arc<Mutex<ConnectionInfo>>,Arc<Outbox>){
+        self.next_connection_id+=1;
+        let info=Arc::new(Mutex::new(ConnectionInfo{
+            id:self.next_connection_id,
+            peer_addr:peer_addr.to_string(),
+            round_trip_time:None,
+            frame_format,
+        }));
+        self.connections.insert(self.next_connection_id,Arc::clone(&info));
+        let outbox=Arc::new(Outbox::default());
+        self.outboxes.insert(self.next_connection_id,Arc::clone(&outbox));
+        (info,outbox)
+    }
+
+    fn unregister(&mut self,id:u64){
+        let deliveries=self.rooms.leave_all(id);
+        self.deliver(deliveries);
+        self.connections.remove(&id);
+        self.outboxes.remove(&id);
+    }
+
+    /// Queues room events for the connections they are meant for, dropping
+    /// those for connections with too many messages waiting
+    fn deliver(&self,deliveries:Deliveries){
+        for (id,event) in deliveries{
+            let Some(outbox)=self.outboxes.get(&id) else{
+                continue;
+            };
+            let message=ServerMessage{
+                message:Some(server_message::Message::RoomEvent(event)),
+                signature:None,
+            };
+            if !push(outbox,message){
+                warn!("Dropping room event for connection {}: {} messages waiting",id,MAX_PENDING_MESSAGES);
+            }
+        }
+    }
+
+    /// Forgets a connection counted by `admit`
+    fn release(&mut self,ip:Option<IpAddr>){
+        self.connection_count-=1;
+        if let Some(ip)=ip{
+            if let Some(count)=self.connections_per_ip.get_mut(&ip){
+                *count-=1;
+                if *count==0{
+                    self.connections_per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+/// Builder for a [`Server`] with optional features such as TLS
+#[derive(Default)]
+pub struct ServerBuilder {
+    #[cfg(feature = "tls")]
+    tls: Option<(PathBuf, PathBuf)>,
+    #[cfg(feature = "tls")]
+    client_ca: Option<PathBuf>,
+    settings: ClientSettings,
+    admission: Admission,
+}
+
+impl ServerBuilder {
+    /// Serves clients over TLS, using the PEM encoded certificate chain and
+    /// private key at the given paths
+    ///
+    /// The files are read when the server is built and again on
+    /// [`Server::reload_tls`].
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        self.tls = Some((cert_path.into(), key_path.into()));
+        self
+    }
+
+    /// Requires TLS clients to present a certificate issued by one of the CAs
+    /// in the PEM file at `ca_path`
+    ///
+    /// The certificate's subject and alternative names become the identity of
+    /// the connection, see [`crate::auth`]. Only takes effect together with
+    /// [`ServerBuilder::tls`].
+    #[cfg(feature = "tls")]
+    pub fn client_auth(mut self, ca_path: impl Into<PathBuf>) -> Self {
+        self.client_ca = Some(ca_path.into());
+        self
+    }
+
+    /// Checks every request against `policy`, denied ones are answered with a
+    /// `PERMISSION_DENIED` error
+    pub fn authorize(mut self, policy: impl Authorize + 'static) -> Self {
+        self.settings.authorizer = Some(Arc::new(policy));
+        self
+    }
+
+    /// Requires clients to authenticate with an `AuthRequest` against `tokens`
+    /// before sending anything else
+    ///
+    /// Meant for links without TLS; clients that presented a TLS client
+    /// certificate are authenticated already.
+    pub fn require_auth(mut self, tokens: TokenStore) -> Self {
+        self.settings.tokens = Some(tokens);
+        self
+    }
+
+    /// Verifies signed requests with `signer` and signs every response
+    ///
+    /// With `required` set, unsigned requests are rejected too; otherwise
+    /// only requests that carry a signature are checked. See [`crate::signing`].
+    pub fn sign_messages(mut self, signer: MessageSigner, required: bool) -> Self {
+        self.settings.signer = Some(signer);
+        self.settings.require_signatures = required;
+        self
+    }
+
+    /// Limits how fast clients may send requests, see [`crate::rate_limit`]
+    ///
+    /// Every rate has to be positive and every burst size at least 1, building
+    /// the server fails otherwise.
+    pub fn rate_limits(mut self, limits: RateLimits) -> Self {
+        self.settings.rate_limiter = Some(RateLimiter::new(limits));
+        self
+    }
+
+    /// Closes connections of clients that stall for longer than `timeouts`
+    ///
+    /// Each timeout exceeded is logged and counted in [`Server::metrics`]. The
+    /// idle timeout does not apply to serial ports.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.settings.timeouts = timeouts;
+        self
+    }
+
+    /// Sends every client a `Ping` each `interval` and closes its connection
+    /// once `max_missed` pings in a row went unanswered
+    ///
+    /// Clients with [`FrameFormat::Raw`] framing are not pinged.
+    ///
+    /// The round trip time of the answers is kept per connection, see
+    /// [`Server::connections`].
+    pub fn heartbeat(mut self, interval: Duration, max_missed: u32) -> Self {
+        self.settings.heartbeat = Some(HeartbeatSettings { interval, max_missed });
+        self
+    }
+
+    /// How messages are delimited on stream sockets, [`FrameFormat::Raw`] by
+    /// default
+    ///
+    /// Clients of a server left with raw framing can still switch their
+    /// connection to length-delimited framing by listing
+    /// [`handshake::FEATURE_LENGTH_DELIMITED`] in their `Hello`; the server's
+    /// `Hello` is the last message without a length prefix, and the client
+    /// has to wait for it before sending the next. Serial ports always use
+    /// COBS frames.
+    pub fn frame_format(mut self, format: FrameFormat) -> Self {
+        self.settings.frame_format = format;
+        self
+    }
+
+    /// Limits frames from socket clients to payloads of `max_len` bytes,
+    /// [`DEFAULT_MAX_FRAME_SIZE`] by default
+    ///
+    /// The limit is checked against the length a length-delimited frame
+    /// announces, before any of it is buffered, and against the size of a
+    /// read with [`FrameFormat::Raw`], where the bytes arriving right after
+    /// are taken as the rest of the message. The client gets a
+    /// `FRAME_TOO_LARGE` error stating the limit, then `action` is taken.
+    /// Serial frames are limited to [`crate::cobs::MAX_PAYLOAD_LEN`].
+    pub fn max_frame_size(mut self, max_len: usize, action: OversizedFrameAction) -> Self {
+        self.settings.frame_limit = FrameLimit { max_len, action };
+        self
+    }
+
+    /// Name the server introduces itself with in the `Hello` handshake,
+    /// [`handshake::DEFAULT_SERVER_NAME`] if not set
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.settings.server_name = Some(name.into());
+        self
+    }
+
+    /// Offers an application defined feature in the handshake, next to the
+    /// ones implied by the configuration, see [`crate::handshake`]
+    pub fn feature(mut self, feature: impl Into<String>) -> Self {
+        self.settings.extra_features.push(feature.into());
+        self
+    }
+
+    /// Offers the compression algorithms compiled in (cargo features `zstd`
+    /// and `lz4`) in the handshake
+    ///
+    /// Once a client agreed on one, every payload in both directions carries a
+    /// compression flag and those of at least `threshold` bytes are
+    /// compressed, see [`crate::compression`].
+    pub fn compression(mut self, threshold: usize) -> Self {
+        self.settings.compression_threshold = Some(threshold);
+        self
+    }
+
+    /// Limits batches to `max` requests, [`DEFAULT_MAX_BATCH_SIZE`] by default
+    ///
+    /// Larger batches are answered with a `BATCH_TOO_LARGE` error stating the
+    /// limit, none of their requests is processed.
+    pub fn max_batch_size(mut self, max: usize) -> Self {
+        self.settings.max_batch_size = Some(max);
+        self
+    }
+
+    /// Limits the streams a connection may have open at a time to `max`,
+    /// [`DEFAULT_MAX_STREAMS`] by default
+    ///
+    /// A request for another one is answered with a `StreamEnd` carrying a
+    /// `TOO_MANY_STREAMS` error.
+    pub fn max_streams(mut self, max: usize) -> Self {
+        self.settings.max_streams = Some(max);
+        self
+    }
+
+    /// Answers a `StreamRequest` naming `method` with the items of the
+    /// handler `open` returns
+    ///
+    /// The stream is flow controlled and cancelled like the built-in ones;
+    /// its [`crate::streaming::StreamContext`] carries the request's
+    /// cancellation token and deadline. Registering a method again replaces
+    /// its handler.
+    pub fn stream_handler(mut self, method: impl Into<String>, open: impl OpenStreamHandler + 'static) -> Self {
+        self.settings.stream_handlers.insert(method.into(), Arc::new(open));
+        self
+    }
+
+    /// Queues up to `capacity` published events per subscribed connection,
+    /// [`DEFAULT_SUBSCRIBER_QUEUE_LEN`] by default
+    ///
+    /// Events published while a connection's queue is full are handled as
+    /// `action` says and counted in [`Server::metrics`], see [`crate::pubsub`].
+    pub fn subscriber_queue(mut self, capacity: usize, action: SlowSubscriberAction) -> Self {
+        self.settings.subscriber_queue_len = Some(capacity);
+        self.settings.slow_subscriber_action = action;
+        self
+    }
+
+    /// Keeps the last `len` messages of each chat room, replayed to members
+    /// joining later, [`DEFAULT_ROOM_HISTORY_LEN`] by default
+    pub fn room_history(mut self, len: usize) -> Self {
+        self.settings.room_history_len = Some(len);
+        self
+    }
+
+    /// Keeps the messages of a chat room for `retention` after they were
+    /// posted, [`DEFAULT_ROOM_HISTORY_RETENTION`] by default
+    ///
+    /// The history outlives the members: a room nobody is in any more is
+    /// only forgotten once its last message expired.
+    pub fn room_history_retention(mut self, retention: Duration) -> Self {
+        self.settings.room_history_retention = Some(retention);
+        self
+    }
+
+    /// Keeps the key-value store in a directory, so it survives restarts and
+    /// crashes of the server, along with the subscriptions of authenticated
+    /// clients and the counters of [`Server::metrics`]
+    ///
+    /// What a previous server left in the directory is recovered when the
+    /// server is built, which fails if the directory cannot be used or holds a
+    /// damaged snapshot. See [`crate::persistence`].
+    pub fn persistence(mut self, persistence: Persistence) -> Self {
+        self.settings.persistence = Some(persistence);
+        self
+    }
+
+    /// Limits the number of concurrent connections, clients beyond it get a
+    /// `SERVER_BUSY` error and are disconnected
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.admission.max_connections = Some(max);
+        self
+    }
+
+    /// Limits the number of concurrent connections from one IP address, like
+    /// [`ServerBuilder::max_connections`]
+    pub fn max_connections_per_ip(mut self, max: usize) -> Self {
+        self.admission.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Closes connections from addresses `filter` does not allow right after
+    /// accepting them
+    pub fn ip_filter(mut self, filter: IpFilter) -> Self {
+        self.admission.ip_filter = Some(filter);
+        self
+    }
+
+    /// Builds a server listening on the TCP address `addr`
+    pub fn bind(self, addr: &str) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        self.listener(listener)
+    }
+
+    /// Builds a server on an already bound TCP listener
+    pub fn listener(self, listener: TcpListener) -> io::Result<Server> {
+        self.build(Listener::Tcp(listener))
+    }
+
+    /// Builds a server listening on a unix domain socket, see [`Server::bind_unix`]
+    #[cfg(unix)]
+    pub fn bind_unix(self, path: impl AsRef<Path>, mode: Option<u32>) -> io::Result<Server> {
+        let socket = UnixSocket::bind(path.as_ref(), mode)?;
+        self.build(Listener::Unix(socket))
+    }
+
+    fn build(self, listener: Listener) -> io::Result<Server> {
+        let mut server = Server::with_listener(listener);
+        #[cfg(feature = "tls")]
+        if let Some((cert_path, key_path)) = self.tls {
+            server.tls = Some(TlsAcceptor::new(cert_path, key_path, self.client_ca)?);
+        }
+        if let Some(limiter) = &self.settings.rate_limiter {
+            limiter.limits().check()?;
+        }
+        if let Some(persistence) = &self.settings.persistence {
+            let store = Store::open(persistence)?;
+            //counting goes on from where the previous server stopped
+            for (name, value) in store.counters() {
+                match Counter::named(&name) {
+                    Some(counter) => self.settings.metrics.add(counter, value),
+                    None => warn!("Ignoring unknown counter {} in {}", name, persistence.dir.display()),
+                }
+            }
+            server.state.lock().unwrap().kv = Arc::new(store);
+        }
+        server.settings = Arc::new(self.settings);
+        server.admission = self.admission;
+        Ok(server)
+    }
 }
+
 impl Server {
     /// Creates a new server instance
     pub fn new(addr: &str) -> io::Result<Self> {
         let listener = TcpListener::bind(addr)?;
+        Ok(Self::from_listener(listener))
+    }
+
+    /// Returns a builder to configure optional features of the server
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Creates a server on an already bound listener, e.g. one inherited from
+    /// a service manager or a previous instance of the server
+    pub fn from_listener(listener: TcpListener) -> Self {
+        Self::with_listener(Listener::Tcp(listener))
+    }
+
+    /// Creates a server listening on a unix domain socket at `path`
+    ///
+    /// A stale socket file left behind by a crashed server is replaced, and
+    /// `mode` (e.g. `0o660`) restricts who may connect, from the moment the
+    /// socket appears at `path`. The socket file is removed again when the
+    /// server shuts down, and bound anew if it is run again.
+    #[cfg(unix)]
+    pub fn bind_unix(path: impl AsRef<Path>, mode: Option<u32>) -> io::Result<Self> {
+        Self::builder().bind_unix(path, mode)
+    }
+
+    fn with_listener(listener: Listener) -> Self {
         let is_running = Arc::new(AtomicBool::new(false));
         let state=Arc::new(Mutex::new(ServerState{
             connection_count:0,
+            connections_per_ip:HashMap::new(),
+            next_connection_id:0,
+            connections:HashMap::new(),
+            outboxes:HashMap::new(),
+            rooms:Rooms::default(),
+            kv:Arc::default(),
         }));
-        Ok(Server {
+        Server {
             listener,
             is_running,
+            lifecycle: Mutex::default(),
             state,
+            #[cfg(feature = "tls")]
+            tls: None,
+            settings: Arc::default(),
+            admission: Admission::default(),
+        }
+    }
+
+    /// Reloads the TLS certificate and key from the files given to
+    /// [`ServerBuilder::tls`], for connections accepted from now on
+    #[cfg(feature = "tls")]
+    pub fn reload_tls(&self) -> io::Result<()> {
+        match &self.tls {
+            Some(tls) => tls.reload(),
+            None => Err(io::Error::new(ErrorKind::Unsupported, "TLS is not enabled")),
+        }
+    }
+
+    /// Counters of the server, e.g. of throttled requests
+    pub fn metrics(&self) -> &Metrics {
+        &self.settings.metrics
+    }
+
+    /// Open connections, ordered by id
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let state = self.state.lock().unwrap();
+        let mut connections: Vec<ConnectionInfo> =
+            state.connections.values().map(|info| info.lock().unwrap().clone()).collect();
+        connections.sort_by_key(|info| info.id);
+        connections
+    }
+
+    /// Sends `message`, usually a `Notification`, to the connection `id` (see
+    /// [`Server::connections`]) without it having asked for anything
+    ///
+    /// The message is queued for the thread serving the connection, which
+    /// sends it the next time it polls, signed and compressed like its
+    /// responses. Fails with `NotFound` if the connection is closed, with
+    /// `Unsupported` if it uses [`FrameFormat::Raw`], where the message could
+    /// run into a response, and with `WouldBlock` if [`MAX_PENDING_MESSAGES`]
+    /// are still waiting to be sent.
+    pub fn send_to(&self, id: u64, message: ServerMessage) -> io::Result<()> {
+        let (info, outbox) = {
+            let state = self.state.lock().unwrap();
+            (state.connections.get(&id).cloned(), state.outboxes.get(&id).cloned())
+        };
+        let (Some(info), Some(outbox)) = (info, outbox) else {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("no connection with id {}", id)));
+        };
+        if info.lock().unwrap().frame_format == Some(FrameFormat::Raw) {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!("connection {} uses raw framing", id),
+            ));
+        }
+        if !push(&outbox, message) {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                format!("{} messages are waiting for connection {}", MAX_PENDING_MESSAGES, id),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Key-value store shared by the connections, e.g. to preset or inspect keys
+    pub fn kv(&self) -> Arc<Store> {
+        Arc::clone(&self.state.lock().unwrap().kv)
+    }
+
+    /// Looks after the key-value store while the server runs: removes expired
+    /// keys, which lookups skip in the meantime, and logs the counters and
+    /// syncs and snapshots the log of a persistent store
+    fn spawn_store_maintenance(&self)->JoinHandle<()>{
+        let is_running=Arc::clone(&self.is_running);
+        let settings=Arc::clone(&self.settings);
+        let store=self.kv();
+        thread::spawn(move||{
+            let snapshot_interval=settings.persistence.as_ref().map(|persistence|persistence.snapshot_interval);
+            let mut next_snapshot=snapshot_interval.map(|interval|Instant::now()+interval);
+            while is_running.load(Ordering::SeqCst){
+                thread::sleep(STORE_MAINTENANCE_INTERVAL);
+                let now=Instant::now();
+                let expired=store.remove_expired(now);
+                if expired>0{
+                    debug!("Removed {} expired keys",expired);
+                    settings.metrics.add(Counter::KeysExpired,expired as u64);
+                }
+                if let Err(e)=store.log_counters(settings.metrics.values()){
+                    error!("Failed to log the counters: {}",e);
+                }
+                if let Err(e)=store.sync(now){
+                    error!("Failed to sync the log of the key-value store: {}",e);
+                }
+                if let (Some(at),Some(interval))=(next_snapshot,snapshot_interval){
+                    if now>=at{
+                        if let Err(e)=store.snapshot(){
+                            error!("Failed to write a snapshot of the key-value store: {}",e);
+                        }
+                        next_snapshot=Some(now+interval);
+                    }
+                }
+            }
         })
     }
 
+    /// Tells a client turned away at accept that the server is busy, on a best
+    /// effort basis
+    fn send_busy(&self,mut stream:Box<dyn Stream>,reason:String){
+        //a TLS client would need a handshake before it could read the error
+        #[cfg(feature = "tls")]
+        if self.tls.is_some(){
+            return;
+        }
+        let mut response=ServerMessage{
+            message:Some(server_message::Message::ErrorResponse(ErrorResponse{
+                code:ErrorCode::ServerBusy as i32,
+                message:format!("server busy: {}",reason),
+            })),
+            signature:None,
+        };
+        if let Some(signer)=&self.settings.signer{
+            signer.sign(&mut response,1);
+        }
+        let frame=framing::stream_framing(self.settings.frame_format,self.settings.frame_limit.max_len).encode_frame(&response.encode_to_vec());
+        if let Err(e)=transport::write_all_within(stream.as_mut(),&frame,self.settings.timeouts.write){
+            warn!("Failed to send busy error: {}",e);
+        }
+    }
+
+    /// Prepares an accepted connection for its client thread
+    fn set_up_stream(&self, stream: Box<dyn Stream>) -> io::Result<Box<dyn Stream>> {
+        stream.set_nonblocking(true)?;//set the client stream to non blocking
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return tls.accept(stream);
+        }
+        Ok(stream)
+    }
+
     /// Runs the server, listening for incoming connections and handling them
+    ///
+    /// Returns once [`Server::stop`] has been called and every client thread has
+    /// finished answering the requests it had already received.
     pub fn run(&self) -> io::Result<()> {
-        self.is_running.store(true, Ordering::SeqCst); // Set the server as running
+        //a server stopped before it ran still shuts down as usual
+        let serving=self.start_running(true);
+        if !serving.started {
+            info!("Server was stopped before it ran.");
+        }
+        //the socket file of a unix socket is gone if the server ran before
+        self.listener.reopen()?;
         info!("Server is running on {}", self.listener.local_addr()?);
 
         // Set the listener to non-blocking mode
         self.listener.set_nonblocking(true)?;
 
+        //handles of the client threads, joined on shutdown
+        let mut workers:Vec<JoinHandle<()>>=Vec::new();
+        let maintenance=self.spawn_store_maintenance();
+
         while self.is_running.load(Ordering::SeqCst) {
             match self.listener.accept() {
                 Ok((stream, addr)) => {
                     info!("New client connected: {}", addr);
+                    let context=ConnectionContext::new(addr.clone());
+                    let ip=context.peer_ip;
+
+                    if let (Some(filter),Some(ip))=(&self.admission.ip_filter,ip){
+                        if !filter.is_allowed(ip){
+                            warn!("Closing connection from denied address {}",addr);
+                            self.settings.metrics.increment(Counter::ConnectionsDenied);
+                            continue;
+                        }
+                    }
 
                     //update connection count safely
-                    {
+                    let admitted={
                         let mut state=self.state.lock().unwrap();
-                        state.connection_count+=1;
-                        info!("Active connections: {}",state.connection_count);
+                        let admitted=state.admit(ip,&self.admission);
+                        if admitted.is_ok(){
+                            info!("Active connections: {}",state.connection_count);
+                        }
+                        admitted
+                    };
+                    if let Err(reason)=admitted{
+                        warn!("Turning away client {}: {}",addr,reason);
+                        self.settings.metrics.increment(Counter::ConnectionsRejected);
+                        self.send_busy(stream,reason);
+                        continue;
                     }
 
-                    stream.set_nonblocking(true)?;//set the client stream to non blocking
+                    let stream=match self.set_up_stream(stream){
+                        Ok(stream)=>stream,
+                        Err(e)=>{
+                            error!("Failed to set up connection {}: {}",addr,e);
+                            self.state.lock().unwrap().release(ip);
+                            continue;
+                        }
+                    };
                     
                     //create a new arc clone for this client's thread 
                     let is_running=Arc::clone(&self.is_running);
                     //clone the state Arc of the thread
                     let thread_state=Arc::clone(&self.state);
+                    let settings=Arc::clone(&self.settings);
+                    let (info,outbox)=self.state.lock().unwrap().register(&addr,Some(settings.frame_format));
                     //spawn a new thread for this client
-                    thread::spawn(move||{
-                        let mut client = Client::new(stream);
-                        while is_running.load(Ordering::SeqCst) {
-                            match client.handle(){
-                                Ok(true)=>{
-                                    //connection still alive
-                                    thread::sleep(Duration::from_millis(10));
-                                }
-                                Ok(false)=>{
-                                    //client disconnected
-                                    info!("Client disconnected");
-                                    break;
-                                }
-                                Err(e)=>{
-                                    error!("Error handling client {}: {}",addr,e);
-                                    break;
-                                }
-                            }
-                        }
+                    let worker=thread::spawn(move||{
+                        let id=info.lock().unwrap().id;
+                        let framing=framing::stream_framing(settings.frame_format,settings.frame_limit.max_len);
+                        let client = Client::new(stream, framing, context, settings, info, outbox, Arc::clone(&thread_state), Arc::clone(&is_running));
+                        serve(client,&addr,&is_running);
                         //decrease connection count when disconnected 
                         let mut state= thread_state.lock().unwrap();
-                        state.connection_count-=1;
+                        state.release(ip);
+                        state.unregister(id);
                         info!("Client handler thread for {} stopped",addr);
                     });
-                    
-                    
+                    workers.retain(|worker|!worker.is_finished());
+                    workers.push(worker);
                 }
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                     // No incoming connections, sleep briefly to reduce CPU usage
@@ -174,17 +1918,97 @@ impl Server {
             }
         }
 
+        //stop accepting before draining, so a restarted server can take over
+        self.listener.close();
+
+        info!("Waiting for {} client thread(s) to finish", workers.len());
+        for worker in workers{
+            if worker.join().is_err(){
+                error!("Client handler thread panicked");
+            }
+        }
+
+        if maintenance.join().is_err(){
+            error!("Key-value store maintenance thread panicked");
+        }
+        //the counts of the last moments, the snapshot keeps them too
+        if let Err(e)=self.kv().log_counters(self.settings.metrics.values()){
+            error!("Failed to log the counters: {}",e);
+        }
+        //a restarted server need not replay the log
+        if let Err(e)=self.kv().snapshot(){
+            error!("Failed to write a snapshot of the key-value store: {}",e);
+        }
+
+        info!("Metrics: {}", self.settings.metrics);
         info!("Server stopped.");
         Ok(())
     }
 
+    /// Serves a client attached to the serial device at `path`
+    ///
+    /// The same messages as on sockets are exchanged, in COBS frames with a CRC
+    /// (see [`crate::cobs`]). Blocks until the server is stopped or the device
+    /// fails, so it is usually called on a thread of its own next to [`Server::run`].
+    #[cfg(unix)]
+    pub fn serve_serial(&self, path: impl AsRef<Path>, config: &SerialConfig) -> io::Result<()> {
+        let path = path.as_ref();
+        //also when it is served without run()
+        let serving=self.start_running(false);
+        if !serving.started {
+            return Ok(());
+        }
+        let port = SerialPort::open(path, config)?;
+        let addr = format!("serial:{}", path.display());
+        info!("Serving {} at {} baud", addr, config.baud_rate);
+
+        let (info,outbox)={
+            let mut state=self.state.lock().unwrap();
+            state.connection_count+=1;
+            info!("Active connections: {}",state.connection_count);
+            state.register(&addr,None)
+        };
+        let id=info.lock().unwrap().id;
+        let mut client = Client::new(Box::new(port), Box::<CobsFraming>::default(), ConnectionContext::new(addr.clone()), Arc::clone(&self.settings), info, outbox, Arc::clone(&self.state), Arc::clone(&self.is_running));
+        //a device stays attached however long it is quiet
+        client.timeouts.idle=None;
+        serve(client, &addr, &self.is_running);
+        {
+            let mut state=self.state.lock().unwrap();
+            state.connection_count-=1;
+            state.unregister(id);
+        }
+        info!("Stopped serving {}", addr);
+        Ok(())
+    }
+
+    /// Sets the `is_running` flag, unless a stop was requested before or the
+    /// entry points already serving are shutting down; `run` passes
+    /// `ends_stop`, its return ends a pending stop
+    fn start_running(&self, ends_stop: bool) -> Serving<'_> {
+        let mut lifecycle=self.lifecycle.lock().unwrap();
+        let started=!lifecycle.stop_pending&&(lifecycle.active==0||self.is_running.load(Ordering::SeqCst));
+        if started{
+            lifecycle.active+=1;
+            self.is_running.store(true, Ordering::SeqCst); // Set the server as running
+        }
+        Serving{lifecycle:&self.lifecycle,started,ends_stop}
+    }
+
     /// Stops the server by setting the `is_running` flag to `false`
+    ///
+    /// This is the graceful shutdown path: no new connections are accepted and
+    /// `run` returns after the client threads have drained their connections.
+    /// A server stopped before it runs stays stopped until a `run` returned:
+    /// every `run` and [`Server::serve_serial`] started until then returns
+    /// right away. Once `run` returned, the server may be run again.
     pub fn stop(&self) {
-        if self.is_running.load(Ordering::SeqCst) {
-            self.is_running.store(false, Ordering::SeqCst);
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        if lifecycle.active == 0 {
+            lifecycle.stop_pending = true;
+            warn!("Server is not running, it stops as soon as it is run.");
+        } else if self.is_running.swap(false, Ordering::SeqCst) {
             info!("Shutdown signal sent.");
-        } else {
-            warn!("Server was already stopped or not running.");
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,311 @@
+//! Message signatures for links an attacker may tamper with
+//!
+//! A signed envelope carries a [`Signature`]: HMAC-SHA256, keyed with a
+//! pre-shared key, over the direction (`c2s` or `s2c`), the name of the
+//! message type, the big endian nonce, the big endian timestamp and the
+//! envelope as it is sent, with its signature field cut out. The receiver
+//! checks the bytes it got rather than its own encoding of what it decoded,
+//! so fields it does not know and encodings that differ from prost's are
+//! covered too. A signed response can thus not be reflected back as a request
+//! or the other way around. The receiver checks
+//! the MAC and keeps a [`ReplayGuard`] per connection, so recorded messages
+//! cannot be sent again; the server also keeps a [`ReplayWindow`] shared by
+//! all its connections, so they cannot be sent on a new connection either.
+//! Both directions work alike, the server signs its replies with the key it
+//! verifies requests with.
+
+use crate::message::{client_message, server_message, ClientMessage, ServerMessage, Signature};
+use hmac::{Hmac, Mac};
+use prost::Message;
+use sha2::Sha256;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How far the timestamp of a message may be off the receiver's clock
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
+/// Field number of the signature in both envelopes
+const SIGNATURE_FIELD: u64 = 15;
+
+/// Envelope that can carry a [`Signature`]
+pub trait Signed: Message + Clone {
+    /// Label of the direction the envelope is sent in, part of the MAC
+    const DIRECTION: &'static [u8];
+
+    fn signature(&self) -> Option<&Signature>;
+    fn set_signature(&mut self, signature: Option<Signature>);
+
+    /// Name of the message in the envelope, part of the MAC
+    fn message_type(&self) -> &'static str;
+}
+
+impl Signed for ClientMessage {
+    const DIRECTION: &'static [u8] = b"c2s";
+
+    fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    fn set_signature(&mut self, signature: Option<Signature>) {
+        self.signature = signature;
+    }
+
+    fn message_type(&self) -> &'static str {
+        let Some(message) = &self.message else {
+            return "";
+        };
+        match message {
+            client_message::Message::EchoMessage(_) => "EchoMessage",
+            client_message::Message::AddRequest(_) => "AddRequest",
+            client_message::Message::AuthRequest(_) => "AuthRequest",
+            client_message::Message::Pong(_) => "Pong",
+            client_message::Message::Hello(_) => "Hello",
+            client_message::Message::BatchRequest(_) => "BatchRequest",
+            client_message::Message::RangeSumRequest(_) => "RangeSumRequest",
+            client_message::Message::SubscribeCounter(_) => "SubscribeCounter",
+            client_message::Message::StreamRequest(_) => "StreamRequest",
+            client_message::Message::StreamCredit(_) => "StreamCredit",
+            client_message::Message::CancelRequest(_) => "CancelRequest",
+            client_message::Message::Subscribe(_) => "Subscribe",
+            client_message::Message::Unsubscribe(_) => "Unsubscribe",
+            client_message::Message::Publish(_) => "Publish",
+            client_message::Message::JoinRoom(_) => "JoinRoom",
+            client_message::Message::LeaveRoom(_) => "LeaveRoom",
+            client_message::Message::RoomMessage(_) => "RoomMessage",
+            client_message::Message::KvGet(_) => "KvGet",
+            client_message::Message::KvSet(_) => "KvSet",
+            client_message::Message::KvDelete(_) => "KvDelete",
+            client_message::Message::KvCompareAndSwap(_) => "KvCompareAndSwap",
+            client_message::Message::KvIncrement(_) => "KvIncrement",
+        }
+    }
+}
+
+impl Signed for ServerMessage {
+    const DIRECTION: &'static [u8] = b"s2c";
+
+    fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    fn set_signature(&mut self, signature: Option<Signature>) {
+        self.signature = signature;
+    }
+
+    fn message_type(&self) -> &'static str {
+        let Some(message) = &self.message else {
+            return "";
+        };
+        match message {
+            server_message::Message::EchoMessage(_) => "EchoMessage",
+            server_message::Message::AddResponse(_) => "AddResponse",
+            server_message::Message::ErrorResponse(_) => "ErrorResponse",
+            server_message::Message::AuthResponse(_) => "AuthResponse",
+            server_message::Message::Ping(_) => "Ping",
+            server_message::Message::Hello(_) => "Hello",
+            server_message::Message::BatchResponse(_) => "BatchResponse",
+            server_message::Message::StreamItem(_) => "StreamItem",
+            server_message::Message::StreamEnd(_) => "StreamEnd",
+            server_message::Message::SubscriptionResponse(_) => "SubscriptionResponse",
+            server_message::Message::PublishResponse(_) => "PublishResponse",
+            server_message::Message::Event(_) => "Event",
+            server_message::Message::Notification(_) => "Notification",
+            server_message::Message::RoomEvent(_) => "RoomEvent",
+            server_message::Message::KvResponse(_) => "KvResponse",
+        }
+    }
+}
+
+/// Why a signed message was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The message carries no signature
+    Missing,
+    /// The MAC does not match, the message was altered or signed with another key
+    Invalid,
+    /// The nonce is not larger than the one of the previous message
+    Replayed,
+    /// The timestamp is further off than [`MAX_CLOCK_SKEW`]
+    Expired,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            SignatureError::Missing => "message is not signed",
+            SignatureError::Invalid => "signature does not match the message",
+            SignatureError::Replayed => "nonce was used before",
+            SignatureError::Expired => "timestamp is out of the accepted window",
+        };
+        f.write_str(description)
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Signs and verifies envelopes with a pre-shared key
+#[derive(Clone)]
+pub struct MessageSigner {
+    key: Vec<u8>,
+}
+
+impl MessageSigner {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        MessageSigner { key: key.into() }
+    }
+
+    /// Signs `message` with `nonce` and the current time
+    ///
+    /// The MAC covers the encoding without signature, which is what the
+    /// receiver is left with once it cuts the signature field out.
+    pub fn sign<M: Signed>(&self, message: &mut M, nonce: u64) {
+        message.set_signature(None);
+        let timestamp_ms = now_ms();
+        let mac = self
+            .mac::<M>(message.message_type(), nonce, timestamp_ms, &message.encode_to_vec())
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        message.set_signature(Some(Signature {
+            nonce,
+            timestamp_ms,
+            mac,
+        }));
+    }
+
+    /// Checks that `message`, decoded from the bytes `payload`, carries a
+    /// signature made with this key, without looking at its nonce and timestamp
+    pub fn verify_encoded<M: Signed>(&self, message: &M, payload: &[u8]) -> Result<Signature, SignatureError> {
+        let signature = message.signature().ok_or(SignatureError::Missing)?.clone();
+        let unsigned = without_signature(payload).ok_or(SignatureError::Invalid)?;
+        self.mac::<M>(message.message_type(), signature.nonce, signature.timestamp_ms, &unsigned)
+            .verify_slice(&signature.mac)
+            .map_err(|_| SignatureError::Invalid)?;
+        Ok(signature)
+    }
+
+    /// Checks `message` like [`MessageSigner::verify_encoded`], over its
+    /// encoding by prost; only for messages without fields unknown to this side
+    pub fn verify<M: Signed>(&self, message: &M) -> Result<Signature, SignatureError> {
+        self.verify_encoded(message, &message.encode_to_vec())
+    }
+
+    /// MAC of an envelope of type `message_type` encoded without signature as `unsigned`
+    fn mac<M: Signed>(&self, message_type: &str, nonce: u64, timestamp_ms: u64, unsigned: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(M::DIRECTION);
+        //the length keeps the name apart from the nonce
+        mac.update(&[message_type.len() as u8]);
+        mac.update(message_type.as_bytes());
+        mac.update(&nonce.to_be_bytes());
+        mac.update(&timestamp_ms.to_be_bytes());
+        mac.update(unsigned);
+        mac
+    }
+}
+
+impl fmt::Debug for MessageSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        //never print the key
+        f.write_str("MessageSigner")
+    }
+}
+
+/// Rejects messages received before on one connection
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    last_nonce: Option<u64>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts `signature` if its nonce is larger than all before and its
+    /// timestamp is close to the current time
+    pub fn check(&mut self, signature: &Signature) -> Result<(), SignatureError> {
+        if self.last_nonce.is_some_and(|last| signature.nonce <= last) {
+            return Err(SignatureError::Replayed);
+        }
+        if now_ms().abs_diff(signature.timestamp_ms) > MAX_CLOCK_SKEW.as_millis() as u64 {
+            return Err(SignatureError::Expired);
+        }
+        self.last_nonce = Some(signature.nonce);
+        Ok(())
+    }
+}
+
+/// Signatures accepted on any connection, until their timestamp is out of
+/// the [`MAX_CLOCK_SKEW`] window and they would be rejected anyway
+///
+/// Nonces only increase per connection, so a [`ReplayGuard`] alone lets a
+/// recorded message through on a new connection. The window is per key: a
+/// signature is told apart by its MAC, which differs for every message.
+#[derive(Debug, Default)]
+pub struct ReplayWindow {
+    //timestamp and MAC of every signature accepted, oldest first
+    seen: Mutex<BTreeSet<(u64, Vec<u8>)>>,
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts `signature` if it was not accepted before and its timestamp is
+    /// close to the current time
+    pub fn check(&self, signature: &Signature) -> Result<(), SignatureError> {
+        let now = now_ms();
+        let max_skew = MAX_CLOCK_SKEW.as_millis() as u64;
+        if now.abs_diff(signature.timestamp_ms) > max_skew {
+            return Err(SignatureError::Expired);
+        }
+        let mut seen = self.seen.lock().unwrap();
+        while seen.first().is_some_and(|(timestamp_ms, _)| now.abs_diff(*timestamp_ms) > max_skew) {
+            seen.pop_first();
+        }
+        if !seen.insert((signature.timestamp_ms, signature.mac.clone())) {
+            return Err(SignatureError::Replayed);
+        }
+        Ok(())
+    }
+}
+
+/// The encoded envelope `payload` with every signature field cut out and the
+/// other fields left as they are, `None` if it is not a valid encoding
+fn without_signature(payload: &[u8]) -> Option<Vec<u8>> {
+    let mut unsigned = Vec::with_capacity(payload.len());
+    let mut rest = payload;
+    while !rest.is_empty() {
+        let field = rest;
+        let key = prost::encoding::decode_varint(&mut rest).ok()?;
+        let len = match key & 0x7 {
+            0 => {
+                prost::encoding::decode_varint(&mut rest).ok()?;
+                0
+            }
+            1 => 8,
+            2 => usize::try_from(prost::encoding::decode_varint(&mut rest).ok()?).ok()?,
+            5 => 4,
+            //groups have no place in proto3
+            _ => return None,
+        };
+        rest = rest.get(len..)?;
+        if key >> 3 != SIGNATURE_FIELD {
+            unsigned.extend_from_slice(&field[..field.len() - rest.len()]);
+        }
+    }
+    Some(unsigned)
+}
+
+/// Milliseconds since the unix epoch
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
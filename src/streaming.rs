@@ -0,0 +1,328 @@
+//! Server-streaming responses with credit based flow control
+//!
+//! A `RangeSumRequest` or `SubscribeCounter` is answered with a sequence of
+//! `StreamItem`s tagged with its request id, closed by a `StreamEnd`. Items
+//! are computed when they are sent rather than queued: every item spends one
+//! credit, granted with the request and later with `StreamCredit`, and a
+//! stream out of credit waits. A slow client thus only holds up its own
+//! streams and the server buffers nothing for it. Each stream checks its
+//! [`CancellationToken`] before every item, ending early once the request is
+//! cancelled or its deadline passed.
+//!
+//! Applications add streams of their own, opened with a `StreamRequest`
+//! naming them, by implementing [`StreamHandler`].
+
+use crate::{
+    auth::ConnectionContext,
+    cancel::{CancellationToken, StopReason},
+    message::{RangeSumRequest, SubscribeCounter},
+};
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Produces the items of a stream, one at a time
+///
+/// The built-in streams are handlers like the ones an application registers
+/// with [`crate::server::ServerBuilder::stream_handler`]. The server checks
+/// the request's [`CancellationToken`] before every call; a handler doing
+/// lengthy work in a call, or on threads of its own, watches the token it
+/// gets in the [`StreamContext`].
+pub trait StreamHandler: Send {
+    /// Next step of the stream, only asked for while the client has credit
+    fn next(&mut self, context: &StreamContext) -> StreamStep;
+
+    /// Whether the stream has no items left, so it can end while the client
+    /// has no credit
+    fn is_complete(&self) -> bool {
+        false
+    }
+}
+
+/// Opens the [`StreamHandler`] for a `StreamRequest`, given its payload
+///
+/// An error ends the stream right away with an `INVALID_REQUEST` error
+/// carrying the message. Implemented for closures, like
+/// [`crate::auth::Authorize`].
+pub trait OpenStreamHandler: Send + Sync {
+    fn open(&self, context: &ConnectionContext, payload: &[u8]) -> Result<Box<dyn StreamHandler>, String>;
+}
+
+impl<F> OpenStreamHandler for F
+where
+    F: Fn(&ConnectionContext, &[u8]) -> Result<Box<dyn StreamHandler>, String> + Send + Sync,
+{
+    fn open(&self, context: &ConnectionContext, payload: &[u8]) -> Result<Box<dyn StreamHandler>, String> {
+        self(context, payload)
+    }
+}
+
+/// What a [`StreamHandler`] does next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamStep {
+    /// Send an item with this value, which takes a credit
+    Item(i64),
+    /// The stream is complete, the client gets a `StreamEnd`
+    End,
+    /// Nothing to send yet, asked again the next time the connection polls
+    Wait,
+}
+
+/// The request a [`StreamHandler`] streams the items of
+#[derive(Debug)]
+pub struct StreamContext<'a> {
+    request_id: u64,
+    token: &'a CancellationToken,
+    now: Instant,
+}
+
+impl StreamContext<'_> {
+    pub fn request_id(&self) -> u64 {
+        self.request_id
+    }
+
+    /// Token cancelled once the client cancels the request, carrying its
+    /// deadline; a clone can be handed to threads working for the handler
+    pub fn token(&self) -> &CancellationToken {
+        self.token
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.token.deadline()
+    }
+
+    /// Time the connection polled its streams at
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+}
+
+/// Running sums of `next..end`
+struct RangeSum {
+    next: i64,
+    end: i64,
+    sum: i64,
+}
+
+impl StreamHandler for RangeSum {
+    fn next(&mut self, _context: &StreamContext) -> StreamStep {
+        if self.is_complete() {
+            return StreamStep::End;
+        }
+        //sums beyond the range of an int64 stay at its limit
+        self.sum = self.sum.saturating_add(self.next);
+        self.next += 1;
+        StreamStep::Item(self.sum)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.next >= self.end
+    }
+}
+
+/// Values up to `count`, or without end if it is 0, one per `interval`
+struct Counter {
+    value: u64,
+    count: u64,
+    interval: Duration,
+    next_at: Instant,
+}
+
+impl StreamHandler for Counter {
+    fn next(&mut self, context: &StreamContext) -> StreamStep {
+        if self.is_complete() {
+            return StreamStep::End;
+        }
+        if context.now() < self.next_at {
+            return StreamStep::Wait;
+        }
+        self.value += 1;
+        //counted from now, so a stream that ran out of credit does not burst
+        self.next_at = context.now() + self.interval;
+        StreamStep::Item(self.value as i64)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.count != 0 && self.value >= self.count
+    }
+}
+
+/// A stream in progress
+pub(crate) struct OpenStream {
+    pub request_id: u64,
+    handler: Box<dyn StreamHandler>,
+    credits: u64,
+    token: CancellationToken,
+}
+
+impl fmt::Debug for OpenStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenStream")
+            .field("request_id", &self.request_id)
+            .field("credits", &self.credits)
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a stream has to send next
+enum Next {
+    Item(i64),
+    End,
+    Stopped(StopReason),
+    Wait,
+}
+
+impl OpenStream {
+    /// Stream of the items `handler` produces, which may send `credits` of
+    /// them before it needs more
+    pub fn new(request_id: u64, handler: Box<dyn StreamHandler>, credits: u32) -> Self {
+        OpenStream {
+            request_id,
+            handler,
+            credits: credits.into(),
+            token: CancellationToken::new(),
+        }
+    }
+
+    pub fn range_sum(request: &RangeSumRequest) -> Self {
+        let handler = RangeSum {
+            next: request.start,
+            end: request.end,
+            sum: 0,
+        };
+        Self::new(request.request_id, Box::new(handler), request.credits)
+    }
+
+    /// Counter stream sending its first value right away
+    pub fn counter(request: &SubscribeCounter, now: Instant) -> Self {
+        let handler = Counter {
+            value: 0,
+            count: request.count,
+            interval: Duration::from_millis(request.interval_ms.into()),
+            next_at: now,
+        };
+        Self::new(request.request_id, Box::new(handler), request.credits)
+    }
+
+    /// Stops the stream once `deadline` passed
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.token = CancellationToken::with_deadline(deadline);
+        self
+    }
+
+    fn next(&mut self, now: Instant) -> Next {
+        if let Some(reason) = self.token.stop_reason(now) {
+            return Next::Stopped(reason);
+        }
+        if self.handler.is_complete() {
+            return Next::End;
+        }
+        if self.credits == 0 {
+            return Next::Wait;
+        }
+        let context = StreamContext {
+            request_id: self.request_id,
+            token: &self.token,
+            now,
+        };
+        match self.handler.next(&context) {
+            StreamStep::Item(value) => {
+                self.credits -= 1;
+                Next::Item(value)
+            }
+            StreamStep::End => Next::End,
+            StreamStep::Wait => Next::Wait,
+        }
+    }
+}
+
+/// Message a stream is ready to send
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamEvent {
+    Item { request_id: u64, value: i64 },
+    /// The stream is complete and was removed
+    End { request_id: u64 },
+    /// The stream was stopped early and removed
+    Stopped { request_id: u64, reason: StopReason },
+}
+
+/// Open streams of a connection, taking turns to send
+#[derive(Debug, Default)]
+pub(crate) struct Streams {
+    streams: VecDeque<OpenStream>,
+}
+
+impl Streams {
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    pub fn contains(&self, request_id: u64) -> bool {
+        self.streams.iter().any(|stream| stream.request_id == request_id)
+    }
+
+    pub fn open(&mut self, stream: OpenStream) {
+        self.streams.push_back(stream);
+    }
+
+    /// Adds `credits` to a stream, returning false if it is not open
+    pub fn grant(&mut self, request_id: u64, credits: u32) -> bool {
+        match self.streams.iter_mut().find(|stream| stream.request_id == request_id) {
+            Some(stream) => {
+                stream.credits = stream.credits.saturating_add(credits.into());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancels a stream, returning false if it is not open
+    pub fn cancel(&mut self, request_id: u64) -> bool {
+        match self.streams.iter().find(|stream| stream.request_id == request_id) {
+            Some(stream) => {
+                stream.token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes all streams, returning their request ids
+    pub fn close_all(&mut self) -> Vec<u64> {
+        self.streams.drain(..).map(|stream| stream.request_id).collect()
+    }
+
+    /// Next message of the first stream, in turn, that can send one
+    pub fn poll(&mut self, now: Instant) -> Option<StreamEvent> {
+        for _ in 0..self.streams.len() {
+            let mut stream = self.streams.pop_front()?;
+            match stream.next(now) {
+                Next::Item(value) => {
+                    let request_id = stream.request_id;
+                    self.streams.push_back(stream);
+                    return Some(StreamEvent::Item { request_id, value });
+                }
+                Next::End => {
+                    return Some(StreamEvent::End {
+                        request_id: stream.request_id,
+                    })
+                }
+                Next::Stopped(reason) => {
+                    return Some(StreamEvent::Stopped {
+                        request_id: stream.request_id,
+                        reason,
+                    })
+                }
+                Next::Wait => self.streams.push_back(stream),
+            }
+        }
+        None
+    }
+}
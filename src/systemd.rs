@@ -0,0 +1,127 @@
+//! Socket activation following the systemd `sd_listen_fds` protocol
+//!
+//! The service manager (or a previous server instance handing over its socket)
+//! passes already bound listening sockets starting at file descriptor 3 and
+//! announces them through the `LISTEN_FDS` and `LISTEN_PID` environment variables.
+
+use log::{info, warn};
+use std::{
+    env,
+    io::{self, ErrorKind},
+    mem,
+    net::TcpListener,
+    os::unix::io::{FromRawFd, RawFd},
+    process,
+};
+
+/// First file descriptor passed by the service manager
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the listening socket passed by the service manager, if any
+///
+/// Returns `Ok(None)` when the process was not socket activated. The activation
+/// variables are removed from the environment so child processes do not pick
+/// up the same socket by mistake.
+///
+/// The server listens on one socket: if more are passed, or descriptor 3 is
+/// not a listening TCP socket, the passed descriptors are closed and an
+/// `InvalidInput` error says why.
+pub fn take_listener() -> io::Result<Option<TcpListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(None),
+    };
+
+    //the variables were meant for another process, e.g. our parent
+    if pid.trim().parse::<u32>().ok() != Some(process::id()) {
+        warn!("ignoring LISTEN_FDS addressed to process {}", pid);
+        return Ok(None);
+    }
+
+    let count: RawFd = fds.trim().parse().map_err(|_| {
+        io::Error::new(ErrorKind::InvalidInput, format!("invalid LISTEN_FDS value: {}", fds))
+    })?;
+    if count < 1 {
+        return Ok(None);
+    }
+    if count > 1 {
+        close_all(LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(count));
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} sockets passed by the service manager, the server listens on exactly one", count),
+        ));
+    }
+    if let Err(e) = check_listening_stream(LISTEN_FDS_START) {
+        close_all(LISTEN_FDS_START..LISTEN_FDS_START + 1);
+        return Err(e);
+    }
+
+    // SAFETY: LISTEN_PID names this process, so by the activation protocol
+    // descriptor 3 is an open socket handed over to us and owned by nobody else
+    let listener = unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    //fails for stream sockets of other families, dropping the listener closes it
+    let addr = listener.local_addr().map_err(|e| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("descriptor {} passed by the service manager is not a TCP socket: {}", LISTEN_FDS_START, e),
+        )
+    })?;
+    info!("using listening socket {} passed by the service manager", addr);
+
+    Ok(Some(listener))
+}
+
+/// Fails unless `fd` is a stream socket accepting connections
+fn check_listening_stream(fd: RawFd) -> io::Result<()> {
+    let not_listening = |reason: String| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("descriptor {} passed by the service manager is {}", fd, reason),
+        )
+    };
+    let socket_type = socket_option(fd, libc::SO_TYPE).map_err(|e| not_listening(format!("not a socket: {}", e)))?;
+    if socket_type != libc::SOCK_STREAM {
+        return Err(not_listening("not a stream socket".to_string()));
+    }
+    if socket_option(fd, libc::SO_ACCEPTCONN)? == 0 {
+        return Err(not_listening("a socket that is not listening".to_string()));
+    }
+    Ok(())
+}
+
+/// Integer valued socket level option of `fd`
+fn socket_option(fd: RawFd, option: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: value and len describe a c_int, the size SOL_SOCKET options have
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Closes descriptors passed by the service manager that will not be used
+fn close_all(fds: std::ops::Range<RawFd>) {
+    for fd in fds {
+        // SAFETY: by the activation protocol the descriptors are ours and
+        // nothing else refers to them; closing one that is not open fails harmlessly
+        if unsafe { libc::close(fd) } == -1 {
+            warn!("failed to close descriptor {}: {}", fd, io::Error::last_os_error());
+        }
+    }
+}
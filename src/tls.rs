@@ -0,0 +1,219 @@
+//! TLS for client connections, built on rustls
+//!
+//! The server's certificate chain and private key are read from PEM files and
+//! can be reloaded at runtime, e.g. after a certificate renewal. Connections
+//! accepted after a reload use the new certificate, established ones keep theirs.
+//!
+//! With a client CA configured, clients must present a certificate issued by
+//! it, and the certificate's subject and alternative names become the
+//! [`Identity`] of the connection.
+
+use crate::{auth::Identity, transport::Stream};
+use log::info;
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig, ServerConnection,
+};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration,
+};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+/// Wraps accepted connections in TLS sessions
+pub(crate) struct TlsAcceptor {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    client_ca_path: Option<PathBuf>,
+    config: RwLock<Arc<ServerConfig>>,
+}
+
+impl TlsAcceptor {
+    /// Loads the PEM encoded certificate chain and private key, and the CA
+    /// bundle client certificates must be issued by if one is given
+    pub fn new(cert_path: PathBuf, key_path: PathBuf, client_ca_path: Option<PathBuf>) -> io::Result<Self> {
+        let config = load_config(&cert_path, &key_path, client_ca_path.as_deref())?;
+        Ok(TlsAcceptor {
+            cert_path,
+            key_path,
+            client_ca_path,
+            config: RwLock::new(config),
+        })
+    }
+
+    /// Reads the certificate, key and client CA files again
+    ///
+    /// On error the previous configuration stays in use.
+    pub fn reload(&self) -> io::Result<()> {
+        let config = load_config(&self.cert_path, &self.key_path, self.client_ca_path.as_deref())?;
+        *self.config.write().unwrap() = config;
+        info!("Reloaded TLS certificate from {}", self.cert_path.display());
+        Ok(())
+    }
+
+    /// Starts a TLS session on `stream`, the handshake completes as data flows
+    pub fn accept(&self, stream: Box<dyn Stream>) -> io::Result<Box<dyn Stream>> {
+        let config = Arc::clone(&self.config.read().unwrap());
+        let connection = ServerConnection::new(config).map_err(tls_error)?;
+        Ok(Box::new(TlsStream { connection, stream }))
+    }
+}
+
+fn load_config(cert_path: &Path, key_path: &Path, client_ca_path: Option<&Path>) -> io::Result<Arc<ServerConfig>> {
+    let certs = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| pem_error(key_path, e))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca_path)? {
+                roots.add(cert).map_err(tls_error)?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certs, key).map_err(tls_error)?;
+    Ok(Arc::new(config))
+}
+
+/// Reads all certificates of a PEM file, failing if there are none
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(path, e))?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("no certificate found in {}", path.display()),
+        ));
+    }
+    Ok(certs)
+}
+
+/// Identity of the subject of a DER encoded certificate
+fn certificate_identity(der: &[u8]) -> Option<Identity> {
+    let (_, cert) = X509Certificate::from_der(der).ok()?;
+    let subject = cert.subject();
+    let common_name = subject
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map(str::to_string);
+    let mut alt_names = Vec::new();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) => {
+                    alt_names.push(name.to_string())
+                }
+                GeneralName::IPAddress(bytes) => {
+                    let addr = match bytes.len() {
+                        4 => IpAddr::from(<[u8; 4]>::try_from(*bytes).unwrap()),
+                        16 => IpAddr::from(<[u8; 16]>::try_from(*bytes).unwrap()),
+                        _ => continue,
+                    };
+                    alt_names.push(addr.to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(Identity {
+        subject: subject.to_string(),
+        common_name,
+        alt_names,
+    })
+}
+
+fn pem_error(path: &Path, e: rustls::pki_types::pem::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("failed to read {}: {}", path.display(), e))
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e)
+}
+
+/// Server side of a TLS session over a non-blocking stream
+struct TlsStream {
+    connection: ServerConnection,
+    stream: Box<dyn Stream>,
+}
+
+impl TlsStream {
+    /// Writes out pending TLS records, failing with `WouldBlock` while the
+    /// socket buffer is full
+    fn write_pending(&mut self) -> io::Result<()> {
+        while self.connection.wants_write() {
+            self.connection.write_tls(&mut self.stream)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            //plaintext already decrypted from earlier records
+            match self.connection.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+
+            //the peer closed the socket without a close_notify
+            if self.connection.read_tls(&mut self.stream)? == 0 {
+                return Ok(0);
+            }
+            let result = self.connection.process_new_packets();
+            //handshake messages and alerts, sent even when processing failed;
+            //they are small, so waiting for the socket buffer is short
+            loop {
+                match self.write_pending() {
+                    Ok(()) => break,
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+                    Err(e) => return Err(e),
+                }
+            }
+            result.map_err(tls_error)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.connection.writer().write(buf)?;
+        if written == 0 && !buf.is_empty() {
+            //the send buffer of the session is full, make room first
+            self.write_pending()?;
+            return self.connection.writer().write(buf);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.connection.writer().flush()?;
+        self.write_pending()
+    }
+}
+
+impl Stream for TlsStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    fn peer_identity(&self) -> Option<Identity> {
+        //the chain starts with the client's own certificate
+        let cert = self.connection.peer_certificates()?.first()?;
+        certificate_identity(cert)
+    }
+}
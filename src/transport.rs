@@ -0,0 +1,271 @@
+//! Sockets the server can accept clients on
+//!
+//! Every transport carries the same byte stream protocol, so the client
+//! handling code only sees a [`Stream`].
+
+use crate::auth::Identity;
+use log::{info, warn};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+/// Connected byte stream a client is served over
+pub(crate) trait Stream: Read + Write + Send {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    /// Identity the peer authenticated with on this stream, if any
+    fn peer_identity(&self) -> Option<Identity> {
+        None
+    }
+}
+
+impl Stream for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Writes all of `data` to a non-blocking stream and flushes it, waiting
+/// while the peer does not read
+///
+/// Gives up with a `TimedOut` error once that took longer than `timeout`.
+pub(crate) fn write_all_within(stream: &mut dyn Stream, data: &[u8], timeout: Option<Duration>) -> io::Result<()> {
+    let started = Instant::now();
+    let mut written = 0;
+    let mut flushed = false;
+    while !flushed {
+        let result = if written < data.len() {
+            stream.write(&data[written..]).and_then(|n| match n {
+                0 => Err(ErrorKind::WriteZero.into()),
+                n => {
+                    written += n;
+                    Ok(())
+                }
+            })
+        } else {
+            stream.flush().map(|()| flushed = true)
+        };
+        match result {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+                    return Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("write did not complete within {:?}", timeout.unwrap()),
+                    ));
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Listening socket of the server
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+}
+
+impl Listener {
+    /// Accepts a connection, returning the stream and a printable peer address
+    pub fn accept(&self) -> io::Result<(Box<dyn Stream>, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept()?;
+                Ok((Box::new(stream), addr.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(socket) => {
+                let (stream, _) = socket.listener.lock().unwrap().accept()?;
+                //clients of a unix socket are usually unnamed
+                Ok((Box::new(stream), format!("unix:{}", socket.path.display())))
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(socket) => socket.listener.lock().unwrap().set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Printable address the server listens on
+    pub fn local_addr(&self) -> io::Result<String> {
+        match self {
+            Listener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            #[cfg(unix)]
+            Listener::Unix(socket) => Ok(format!("unix:{}", socket.path.display())),
+        }
+    }
+
+    /// Releases resources that would outlive the process, i.e. the socket file
+    pub fn close(&self) {
+        #[cfg(unix)]
+        if let Listener::Unix(socket) = self {
+            socket.remove_file();
+        }
+    }
+
+    /// Makes a closed listener reachable again, binding a unix socket at its
+    /// path anew
+    pub fn reopen(&self) -> io::Result<()> {
+        match self {
+            //closing leaves a tcp listener bound
+            Listener::Tcp(_) => Ok(()),
+            #[cfg(unix)]
+            Listener::Unix(socket) => socket.reopen(),
+        }
+    }
+}
+
+/// Unix domain socket bound to a path on the file system
+///
+/// The socket file is removed again when the socket is dropped.
+#[cfg(unix)]
+pub(crate) struct UnixSocket {
+    //replaced when the socket is bound again after its file was removed
+    listener: Mutex<UnixListener>,
+    path: PathBuf,
+    mode: Option<u32>,
+    //set once the file is gone, so a socket bound at the same path by a
+    //newer server is never removed by mistake
+    removed: AtomicBool,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    /// Binds a socket at `path`, replacing a stale socket file left behind by a
+    /// server that did not shut down cleanly, and applies `mode` if given
+    ///
+    /// With a `mode` the socket is bound in a private directory and only moved
+    /// to `path` once its permissions are set, so nobody can connect while it
+    /// still has the permissions of the umask.
+    pub fn bind(path: &Path, mode: Option<u32>) -> io::Result<Self> {
+        Ok(UnixSocket {
+            listener: Mutex::new(bind_listener(path, mode)?),
+            path: path.to_path_buf(),
+            mode,
+            removed: AtomicBool::new(false),
+        })
+    }
+
+    /// Binds the socket at its path again if the file was removed
+    ///
+    /// Connections waiting on the old socket were made before it was closed
+    /// and are dropped with it.
+    fn reopen(&self) -> io::Result<()> {
+        if !self.removed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        *self.listener.lock().unwrap() = bind_listener(&self.path, self.mode)?;
+        self.removed.store(false, Ordering::SeqCst);
+        info!("Bound socket file {} again", self.path.display());
+        Ok(())
+    }
+
+    fn remove_file(&self) {
+        if self.removed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        match fs::remove_file(&self.path) {
+            Ok(()) => info!("Removed socket file {}", self.path.display()),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove socket file {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        self.remove_file();
+    }
+}
+
+#[cfg(unix)]
+fn bind_listener(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    remove_stale_socket(path)?;
+    match mode {
+        Some(mode) => bind_private(path, mode),
+        None => UnixListener::bind(path),
+    }
+}
+
+/// Binds a socket with permissions `mode` inside a directory only we can
+/// enter, then renames it to `path`
+#[cfg(unix)]
+fn bind_private(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(ErrorKind::InvalidInput, format!("{} is not a file path", path.display()))
+    })?;
+    let mut dir_name = std::ffi::OsString::from(".");
+    dir_name.push(name);
+    dir_name.push(format!(".{}", std::process::id()));
+    let dir = path.with_file_name(dir_name);
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let private = dir.join("socket");
+    let bound = UnixListener::bind(&private).and_then(|listener| {
+        fs::set_permissions(&private, fs::Permissions::from_mode(mode))?;
+        fs::rename(&private, path)?;
+        Ok(listener)
+    });
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        warn!("Failed to remove directory {}: {}", dir.display(), e);
+    }
+    bound
+}
+
+/// Removes a socket file nobody is listening on anymore
+///
+/// Fails if `path` is not a socket or another server is still accepting on it.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AddrInUse,
+            format!("another server is listening on {}", path.display()),
+        ));
+    }
+    warn!("Removing stale socket file {}", path.display());
+    fs::remove_file(path)
+}
@@ -0,0 +1,154 @@
+#![cfg(unix)]
+
+use embedded_recruitment_task::message::{client_message, server_message, AddRequest};
+use server_process::{ServerProcess, SERVER_BIN};
+use std::{
+    io,
+    net::{TcpListener, TcpStream, UdpSocket},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        process::CommandExt,
+    },
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod server_process;
+
+/// Builds a command running the server binary with `fds` passed as
+/// descriptors 3 and up, the way systemd does for socket activated services
+fn activation_command(fds: &[RawFd]) -> Command {
+    let fds = fds.to_vec();
+    let mut command = Command::new("sh");
+    // LISTEN_PID must name the server itself, which only the shell knows
+    // right before it execs the binary
+    command
+        .arg("-c")
+        .arg("export LISTEN_PID=$$; exec \"$0\"")
+        .arg(SERVER_BIN)
+        .env("LISTEN_FDS", fds.len().to_string());
+    // SAFETY: only async-signal-safe calls between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            // moved out of the way first, so no descriptor is overwritten
+            // before it was passed on; dup2 clears close-on-exec on the copies
+            for (i, &fd) in fds.iter().enumerate() {
+                if libc::dup2(fd, 100 + i as RawFd) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            for i in 0..fds.len() as RawFd {
+                if libc::dup2(100 + i, 3 + i) == -1 || libc::close(100 + i) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    command
+}
+
+/// Runs the server binary with `listener` passed as descriptor 3
+fn activated_server(listener: &TcpListener) -> ServerProcess {
+    let port = listener.local_addr().unwrap().port() as u32;
+    ServerProcess::start(activation_command(&[listener.as_raw_fd()]), "127.0.0.1", port)
+}
+
+/// Runs the server binary with `fds` passed, expecting it to refuse them, and
+/// returns what it printed to stderr
+fn refused_activation(fds: &[RawFd]) -> String {
+    let mut child = activation_command(fds)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn server process");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().expect("Failed to wait for server process").is_none() {
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            panic!("Server accepted the descriptors");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let output = child.wait_with_output().expect("Failed to wait for server process");
+    assert!(!output.status.success(), "Server exited cleanly");
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn assert_add(client: &mut client::Client, a: i32, b: i32) {
+    let add_request = AddRequest { a, b };
+    assert!(
+        client.send(client_message::Message::AddRequest(add_request)).is_ok(),
+        "Failed to send message"
+    );
+    let response = client.receive();
+    assert!(response.is_ok(), "Failed to receive response");
+    match response.unwrap().message {
+        Some(server_message::Message::AddResponse(add_response)) => {
+            assert_eq!(add_response.result, a + b, "Wrong calculation result");
+        }
+        _ => panic!("Expected AddResponse"),
+    }
+}
+
+#[test]
+fn test_server_uses_inherited_listener() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let port = listener.local_addr().unwrap().port() as u32;
+    let mut server = activated_server(&listener);
+
+    let mut client = client::Client::new("127.0.0.1", port, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_add(&mut client, 1, 2);
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    server.signal("TERM");
+    assert!(server.wait().success(), "Server did not exit cleanly");
+}
+
+#[test]
+fn test_listener_handover_between_instances() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let port = listener.local_addr().unwrap().port() as u32;
+
+    let mut old_server = activated_server(&listener);
+    let mut client = client::Client::new("127.0.0.1", port, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the old server");
+    assert_add(&mut client, 1, 2);
+
+    // Start the replacement on the same socket, then retire the old instance
+    let mut new_server = activated_server(&listener);
+    old_server.signal("TERM");
+    assert!(old_server.wait().success(), "Old server did not exit cleanly");
+
+    // The socket never stopped listening, so new clients are served right away
+    let mut client = client::Client::new("127.0.0.1", port, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the new server");
+    assert_add(&mut client, 3, 4);
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    new_server.signal("TERM");
+    assert!(new_server.wait().success(), "New server did not exit cleanly");
+}
+
+#[test]
+fn test_activation_passing_several_sockets_is_refused() {
+    let first = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let second = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let stderr = refused_activation(&[first.as_raw_fd(), second.as_raw_fd()]);
+    assert!(stderr.contains("2 sockets passed"), "Unexpected error: {}", stderr);
+}
+
+#[test]
+fn test_activation_with_a_socket_not_listening_is_refused() {
+    let udp = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind socket");
+    let stderr = refused_activation(&[udp.as_raw_fd()]);
+    assert!(stderr.contains("not a stream socket"), "Unexpected error: {}", stderr);
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let connected = TcpStream::connect(listener.local_addr().unwrap()).expect("Failed to connect");
+    let stderr = refused_activation(&[connected.as_raw_fd()]);
+    assert!(stderr.contains("not listening"), "Unexpected error: {}", stderr);
+}
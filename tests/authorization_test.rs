@@ -0,0 +1,197 @@
+use common::{start_server, stop};
+use embedded_recruitment_task::{
+    auth::{AccessPolicy, ConnectionContext, MessageKind},
+    message::{client_message, server_message, AddRequest, EchoMessage, ErrorCode},
+    server::Server,
+};
+use serial_test::serial;
+
+#[cfg(feature = "tls")]
+mod certs;
+mod client;
+mod common;
+
+fn assert_echo_allowed(client: &mut client::Client) {
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "Hello".to_string(),
+    });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::EchoMessage(echo)) => {
+            assert_eq!(echo.content, "Hello", "Echoed message content does not match");
+        }
+        other => panic!("Expected EchoMessage, but received {:?}", other),
+    }
+}
+
+fn assert_add_allowed(client: &mut client::Client) {
+    let message = client_message::Message::AddRequest(AddRequest { a: 2, b: 3 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::AddResponse(add_response)) => {
+            assert_eq!(add_response.result, 5, "Wrong calculation result");
+        }
+        other => panic!("Expected AddResponse, but received {:?}", other),
+    }
+}
+
+fn assert_add_denied(client: &mut client::Client) {
+    let message = client_message::Message::AddRequest(AddRequest { a: 2, b: 3 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code(), ErrorCode::PermissionDenied, "Wrong error code");
+        }
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn test_policy_denies_message_types() {
+    let policy = AccessPolicy::new().allow_anyone(&[MessageKind::Echo]);
+    let (server, handle) = start_server(Server::builder().authorize(policy));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_echo_allowed(&mut client);
+    assert_add_denied(&mut client);
+    // the connection stays usable after a denied request
+    assert_echo_allowed(&mut client);
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_closure_authorizer() {
+    let authorizer = |context: &ConnectionContext, kind: MessageKind| {
+        context.identity.is_none() && kind == MessageKind::Add
+    };
+    let (server, handle) = start_server(Server::builder().authorize(authorizer));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_add_allowed(&mut client);
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+#[cfg(feature = "tls")]
+mod mtls {
+    use super::*;
+    use certs::{TempDir, TestCa};
+    use embedded_recruitment_task::server::ServerBuilder;
+    use std::path::PathBuf;
+
+    struct Pki {
+        dir: TempDir,
+        ca_path: PathBuf,
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        ca: TestCa,
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Self {
+            let dir = TempDir::new(name);
+            let ca = TestCa::new("Test CA");
+            let ca_path = dir.write("ca.pem", &ca.pem());
+            let (cert, key) = ca.issue("server", &["localhost"]);
+            let cert_path = dir.write("server.pem", &cert);
+            let key_path = dir.write("server.key", &key);
+            Pki {
+                dir,
+                ca_path,
+                cert_path,
+                key_path,
+                ca,
+            }
+        }
+
+        fn builder(&self) -> ServerBuilder {
+            Server::builder()
+                .tls(&self.cert_path, &self.key_path)
+                .client_auth(&self.ca_path)
+        }
+
+        /// Client authenticating with a certificate issued by `ca`
+        fn client(&self, ca: &TestCa, common_name: &str, alt_names: &[&str]) -> client::Client {
+            let (cert, key) = ca.issue(common_name, alt_names);
+            let cert_path = self.dir.write(&format!("{}.pem", common_name), &cert);
+            let key_path = self.dir.write(&format!("{}.key", common_name), &key);
+            client::Client::new("localhost", 8080, 1000)
+                .with_tls_identity(&self.ca_path, cert_path, key_path)
+                .expect("Failed to load client certificate")
+        }
+    }
+
+    /// With TLS 1.3 the server checks the client certificate after the
+    /// client considers the handshake done, so a rejection may only show up
+    /// on the first exchange
+    fn assert_rejected(mut client: client::Client) {
+        let result = client.connect().and_then(|()| {
+            let message = client_message::Message::AddRequest(AddRequest { a: 1, b: 2 });
+            client.send(message)?;
+            client.receive()
+        });
+        assert!(result.is_err(), "Client without a valid certificate was served");
+    }
+
+    #[test]
+    #[serial]
+    fn test_mtls_rejects_clients_without_valid_certificate() {
+        let pki = Pki::new("mtls-reject");
+        let (server, handle) = start_server(pki.builder());
+
+        let anonymous = client::Client::new("localhost", 8080, 1000)
+            .with_tls(&pki.ca_path)
+            .expect("Failed to load CA bundle");
+        assert_rejected(anonymous);
+
+        let other_ca = TestCa::new("Other CA");
+        assert_rejected(pki.client(&other_ca, "intruder", &[]));
+
+        let mut client = pki.client(&pki.ca, "sensor-1", &[]);
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+        assert_add_allowed(&mut client);
+
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+        server.stop();
+        assert!(handle.join().is_ok(), "Server thread panicked or failed to join");
+    }
+
+    #[test]
+    #[serial]
+    fn test_mtls_identity_policy() {
+        let pki = Pki::new("mtls-policy");
+        let policy = AccessPolicy::new()
+            .allow("sensor-1", &[MessageKind::Echo])
+            .allow("admin.example.com", &[MessageKind::Echo, MessageKind::Add]);
+        let (server, handle) = start_server(pki.builder().authorize(policy));
+
+        // matched by common name
+        let mut sensor = pki.client(&pki.ca, "sensor-1", &[]);
+        assert!(sensor.connect().is_ok(), "Failed to connect to the server");
+        assert_echo_allowed(&mut sensor);
+        assert_add_denied(&mut sensor);
+
+        // matched by subject alternative name
+        let mut admin = pki.client(&pki.ca, "operator", &["admin.example.com"]);
+        assert!(admin.connect().is_ok(), "Failed to connect to the server");
+        assert_add_allowed(&mut admin);
+
+        // trusted, but not in the policy
+        let mut unknown = pki.client(&pki.ca, "sensor-2", &[]);
+        assert!(unknown.connect().is_ok(), "Failed to connect to the server");
+        assert_add_denied(&mut unknown);
+
+        assert!(sensor.disconnect().is_ok());
+        assert!(admin.disconnect().is_ok());
+        assert!(unknown.disconnect().is_ok());
+        server.stop();
+        assert!(handle.join().is_ok(), "Server thread panicked or failed to join");
+    }
+}
@@ -0,0 +1,127 @@
+use common::{start_server, stop};
+use embedded_recruitment_task::{
+    auth::{ConnectionContext, MessageKind},
+    message::{
+        batch_item, batch_result, client_message, server_message, AddRequest, AddResponse, BatchItem, BatchRequest,
+        BatchResult, EchoMessage, ErrorCode,
+    },
+    server::Server,
+};
+use serial_test::serial;
+
+mod client;
+mod common;
+
+fn add(a: i32, b: i32) -> BatchItem {
+    BatchItem {
+        request: Some(batch_item::Request::AddRequest(AddRequest { a, b })),
+    }
+}
+
+fn echo(content: &str) -> BatchItem {
+    BatchItem {
+        request: Some(batch_item::Request::EchoMessage(EchoMessage {
+            content: content.to_string(),
+        })),
+    }
+}
+
+/// Sends `requests` as one batch, returning the results
+fn send_batch(client: &mut client::Client, requests: Vec<BatchItem>) -> Vec<batch_result::Result> {
+    let message = client_message::Message::BatchRequest(BatchRequest { requests });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::BatchResponse(batch)) => batch
+            .results
+            .into_iter()
+            .map(|BatchResult { result }| result.expect("Empty result"))
+            .collect(),
+        other => panic!("Expected BatchResponse, but received {:?}", other),
+    }
+}
+
+fn error_code(result: &batch_result::Result) -> Option<ErrorCode> {
+    match result {
+        batch_result::Result::ErrorResponse(error) => Some(error.code()),
+        _ => None,
+    }
+}
+
+#[test]
+#[serial]
+fn test_batch_is_answered_in_order() {
+    let (server, handle) = start_server(Server::builder());
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let mut requests: Vec<BatchItem> = (0..20).map(|a| add(a, 1)).collect();
+    requests.insert(3, echo("in between"));
+    requests.insert(5, BatchItem { request: None });
+    let results = send_batch(&mut client, requests);
+
+    assert_eq!(results.len(), 22, "Wrong number of results");
+    assert_eq!(results[3], batch_result::Result::EchoMessage(EchoMessage { content: "in between".to_string() }));
+    assert_eq!(error_code(&results[5]), Some(ErrorCode::InvalidRequest), "Empty request was not refused");
+    let sums: Vec<i32> = results
+        .iter()
+        .filter_map(|result| match result {
+            batch_result::Result::AddResponse(AddResponse { result }) => Some(*result),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(sums, (1..21).collect::<Vec<_>>(), "Results are out of order");
+
+    // an empty batch gets an empty response
+    assert!(send_batch(&mut client, Vec::new()).is_empty());
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_batch_items_are_authorized() {
+    // Anyone may batch and echo, additions are not allowed
+    let policy = |_: &ConnectionContext, kind: MessageKind| kind != MessageKind::Add;
+    let (server, handle) = start_server(Server::builder().authorize(policy));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let results = send_batch(&mut client, vec![echo("first"), add(1, 2), echo("last")]);
+
+    assert!(matches!(&results[0], batch_result::Result::EchoMessage(echo) if echo.content == "first"));
+    assert_eq!(error_code(&results[1]), Some(ErrorCode::PermissionDenied), "Addition was not denied");
+    assert!(matches!(&results[2], batch_result::Result::EchoMessage(echo) if echo.content == "last"));
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_batch_size_is_limited() {
+    let (server, handle) = start_server(Server::builder().max_batch_size(4));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(send_batch(&mut client, (0..4).map(|a| add(a, a)).collect()).len(), 4);
+
+    let message = client_message::Message::BatchRequest(BatchRequest {
+        requests: (0..5).map(|a| add(a, a)).collect(),
+    });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code(), ErrorCode::BatchTooLarge);
+            assert!(error.message.contains("limit of 4"), "Unclear error: {}", error.message);
+        }
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+
+    // the connection stays usable
+    assert_eq!(send_batch(&mut client, vec![add(2, 3)]), vec![batch_result::Result::AddResponse(AddResponse { result: 5 })]);
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
@@ -0,0 +1,162 @@
+use common::{start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    cancel::CancellationToken,
+    message::{client_message, server_message, CancelRequest, EchoMessage, ErrorCode, StreamRequest, SubscribeCounter},
+    server::{FrameFormat, Server},
+    streaming::{StreamContext, StreamHandler, StreamStep},
+};
+use serial_test::serial;
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+mod client;
+mod common;
+
+fn receive(client: &mut client::Client) -> server_message::Message {
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Received an empty message")
+}
+
+fn subscribe(request_id: u64) -> client_message::Message {
+    client_message::Message::SubscribeCounter(SubscribeCounter {
+        request_id,
+        count: 0,
+        interval_ms: 10,
+        credits: u32::MAX,
+    })
+}
+
+fn cancel(request_id: u64) -> client_message::Message {
+    client_message::Message::CancelRequest(CancelRequest { request_id })
+}
+
+#[test]
+#[serial]
+fn test_cancel_stream() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.send(subscribe(5)).is_ok());
+    for value in 1..=3 {
+        match receive(&mut client) {
+            server_message::Message::StreamItem(item) => assert_eq!((item.request_id, item.value), (5, value)),
+            other => panic!("Expected StreamItem, but received {:?}", other),
+        }
+    }
+
+    // Items already on their way may still arrive before the end
+    assert!(client.send(cancel(5)).is_ok());
+    let end = loop {
+        match receive(&mut client) {
+            server_message::Message::StreamItem(item) => assert_eq!(item.request_id, 5),
+            server_message::Message::StreamEnd(end) => break end,
+            other => panic!("Expected a stream message, but received {:?}", other),
+        }
+    };
+    assert_eq!(end.request_id, 5);
+    assert_eq!(end.error.expect("No error").code(), ErrorCode::Cancelled);
+
+    // Cancelling a finished request is ignored, and its id may be used again
+    assert!(client.send(cancel(5)).is_ok());
+    let echo = EchoMessage {
+        content: "next".to_string(),
+    };
+    assert!(client.send(client_message::Message::EchoMessage(echo.clone())).is_ok());
+    assert_eq!(receive(&mut client), server_message::Message::EchoMessage(echo));
+    assert!(client.send(subscribe(5)).is_ok());
+    assert!(matches!(receive(&mut client), server_message::Message::StreamItem(item) if item.value == 1));
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+/// Counts up from the payload's first byte, handing its token to the test
+struct Tail {
+    value: i64,
+    tokens: Option<Sender<(u64, CancellationToken)>>,
+}
+
+impl StreamHandler for Tail {
+    fn next(&mut self, context: &StreamContext) -> StreamStep {
+        if let Some(tokens) = self.tokens.take() {
+            tokens.send((context.request_id(), context.token().clone())).expect("Test is gone");
+        }
+        self.value += 1;
+        StreamStep::Item(self.value)
+    }
+}
+
+fn stream_request(request_id: u64, method: &str) -> client_message::Message {
+    client_message::Message::StreamRequest(StreamRequest {
+        request_id,
+        method: method.to_string(),
+        payload: vec![],
+        credits: u32::MAX,
+    })
+}
+
+#[test]
+#[serial]
+fn test_stream_handlers_observe_cancellation() {
+    let (tokens, handed_out) = mpsc::channel();
+    let builder = Server::builder().stream_handler("tail", move |_: &_, payload: &[u8]| {
+        let value = payload.first().copied().unwrap_or(0).into();
+        let tokens = Some(tokens.clone());
+        Ok(Box::new(Tail { value, tokens }) as Box<dyn StreamHandler>)
+    });
+    let (server, handle) = start_length_delimited_server(builder);
+
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.send(stream_request(3, "tail")).is_ok());
+    assert!(matches!(receive(&mut client), server_message::Message::StreamItem(item) if item.value == 1));
+
+    // the token carries the deadline the client set from its timeout
+    let (request_id, token) = handed_out.recv().expect("Handler was not asked for an item");
+    assert_eq!(request_id, 3);
+    assert!(token.deadline().is_some(), "Token has no deadline");
+    assert!(!token.is_cancelled());
+
+    assert!(client.send(cancel(3)).is_ok());
+    let end = loop {
+        match receive(&mut client) {
+            server_message::Message::StreamItem(item) => assert_eq!(item.request_id, 3),
+            server_message::Message::StreamEnd(end) => break end,
+            other => panic!("Expected a stream message, but received {:?}", other),
+        }
+    };
+    assert_eq!(end.error.expect("No error").code(), ErrorCode::Cancelled);
+    assert!(token.is_cancelled(), "Handler's token was not cancelled");
+
+    // streams nobody registered end right away
+    assert!(client.send(stream_request(4, "head")).is_ok());
+    match receive(&mut client) {
+        server_message::Message::StreamEnd(end) => {
+            assert_eq!(end.request_id, 4);
+            assert_eq!(end.error.expect("No error").code(), ErrorCode::InvalidRequest);
+        }
+        other => panic!("Expected StreamEnd, but received {:?}", other),
+    }
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+fn test_cancellation_token() {
+    let token = CancellationToken::new();
+    let handler = token.clone();
+    assert!(!handler.is_cancelled());
+
+    let worker = thread::spawn(move || {
+        while !handler.is_cancelled() {
+            thread::yield_now();
+        }
+    });
+    token.cancel();
+    assert!(worker.join().is_ok());
+    assert!(token.is_cancelled());
+}
@@ -0,0 +1,976 @@
+//! Cross-checks of the no_std codec against the prost generated messages
+
+use embedded_recruitment_codec::{self as codec, framing, length_delimited, Message as _};
+use embedded_recruitment_task::{
+    cobs,
+    message::{
+        batch_item, batch_result, client_message, server_message, AddRequest, AddResponse, AuthRequest, AuthResponse,
+        BatchItem, BatchRequest, BatchResponse, BatchResult, CancelRequest, ClientMessage, EchoMessage, ErrorCode,
+        ErrorResponse, Event, Hello, JoinRoom, KvCompareAndSwap, KvDelete, KvGet, KvIncrement, KvResponse, KvSet,
+        LeaveRoom, Notification, Ping, Pong, Publish, PublishResponse, RangeSumRequest, RoomEvent, RoomEventKind,
+        RoomMessage, ServerMessage, Signature, StreamCredit, StreamEnd, StreamItem, StreamRequest, Subscribe,
+        SubscribeCounter, SubscriptionResponse, Unsubscribe,
+    },
+};
+use pretty_assertions::assert_eq;
+use prost::Message;
+
+fn signature() -> Signature {
+    Signature {
+        nonce: 300,
+        timestamp_ms: 1_700_000_000_000,
+        mac: vec![0xAB; 32],
+    }
+}
+
+fn client_messages() -> Vec<ClientMessage> {
+    let mut messages = vec![ClientMessage::default()];
+    for content in ["", "Hello, World!", "héllo wörld ✓", &"x".repeat(300)] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: content.to_string(),
+            })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    for (a, b) in [(0, 0), (10, 20), (-1, 1), (i32::MIN, i32::MAX), (127, 128), (0, -300)] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::AddRequest(AddRequest { a, b })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    messages.push(ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 })),
+        deadline_ms: 0,
+        signature: Some(signature()),
+    });
+    let auth_requests = [
+        ("", "", vec![]),
+        ("secret", "", vec![]),
+        ("", "sensor-1", vec![0, 1, 255]),
+    ];
+    for (token, client_id, challenge_response) in auth_requests {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::AuthRequest(AuthRequest {
+                token: token.to_string(),
+                client_id: client_id.to_string(),
+                challenge_response,
+            })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    for sequence in [0, 1, u64::MAX] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::Pong(Pong { sequence })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    for hello in hellos() {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::Hello(hello)),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    let items = vec![
+        BatchItem {
+            request: Some(batch_item::Request::AddRequest(AddRequest { a: 1, b: -2 })),
+        },
+        // empty items are still encoded
+        BatchItem { request: None },
+        BatchItem {
+            request: Some(batch_item::Request::EchoMessage(EchoMessage {
+                content: "batched".to_string(),
+            })),
+        },
+    ];
+    for requests in [Vec::new(), items] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::BatchRequest(BatchRequest { requests })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    for (start, end) in [(0, 0), (1, 100), (-5, i64::MAX), (i64::MIN, -1)] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::RangeSumRequest(RangeSumRequest {
+                request_id: 7,
+                start,
+                end,
+                credits: 16,
+            })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    for (count, credits) in [(0, 0), (10, u32::MAX)] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::SubscribeCounter(SubscribeCounter {
+                request_id: u64::MAX,
+                count,
+                interval_ms: 250,
+                credits,
+            })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    messages.push(ClientMessage {
+        message: Some(client_message::Message::StreamCredit(StreamCredit {
+            request_id: 7,
+            credits: 300,
+        })),
+        deadline_ms: 0,
+        signature: None,
+    });
+    for (method, payload) in [("", vec![]), ("tail", vec![0, 0xFF])] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::StreamRequest(StreamRequest {
+                request_id: 9,
+                method: method.to_string(),
+                payload,
+                credits: 4,
+            })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    for request_id in [0, 7, u64::MAX] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::CancelRequest(CancelRequest { request_id })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    for deadline_ms in [1, 1_700_000_000_000, u64::MAX] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 })),
+            deadline_ms,
+            signature: Some(signature()),
+        });
+    }
+    for topic in ["", "sensors/kitchen/temp", "sensors/+/temp", "#"] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::Subscribe(Subscribe {
+                topic: topic.to_string(),
+            })),
+            deadline_ms: 0,
+            signature: None,
+        });
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::Unsubscribe(Unsubscribe {
+                topic: topic.to_string(),
+            })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    for (topic, payload) in [("", vec![]), ("sensors/kitchen/temp", vec![0, 21, 255]), ("alerts", vec![7; 300])] {
+        messages.push(ClientMessage {
+            message: Some(client_message::Message::Publish(Publish {
+                topic: topic.to_string(),
+                payload,
+            })),
+            deadline_ms: 0,
+            signature: None,
+        });
+    }
+    let room_requests = [
+        client_message::Message::JoinRoom(JoinRoom::default()),
+        client_message::Message::JoinRoom(JoinRoom {
+            room: "field-team".to_string(),
+            name: "Kim".to_string(),
+        }),
+        client_message::Message::LeaveRoom(LeaveRoom {
+            room: "field-team".to_string(),
+        }),
+        client_message::Message::RoomMessage(RoomMessage {
+            room: "field-team".to_string(),
+            content: "on site ✓".to_string(),
+        }),
+    ];
+    let kv_requests = [
+        client_message::Message::KvGet(KvGet {
+            key: "config/interval".to_string(),
+        }),
+        client_message::Message::KvSet(KvSet {
+            key: "config/interval".to_string(),
+            value: b"500".to_vec(),
+            ttl_ms: 60_000,
+        }),
+        client_message::Message::KvDelete(KvDelete::default()),
+        client_message::Message::KvCompareAndSwap(KvCompareAndSwap {
+            key: "leader".to_string(),
+            expected_version: 300,
+            value: vec![0, 0xFF],
+            ttl_ms: 0,
+        }),
+        client_message::Message::KvIncrement(KvIncrement {
+            key: "boots".to_string(),
+            delta: -2,
+        }),
+        client_message::Message::KvIncrement(KvIncrement {
+            key: "boots".to_string(),
+            delta: i64::MAX,
+        }),
+    ];
+    for request in room_requests.into_iter().chain(kv_requests) {
+        // signed and with a deadline, as oneof fields come after the envelope's
+        messages.push(ClientMessage {
+            message: Some(request),
+            deadline_ms: 1_700_000_000_000,
+            signature: Some(signature()),
+        });
+    }
+    messages
+}
+
+fn hellos() -> Vec<Hello> {
+    vec![
+        Hello::default(),
+        Hello {
+            protocol_version: 1,
+            features: vec!["heartbeat".to_string(), String::new(), "zstd".to_string()],
+            name: "sensor-fw/2.1.0".to_string(),
+        },
+        Hello {
+            protocol_version: u32::MAX,
+            features: vec![],
+            name: String::new(),
+        },
+    ]
+}
+
+fn server_messages() -> Vec<ServerMessage> {
+    let mut messages = vec![ServerMessage::default()];
+    for content in ["", "Hello, World!", &"y".repeat(200)] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::EchoMessage(EchoMessage {
+                content: content.to_string(),
+            })),
+            signature: None,
+        });
+    }
+    for result in [0, 30, -30, i32::MIN, i32::MAX] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::AddResponse(AddResponse { result })),
+            signature: None,
+        });
+    }
+    for (authenticated, challenge) in [(true, vec![]), (false, vec![0; 32])] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::AuthResponse(AuthResponse {
+                authenticated,
+                challenge,
+            })),
+            signature: None,
+        });
+    }
+    for hello in hellos() {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::Hello(hello)),
+            signature: None,
+        });
+    }
+    for sequence in [0, 300] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::Ping(Ping { sequence })),
+            signature: None,
+        });
+    }
+    let results = vec![
+        BatchResult {
+            result: Some(batch_result::Result::AddResponse(AddResponse { result: 0 })),
+        },
+        BatchResult {
+            result: Some(batch_result::Result::ErrorResponse(ErrorResponse {
+                code: ErrorCode::PermissionDenied as i32,
+                message: "AddRequest is not allowed".to_string(),
+            })),
+        },
+        BatchResult {
+            result: Some(batch_result::Result::EchoMessage(EchoMessage {
+                content: "batched".to_string(),
+            })),
+        },
+        BatchResult { result: None },
+    ];
+    for results in [Vec::new(), results] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::BatchResponse(BatchResponse { results })),
+            signature: None,
+        });
+    }
+    for (topic, subscribed) in [("", false), ("sensors/#", true)] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::SubscriptionResponse(SubscriptionResponse {
+                topic: topic.to_string(),
+                subscribed,
+            })),
+            signature: None,
+        });
+    }
+    for receivers in [0, 1, u32::MAX] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::PublishResponse(PublishResponse { receivers })),
+            signature: None,
+        });
+    }
+    for (topic, payload) in [("", vec![]), ("sensors/kitchen/temp", vec![0, 21, 255])] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::Event(Event {
+                topic: topic.to_string(),
+                payload,
+            })),
+            signature: None,
+        });
+    }
+    for (kind, payload) in [("", vec![]), ("reboot", vec![1, 0, 200])] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::Notification(Notification {
+                kind: kind.to_string(),
+                payload,
+            })),
+            signature: None,
+        });
+    }
+    let room_events = [
+        RoomEvent::default(),
+        RoomEvent {
+            room: "field-team".to_string(),
+            kind: RoomEventKind::MessagePosted as i32,
+            member: "Kim".to_string(),
+            content: "on site".to_string(),
+            replayed: true,
+        },
+        RoomEvent {
+            room: "field-team".to_string(),
+            kind: RoomEventKind::MemberLeft as i32,
+            member: "127.0.0.1:40000".to_string(),
+            content: String::new(),
+            replayed: false,
+        },
+    ];
+    for event in room_events {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::RoomEvent(event)),
+            signature: Some(signature()),
+        });
+    }
+    let kv_responses = [
+        KvResponse::default(),
+        KvResponse {
+            key: "config/interval".to_string(),
+            found: true,
+            value: b"500".to_vec(),
+            version: 1 << 40,
+            applied: true,
+        },
+        KvResponse {
+            key: "leader".to_string(),
+            found: true,
+            value: vec![1],
+            version: 7,
+            applied: false,
+        },
+    ];
+    for response in kv_responses {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::KvResponse(response)),
+            signature: Some(signature()),
+        });
+    }
+    for value in [0, 1, -1, i64::MIN, i64::MAX] {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::StreamItem(StreamItem { request_id: 7, value })),
+            signature: None,
+        });
+    }
+    let stream_errors = [
+        None,
+        Some(ErrorResponse::default()),
+        Some(ErrorResponse {
+            code: ErrorCode::TooManyStreams as i32,
+            message: "no more than 8 streams may be open at a time".to_string(),
+        }),
+    ];
+    for error in stream_errors {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::StreamEnd(StreamEnd { request_id: 7, error })),
+            signature: None,
+        });
+    }
+    messages.push(ServerMessage {
+        message: Some(server_message::Message::AddResponse(AddResponse { result: 3 })),
+        signature: Some(signature()),
+    });
+    messages.push(ServerMessage {
+        message: None,
+        signature: Some(Signature::default()),
+    });
+    let errors = [
+        (ErrorCode::Unspecified as i32, ""),
+        (ErrorCode::PermissionDenied as i32, "denied"),
+        // a code added in a newer server
+        (99, ""),
+    ];
+    for (code, message) in errors {
+        messages.push(ServerMessage {
+            message: Some(server_message::Message::ErrorResponse(ErrorResponse {
+                code,
+                message: message.to_string(),
+            })),
+            signature: None,
+        });
+    }
+    messages
+}
+
+fn to_codec_client(message: &ClientMessage) -> codec::ClientMessage<'_> {
+    codec::ClientMessage {
+        message: message.message.as_ref().map(|message| match message {
+            client_message::Message::EchoMessage(echo) => {
+                codec::client_message::Message::EchoMessage(codec::EchoMessage { content: &echo.content })
+            }
+            client_message::Message::AddRequest(add) => {
+                codec::client_message::Message::AddRequest(codec::AddRequest { a: add.a, b: add.b })
+            }
+            client_message::Message::AuthRequest(auth) => {
+                codec::client_message::Message::AuthRequest(codec::AuthRequest {
+                    token: &auth.token,
+                    client_id: &auth.client_id,
+                    challenge_response: &auth.challenge_response,
+                })
+            }
+            client_message::Message::Pong(pong) => {
+                codec::client_message::Message::Pong(codec::Pong { sequence: pong.sequence })
+            }
+            client_message::Message::Hello(hello) => codec::client_message::Message::Hello(to_codec_hello(hello)),
+            client_message::Message::BatchRequest(batch) => {
+                codec::client_message::Message::BatchRequest(to_codec_batch_request(batch))
+            }
+            client_message::Message::RangeSumRequest(range_sum) => {
+                codec::client_message::Message::RangeSumRequest(codec::RangeSumRequest {
+                    request_id: range_sum.request_id,
+                    start: range_sum.start,
+                    end: range_sum.end,
+                    credits: range_sum.credits,
+                })
+            }
+            client_message::Message::SubscribeCounter(subscribe) => {
+                codec::client_message::Message::SubscribeCounter(codec::SubscribeCounter {
+                    request_id: subscribe.request_id,
+                    count: subscribe.count,
+                    interval_ms: subscribe.interval_ms,
+                    credits: subscribe.credits,
+                })
+            }
+            client_message::Message::StreamCredit(credit) => {
+                codec::client_message::Message::StreamCredit(codec::StreamCredit {
+                    request_id: credit.request_id,
+                    credits: credit.credits,
+                })
+            }
+            client_message::Message::StreamRequest(stream) => {
+                codec::client_message::Message::StreamRequest(codec::StreamRequest {
+                    request_id: stream.request_id,
+                    method: &stream.method,
+                    payload: &stream.payload,
+                    credits: stream.credits,
+                })
+            }
+            client_message::Message::CancelRequest(cancel) => {
+                codec::client_message::Message::CancelRequest(codec::CancelRequest {
+                    request_id: cancel.request_id,
+                })
+            }
+            client_message::Message::Subscribe(subscribe) => {
+                codec::client_message::Message::Subscribe(codec::Subscribe { topic: &subscribe.topic })
+            }
+            client_message::Message::Unsubscribe(unsubscribe) => {
+                codec::client_message::Message::Unsubscribe(codec::Unsubscribe {
+                    topic: &unsubscribe.topic,
+                })
+            }
+            client_message::Message::Publish(publish) => codec::client_message::Message::Publish(codec::Publish {
+                topic: &publish.topic,
+                payload: &publish.payload,
+            }),
+            client_message::Message::JoinRoom(join) => codec::client_message::Message::JoinRoom(codec::JoinRoom {
+                room: &join.room,
+                name: &join.name,
+            }),
+            client_message::Message::LeaveRoom(leave) => {
+                codec::client_message::Message::LeaveRoom(codec::LeaveRoom { room: &leave.room })
+            }
+            client_message::Message::RoomMessage(message) => {
+                codec::client_message::Message::RoomMessage(codec::RoomMessage {
+                    room: &message.room,
+                    content: &message.content,
+                })
+            }
+            client_message::Message::KvGet(get) => codec::client_message::Message::KvGet(codec::KvGet { key: &get.key }),
+            client_message::Message::KvSet(set) => codec::client_message::Message::KvSet(codec::KvSet {
+                key: &set.key,
+                value: &set.value,
+                ttl_ms: set.ttl_ms,
+            }),
+            client_message::Message::KvDelete(delete) => {
+                codec::client_message::Message::KvDelete(codec::KvDelete { key: &delete.key })
+            }
+            client_message::Message::KvCompareAndSwap(swap) => {
+                codec::client_message::Message::KvCompareAndSwap(codec::KvCompareAndSwap {
+                    key: &swap.key,
+                    expected_version: swap.expected_version,
+                    value: &swap.value,
+                    ttl_ms: swap.ttl_ms,
+                })
+            }
+            client_message::Message::KvIncrement(increment) => {
+                codec::client_message::Message::KvIncrement(codec::KvIncrement {
+                    key: &increment.key,
+                    delta: increment.delta,
+                })
+            }
+        }),
+        deadline_ms: message.deadline_ms,
+        signature: message.signature.as_ref().map(to_codec_signature),
+    }
+}
+
+fn to_codec_server(message: &ServerMessage) -> codec::ServerMessage<'_> {
+    codec::ServerMessage {
+        message: message.message.as_ref().map(|message| match message {
+            server_message::Message::EchoMessage(echo) => {
+                codec::server_message::Message::EchoMessage(codec::EchoMessage { content: &echo.content })
+            }
+            server_message::Message::AddResponse(add) => {
+                codec::server_message::Message::AddResponse(codec::AddResponse { result: add.result })
+            }
+            server_message::Message::ErrorResponse(error) => {
+                codec::server_message::Message::ErrorResponse(to_codec_error(error))
+            }
+            server_message::Message::AuthResponse(auth) => {
+                codec::server_message::Message::AuthResponse(codec::AuthResponse {
+                    authenticated: auth.authenticated,
+                    challenge: &auth.challenge,
+                })
+            }
+            server_message::Message::Ping(ping) => {
+                codec::server_message::Message::Ping(codec::Ping { sequence: ping.sequence })
+            }
+            server_message::Message::Hello(hello) => codec::server_message::Message::Hello(to_codec_hello(hello)),
+            server_message::Message::BatchResponse(batch) => {
+                codec::server_message::Message::BatchResponse(to_codec_batch_response(batch))
+            }
+            server_message::Message::StreamItem(item) => {
+                codec::server_message::Message::StreamItem(codec::StreamItem {
+                    request_id: item.request_id,
+                    value: item.value,
+                })
+            }
+            server_message::Message::StreamEnd(end) => codec::server_message::Message::StreamEnd(codec::StreamEnd {
+                request_id: end.request_id,
+                error: end.error.as_ref().map(to_codec_error),
+            }),
+            server_message::Message::SubscriptionResponse(subscription) => {
+                codec::server_message::Message::SubscriptionResponse(codec::SubscriptionResponse {
+                    topic: &subscription.topic,
+                    subscribed: subscription.subscribed,
+                })
+            }
+            server_message::Message::PublishResponse(publish) => {
+                codec::server_message::Message::PublishResponse(codec::PublishResponse {
+                    receivers: publish.receivers,
+                })
+            }
+            server_message::Message::Event(event) => codec::server_message::Message::Event(codec::Event {
+                topic: &event.topic,
+                payload: &event.payload,
+            }),
+            server_message::Message::Notification(notification) => {
+                codec::server_message::Message::Notification(codec::Notification {
+                    kind: &notification.kind,
+                    payload: &notification.payload,
+                })
+            }
+            server_message::Message::RoomEvent(event) => codec::server_message::Message::RoomEvent(codec::RoomEvent {
+                room: &event.room,
+                kind: event.kind,
+                member: &event.member,
+                content: &event.content,
+                replayed: event.replayed,
+            }),
+            server_message::Message::KvResponse(response) => codec::server_message::Message::KvResponse(codec::KvResponse {
+                key: &response.key,
+                found: response.found,
+                value: &response.value,
+                version: response.version,
+                applied: response.applied,
+            }),
+        }),
+        signature: message.signature.as_ref().map(to_codec_signature),
+    }
+}
+
+fn to_codec_hello(hello: &Hello) -> codec::Hello<'_> {
+    // leaked, the test data lives until the end of the test anyway
+    let features: Vec<&str> = hello.features.iter().map(String::as_str).collect();
+    codec::Hello {
+        protocol_version: hello.protocol_version,
+        features: codec::RepeatedStr::Slice(Vec::leak(features)),
+        name: &hello.name,
+    }
+}
+
+fn to_codec_batch_request(batch: &BatchRequest) -> codec::BatchRequest<'_> {
+    let requests: Vec<codec::BatchItem> = batch
+        .requests
+        .iter()
+        .map(|item| codec::BatchItem {
+            request: item.request.as_ref().map(|request| match request {
+                batch_item::Request::EchoMessage(echo) => {
+                    codec::batch_item::Request::EchoMessage(codec::EchoMessage { content: &echo.content })
+                }
+                batch_item::Request::AddRequest(add) => {
+                    codec::batch_item::Request::AddRequest(codec::AddRequest { a: add.a, b: add.b })
+                }
+            }),
+        })
+        .collect();
+    codec::BatchRequest {
+        requests: codec::RepeatedMessage::Slice(Vec::leak(requests)),
+    }
+}
+
+fn to_codec_batch_response(batch: &BatchResponse) -> codec::BatchResponse<'_> {
+    let results: Vec<codec::BatchResult> = batch
+        .results
+        .iter()
+        .map(|result| codec::BatchResult {
+            result: result.result.as_ref().map(|result| match result {
+                batch_result::Result::EchoMessage(echo) => {
+                    codec::batch_result::Result::EchoMessage(codec::EchoMessage { content: &echo.content })
+                }
+                batch_result::Result::AddResponse(add) => {
+                    codec::batch_result::Result::AddResponse(codec::AddResponse { result: add.result })
+                }
+                batch_result::Result::ErrorResponse(error) => {
+                    codec::batch_result::Result::ErrorResponse(to_codec_error(error))
+                }
+            }),
+        })
+        .collect();
+    codec::BatchResponse {
+        results: codec::RepeatedMessage::Slice(Vec::leak(results)),
+    }
+}
+
+fn to_codec_error(error: &ErrorResponse) -> codec::ErrorResponse<'_> {
+    codec::ErrorResponse {
+        code: error.code,
+        message: &error.message,
+    }
+}
+
+fn to_codec_signature(signature: &Signature) -> codec::Signature<'_> {
+    codec::Signature {
+        nonce: signature.nonce,
+        timestamp_ms: signature.timestamp_ms,
+        mac: &signature.mac,
+    }
+}
+
+/// Checks that the codec encodes `codec` to exactly the bytes prost encodes
+/// `prost` to, and that either side decodes what the other encoded
+macro_rules! assert_matches_prost {
+    ($prost:expr, $codec:expr) => {{
+        let (prost, codec) = ($prost, $codec);
+        let expected = prost.encode_to_vec();
+        let mut buf = [0u8; 512];
+        let len = codec.encode(&mut buf).expect("Failed to encode");
+        assert_eq!(&buf[..len], expected.as_slice(), "Encoding differs for {:?}", prost);
+        assert_eq!(codec.encoded_len(), len);
+        assert_eq!(decode_codec(&codec, &expected).expect("Failed to decode prost bytes"), codec);
+        assert_eq!(&decode_prost(prost, &buf[..len]), prost);
+    }};
+}
+
+/// Decodes `buf` as the codec message type of `_like`
+fn decode_codec<'a, M: codec::Message<'a>>(_like: &M, buf: &'a [u8]) -> Result<M, codec::Error> {
+    M::decode(buf)
+}
+
+/// Decodes `buf` as the prost message type of `_like`
+fn decode_prost<M: Message + Default>(_like: &M, buf: &[u8]) -> M {
+    M::decode(buf).expect("Failed to decode codec bytes")
+}
+
+/// Checks the message inside the oneof `$prost` on its own, giving the name of
+/// its type; the match has to name every variant, so new messages are not missed
+macro_rules! assert_oneof_matches_prost {
+    ($oneof:ident, $prost:expr, $codec:expr, [$($variant:ident),* $(,)?]) => {
+        match ($prost, $codec) {
+            $(
+                (Some($oneof::Message::$variant(prost)), Some(codec::$oneof::Message::$variant(codec))) => {
+                    assert_matches_prost!(prost, codec);
+                    Some(stringify!($variant))
+                }
+                (Some($oneof::Message::$variant(_)), codec) => {
+                    panic!("Converted {} to {:?}", stringify!($variant), codec)
+                }
+            )*
+            (None, _) => None,
+        }
+    };
+}
+
+#[test]
+fn test_every_message_matches_prost_on_its_own() {
+    let mut checked = std::collections::BTreeSet::new();
+    for message in client_messages() {
+        let codec_message = to_codec_client(&message);
+        #[rustfmt::skip]
+        let variant = assert_oneof_matches_prost!(client_message, &message.message, codec_message.message, [
+            EchoMessage, AddRequest, AuthRequest, Pong, Hello, BatchRequest, RangeSumRequest, SubscribeCounter,
+            StreamCredit, CancelRequest, Subscribe, Unsubscribe, Publish, JoinRoom, LeaveRoom, RoomMessage, KvGet,
+            KvSet, KvDelete, KvCompareAndSwap, KvIncrement, StreamRequest,
+        ]);
+        checked.extend(variant);
+        if let Some(signature) = &message.signature {
+            assert_matches_prost!(signature, to_codec_signature(signature));
+            checked.insert("Signature");
+        }
+        if let Some(client_message::Message::BatchRequest(batch)) = &message.message {
+            for (item, codec_item) in batch.requests.iter().zip(to_codec_batch_request(batch).requests) {
+                assert_matches_prost!(item, codec_item);
+                checked.insert("BatchItem");
+            }
+        }
+    }
+    for message in server_messages() {
+        let codec_message = to_codec_server(&message);
+        #[rustfmt::skip]
+        let variant = assert_oneof_matches_prost!(server_message, &message.message, codec_message.message, [
+            EchoMessage, AddResponse, ErrorResponse, AuthResponse, Ping, Hello, BatchResponse, StreamItem, StreamEnd,
+            SubscriptionResponse, PublishResponse, Event, Notification, RoomEvent, KvResponse,
+        ]);
+        checked.extend(variant);
+        if let Some(server_message::Message::BatchResponse(batch)) = &message.message {
+            for (result, codec_result) in batch.results.iter().zip(to_codec_batch_response(batch).results) {
+                assert_matches_prost!(result, codec_result);
+                checked.insert("BatchResult");
+            }
+        }
+    }
+    // 22 client and 15 server messages, EchoMessage and Hello go both ways,
+    // and the ones only found inside them
+    assert_eq!(checked.len(), 22 + 15 - 2 + 3, "Not every message was checked: {:?}", checked);
+}
+
+#[test]
+fn test_client_messages_match_prost() {
+    for message in client_messages() {
+        let expected = message.encode_to_vec();
+        let codec_message = to_codec_client(&message);
+
+        let mut buf = [0u8; 512];
+        let len = codec_message.encode(&mut buf).expect("Failed to encode");
+        assert_eq!(&buf[..len], expected.as_slice(), "Encoding differs for {:?}", message);
+        assert_eq!(codec_message.encoded_len(), len);
+
+        let decoded = codec::ClientMessage::decode(&expected).expect("Failed to decode prost bytes");
+        assert_eq!(decoded, codec_message);
+        assert_eq!(ClientMessage::decode(&buf[..len]).unwrap(), message);
+    }
+}
+
+#[test]
+fn test_server_messages_match_prost() {
+    for message in server_messages() {
+        let expected = message.encode_to_vec();
+        let codec_message = to_codec_server(&message);
+
+        let mut buf = [0u8; 512];
+        let len = codec_message.encode(&mut buf).expect("Failed to encode");
+        assert_eq!(&buf[..len], expected.as_slice(), "Encoding differs for {:?}", message);
+        assert_eq!(codec_message.encoded_len(), len);
+
+        let decoded = codec::ServerMessage::decode(&expected).expect("Failed to decode prost bytes");
+        assert_eq!(decoded, codec_message);
+        assert_eq!(ServerMessage::decode(&buf[..len]).unwrap(), message);
+    }
+}
+
+#[test]
+fn test_codec_errors() {
+    let message = codec::ClientMessage {
+        message: Some(codec::client_message::Message::EchoMessage(codec::EchoMessage {
+            content: "does not fit",
+        })),
+        deadline_ms: 0,
+        signature: None,
+    };
+    let mut small = [0u8; 4];
+    assert_eq!(message.encode(&mut small), Err(codec::Error::BufferTooSmall));
+
+    let mut buf = [0u8; 64];
+    let len = message.encode(&mut buf).unwrap();
+    assert_eq!(codec::ClientMessage::decode(&buf[..len - 1]), Err(codec::Error::Truncated));
+
+    // Invalid UTF-8 in the echo content
+    let bytes = [0x0A, 0x03, 0x0A, 0x01, 0xFF];
+    assert_eq!(codec::ClientMessage::decode(&bytes), Err(codec::Error::InvalidUtf8));
+}
+
+#[test]
+fn test_codec_skips_unknown_fields() {
+    let mut bytes = ServerMessage {
+        message: Some(server_message::Message::AddResponse(AddResponse { result: 7 })),
+        signature: None,
+    }
+    .encode_to_vec();
+    // Field 31 as varint, field 30 as string: e.g. added in a newer server
+    bytes.extend_from_slice(&[0xF8, 0x01, 0x2A, 0xF2, 0x01, 0x02, b'h', b'i']);
+
+    let decoded = codec::ServerMessage::decode(&bytes).expect("Failed to skip unknown fields");
+    assert_eq!(
+        decoded.message,
+        Some(codec::server_message::Message::AddResponse(codec::AddResponse { result: 7 }))
+    );
+}
+
+#[test]
+fn test_framing_matches_server() {
+    let payloads: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![0, 0],
+        (1..=255).collect(),
+        (0..700).map(|i| (i % 5) as u8).collect(),
+        ClientMessage {
+            message: Some(client_message::Message::AddRequest(AddRequest { a: -1, b: 0 })),
+            deadline_ms: 0,
+            signature: None,
+        }
+        .encode_to_vec(),
+    ];
+
+    let mut decoder = framing::FrameDecoder::<{ framing::max_frame_len(700) }>::new();
+    for payload in payloads {
+        let expected = cobs::encode_frame(&payload);
+        let mut frame = [0u8; framing::max_frame_len(700)];
+        let len = framing::encode_frame(&payload, &mut frame).expect("Failed to encode frame");
+        assert_eq!(&frame[..len], expected.as_slice());
+        assert!(len <= framing::max_frame_len(payload.len()));
+
+        let mut decoded = None;
+        for &byte in &expected {
+            if let Some(result) = decoder.push(byte) {
+                decoded = Some(result.expect("Failed to decode frame").to_vec());
+            }
+        }
+        assert_eq!(decoded, Some(payload));
+    }
+}
+
+#[test]
+fn test_frame_decoder_errors() {
+    let mut decoder = framing::FrameDecoder::<16>::new();
+
+    let mut frame = cobs::encode_frame(b"abc");
+    frame[2] ^= 0x10;
+    let results: Vec<_> = frame
+        .iter()
+        .filter_map(|&byte| decoder.push(byte).map(|result| result.map(|payload| payload.to_vec())))
+        .collect();
+    assert_eq!(results, vec![Err(codec::Error::CrcMismatch)]);
+
+    // Too large for the buffer, the next frame is decoded again
+    let mut stream = cobs::encode_frame(&[7; 40]);
+    stream.extend(cobs::encode_frame(b"ok"));
+    let results: Vec<_> = stream
+        .iter()
+        .filter_map(|&byte| decoder.push(byte).map(|result| result.map(|payload| payload.to_vec())))
+        .collect();
+    assert_eq!(results, vec![Err(codec::Error::BufferTooSmall), Ok(b"ok".to_vec())]);
+
+    let mut out = [0u8; 4];
+    assert_eq!(framing::encode_frame(b"abc", &mut out), Err(codec::Error::BufferTooSmall));
+}
+
+/// `payload` with prost's length delimiter in front
+fn prost_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::new();
+    prost::encode_length_delimiter(payload.len(), &mut frame).unwrap();
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[test]
+fn test_length_delimited_framing_matches_prost() {
+    let payloads: Vec<Vec<u8>> = vec![vec![], vec![0], (0..=255).collect(), vec![0x80; 300]];
+    let messages = client_messages().into_iter().map(|message| message.encode_to_vec());
+
+    let mut decoder = length_delimited::FrameDecoder::<512>::new();
+    for payload in payloads.into_iter().chain(messages) {
+        let expected = prost_frame(&payload);
+        let mut frame = [0u8; length_delimited::max_frame_len(512)];
+        let len = length_delimited::encode_frame(&payload, &mut frame).expect("Failed to encode frame");
+        assert_eq!(&frame[..len], expected.as_slice());
+        assert_eq!(len, length_delimited::max_frame_len(payload.len()));
+        assert_eq!(length_delimited::decode_frame(&expected), Ok((payload.as_slice(), len)));
+
+        let mut decoded = None;
+        for &byte in &expected {
+            if let Some(result) = decoder.push(byte) {
+                assert!(decoded.is_none(), "More than one frame decoded");
+                decoded = Some(result.expect("Failed to decode frame").to_vec());
+            }
+        }
+        assert_eq!(decoded, Some(payload));
+    }
+
+    // the codec reads what prost writes and the other way around
+    let message = ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a: 40, b: 2 })),
+        deadline_ms: 0,
+        signature: None,
+    };
+    let prost_frame = message.encode_length_delimited_to_vec();
+    let (payload, len) = length_delimited::decode_frame(&prost_frame).expect("Failed to decode prost frame");
+    assert_eq!(len, prost_frame.len());
+    assert_eq!(codec::ClientMessage::decode(payload), Ok(to_codec_client(&message)));
+    let mut frame = [0u8; 64];
+    let len = length_delimited::encode_frame(payload, &mut frame).unwrap();
+    assert_eq!(ClientMessage::decode_length_delimited(&frame[..len]).unwrap(), message);
+}
+
+#[test]
+fn test_length_delimited_decoder_errors() {
+    let mut decoder = length_delimited::FrameDecoder::<16>::new();
+    let mut push_all = |bytes: &[u8]| -> Vec<Result<Vec<u8>, codec::Error>> {
+        bytes
+            .iter()
+            .filter_map(|&byte| decoder.push(byte).map(|result| result.map(|payload| payload.to_vec())))
+            .collect()
+    };
+
+    // Too large for the buffer: reported once the prefix is in, the next frame is decoded again
+    let mut stream = prost_frame(&[7; 40]);
+    stream.extend(prost_frame(b"ok"));
+    assert_eq!(push_all(&stream[..1]), vec![Err(codec::Error::BufferTooSmall)]);
+    assert_eq!(push_all(&stream[1..]), vec![Ok(b"ok".to_vec())]);
+
+    // A prefix longer than any varint
+    assert_eq!(push_all(&[0xFF; 10]), vec![Err(codec::Error::InvalidVarint)]);
+
+    assert_eq!(length_delimited::decode_frame(&[]), Err(codec::Error::Truncated));
+    assert_eq!(length_delimited::decode_frame(&[3, b'a', b'b']), Err(codec::Error::Truncated));
+    assert_eq!(length_delimited::decode_frame(&[0xFF; 11]), Err(codec::Error::InvalidVarint));
+    let mut out = [0u8; 3];
+    assert_eq!(length_delimited::encode_frame(b"abc", &mut out), Err(codec::Error::BufferTooSmall));
+}
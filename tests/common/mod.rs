@@ -0,0 +1,48 @@
+#![allow(dead_code)]
+
+use crate::client;
+use embedded_recruitment_task::server::{FrameFormat, Server, ServerBuilder};
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+/// Runs `server` on a thread of its own until it is stopped
+pub fn run(server: Arc<Server>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        server.run().expect("Server encountered an error");
+    })
+}
+
+/// Binds the server built by `builder` to localhost:8080 and runs it
+pub fn start_server(builder: ServerBuilder) -> (Arc<Server>, JoinHandle<()>) {
+    let server = Arc::new(builder.bind("localhost:8080").expect("Failed to start server"));
+    let handle = run(Arc::clone(&server));
+    (server, handle)
+}
+
+/// Starts the server like [`start_server`], prefixing messages with their
+/// length for tests where several of them may arrive back to back
+pub fn start_length_delimited_server(builder: ServerBuilder) -> (Arc<Server>, JoinHandle<()>) {
+    start_server(builder.frame_format(FrameFormat::LengthDelimited))
+}
+
+/// Stops the server and waits for its thread to finish
+pub fn stop(server: Arc<Server>, handle: JoinHandle<()>) {
+    server.stop();
+    assert!(handle.join().is_ok(), "Server thread panicked or failed to join");
+}
+
+/// Client connected to the server on localhost:8080
+pub fn connect() -> client::Client {
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client
+}
+
+/// Client connected to a server started with [`start_length_delimited_server`]
+pub fn connect_length_delimited() -> client::Client {
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client
+}
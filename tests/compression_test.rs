@@ -0,0 +1,126 @@
+#![cfg(any(feature = "zstd", feature = "lz4"))]
+
+use common::{start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    compression::{self, Algorithm, FLAG_RAW},
+    handshake::PROTOCOL_VERSION,
+    message::{client_message, server_message, EchoMessage, ErrorCode, Hello},
+    server::{FrameFormat, Server},
+};
+use serial_test::serial;
+
+mod client;
+mod common;
+
+fn hello() -> Hello {
+    Hello {
+        protocol_version: PROTOCOL_VERSION,
+        features: Vec::new(),
+        name: "sensor-fw/2.1.0".to_string(),
+    }
+}
+
+fn echo(client: &mut client::Client, content: String) {
+    let message = client_message::Message::EchoMessage(EchoMessage { content: content.clone() });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, content),
+        other => panic!("Expected EchoMessage, but received {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn test_compressed_round_trip() {
+    let (server, handle) = start_length_delimited_server(Server::builder().compression(256));
+
+    let mut client = client::Client::new("localhost", 8080, 1000)
+        .with_frame_format(FrameFormat::LengthDelimited)
+        .with_compression(256);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let response = client.handshake(hello()).expect("Handshake failed");
+    // only the algorithm picked is confirmed, the first one compiled in
+    assert_eq!(response.features, vec![Algorithm::SUPPORTED[0].feature().to_string()]);
+
+    // payloads on both sides of the threshold
+    echo(&mut client, "short".to_string());
+    echo(&mut client, "temperature=21.5;".repeat(1000));
+    echo(&mut client, "short again".to_string());
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_compression_is_optional() {
+    let (server, handle) = start_length_delimited_server(Server::builder().compression(256));
+
+    // A client not offering any algorithm keeps talking plain frames
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let response = client.handshake(hello()).expect("Handshake failed");
+    assert!(response.features.is_empty(), "Unexpected features {:?}", response.features);
+    echo(&mut client, "temperature=21.5;".repeat(1000));
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_frame_that_does_not_decompress_is_answered() {
+    let (server, handle) = start_length_delimited_server(Server::builder().compression(256));
+
+    let mut client = client::Client::new("localhost", 8080, 1000)
+        .with_frame_format(FrameFormat::LengthDelimited)
+        .with_compression(256);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client.handshake(hello()).expect("Handshake failed");
+
+    // a length-delimited frame with an unknown compression flag
+    assert!(client.send_raw(&[3, 0xff, 1, 2]).is_ok(), "Failed to send frame");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code, ErrorCode::InvalidRequest as i32, "Unexpected error {:?}", error);
+        }
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+    // the connection is still served
+    echo(&mut client, "still there".to_string());
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+fn test_compress_and_decompress() {
+    for &algorithm in Algorithm::SUPPORTED {
+        let short = compression::compress(algorithm, b"short", 64);
+        assert_eq!(short, [&[FLAG_RAW][..], b"short"].concat(), "Short payload was compressed");
+
+        let payload = "temperature=21.5;".repeat(1000).into_bytes();
+        let frame = compression::compress(algorithm, &payload, 64);
+        assert_ne!(frame[0], FLAG_RAW, "{} did not compress", algorithm.feature());
+        assert!(frame.len() < payload.len() / 10, "{} compressed poorly", algorithm.feature());
+        assert_eq!(compression::decompress(&frame, payload.len()).unwrap(), payload);
+
+        // a frame inflating beyond the limit is refused
+        assert!(compression::decompress(&frame, payload.len() - 1).is_err(), "Decompressed beyond the limit");
+    }
+    assert!(compression::decompress(&[], 64).is_err(), "Accepted a frame without flag");
+    assert!(compression::decompress(&[0xff, 1, 2], 64).is_err(), "Accepted an unknown flag");
+}
+
+#[test]
+fn test_select() {
+    let mut features = vec!["heartbeat".to_string()];
+    features.extend(Algorithm::SUPPORTED.iter().rev().map(|algorithm| algorithm.feature().to_string()));
+    let selected = compression::select(&mut features);
+    let last = *Algorithm::SUPPORTED.last().unwrap();
+    assert_eq!(selected, Some(last), "The first algorithm in the list is used");
+    assert_eq!(features, vec!["heartbeat".to_string(), last.feature().to_string()]);
+
+    let mut features = vec!["heartbeat".to_string()];
+    assert_eq!(compression::select(&mut features), None);
+}
@@ -0,0 +1,134 @@
+use common::{start_server, stop};
+use embedded_recruitment_task::{
+    ip_filter::{Cidr, IpFilter},
+    message::{client_message, server_message, AddRequest, ErrorCode},
+    metrics::Counter,
+    server::Server,
+};
+use serial_test::serial;
+use std::{
+    net::IpAddr,
+    thread,
+    time::Duration,
+};
+
+mod client;
+mod common;
+
+/// Connects a client and makes sure it is served
+fn connect_served() -> client::Client {
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let message = client_message::Message::AddRequest(AddRequest { a: 1, b: 1 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::AddResponse(add_response)) => assert_eq!(add_response.result, 2),
+        other => panic!("Expected AddResponse, but received {:?}", other),
+    }
+    client
+}
+
+/// Connects a client the server turns away as busy
+fn assert_busy() {
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    match client.receive().expect("Failed to receive busy error").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code(), ErrorCode::ServerBusy, "Wrong error code")
+        }
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+    assert!(client.receive().is_err(), "Connection was not closed");
+}
+
+#[test]
+#[serial]
+fn test_max_connections() {
+    let (server, handle) = start_server(Server::builder().max_connections(2));
+
+    let mut first = connect_served();
+    let _second = connect_served();
+    assert_busy();
+    assert_eq!(server.metrics().get(Counter::ConnectionsRejected), 1);
+
+    // room for a new client once one leaves
+    assert!(first.disconnect().is_ok());
+    thread::sleep(Duration::from_millis(200));
+    let _third = connect_served();
+
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_max_connections_per_ip() {
+    let (server, handle) = start_server(Server::builder().max_connections(10).max_connections_per_ip(1));
+
+    let _first = connect_served();
+    assert_busy();
+    assert_busy();
+    assert_eq!(server.metrics().get(Counter::ConnectionsRejected), 2);
+
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_ip_filter_at_accept() {
+    let loopback: Cidr = "127.0.0.0/8".parse().unwrap();
+
+    let filter = IpFilter::new().allow("0.0.0.0/0".parse().unwrap()).deny(loopback);
+    let (server, handle) = start_server(Server::builder().ip_filter(filter));
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.receive().is_err(), "Denied connection was not closed");
+    assert_eq!(server.metrics().get(Counter::ConnectionsDenied), 1);
+    stop(server, handle);
+
+    let filter = IpFilter::new().allow("10.0.0.0/8".parse().unwrap());
+    let (server, handle) = start_server(Server::builder().ip_filter(filter));
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.receive().is_err(), "Connection from outside the allowed range was not closed");
+    stop(server, handle);
+
+    let filter = IpFilter::new().allow(loopback);
+    let (server, handle) = start_server(Server::builder().ip_filter(filter));
+    let _client = connect_served();
+    assert_eq!(server.metrics().get(Counter::ConnectionsDenied), 0);
+    stop(server, handle);
+}
+
+#[test]
+fn test_cidr_ranges() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+    let private: Cidr = "10.0.0.0/8".parse().unwrap();
+    assert!(private.contains(ip("10.1.2.3")));
+    assert!(!private.contains(ip("11.0.0.1")));
+    // IPv4 clients of a dual stack socket
+    assert!(private.contains(ip("::ffff:10.0.0.1")));
+    assert!(!private.contains(ip("fd00::1")));
+
+    let single: Cidr = "192.168.1.1".parse().unwrap();
+    assert_eq!(single.to_string(), "192.168.1.1/32");
+    assert!(single.contains(ip("192.168.1.1")));
+    assert!(!single.contains(ip("192.168.1.2")));
+
+    let unique_local: Cidr = "fd00::/8".parse().unwrap();
+    assert!(unique_local.contains(ip("fd12:3456::1")));
+    assert!(!unique_local.contains(ip("fe80::1")));
+
+    let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+    assert!(everything.contains(ip("203.0.113.7")));
+
+    for invalid in ["10.0.0.0/33", "::/129", "10.0.0/8", "host/8", "10.0.0.0/"] {
+        assert!(invalid.parse::<Cidr>().is_err(), "Parsed invalid range {}", invalid);
+    }
+
+    let filter = IpFilter::new().allow(private).deny("10.0.0.0/24".parse().unwrap());
+    assert!(filter.is_allowed(ip("10.0.1.1")));
+    assert!(!filter.is_allowed(ip("10.0.0.1")), "Deny must win over allow");
+    assert!(!filter.is_allowed(ip("192.0.2.1")));
+    assert!(IpFilter::new().is_allowed(ip("192.0.2.1")));
+}
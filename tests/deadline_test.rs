@@ -0,0 +1,111 @@
+use common::{start_length_delimited_server, start_server, stop};
+use embedded_recruitment_task::{
+    cancel::{self, CancellationToken, StopReason},
+    message::{client_message, server_message, AddRequest, AddResponse, ClientMessage, ErrorCode, SubscribeCounter},
+    metrics::Counter,
+    server::{FrameFormat, Server},
+};
+use serial_test::serial;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod client;
+mod common;
+
+fn receive(client: &mut client::Client) -> server_message::Message {
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Received an empty message")
+}
+
+/// Milliseconds since the unix epoch, `offset` from now
+fn unix_ms(offset: Duration, ahead: bool) -> u64 {
+    let now = SystemTime::now();
+    let at = if ahead { now + offset } else { now - offset };
+    at.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+fn add(a: i32, b: i32, deadline_ms: u64) -> ClientMessage {
+    ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a, b })),
+        deadline_ms,
+        signature: None,
+    }
+}
+
+#[test]
+#[serial]
+fn test_expired_request_is_skipped() {
+    let (server, handle) = start_server(Server::builder());
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.send_envelope(&add(1, 2, unix_ms(Duration::from_secs(1), false))).is_ok());
+    match receive(&mut client) {
+        server_message::Message::ErrorResponse(error) => assert_eq!(error.code(), ErrorCode::DeadlineExceeded),
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+    assert_eq!(server.metrics().get(Counter::DeadlinesExceeded), 1);
+
+    // requests without a deadline or one still ahead are answered
+    assert!(client.send_envelope(&add(1, 2, 0)).is_ok());
+    assert_eq!(receive(&mut client), server_message::Message::AddResponse(AddResponse { result: 3 }));
+    assert!(client.send_envelope(&add(2, 3, unix_ms(Duration::from_secs(10), true))).is_ok());
+    assert_eq!(receive(&mut client), server_message::Message::AddResponse(AddResponse { result: 5 }));
+    // as are those of the client, which sets the deadline from its timeout
+    assert!(client.send(client_message::Message::AddRequest(AddRequest { a: 3, b: 4 })).is_ok());
+    assert_eq!(receive(&mut client), server_message::Message::AddResponse(AddResponse { result: 7 }));
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_stream_ends_at_deadline() {
+    // the items follow each other without pause, so they are length delimited
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    // the client's timeout of 200ms is the deadline of an endless counter
+    let mut client = client::Client::new("localhost", 8080, 200).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let started = Instant::now();
+    let subscribe = SubscribeCounter {
+        request_id: 3,
+        count: 0,
+        interval_ms: 20,
+        credits: u32::MAX,
+    };
+    assert!(client.send(client_message::Message::SubscribeCounter(subscribe)).is_ok());
+    let mut items = 0;
+    let end = loop {
+        match receive(&mut client) {
+            server_message::Message::StreamItem(item) => {
+                assert_eq!(item.request_id, 3);
+                items += 1;
+            }
+            server_message::Message::StreamEnd(end) => break end,
+            other => panic!("Expected a stream message, but received {:?}", other),
+        }
+    };
+    assert!(items > 0, "Stream ended before its first item");
+    assert!(started.elapsed() >= Duration::from_millis(150), "Stream ended early");
+    assert_eq!(end.request_id, 3);
+    assert_eq!(end.error.expect("No error").code(), ErrorCode::DeadlineExceeded);
+    assert_eq!(server.metrics().get(Counter::DeadlinesExceeded), 1);
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+fn test_token_deadline() {
+    assert_eq!(cancel::deadline_from_unix_ms(0), None);
+    let now = Instant::now();
+    let past = cancel::deadline_from_unix_ms(unix_ms(Duration::from_secs(5), false)).expect("No deadline");
+    assert!(past >= now && past <= Instant::now());
+
+    let token = CancellationToken::with_deadline(Some(now + Duration::from_millis(50)));
+    assert_eq!(token.stop_reason(now), None);
+    assert_eq!(token.stop_reason(now + Duration::from_millis(50)), Some(StopReason::DeadlineExceeded));
+    token.cancel();
+    assert_eq!(token.stop_reason(now), Some(StopReason::Cancelled));
+}
@@ -0,0 +1,149 @@
+use common::{connect, start_length_delimited_server, start_server, stop};
+use embedded_recruitment_task::{
+    message::{client_message, server_message, ClientMessage, EchoMessage, ErrorCode},
+    metrics::Counter,
+    server::{OversizedFrameAction, FrameFormat, Server},
+};
+use prost::Message;
+use serial_test::serial;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod client;
+mod common;
+
+const MAX_FRAME_SIZE: usize = 100;
+
+/// Echo request whose encoded envelope is exactly `len` bytes long, with the
+/// deadline the client sets
+fn echo_of_len(len: usize) -> client_message::Message {
+    let deadline = SystemTime::now() + Duration::from_secs(1);
+    let deadline_ms = deadline.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let message = (0..len)
+        .map(|content_len| {
+            client_message::Message::EchoMessage(EchoMessage {
+                content: "x".repeat(content_len),
+            })
+        })
+        .find(|message| {
+            let envelope = ClientMessage {
+                message: Some(message.clone()),
+                deadline_ms,
+                signature: None,
+            };
+            envelope.encoded_len() == len
+        });
+    message.expect("No echo request of that length")
+}
+
+fn assert_echoed(client: &mut client::Client, message: client_message::Message) {
+    assert!(client.send(message.clone()).is_ok(), "Failed to send message");
+    let client_message::Message::EchoMessage(echo) = message else {
+        unreachable!()
+    };
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::EchoMessage(response)) => assert_eq!(response, echo),
+        other => panic!("Expected EchoMessage, but received {:?}", other),
+    }
+}
+
+fn assert_too_large(client: &mut client::Client) {
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code(), ErrorCode::FrameTooLarge);
+            assert!(
+                error.message.contains(&format!("limit of {} bytes", MAX_FRAME_SIZE)),
+                "Error does not state the limit: {}",
+                error.message
+            );
+        }
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn test_oversized_frame_is_drained() {
+    let builder = Server::builder().max_frame_size(MAX_FRAME_SIZE, OversizedFrameAction::Drain);
+    let (server, handle) = start_length_delimited_server(builder);
+
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Frames up to the limit are served
+    assert_echoed(&mut client, echo_of_len(MAX_FRAME_SIZE - 1));
+    assert_echoed(&mut client, echo_of_len(MAX_FRAME_SIZE));
+
+    // One byte over is rejected, and the connection stays in sync
+    assert!(client.send(echo_of_len(MAX_FRAME_SIZE + 1)).is_ok(), "Failed to send message");
+    assert_too_large(&mut client);
+    assert_echoed(&mut client, echo_of_len(20));
+
+    // A large frame sent in several writes is skipped as a whole
+    let large = echo_of_len(5000);
+    assert!(client.send(large).is_ok(), "Failed to send message");
+    assert_too_large(&mut client);
+    assert_echoed(&mut client, echo_of_len(MAX_FRAME_SIZE));
+
+    assert_eq!(server.metrics().get(Counter::FramesTooLarge), 2);
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_oversized_frame_closes_connection() {
+    let builder = Server::builder().max_frame_size(MAX_FRAME_SIZE, OversizedFrameAction::Close);
+    let (server, handle) = start_length_delimited_server(builder);
+
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_echoed(&mut client, echo_of_len(MAX_FRAME_SIZE));
+
+    // Only the header of a frame announcing a gigabyte, rejected before it arrives
+    let mut header = Vec::new();
+    prost::encode_length_delimiter(1 << 30, &mut header).unwrap();
+    assert!(client.send_raw(&header).is_ok(), "Failed to send frame header");
+    assert_too_large(&mut client);
+    assert!(client.receive().is_err(), "Connection was not closed");
+
+    assert_eq!(server.metrics().get(Counter::FramesTooLarge), 1);
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_oversized_raw_message_is_drained() {
+    let builder = Server::builder().max_frame_size(MAX_FRAME_SIZE, OversizedFrameAction::Drain);
+    let (server, handle) = start_server(builder);
+
+    // a raw message is as long as a read, which the limit applies to
+    let mut client = connect();
+    assert_echoed(&mut client, echo_of_len(MAX_FRAME_SIZE));
+    assert!(client.send(echo_of_len(MAX_FRAME_SIZE + 1)).is_ok(), "Failed to send message");
+    assert_too_large(&mut client);
+    assert_echoed(&mut client, echo_of_len(20));
+
+    // the rest of a large message is not taken for messages of its own
+    assert!(client.send(echo_of_len(5000)).is_ok(), "Failed to send message");
+    assert_too_large(&mut client);
+    assert_echoed(&mut client, echo_of_len(MAX_FRAME_SIZE));
+
+    assert_eq!(server.metrics().get(Counter::FramesTooLarge), 2);
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_oversized_raw_message_closes_connection() {
+    let builder = Server::builder().max_frame_size(MAX_FRAME_SIZE, OversizedFrameAction::Close);
+    let (server, handle) = start_server(builder);
+
+    let mut client = connect();
+    assert!(client.send(echo_of_len(2000)).is_ok(), "Failed to send message");
+    assert_too_large(&mut client);
+    assert!(client.receive().is_err(), "Connection was not closed");
+
+    assert_eq!(server.metrics().get(Counter::FramesTooLarge), 1);
+    stop(server, handle);
+}
@@ -0,0 +1,272 @@
+use common::{start_server, stop};
+use embedded_recruitment_task::{
+    auth::{ConnectionContext, MessageKind},
+    handshake::{self, FEATURE_LENGTH_DELIMITED, PROTOCOL_VERSION},
+    message::{
+        client_message, server_message, AddRequest, ClientMessage, ErrorCode, Hello, JoinRoom, Notification,
+        RangeSumRequest, ServerMessage, Subscribe, SubscribeCounter,
+    },
+    metrics::Counter,
+    server::{FrameFormat, Server},
+};
+use prost::Message;
+use serial_test::serial;
+use std::{
+    io::ErrorKind,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+
+fn exchange(client: &mut client::Client, message: client_message::Message) -> server_message::Message {
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Received an empty message")
+}
+
+fn hello(protocol_version: u32, features: &[&str]) -> client_message::Message {
+    client_message::Message::Hello(Hello {
+        protocol_version,
+        features: features.iter().map(|feature| feature.to_string()).collect(),
+        name: "sensor-fw/2.1.0".to_string(),
+    })
+}
+
+#[test]
+#[serial]
+fn test_hello_negotiates_version_and_features() {
+    // Additions are only for clients that negotiated the "sums" feature
+    let policy = |context: &ConnectionContext, kind: MessageKind| {
+        kind != MessageKind::Add || context.protocol.as_ref().is_some_and(|protocol| protocol.supports("sums"))
+    };
+    let builder = Server::builder()
+        .name("test-server/1.0")
+        .heartbeat(Duration::from_secs(60), 3)
+        .feature("sums")
+        .authorize(policy);
+    let (server, handle) = start_server(builder);
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let add = || client_message::Message::AddRequest(AddRequest { a: 2, b: 3 });
+    match exchange(&mut client, add()) {
+        server_message::Message::ErrorResponse(error) => assert_eq!(error.code(), ErrorCode::PermissionDenied),
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+
+    // A newer client is answered with the version the server speaks
+    let response = exchange(&mut client, hello(PROTOCOL_VERSION + 1, &["zstd", "sums", "heartbeat"]));
+    assert_eq!(
+        response,
+        server_message::Message::Hello(Hello {
+            protocol_version: PROTOCOL_VERSION,
+            features: vec!["heartbeat".to_string(), "sums".to_string()],
+            name: "test-server/1.0".to_string(),
+        }),
+        "Wrong handshake response"
+    );
+    match exchange(&mut client, add()) {
+        server_message::Message::AddResponse(add_response) => assert_eq!(add_response.result, 5),
+        other => panic!("Expected AddResponse, but received {:?}", other),
+    }
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_unsupported_version_is_rejected() {
+    let (server, handle) = start_server(Server::builder());
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    match exchange(&mut client, hello(0, &[])) {
+        server_message::Message::ErrorResponse(error) => {
+            assert_eq!(error.code(), ErrorCode::UnsupportedVersion);
+            assert!(error.message.contains("version 0"), "Unclear error: {}", error.message);
+        }
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+    assert!(client.receive().is_err(), "Connection was not closed");
+    assert_eq!(server.metrics().get(Counter::HandshakesRejected), 1);
+
+    stop(server, handle);
+}
+
+/// Two AddRequests with their length in front, sent in one write
+fn two_length_delimited_requests() -> Vec<u8> {
+    [(1, 2), (3, 4)]
+        .into_iter()
+        .flat_map(|(a, b)| {
+            ClientMessage {
+                message: Some(client_message::Message::AddRequest(AddRequest { a, b })),
+                ..ClientMessage::default()
+            }
+            .encode_length_delimited_to_vec()
+        })
+        .collect()
+}
+
+fn assert_sums(client: &mut client::Client, expected: &[i32]) {
+    for &sum in expected {
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::AddResponse(add_response)) => assert_eq!(add_response.result, sum),
+            other => panic!("Expected AddResponse, but received {:?}", other),
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn test_hello_switches_to_length_delimited_framing() {
+    let (server, handle) = start_server(Server::builder());
+
+    // messages are raw until the client asks for length prefixes
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let hello = Hello {
+        protocol_version: PROTOCOL_VERSION,
+        features: vec![FEATURE_LENGTH_DELIMITED.to_string()],
+        name: String::new(),
+    };
+    let response = client.handshake(hello.clone()).expect("Handshake failed");
+    assert_eq!(response.features, vec![FEATURE_LENGTH_DELIMITED.to_string()]);
+    // requests sent back to back are told apart from now on
+    assert!(client.send_raw(&two_length_delimited_requests()).is_ok(), "Failed to send messages");
+    assert_sums(&mut client, &[3, 7]);
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // other clients still speak raw messages
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let add = client_message::Message::AddRequest(AddRequest { a: 2, b: 3 });
+    match exchange(&mut client, add) {
+        server_message::Message::AddResponse(add_response) => assert_eq!(add_response.result, 5),
+        other => panic!("Expected AddResponse, but received {:?}", other),
+    }
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+
+    // a server configured for length prefixes expects them from the start,
+    // and confirms the feature to clients asking for it
+    let (server, handle) = start_server(Server::builder().frame_format(FrameFormat::LengthDelimited));
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.send_raw(&two_length_delimited_requests()).is_ok(), "Failed to send messages");
+    assert_sums(&mut client, &[3, 7]);
+    let response = client.handshake(hello).expect("Handshake failed");
+    assert_eq!(response.features, vec![FEATURE_LENGTH_DELIMITED.to_string()]);
+    assert!(client.send_raw(&two_length_delimited_requests()).is_ok(), "Failed to send messages");
+    assert_sums(&mut client, &[3, 7]);
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+fn assert_sum(client: &mut client::Client, a: i32, b: i32) {
+    match exchange(client, client_message::Message::AddRequest(AddRequest { a, b })) {
+        server_message::Message::AddResponse(add_response) => assert_eq!(add_response.result, a + b),
+        other => panic!("Expected AddResponse, but received {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn test_raw_connections_get_nothing_they_did_not_ask_for() {
+    let (server, handle) = start_server(Server::builder().heartbeat(Duration::from_millis(100), 10));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let requests = [
+        client_message::Message::Subscribe(Subscribe {
+            topic: "sensors/#".to_string(),
+        }),
+        client_message::Message::JoinRoom(JoinRoom {
+            room: "lobby".to_string(),
+            name: String::new(),
+        }),
+        client_message::Message::RangeSumRequest(RangeSumRequest {
+            request_id: 1,
+            start: 0,
+            end: 100,
+            credits: 0,
+        }),
+        client_message::Message::SubscribeCounter(SubscribeCounter {
+            request_id: 2,
+            count: 0,
+            interval_ms: 10,
+            credits: 0,
+        }),
+    ];
+    for request in requests {
+        match exchange(&mut client, request.clone()) {
+            server_message::Message::ErrorResponse(error) => assert_eq!(error.code(), ErrorCode::FramingRequired),
+            other => panic!("Expected ErrorResponse for {:?}, but received {:?}", request, other),
+        }
+    }
+    let connection = server.connections().pop().expect("Connection not listed");
+    assert_eq!(connection.frame_format, Some(FrameFormat::Raw));
+    let notification = ServerMessage {
+        message: Some(server_message::Message::Notification(Notification {
+            kind: "reboot".to_string(),
+            payload: vec![],
+        })),
+        signature: None,
+    };
+    let error = server.send_to(connection.id, notification).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+    // nor is it pinged, the responses keep coming one per read
+    thread::sleep(Duration::from_millis(300));
+    assert_sum(&mut client, 2, 3);
+    assert_eq!(server.connections()[0].round_trip_time, None, "Raw connection was pinged");
+
+    // once the messages are length delimited, the server may send them on its own
+    let hello = Hello {
+        protocol_version: PROTOCOL_VERSION,
+        features: vec![FEATURE_LENGTH_DELIMITED.to_string()],
+        name: String::new(),
+    };
+    assert!(client.handshake(hello).is_ok(), "Handshake failed");
+    let subscribe = client_message::Message::Subscribe(Subscribe {
+        topic: "sensors/#".to_string(),
+    });
+    match exchange(&mut client, subscribe) {
+        server_message::Message::SubscriptionResponse(response) => assert!(response.subscribed),
+        other => panic!("Expected SubscriptionResponse, but received {:?}", other),
+    }
+    thread::sleep(Duration::from_millis(250));
+    assert_sum(&mut client, 2, 3);
+    let pinged = Instant::now();
+    while server.connections()[0].round_trip_time.is_none() {
+        assert!(pinged.elapsed() < Duration::from_secs(1), "Heartbeats not answered");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.connections()[0].frame_format, Some(FrameFormat::LengthDelimited));
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+#[test]
+fn test_negotiate() {
+    let server_features = vec!["heartbeat".to_string(), "signing".to_string()];
+    let hello = Hello {
+        protocol_version: handshake::MIN_PROTOCOL_VERSION,
+        features: vec!["signing".to_string(), "unknown".to_string()],
+        name: "client".to_string(),
+    };
+    let protocol = handshake::negotiate(&hello, &server_features).expect("Handshake failed");
+    assert_eq!(protocol.version, handshake::MIN_PROTOCOL_VERSION);
+    assert_eq!(protocol.features, vec!["signing".to_string()]);
+    assert_eq!(protocol.peer_name, "client");
+    assert!(protocol.supports("signing") && !protocol.supports("heartbeat"));
+
+    let old = Hello {
+        protocol_version: handshake::MIN_PROTOCOL_VERSION - 1,
+        ..hello
+    };
+    assert!(handshake::negotiate(&old, &server_features).is_err(), "Accepted an old version");
+}
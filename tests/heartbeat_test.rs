@@ -0,0 +1,86 @@
+use common::{start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    message::{client_message, server_message, AddRequest, ServerMessage},
+    metrics::Counter,
+    server::{FrameFormat, Server},
+};
+use prost::Message;
+use serial_test::serial;
+use std::{
+    io::Read,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+
+#[test]
+#[serial]
+fn test_heartbeats_are_answered() {
+    let (server, handle) = start_length_delimited_server(Server::builder().heartbeat(Duration::from_millis(50), 3));
+
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // The client answers the pings arriving between the responses
+    for a in 0..6 {
+        thread::sleep(Duration::from_millis(60));
+        let message = client_message::Message::AddRequest(AddRequest { a, b: 1 });
+        assert!(client.send(message).is_ok(), "Failed to send message");
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::AddResponse(add_response)) => assert_eq!(add_response.result, a + 1),
+            other => panic!("Expected AddResponse, but received {:?}", other),
+        }
+    }
+
+    let connections = server.connections();
+    assert_eq!(connections.len(), 1, "Wrong number of open connections");
+    let round_trip_time = connections[0].round_trip_time.expect("No round trip time was recorded");
+    assert!(round_trip_time < Duration::from_secs(1), "Implausible round trip time {:?}", round_trip_time);
+    assert_eq!(server.metrics().get(Counter::HeartbeatTimeouts), 0);
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_missed_heartbeats_disconnect() {
+    let (server, handle) = start_length_delimited_server(Server::builder().heartbeat(Duration::from_millis(50), 3));
+
+    // A peer that reads the pings but never answers them
+    let mut stream = TcpStream::connect("localhost:8080").expect("Failed to connect to the server");
+    let started = Instant::now();
+    let mut sequences = Vec::new();
+    let mut buffer = Vec::new();
+    loop {
+        assert!(started.elapsed() < Duration::from_secs(5), "Connection was not closed");
+        let mut chunk = [0u8; 64];
+        let read = stream.read(&mut chunk).expect("Failed to read from the server");
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        while let Ok(message) = ServerMessage::decode_length_delimited(buffer.as_slice()) {
+            let len = message.encoded_len();
+            buffer.drain(..prost::length_delimiter_len(len) + len);
+            match message.message {
+                Some(server_message::Message::Ping(ping)) => sequences.push(ping.sequence),
+                other => panic!("Expected Ping, but received {:?}", other),
+            }
+        }
+    }
+
+    assert_eq!(sequences, vec![1, 2, 3], "Wrong pings before the disconnect");
+    assert!(started.elapsed() >= Duration::from_millis(150), "Disconnected too early");
+    assert_eq!(server.metrics().get(Counter::HeartbeatTimeouts), 1);
+    // the connection is unlisted right after the socket is closed
+    while !server.connections().is_empty() {
+        assert!(started.elapsed() < Duration::from_secs(5), "Closed connection is still listed");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    stop(server, handle);
+}
@@ -0,0 +1,251 @@
+use common::{connect, start_server, stop};
+use embedded_recruitment_task::{
+    kv::{KvError, Store, Update},
+    message::{
+        client_message, server_message, ErrorCode, KvCompareAndSwap, KvDelete, KvGet, KvIncrement, KvResponse, KvSet,
+    },
+    metrics::Counter,
+    server::Server,
+};
+use serial_test::serial;
+use std::{
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+
+fn request(client: &mut client::Client, message: client_message::Message) -> KvResponse {
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    match response.message {
+        Some(server_message::Message::KvResponse(response)) => response,
+        other => panic!("Expected KvResponse, but received {:?}", other),
+    }
+}
+
+fn get(client: &mut client::Client, key: &str) -> KvResponse {
+    request(client, client_message::Message::KvGet(KvGet { key: key.to_string() }))
+}
+
+fn set(client: &mut client::Client, key: &str, value: &[u8], ttl_ms: u64) -> KvResponse {
+    let set = KvSet {
+        key: key.to_string(),
+        value: value.to_vec(),
+        ttl_ms,
+    };
+    request(client, client_message::Message::KvSet(set))
+}
+
+fn compare_and_swap(client: &mut client::Client, key: &str, expected_version: u64, value: &[u8]) -> KvResponse {
+    let swap = KvCompareAndSwap {
+        key: key.to_string(),
+        expected_version,
+        value: value.to_vec(),
+        ttl_ms: 0,
+    };
+    request(client, client_message::Message::KvCompareAndSwap(swap))
+}
+
+fn increment(client: &mut client::Client, key: &str, delta: i64) -> KvResponse {
+    let increment = KvIncrement {
+        key: key.to_string(),
+        delta,
+    };
+    request(client, client_message::Message::KvIncrement(increment))
+}
+
+fn expect_invalid_request(client: &mut client::Client, message: client_message::Message) {
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    match response.message {
+        Some(server_message::Message::ErrorResponse(error)) => assert_eq!(error.code(), ErrorCode::InvalidRequest),
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn test_kv_operations() {
+    let (server, handle) = start_server(Server::builder());
+
+    let mut client = connect();
+    let missing = get(&mut client, "interval");
+    assert!(!missing.found && !missing.applied);
+    assert_eq!(missing.version, 0);
+
+    let first = set(&mut client, "interval", b"500", 0);
+    assert!(first.found && first.applied);
+    assert_eq!(first.value, b"500");
+    assert_eq!(get(&mut client, "interval"), KvResponse { applied: false, ..first.clone() });
+
+    // a swap against an outdated version answers with the current entry
+    let second = set(&mut client, "interval", b"250", 0);
+    assert!(second.version > first.version);
+    let refused = compare_and_swap(&mut client, "interval", first.version, b"1000");
+    assert_eq!(refused, KvResponse { applied: false, ..second.clone() });
+    let swapped = compare_and_swap(&mut client, "interval", second.version, b"1000");
+    assert!(swapped.applied);
+    assert_eq!(swapped.value, b"1000");
+
+    // version 0 only swaps keys that do not exist yet
+    assert!(!compare_and_swap(&mut client, "interval", 0, b"1").applied);
+    assert!(compare_and_swap(&mut client, "leader", 0, b"device-1").applied);
+
+    let deleted = request(
+        &mut client,
+        client_message::Message::KvDelete(KvDelete {
+            key: "interval".to_string(),
+        }),
+    );
+    assert!(deleted.applied && !deleted.found);
+    assert!(!get(&mut client, "interval").found);
+
+    assert_eq!(increment(&mut client, "boots", 1).value, b"1");
+    assert_eq!(increment(&mut client, "boots", -3).value, b"-2");
+    // values set by the server are shared with all connections
+    server.kv().set("boots", b"41".to_vec(), None, Instant::now()).expect("Failed to set");
+    assert_eq!(increment(&mut client, "boots", 1).value, b"42");
+
+    let invalid = [
+        client_message::Message::KvGet(KvGet::default()),
+        client_message::Message::KvIncrement(KvIncrement {
+            key: "leader".to_string(),
+            delta: 1,
+        }),
+        client_message::Message::KvIncrement(KvIncrement {
+            key: "boots".to_string(),
+            delta: i64::MAX,
+        }),
+    ];
+    for message in invalid {
+        expect_invalid_request(&mut client, message);
+    }
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_concurrent_compare_and_swap() {
+    const CLIENTS: usize = 8;
+    const UPDATES: usize = 25;
+    let (server, handle) = start_server(Server::builder());
+
+    // every client adds one to the counter UPDATES times, retrying whenever
+    // another one got in between its read and its swap
+    let workers: Vec<JoinHandle<()>> = (0..CLIENTS)
+        .map(|_| {
+            thread::spawn(|| {
+                let mut client = connect();
+                for _ in 0..UPDATES {
+                    let mut current = get(&mut client, "counter");
+                    loop {
+                        let count: usize = if current.found {
+                            String::from_utf8(current.value.clone()).unwrap().parse().unwrap()
+                        } else {
+                            0
+                        };
+                        let next = (count + 1).to_string();
+                        let response = compare_and_swap(&mut client, "counter", current.version, next.as_bytes());
+                        if response.applied {
+                            break;
+                        }
+                        current = response;
+                    }
+                }
+                assert!(client.disconnect().is_ok());
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("Client thread panicked");
+    }
+
+    let entry = server.kv().get("counter", Instant::now()).expect("Counter is missing");
+    assert_eq!(entry.value, (CLIENTS * UPDATES).to_string().into_bytes());
+
+    // increments need no retries
+    let workers: Vec<JoinHandle<()>> = (0..CLIENTS)
+        .map(|_| {
+            thread::spawn(|| {
+                let mut client = connect();
+                for _ in 0..UPDATES {
+                    assert!(increment(&mut client, "increments", 1).applied);
+                }
+                assert!(client.disconnect().is_ok());
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("Client thread panicked");
+    }
+    let entry = server.kv().get("increments", Instant::now()).expect("Counter is missing");
+    assert_eq!(entry.value, (CLIENTS * UPDATES).to_string().into_bytes());
+
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_keys_expire() {
+    let (server, handle) = start_server(Server::builder());
+
+    let mut client = connect();
+    assert!(set(&mut client, "session", b"abc", 100).found);
+    assert!(set(&mut client, "config", b"1", 0).found);
+    // an increment keeps the time to live
+    assert_eq!(increment(&mut client, "session-count", 1).value, b"1");
+    set(&mut client, "session-count", b"1", 100);
+    assert_eq!(increment(&mut client, "session-count", 1).value, b"2");
+    assert!(get(&mut client, "session").found);
+
+    thread::sleep(Duration::from_millis(150));
+    assert!(!get(&mut client, "session").found);
+    assert!(!get(&mut client, "session-count").found);
+    assert!(get(&mut client, "config").found);
+
+    // the background expiry removes them, without anyone asking for them
+    let started = Instant::now();
+    while server.metrics().get(Counter::KeysExpired) < 2 {
+        assert!(started.elapsed() < Duration::from_secs(5), "Expired keys were not removed");
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(server.metrics().get(Counter::KeysExpired), 2);
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+fn test_store_versions() {
+    let store = Store::default();
+    let now = Instant::now();
+
+    let first = store.set("key", b"a".to_vec(), None, now).expect("Failed to set");
+    assert_eq!(store.delete("key", now), Ok(true));
+    assert_eq!(store.delete("key", now), Ok(false));
+    // a key set again never gets a version it had before
+    let second = store.set("key", b"a".to_vec(), None, now).expect("Failed to set");
+    assert!(second.version > first.version);
+    let refused = store.compare_and_swap("key", first.version, b"b".to_vec(), None, now);
+    assert_eq!(refused, Ok(Update { applied: false, entry: Some(second) }));
+
+    let ttl = Some(Duration::from_secs(1));
+    let expiring = store.set("expiring", b"1".to_vec(), ttl, now).expect("Failed to set");
+    assert_eq!(expiring.expires_at, Some(now + Duration::from_secs(1)));
+    let later = now + Duration::from_secs(1);
+    assert_eq!(store.get("expiring", later), None);
+    // an expired key counts as missing for a swap
+    let swap = store.compare_and_swap("expiring", expiring.version, b"2".to_vec(), None, later);
+    assert!(!swap.expect("Failed to swap").applied);
+    assert_eq!(store.remove_expired(later), 1);
+    assert_eq!(store.remove_expired(later), 0);
+
+    assert_eq!(store.increment("key", 1, now), Err(KvError::NotANumber));
+    let lowest = store.increment("number", i64::MIN, now).expect("Failed to increment");
+    assert_eq!(lowest.value, i64::MIN.to_string().into_bytes());
+    assert_eq!(store.increment("number", -1, now), Err(KvError::Overflow));
+}
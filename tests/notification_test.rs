@@ -0,0 +1,101 @@
+use common::{start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    message::{client_message, server_message, EchoMessage, Notification, ServerMessage},
+    server::{FrameFormat, Server},
+};
+use serial_test::serial;
+use std::{
+    io::ErrorKind,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+
+fn echo(client: &mut client::Client, content: &str) {
+    let echo = EchoMessage {
+        content: content.to_string(),
+    };
+    assert!(client.send(client_message::Message::EchoMessage(echo.clone())).is_ok());
+    let response = client.receive().expect("Failed to receive response");
+    assert_eq!(response.message, Some(server_message::Message::EchoMessage(echo)));
+}
+
+/// Connects a client and returns it with the id of its connection
+fn connect(server: &Server) -> (client::Client, u64) {
+    let known: Vec<u64> = server.connections().iter().map(|info| info.id).collect();
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    // answered once the connection is listed
+    echo(&mut client, "hello");
+    let id = server
+        .connections()
+        .iter()
+        .map(|info| info.id)
+        .find(|id| !known.contains(id))
+        .expect("Connection is not listed");
+    (client, id)
+}
+
+fn notification(kind: &str, payload: &[u8]) -> Notification {
+    Notification {
+        kind: kind.to_string(),
+        payload: payload.to_vec(),
+    }
+}
+
+fn push(notification: Notification) -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::Notification(notification)),
+        signature: None,
+    }
+}
+
+#[test]
+#[serial]
+fn test_send_to_one_connection() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let (mut listening, listening_id) = connect(&server);
+    let (mut other, other_id) = connect(&server);
+    let notifications = listening.notifications();
+
+    // pushed messages are queued in order and pass around the responses
+    assert!(server.send_to(listening_id, push(notification("reboot", b"now"))).is_ok());
+    assert!(server.send_to(listening_id, push(notification("config", &[1, 2, 3]))).is_ok());
+    echo(&mut listening, "after the notifications");
+    assert_eq!(notifications.try_recv(), Ok(notification("reboot", b"now")));
+    assert_eq!(notifications.try_recv(), Ok(notification("config", &[1, 2, 3])));
+    assert!(notifications.try_recv().is_err(), "Notification was delivered twice");
+
+    // without a channel, receive returns them like any other message
+    assert!(server.send_to(other_id, push(notification("ping", b""))).is_ok());
+    let received = other.receive().expect("Failed to receive notification");
+    assert_eq!(received.message, Some(server_message::Message::Notification(notification("ping", b""))));
+
+    assert!(listening.disconnect().is_ok());
+    assert!(other.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_send_to_closed_connection() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let error = server.send_to(12345, push(notification("reboot", b""))).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+
+    let (mut client, id) = connect(&server);
+    assert!(client.disconnect().is_ok());
+    let started = Instant::now();
+    while server.connections().iter().any(|info| info.id == id) {
+        assert!(started.elapsed() < Duration::from_secs(5), "Closed connection is still listed");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let error = server.send_to(id, push(notification("reboot", b""))).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+
+    stop(server, handle);
+}
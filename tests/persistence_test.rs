@@ -0,0 +1,359 @@
+use common::{connect, connect_length_delimited, start_length_delimited_server, start_server, stop};
+use embedded_recruitment_task::{
+    auth::TokenStore,
+    kv::Store,
+    message::{
+        client_message, server_message, AuthRequest, AuthResponse, Event, KvGet, KvIncrement, KvResponse, KvSet,
+        Publish, PublishResponse, Subscribe, SubscriptionResponse, Unsubscribe,
+    },
+    metrics::Counter,
+    persistence::{FsyncPolicy, Persistence},
+    server::Server,
+};
+use serial_test::serial;
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+#[cfg(unix)]
+mod server_process;
+
+fn request(client: &mut client::Client, message: client_message::Message) -> KvResponse {
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    match response.message {
+        Some(server_message::Message::KvResponse(response)) => response,
+        other => panic!("Expected KvResponse, but received {:?}", other),
+    }
+}
+
+fn get(client: &mut client::Client, key: &str) -> KvResponse {
+    request(client, client_message::Message::KvGet(KvGet { key: key.to_string() }))
+}
+
+fn set(client: &mut client::Client, key: &str, value: &[u8], ttl_ms: u64) -> KvResponse {
+    let set = KvSet {
+        key: key.to_string(),
+        value: value.to_vec(),
+        ttl_ms,
+    };
+    request(client, client_message::Message::KvSet(set))
+}
+
+fn exchange(client: &mut client::Client, message: client_message::Message) -> server_message::Message {
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Received an empty message")
+}
+
+fn authenticate(client: &mut client::Client, token: &str) {
+    let request = AuthRequest {
+        token: token.to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        exchange(client, client_message::Message::AuthRequest(request)),
+        server_message::Message::AuthResponse(AuthResponse {
+            authenticated: true,
+            challenge: vec![],
+        }),
+        "Authentication failed"
+    );
+}
+
+fn subscription(client: &mut client::Client, message: client_message::Message) -> SubscriptionResponse {
+    match exchange(client, message) {
+        server_message::Message::SubscriptionResponse(response) => response,
+        other => panic!("Expected SubscriptionResponse, but received {:?}", other),
+    }
+}
+
+/// Publishes `payload` on `topic`, returning the number of receivers
+fn publish(client: &mut client::Client, topic: &str, payload: &[u8]) -> u32 {
+    let message = client_message::Message::Publish(Publish {
+        topic: topic.to_string(),
+        payload: payload.to_vec(),
+    });
+    match exchange(client, message) {
+        server_message::Message::PublishResponse(PublishResponse { receivers }) => receivers,
+        other => panic!("Expected PublishResponse, but received {:?}", other),
+    }
+}
+
+/// Empty data directory for the test `name`
+fn data_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("server-{}-{}", std::process::id(), name));
+    fs::remove_dir_all(&dir).ok();
+    dir
+}
+
+/// Files of `dir` named `prefix` and a number
+fn files(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("Failed to read data directory")
+        .map(|file| file.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            name.strip_prefix(prefix).is_some_and(|generation| generation.parse::<u64>().is_ok())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn latest_log(dir: &Path) -> PathBuf {
+    files(dir, "wal-").pop().expect("No log in data directory")
+}
+
+#[test]
+#[serial]
+fn test_store_survives_restart() {
+    let dir = data_dir("restart");
+    let persistence = Persistence::new(&dir).snapshot_interval(Duration::from_millis(100));
+    let (server, handle) = start_server(Server::builder().persistence(persistence.clone()));
+    let recovered = files(&dir, "snapshot-");
+
+    let mut client = connect();
+    let interval = set(&mut client, "interval", b"500", 0);
+    set(&mut client, "leader", b"device-1", 0);
+    let session = set(&mut client, "session", b"abc", 300);
+    let boots = KvIncrement {
+        key: "boots".to_string(),
+        delta: 3,
+    };
+    request(&mut client, client_message::Message::KvIncrement(boots));
+    server.kv().delete("leader", Instant::now()).expect("Failed to delete");
+
+    // a snapshot replaces the files of older generations
+    let started = Instant::now();
+    let snapshotted = || files(&dir, "snapshot-") != recovered && files(&dir, "wal-").len() == 1;
+    while !snapshotted() || files(&dir, "snapshot-").len() != 1 {
+        assert!(started.elapsed() < Duration::from_secs(5), "Old generations were not removed");
+        thread::sleep(Duration::from_millis(20));
+    }
+    let last = set(&mut client, "interval", b"250", 0);
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+
+    let (server, handle) = start_server(Server::builder().persistence(persistence));
+    let mut client = connect();
+    assert_eq!(get(&mut client, "interval"), KvResponse { applied: false, ..last.clone() });
+    assert!(last.version > interval.version);
+    assert!(!get(&mut client, "leader").found);
+    assert_eq!(get(&mut client, "boots").value, b"3");
+    // keys keep their expiry across the restart
+    assert_eq!(get(&mut client, "session").version, session.version);
+    thread::sleep(Duration::from_millis(300));
+    assert!(!get(&mut client, "session").found);
+    // versions go on from where they were
+    assert!(set(&mut client, "interval", b"100", 0).version > last.version);
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_damaged_log_is_cut_off() {
+    let dir = data_dir("damaged");
+    let persistence = Persistence::new(&dir).fsync(FsyncPolicy::Never);
+    let now = Instant::now();
+
+    // a crash in the middle of an append leaves part of the last record
+    let store = Store::open(&persistence).expect("Failed to open store");
+    for key in ["a", "b", "c"] {
+        store.set(key, key.as_bytes().to_vec(), None, now).expect("Failed to set");
+    }
+    drop(store);
+    let log = latest_log(&dir);
+    let len = fs::metadata(&log).unwrap().len();
+    fs::OpenOptions::new().write(true).open(&log).unwrap().set_len(len - 3).unwrap();
+
+    let store = Store::open(&persistence).expect("Failed to open store");
+    assert_eq!(store.get("a", now).expect("Key a is missing").value, b"a");
+    assert!(store.get("b", now).is_some());
+    assert!(store.get("c", now).is_none());
+
+    // a record whose checksum does not match ends the log as well
+    store.set("d", b"d".to_vec(), None, now).expect("Failed to set");
+    store.set("e", b"e".to_vec(), None, now).expect("Failed to set");
+    drop(store);
+    let log = latest_log(&dir);
+    let mut data = fs::read(&log).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xFF;
+    fs::write(&log, data).unwrap();
+
+    let store = Store::open(&persistence).expect("Failed to open store");
+    assert!(store.get("d", now).is_some());
+    assert!(store.get("e", now).is_none());
+    assert!(store.get("b", now).is_some());
+    drop(store);
+
+    // a damaged snapshot is not mistaken for an empty store
+    let snapshot = files(&dir, "snapshot-").pop().expect("No snapshot in data directory");
+    fs::write(&snapshot, b"garbage").unwrap();
+    let error = Store::open(&persistence).expect_err("Opened a store with a damaged snapshot");
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+#[serial]
+fn test_subscriptions_survive_restart() {
+    let dir = data_dir("subscriptions");
+    let start = || {
+        let mut tokens = TokenStore::new();
+        tokens.insert("sensor-1", "s3cret");
+        tokens.insert("sensor-2", "other");
+        let builder = Server::builder().persistence(Persistence::new(&dir)).require_auth(tokens);
+        start_length_delimited_server(builder)
+    };
+
+    let (server, handle) = start();
+    let mut client = connect_length_delimited();
+    authenticate(&mut client, "s3cret");
+    for message in [
+        client_message::Message::Subscribe(Subscribe {
+            topic: "sensors/#".to_string(),
+        }),
+        client_message::Message::Subscribe(Subscribe {
+            topic: "alerts".to_string(),
+        }),
+    ] {
+        assert!(subscription(&mut client, message).subscribed, "Subscription refused");
+    }
+    let unsubscribe = client_message::Message::Unsubscribe(Unsubscribe {
+        topic: "alerts".to_string(),
+    });
+    assert!(!subscription(&mut client, unsubscribe).subscribed, "Still subscribed");
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+
+    // authenticating again is all it takes to get the events of before
+    let (server, handle) = start();
+    let mut client = connect_length_delimited();
+    authenticate(&mut client, "s3cret");
+    let mut publisher = connect_length_delimited();
+    authenticate(&mut publisher, "other");
+    assert_eq!(publish(&mut publisher, "sensors/temp", b"21.5"), 1);
+    let event = client.receive().expect("Failed to receive event").message;
+    assert_eq!(
+        event,
+        Some(server_message::Message::Event(Event {
+            topic: "sensors/temp".to_string(),
+            payload: b"21.5".to_vec(),
+        }))
+    );
+    assert_eq!(publish(&mut publisher, "alerts", b"none"), 0, "Unsubscribed topic came back");
+
+    assert!(client.disconnect().is_ok());
+    assert!(publisher.disconnect().is_ok());
+    stop(server, handle);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_counters_survive_kill() {
+    use server_process::{ServerProcess, SERVER_BIN};
+    use std::process::Command;
+
+    let dir = data_dir("counters");
+    let mut command = Command::new(SERVER_BIN);
+    command.env("SERVER_DATA_DIR", &dir);
+    let mut process = ServerProcess::start(command, "localhost", 8080);
+
+    // the key expires well before the kill, and the counter is logged soon after
+    let mut client = connect();
+    set(&mut client, "session", b"abc", 50);
+    thread::sleep(Duration::from_millis(500));
+    process.signal("KILL");
+    process.wait();
+
+    let (server, handle) = start_server(Server::builder().persistence(Persistence::new(&dir)));
+    assert_eq!(server.metrics().get(Counter::KeysExpired), 1, "Counter lost in the kill");
+    stop(server, handle);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_recovery_after_kill_during_writes() {
+    use server_process::{ServerProcess, SERVER_BIN};
+    use std::process::Command;
+
+    const CLIENTS: usize = 4;
+    let dir = data_dir("killed");
+    let start = || {
+        let mut command = Command::new(SERVER_BIN);
+        command.env("SERVER_DATA_DIR", &dir).env("SERVER_SNAPSHOT_INTERVAL_MS", "50");
+        ServerProcess::start(command, "localhost", 8080)
+    };
+
+    // every client increments its own counter until the server is gone,
+    // counting the increments it got an answer for
+    let mut server = start();
+    let workers: Vec<JoinHandle<i64>> = (0..CLIENTS)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut client = connect();
+                let mut acknowledged = 0;
+                loop {
+                    let increment = KvIncrement {
+                        key: format!("counter-{}", i),
+                        delta: 1,
+                    };
+                    if client.send(client_message::Message::KvIncrement(increment)).is_err() {
+                        return acknowledged;
+                    }
+                    match client.receive() {
+                        Ok(response) => match response.message {
+                            Some(server_message::Message::KvResponse(response)) => {
+                                acknowledged += 1;
+                                assert_eq!(response.value, acknowledged.to_string().into_bytes());
+                            }
+                            other => panic!("Expected KvResponse, but received {:?}", other),
+                        },
+                        Err(_) => return acknowledged,
+                    }
+                }
+            })
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(500));
+    server.signal("KILL");
+    server.wait();
+    let acknowledged: Vec<i64> = workers
+        .into_iter()
+        .map(|worker| worker.join().expect("Client thread panicked"))
+        .collect();
+
+    // no answered increment is lost, the one in flight may have made it
+    let server = start();
+    let mut client = connect();
+    for (i, acknowledged) in acknowledged.into_iter().enumerate() {
+        assert!(acknowledged > 0, "Client {} got no answer before the kill", i);
+        let counter = get(&mut client, &format!("counter-{}", i));
+        let value: i64 = String::from_utf8(counter.value).unwrap().parse().unwrap();
+        assert!(
+            value == acknowledged || value == acknowledged + 1,
+            "Counter {} is {} after {} answered increments",
+            i,
+            value,
+            acknowledged
+        );
+    }
+    assert!(client.disconnect().is_ok());
+    drop(server);
+    fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,187 @@
+use common::{connect_length_delimited, start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    message::{
+        client_message, server_message, ErrorCode, Event, Publish, PublishResponse, Subscribe, SubscriptionResponse,
+        Unsubscribe,
+    },
+    pubsub::{self, Broker, Delivery, SlowSubscriberAction, TopicError},
+    server::Server,
+};
+use serial_test::serial;
+use std::{thread, time::Duration};
+
+mod client;
+mod common;
+
+fn receive(client: &mut client::Client) -> server_message::Message {
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Received an empty message")
+}
+
+fn subscribe(client: &mut client::Client, topic: &str) {
+    let message = client_message::Message::Subscribe(Subscribe {
+        topic: topic.to_string(),
+    });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    let expected = SubscriptionResponse {
+        topic: topic.to_string(),
+        subscribed: true,
+    };
+    assert_eq!(receive(client), server_message::Message::SubscriptionResponse(expected));
+}
+
+/// Publishes `payload` on `topic`, returning the number of receivers
+fn publish(client: &mut client::Client, topic: &str, payload: &[u8]) -> u32 {
+    let message = client_message::Message::Publish(Publish {
+        topic: topic.to_string(),
+        payload: payload.to_vec(),
+    });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match receive(client) {
+        server_message::Message::PublishResponse(PublishResponse { receivers }) => receivers,
+        other => panic!("Expected PublishResponse, but received {:?}", other),
+    }
+}
+
+fn event(topic: &str, payload: &[u8]) -> server_message::Message {
+    server_message::Message::Event(Event {
+        topic: topic.to_string(),
+        payload: payload.to_vec(),
+    })
+}
+
+#[test]
+#[serial]
+fn test_events_reach_matching_subscribers() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let mut kitchen = connect_length_delimited();
+    let mut sensors = connect_length_delimited();
+    let mut publisher = connect_length_delimited();
+    subscribe(&mut kitchen, "sensors/kitchen/+");
+    subscribe(&mut sensors, "sensors/#");
+
+    assert_eq!(publish(&mut publisher, "sensors/kitchen/temp", b"21.5"), 2);
+    assert_eq!(receive(&mut kitchen), event("sensors/kitchen/temp", b"21.5"));
+    assert_eq!(receive(&mut sensors), event("sensors/kitchen/temp", b"21.5"));
+
+    assert_eq!(publish(&mut publisher, "sensors/hall/temp", b"19"), 1);
+    assert_eq!(publish(&mut publisher, "alerts", b"none"), 0);
+    assert_eq!(receive(&mut sensors), event("sensors/hall/temp", b"19"));
+
+    // after unsubscribing, only events queued before arrive
+    let message = client_message::Message::Unsubscribe(Unsubscribe {
+        topic: "sensors/#".to_string(),
+    });
+    assert!(sensors.send(message).is_ok());
+    let expected = SubscriptionResponse {
+        topic: "sensors/#".to_string(),
+        subscribed: false,
+    };
+    assert_eq!(receive(&mut sensors), server_message::Message::SubscriptionResponse(expected));
+    assert_eq!(publish(&mut publisher, "sensors/kitchen/temp", b"22"), 1);
+    assert_eq!(receive(&mut kitchen), event("sensors/kitchen/temp", b"22"));
+
+    // a publisher receives its own events like any subscriber
+    subscribe(&mut publisher, "alerts");
+    assert_eq!(publish(&mut publisher, "alerts", b"smoke"), 1);
+    assert_eq!(receive(&mut publisher), event("alerts", b"smoke"));
+
+    for client in [&mut kitchen, &mut sensors, &mut publisher] {
+        assert!(client.disconnect().is_ok());
+    }
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_invalid_topics_are_refused() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let mut client = connect_length_delimited();
+    let requests = [
+        client_message::Message::Subscribe(Subscribe {
+            topic: "sensors/#/temp".to_string(),
+        }),
+        client_message::Message::Subscribe(Subscribe { topic: String::new() }),
+        client_message::Message::Publish(Publish {
+            topic: "sensors/+".to_string(),
+            payload: Vec::new(),
+        }),
+    ];
+    for request in requests {
+        assert!(client.send(request).is_ok());
+        match receive(&mut client) {
+            server_message::Message::ErrorResponse(error) => assert_eq!(error.code(), ErrorCode::InvalidRequest),
+            other => panic!("Expected ErrorResponse, but received {:?}", other),
+        }
+    }
+    // subscriptions of a closed connection are gone
+    subscribe(&mut client, "sensors/#");
+    assert!(client.disconnect().is_ok());
+    let mut publisher = connect_length_delimited();
+    let mut receivers = publish(&mut publisher, "sensors/hall/temp", b"19");
+    for _ in 0..50 {
+        if receivers == 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+        receivers = publish(&mut publisher, "sensors/hall/temp", b"19");
+    }
+    assert_eq!(receivers, 0, "Subscription outlived its connection");
+
+    assert!(publisher.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+fn test_topic_matching() {
+    let cases = [
+        ("sensors/kitchen/temp", "sensors/kitchen/temp", true),
+        ("sensors/+/temp", "sensors/kitchen/temp", true),
+        ("sensors/+/temp", "sensors/kitchen/humidity", false),
+        ("sensors/+", "sensors/kitchen/temp", false),
+        ("sensors/#", "sensors/kitchen/temp", true),
+        ("sensors/#", "sensors", true),
+        ("#", "alerts", true),
+        ("+/+", "a/b", true),
+        ("+", "", true),
+        ("sensors", "sensors/kitchen", false),
+    ];
+    for (pattern, topic, expected) in cases {
+        assert_eq!(pubsub::matches(pattern, topic), expected, "{} on {}", pattern, topic);
+    }
+    assert_eq!(pubsub::check_pattern("sensors/+/temp"), Ok(()));
+    assert_eq!(pubsub::check_pattern("sensors/#/temp"), Err(TopicError::MisplacedWildcard));
+    assert_eq!(pubsub::check_pattern("sensors/kit+"), Err(TopicError::MisplacedWildcard));
+    assert_eq!(pubsub::check_topic("sensors/#"), Err(TopicError::Wildcard));
+    assert_eq!(pubsub::check_topic(""), Err(TopicError::Empty));
+}
+
+#[test]
+fn test_slow_subscriber_actions() {
+    let broker = Broker::default();
+    let oldest = broker.subscribe(1, "a", 2, SlowSubscriberAction::DropOldest);
+    let newest = broker.subscribe(2, "a", 2, SlowSubscriberAction::DropNewest);
+    let disconnect = broker.subscribe(3, "a", 2, SlowSubscriberAction::Disconnect);
+
+    assert_eq!(broker.publish("a", vec![1]), Delivery { receivers: 3, dropped: 0 });
+    assert_eq!(broker.publish("a", vec![2]), Delivery { receivers: 3, dropped: 0 });
+    // only the subscriber dropping its oldest event takes the third one
+    assert_eq!(broker.publish("a", vec![3]), Delivery { receivers: 1, dropped: 3 });
+
+    let payloads = |subscriber: &pubsub::Subscriber| {
+        std::iter::from_fn(|| subscriber.next_event()).map(|event| event.payload[0]).collect::<Vec<_>>()
+    };
+    assert_eq!(payloads(&oldest), vec![2, 3]);
+    assert_eq!(payloads(&newest), vec![1, 2]);
+    assert!(disconnect.has_overflowed());
+    assert!(payloads(&disconnect).is_empty());
+    assert!(!oldest.has_overflowed());
+
+    assert!(broker.unsubscribe(1, "a"));
+    assert!(!broker.unsubscribe(1, "a"));
+    broker.remove(2);
+    // nothing is queued for a subscriber about to be disconnected
+    assert_eq!(broker.publish("a", vec![4]), Delivery::default());
+}
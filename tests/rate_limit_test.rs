@@ -0,0 +1,185 @@
+use common::{connect, stop};
+use embedded_recruitment_task::{
+    message::{client_message, server_message, AddRequest, ErrorCode},
+    metrics::Counter,
+    rate_limit::{RateLimit, RateLimitAction, RateLimits},
+    server::Server,
+};
+use serial_test::serial;
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+
+fn start_server(limits: RateLimits) -> (Arc<Server>, JoinHandle<()>) {
+    common::start_server(Server::builder().rate_limits(limits))
+}
+
+/// Sends an AddRequest, returning the error code if it was refused
+fn add(client: &mut client::Client) -> Result<(), ErrorCode> {
+    let message = client_message::Message::AddRequest(AddRequest { a: 1, b: 2 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::AddResponse(add_response)) => {
+            assert_eq!(add_response.result, 3, "Wrong calculation result");
+            Ok(())
+        }
+        Some(server_message::Message::ErrorResponse(error)) => Err(error.code()),
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn test_reject_over_connection_limit() {
+    let limits = RateLimits::new(RateLimitAction::Reject).per_connection(RateLimit::new(1.0, 3));
+    let (server, handle) = start_server(limits);
+
+    let mut client = connect();
+    let results: Vec<_> = (0..5).map(|_| add(&mut client)).collect();
+    assert_eq!(
+        results,
+        [Ok(()), Ok(()), Ok(()), Err(ErrorCode::RateLimited), Err(ErrorCode::RateLimited)]
+    );
+    assert_eq!(server.metrics().get(Counter::RequestsRejected), 2);
+
+    // other connections have buckets of their own
+    let mut other = connect();
+    assert_eq!(add(&mut other), Ok(()));
+
+    // the bucket refills over time
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(add(&mut client), Ok(()));
+
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_delay_over_limit() {
+    let limits = RateLimits::new(RateLimitAction::Delay).per_connection(RateLimit::new(10.0, 1));
+    let (server, handle) = start_server(limits);
+
+    let mut client = connect();
+    let start = Instant::now();
+    for _ in 0..4 {
+        assert_eq!(add(&mut client), Ok(()), "Delayed request was refused");
+    }
+    // the first request uses the burst, the other three wait 100 ms each
+    assert!(
+        start.elapsed() >= Duration::from_millis(250),
+        "Requests were not delayed: {:?}",
+        start.elapsed()
+    );
+    assert_eq!(server.metrics().get(Counter::RequestsDelayed), 3);
+
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_delayed_request_does_not_hold_up_shutdown() {
+    // a token every 100 s
+    let limits = RateLimits::new(RateLimitAction::Delay).per_connection(RateLimit::new(0.01, 1));
+    let (server, handle) = start_server(limits);
+
+    let mut client = connect();
+    assert_eq!(add(&mut client), Ok(()));
+    let message = client_message::Message::AddRequest(AddRequest { a: 1, b: 2 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    thread::sleep(Duration::from_millis(200));
+
+    let start = Instant::now();
+    stop(server, handle);
+    assert!(start.elapsed() < Duration::from_secs(2), "Shutdown took {:?}", start.elapsed());
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorResponse(error)) => assert_eq!(error.code(), ErrorCode::RateLimited),
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn test_rates_have_to_be_positive() {
+    for per_second in [0.0, -1.0, f64::NAN] {
+        let limits = RateLimits::new(RateLimitAction::Delay).global(RateLimit::new(per_second, 1));
+        match Server::builder().rate_limits(limits).bind("localhost:8080") {
+            Ok(_) => panic!("Built a server with {} requests per second", per_second),
+            Err(error) => assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput),
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn test_bursts_have_to_allow_a_request() {
+    let limits = RateLimits::new(RateLimitAction::Delay).per_ip(RateLimit::new(10.0, 0));
+    match Server::builder().rate_limits(limits).bind("localhost:8080") {
+        Ok(_) => panic!("Built a server with a burst size of 0"),
+        Err(error) => assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput),
+    }
+}
+
+#[test]
+#[serial]
+fn test_tiny_rate_is_a_long_wait() {
+    let limits = RateLimits::new(RateLimitAction::Reject).per_connection(RateLimit::new(1e-20, 1));
+    let (server, handle) = start_server(limits);
+
+    // the wait of about 3e12 years is reported as a day, the connection lives on
+    let mut client = connect();
+    assert_eq!(add(&mut client), Ok(()));
+    let message = client_message::Message::AddRequest(AddRequest { a: 1, b: 2 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code(), ErrorCode::RateLimited);
+            assert!(error.message.contains("retry in 86400000 ms"), "Unexpected wait: {}", error.message);
+        }
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+    assert_eq!(add(&mut client), Err(ErrorCode::RateLimited));
+
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_disconnect_over_limit() {
+    let limits = RateLimits::new(RateLimitAction::Disconnect).per_connection(RateLimit::new(0.1, 1));
+    let (server, handle) = start_server(limits);
+
+    let mut client = connect();
+    assert_eq!(add(&mut client), Ok(()));
+    assert_eq!(add(&mut client), Err(ErrorCode::RateLimited));
+    assert!(client.receive().is_err(), "Connection was not closed");
+    assert_eq!(server.metrics().get(Counter::RateLimitDisconnects), 1);
+
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_ip_and_global_limits_span_connections() {
+    let limits = RateLimits::new(RateLimitAction::Reject).per_ip(RateLimit::new(0.1, 2));
+    let (server, handle) = start_server(limits);
+    let mut first = connect();
+    let mut second = connect();
+    assert_eq!(add(&mut first), Ok(()));
+    assert_eq!(add(&mut second), Ok(()));
+    // both connections come from the same address
+    assert_eq!(add(&mut first), Err(ErrorCode::RateLimited));
+    assert_eq!(add(&mut second), Err(ErrorCode::RateLimited));
+    stop(server, handle);
+
+    let limits = RateLimits::new(RateLimitAction::Reject).global(RateLimit::new(0.1, 3));
+    let (server, handle) = start_server(limits);
+    let mut clients = [connect(), connect(), connect(), connect()];
+    let results: Vec<_> = clients.iter_mut().map(add).collect();
+    assert_eq!(results, [Ok(()), Ok(()), Ok(()), Err(ErrorCode::RateLimited)]);
+    stop(server, handle);
+}
@@ -0,0 +1,239 @@
+use common::{connect_length_delimited, start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    auth::TokenStore,
+    message::{
+        client_message, server_message, AuthRequest, ErrorCode, JoinRoom, LeaveRoom, RoomEvent, RoomEventKind,
+        RoomMessage,
+    },
+    server::Server,
+};
+use serial_test::serial;
+use std::{thread, time::Duration};
+
+mod client;
+mod common;
+
+fn receive_event(client: &mut client::Client) -> RoomEvent {
+    let response = client.receive().expect("Failed to receive response");
+    match response.message {
+        Some(server_message::Message::RoomEvent(event)) => event,
+        other => panic!("Expected RoomEvent, but received {:?}", other),
+    }
+}
+
+fn expect_error(client: &mut client::Client, code: ErrorCode) {
+    let response = client.receive().expect("Failed to receive response");
+    match response.message {
+        Some(server_message::Message::ErrorResponse(error)) => assert_eq!(error.code(), code),
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+}
+
+fn expect_invalid_request(client: &mut client::Client) {
+    expect_error(client, ErrorCode::InvalidRequest);
+}
+
+fn join(client: &mut client::Client, room: &str, name: &str) {
+    let message = client_message::Message::JoinRoom(JoinRoom {
+        room: room.to_string(),
+        name: name.to_string(),
+    });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+}
+
+fn leave(client: &mut client::Client, room: &str) {
+    let message = client_message::Message::LeaveRoom(LeaveRoom { room: room.to_string() });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+}
+
+fn post(client: &mut client::Client, room: &str, content: &str) {
+    let message = client_message::Message::RoomMessage(RoomMessage {
+        room: room.to_string(),
+        content: content.to_string(),
+    });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+}
+
+fn event(kind: RoomEventKind, member: &str, content: &str, replayed: bool) -> RoomEvent {
+    RoomEvent {
+        room: "crew".to_string(),
+        kind: kind as i32,
+        member: member.to_string(),
+        content: content.to_string(),
+        replayed,
+    }
+}
+
+fn joined(member: &str) -> RoomEvent {
+    event(RoomEventKind::MemberJoined, member, "", false)
+}
+
+fn left(member: &str) -> RoomEvent {
+    event(RoomEventKind::MemberLeft, member, "", false)
+}
+
+fn posted(member: &str, content: &str) -> RoomEvent {
+    event(RoomEventKind::MessagePosted, member, content, false)
+}
+
+#[test]
+#[serial]
+fn test_members_see_the_same_events() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let mut alice = connect_length_delimited();
+    let mut bob = connect_length_delimited();
+    join(&mut alice, "crew", "alice");
+    assert_eq!(receive_event(&mut alice), joined("alice"));
+    join(&mut bob, "crew", "bob");
+    assert_eq!(receive_event(&mut bob), joined("bob"));
+    assert_eq!(receive_event(&mut alice), joined("bob"));
+
+    // bob posts once alice's message went out, so both are in a known order
+    post(&mut alice, "crew", "hi");
+    assert_eq!(receive_event(&mut alice), posted("alice", "hi"));
+    post(&mut bob, "crew", "hello");
+    assert_eq!(receive_event(&mut bob), posted("alice", "hi"));
+    assert_eq!(receive_event(&mut bob), posted("bob", "hello"));
+    assert_eq!(receive_event(&mut alice), posted("bob", "hello"));
+
+    // the one leaving is told like everyone else, and may post no more
+    leave(&mut bob, "crew");
+    assert_eq!(receive_event(&mut bob), left("bob"));
+    assert_eq!(receive_event(&mut alice), left("bob"));
+    post(&mut bob, "crew", "still here?");
+    expect_invalid_request(&mut bob);
+
+    // closing the connection leaves all rooms
+    join(&mut bob, "crew", "bob");
+    assert_eq!(receive_event(&mut bob), event(RoomEventKind::MessagePosted, "alice", "hi", true));
+    assert_eq!(receive_event(&mut bob), event(RoomEventKind::MessagePosted, "bob", "hello", true));
+    assert_eq!(receive_event(&mut bob), joined("bob"));
+    assert_eq!(receive_event(&mut alice), joined("bob"));
+    assert!(bob.disconnect().is_ok());
+    assert_eq!(receive_event(&mut alice), left("bob"));
+
+    assert!(alice.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_history_is_replayed_on_join() {
+    let (server, handle) = start_length_delimited_server(Server::builder().room_history(2));
+
+    let mut alice = connect_length_delimited();
+    join(&mut alice, "crew", "alice");
+    assert_eq!(receive_event(&mut alice), joined("alice"));
+    for content in ["one", "two", "three"] {
+        post(&mut alice, "crew", content);
+        assert_eq!(receive_event(&mut alice), posted("alice", content));
+    }
+
+    // only the latest messages are kept, membership changes are not replayed
+    let mut bob = connect_length_delimited();
+    join(&mut bob, "crew", "bob");
+    assert_eq!(receive_event(&mut bob), event(RoomEventKind::MessagePosted, "alice", "two", true));
+    assert_eq!(receive_event(&mut bob), event(RoomEventKind::MessagePosted, "alice", "three", true));
+    assert_eq!(receive_event(&mut bob), joined("bob"));
+    assert_eq!(receive_event(&mut alice), joined("bob"));
+
+    // the history outlives the members
+    leave(&mut alice, "crew");
+    assert_eq!(receive_event(&mut alice), left("alice"));
+    assert_eq!(receive_event(&mut bob), left("alice"));
+    leave(&mut bob, "crew");
+    assert_eq!(receive_event(&mut bob), left("bob"));
+    join(&mut bob, "crew", "bob");
+    assert_eq!(receive_event(&mut bob), event(RoomEventKind::MessagePosted, "alice", "two", true));
+    assert_eq!(receive_event(&mut bob), event(RoomEventKind::MessagePosted, "alice", "three", true));
+    assert_eq!(receive_event(&mut bob), joined("bob"));
+
+    assert!(alice.disconnect().is_ok());
+    assert!(bob.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_history_expires() {
+    let builder = Server::builder().room_history_retention(Duration::from_millis(200));
+    let (server, handle) = start_length_delimited_server(builder);
+
+    let mut alice = connect_length_delimited();
+    join(&mut alice, "crew", "alice");
+    assert_eq!(receive_event(&mut alice), joined("alice"));
+    post(&mut alice, "crew", "one");
+    assert_eq!(receive_event(&mut alice), posted("alice", "one"));
+    leave(&mut alice, "crew");
+    assert_eq!(receive_event(&mut alice), left("alice"));
+
+    // once expired, the messages are not replayed even though nobody joined since
+    thread::sleep(Duration::from_millis(300));
+    join(&mut alice, "crew", "alice");
+    assert_eq!(receive_event(&mut alice), joined("alice"));
+
+    assert!(alice.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_authenticated_members_keep_their_name() {
+    let mut tokens = TokenStore::new();
+    tokens.insert("sensor-1", "s3cret");
+    let (server, handle) = start_length_delimited_server(Server::builder().require_auth(tokens));
+
+    let mut client = connect_length_delimited();
+    let auth = AuthRequest {
+        token: "s3cret".to_string(),
+        ..Default::default()
+    };
+    assert!(client.send(client_message::Message::AuthRequest(auth)).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::AuthResponse(response)) => assert!(response.authenticated),
+        other => panic!("Expected AuthResponse, but received {:?}", other),
+    }
+
+    // another client's name is refused, the own one or none is the identity
+    join(&mut client, "crew", "sensor-2");
+    expect_error(&mut client, ErrorCode::PermissionDenied);
+    join(&mut client, "crew", "sensor-1");
+    assert_eq!(receive_event(&mut client), joined("sensor-1"));
+    join(&mut client, "other", "");
+    assert_eq!(receive_event(&mut client).member, "sensor-1");
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_invalid_room_operations_are_refused() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let mut client = connect_length_delimited();
+    join(&mut client, "", "alice");
+    expect_invalid_request(&mut client);
+    leave(&mut client, "crew");
+    expect_invalid_request(&mut client);
+
+    join(&mut client, "crew", "alice");
+    assert_eq!(receive_event(&mut client), joined("alice"));
+    join(&mut client, "crew", "alice");
+    expect_invalid_request(&mut client);
+    post(&mut client, "elsewhere", "hi");
+    expect_invalid_request(&mut client);
+
+    // without a name, members are known by their address
+    let mut anonymous = connect_length_delimited();
+    join(&mut anonymous, "crew", "");
+    let event = receive_event(&mut anonymous);
+    assert_eq!(event.kind(), RoomEventKind::MemberJoined);
+    assert!(event.member.starts_with("127.0.0.1:"), "Unexpected member {}", event.member);
+    assert_eq!(receive_event(&mut client), event);
+
+    assert!(client.disconnect().is_ok());
+    assert!(anonymous.disconnect().is_ok());
+    stop(server, handle);
+}
@@ -0,0 +1,327 @@
+#![cfg(unix)]
+
+use embedded_recruitment_task::{
+    cobs::{self, FrameDecoder},
+    message::{client_message, server_message, AddRequest, ClientMessage, EchoMessage, ServerMessage},
+    serial::{Parity, SerialConfig},
+    metrics::Counter,
+    server::{Server, Timeouts},
+};
+use prost::Message;
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    mem,
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::PathBuf,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Pseudo-terminal pair: the server opens the slave side like a UART, the
+/// test plays the device on the master side
+struct Pty {
+    master: File,
+    slave_path: PathBuf,
+    //kept open to look at the line settings the server applied
+    slave: File,
+}
+
+fn open_pty() -> Pty {
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(fd >= 0, "posix_openpt failed");
+        let master = File::from_raw_fd(fd);
+        assert_eq!(libc::grantpt(fd), 0, "grantpt failed");
+        assert_eq!(libc::unlockpt(fd), 0, "unlockpt failed");
+        let mut name = [0 as libc::c_char; 128];
+        assert_eq!(libc::ptsname_r(fd, name.as_mut_ptr(), name.len()), 0, "ptsname_r failed");
+        let slave_path = PathBuf::from(CStr::from_ptr(name.as_ptr()).to_str().unwrap());
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&slave_path)
+            .expect("Failed to open pty slave");
+        Pty {
+            master,
+            slave_path,
+            slave,
+        }
+    }
+}
+
+fn termios(file: &File) -> libc::termios {
+    unsafe {
+        let mut tio: libc::termios = mem::zeroed();
+        assert_eq!(libc::tcgetattr(file.as_raw_fd(), &mut tio), 0, "tcgetattr failed");
+        tio
+    }
+}
+
+/// Starts serving the slave side and waits until the server switched it to raw mode
+fn setup_serial_thread(server: Arc<Server>, pty: &Pty, config: SerialConfig) -> JoinHandle<()> {
+    let path = pty.slave_path.clone();
+    let handle = thread::spawn(move || {
+        server.serve_serial(&path, &config).expect("Failed to serve serial port");
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while termios(&pty.slave).c_lflag & libc::ECHO != 0 {
+        assert!(Instant::now() < deadline, "Serial port was not configured");
+        thread::sleep(Duration::from_millis(10));
+    }
+    handle
+}
+
+fn send(pty: &mut Pty, message: client_message::Message) {
+    let payload = ClientMessage {
+        message: Some(message),
+        deadline_ms: 0,
+        signature: None,
+    }
+    .encode_to_vec();
+    pty.master.write_all(&cobs::encode_frame(&payload)).unwrap();
+}
+
+fn receive(pty: &mut Pty, decoder: &mut FrameDecoder) -> ServerMessage {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    loop {
+        let mut pollfd = libc::pollfd {
+            fd: pty.master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = deadline.saturating_duration_since(Instant::now()).as_millis() as i32;
+        assert!(unsafe { libc::poll(&mut pollfd, 1, timeout) } > 0, "Timed out waiting for a response");
+
+        let mut byte = [0u8; 1];
+        pty.master.read_exact(&mut byte).unwrap();
+        if let Some(frame) = decoder.push(byte[0]) {
+            let payload = frame.expect("Received corrupted frame");
+            return ServerMessage::decode(payload.as_slice()).expect("Failed to decode ServerMessage");
+        }
+    }
+}
+
+#[test]
+fn test_cobs_round_trip() {
+    let mut payloads: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![0],
+        vec![0, 0, 0],
+        vec![1, 2, 0, 3],
+        (1..=253).collect(),
+        (1..=254).collect(),
+        (1..=255).collect(),
+        (0..1000).map(|i| (i % 7) as u8).collect(),
+    ];
+    payloads.push(vec![0xFF; 600]);
+
+    let mut decoder = FrameDecoder::new();
+    for payload in payloads {
+        let frame = cobs::encode_frame(&payload);
+        assert_eq!(frame.last(), Some(&cobs::DELIMITER));
+        assert!(!frame[..frame.len() - 1].contains(&cobs::DELIMITER), "Delimiter inside frame");
+        assert_eq!(cobs::decode_frame(&frame[..frame.len() - 1]).unwrap(), payload);
+
+        let mut decoded = None;
+        for &byte in &frame {
+            if let Some(result) = decoder.push(byte) {
+                decoded = Some(result.unwrap());
+            }
+        }
+        assert_eq!(decoded, Some(payload));
+    }
+}
+
+#[test]
+fn test_cobs_rejects_corrupted_frames() {
+    let mut frame = cobs::encode_frame(b"hello");
+    frame[3] ^= 0x01;
+    assert!(cobs::decode_frame(&frame[..frame.len() - 1]).is_err(), "CRC mismatch not detected");
+
+    // The decoder reports the corrupted frame and recovers on the next one
+    let mut decoder = FrameDecoder::new();
+    let results: Vec<_> = frame
+        .iter()
+        .chain(cobs::encode_frame(b"world").iter())
+        .filter_map(|&byte| decoder.push(byte))
+        .collect();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].is_err());
+    assert_eq!(results[1].as_ref().unwrap(), b"world");
+
+    // Oversized frames are dropped without buffering them
+    let oversized = cobs::encode_frame(&vec![1; cobs::MAX_PAYLOAD_LEN + 10]);
+    let results: Vec<_> = oversized.iter().filter_map(|&byte| decoder.push(byte)).collect();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+}
+
+#[test]
+fn test_serial_echo_and_add() {
+    let mut pty = open_pty();
+    let server = Arc::new(Server::new("127.0.0.1:0").expect("Failed to start server"));
+    let handle = setup_serial_thread(server.clone(), &pty, SerialConfig::default());
+    let mut decoder = FrameDecoder::new();
+
+    let echo_message = EchoMessage {
+        content: "Hello, UART!".to_string(),
+    };
+    send(&mut pty, client_message::Message::EchoMessage(echo_message.clone()));
+    match receive(&mut pty, &mut decoder).message {
+        Some(server_message::Message::EchoMessage(echo)) => {
+            assert_eq!(echo.content, echo_message.content, "Echoed message content does not match");
+        }
+        _ => panic!("Expected EchoMessage, but received a different message"),
+    }
+
+    // Several requests written at once are still answered one by one
+    for a in 0..3 {
+        send(&mut pty, client_message::Message::AddRequest(AddRequest { a, b: 20 }));
+    }
+    for a in 0..3 {
+        match receive(&mut pty, &mut decoder).message {
+            Some(server_message::Message::AddResponse(add_response)) => {
+                assert_eq!(add_response.result, a + 20, "Wrong calculation result");
+            }
+            _ => panic!("Expected AddResponse"),
+        }
+    }
+
+    server.stop();
+    assert!(handle.join().is_ok(), "Serial thread panicked or failed to join");
+}
+
+#[test]
+fn test_serial_discards_corrupted_frame() {
+    let mut pty = open_pty();
+    let server = Arc::new(Server::new("127.0.0.1:0").expect("Failed to start server"));
+    let handle = setup_serial_thread(server.clone(), &pty, SerialConfig::default());
+    let mut decoder = FrameDecoder::new();
+
+    let payload = ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 1 })),
+        deadline_ms: 0,
+        signature: None,
+    }
+    .encode_to_vec();
+    let mut frame = cobs::encode_frame(&payload);
+    frame[1] ^= 0x40;
+    pty.master.write_all(&frame).unwrap();
+
+    // Only the intact request gets an answer
+    send(&mut pty, client_message::Message::AddRequest(AddRequest { a: 2, b: 3 }));
+    match receive(&mut pty, &mut decoder).message {
+        Some(server_message::Message::AddResponse(add_response)) => {
+            assert_eq!(add_response.result, 5, "Wrong calculation result");
+        }
+        _ => panic!("Expected AddResponse"),
+    }
+
+    server.stop();
+    assert!(handle.join().is_ok(), "Serial thread panicked or failed to join");
+}
+
+#[test]
+fn test_serial_read_timeout() {
+    let mut pty = open_pty();
+    let timeouts = Timeouts::new()
+        .read(Duration::from_millis(200))
+        .idle(Duration::from_millis(50));
+    let server = Arc::new(
+        Server::builder()
+            .timeouts(timeouts)
+            .bind("127.0.0.1:0")
+            .expect("Failed to start server"),
+    );
+    let handle = setup_serial_thread(server.clone(), &pty, SerialConfig::default());
+    let mut decoder = FrameDecoder::new();
+
+    // A quiet device is not subject to the idle timeout
+    thread::sleep(Duration::from_millis(150));
+    send(&mut pty, client_message::Message::AddRequest(AddRequest { a: 1, b: 2 }));
+    assert!(matches!(
+        receive(&mut pty, &mut decoder).message,
+        Some(server_message::Message::AddResponse(_))
+    ));
+
+    // A frame missing its delimiter closes the port once the read timeout passed
+    let payload = ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a: 3, b: 4 })),
+        deadline_ms: 0,
+        signature: None,
+    }
+    .encode_to_vec();
+    let frame = cobs::encode_frame(&payload);
+    pty.master.write_all(&frame[..frame.len() - 1]).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !handle.is_finished() {
+        assert!(Instant::now() < deadline, "Serial port was not closed after the read timeout");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(handle.join().is_ok(), "Serial thread panicked or failed to join");
+    assert_eq!(server.metrics().get(Counter::ReadTimeouts), 1);
+    assert_eq!(server.metrics().get(Counter::IdleTimeouts), 0);
+    server.stop();
+}
+
+#[test]
+fn test_serial_line_settings() {
+    let pty = open_pty();
+    let server = Arc::new(Server::new("127.0.0.1:0").expect("Failed to start server"));
+    let config = SerialConfig {
+        baud_rate: 57600,
+        parity: Parity::Odd,
+    };
+    let handle = setup_serial_thread(server.clone(), &pty, config);
+
+    let tio = termios(&pty.slave);
+    unsafe {
+        assert_eq!(libc::cfgetospeed(&tio), libc::B57600, "Baud rate was not applied");
+    }
+    // The pty driver never keeps PARENB, the other parity flags stick
+    assert_ne!(tio.c_iflag & libc::INPCK, 0, "Parity check was not enabled");
+    assert_ne!(tio.c_cflag & libc::PARODD, 0, "Parity is not odd");
+
+    server.stop();
+    assert!(handle.join().is_ok(), "Serial thread panicked or failed to join");
+
+    let unsupported = SerialConfig {
+        baud_rate: 12345,
+        parity: Parity::None,
+    };
+    assert!(server.serve_serial(&pty.slave_path, &unsupported).is_err(), "Accepted an unsupported baud rate");
+}
+
+#[test]
+fn test_stop_before_serving_stops_every_entry_point() {
+    let mut pty = open_pty();
+    let server = Arc::new(Server::new("127.0.0.1:0").expect("Failed to start server"));
+    server.stop();
+
+    // every entry point started after the stop sees it, not just the first
+    let started = Instant::now();
+    assert!(server.serve_serial(&pty.slave_path, &SerialConfig::default()).is_ok());
+    assert!(server.serve_serial(&pty.slave_path, &SerialConfig::default()).is_ok());
+    assert!(server.run().is_ok());
+    assert!(started.elapsed() < Duration::from_secs(5), "Stopped server kept serving");
+
+    // the stop ended with the run, the server serves again
+    let handle = setup_serial_thread(server.clone(), &pty, SerialConfig::default());
+    let mut decoder = FrameDecoder::new();
+    send(&mut pty, client_message::Message::AddRequest(AddRequest { a: 2, b: 2 }));
+    assert!(matches!(
+        receive(&mut pty, &mut decoder).message,
+        Some(server_message::Message::AddResponse(add_response)) if add_response.result == 4
+    ));
+    server.stop();
+    assert!(handle.join().is_ok(), "Serial thread panicked or failed to join");
+}
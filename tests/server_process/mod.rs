@@ -0,0 +1,66 @@
+#![allow(dead_code)]
+
+use crate::client;
+use std::{
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// Path of the server binary built by cargo
+pub const SERVER_BIN: &str = env!("CARGO_BIN_EXE_embedded-recruitment-task");
+
+/// Server binary running as a child process, killed if the test fails early
+pub struct ServerProcess(Child);
+
+impl ServerProcess {
+    /// Starts the server binary on its default address
+    pub fn spawn() -> Self {
+        Self::start(Command::new(SERVER_BIN), "localhost", 8080)
+    }
+
+    /// Runs `command` and waits until the server accepts connections on `ip:port`
+    pub fn start(mut command: Command, ip: &str, port: u32) -> Self {
+        let child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to spawn server process");
+        let mut server = ServerProcess(child);
+
+        for _ in 0..50 {
+            if let Ok(Some(status)) = server.0.try_wait() {
+                panic!("Server process exited early: {}", status);
+            }
+            let mut probe = client::Client::new(ip, port, 100);
+            if probe.connect().is_ok() {
+                probe.disconnect().ok();
+                return server;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        panic!("Server process did not start listening");
+    }
+
+    pub fn signal(&self, signal: &str) {
+        let status = Command::new("kill")
+            .arg(format!("-{}", signal))
+            .arg(self.0.id().to_string())
+            .status()
+            .expect("Failed to run kill");
+        assert!(status.success(), "Failed to send SIG{}", signal);
+    }
+
+    pub fn wait(&mut self) -> ExitStatus {
+        self.0.wait().expect("Failed to wait for server process")
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        if let Ok(None) = self.0.try_wait() {
+            self.0.kill().ok();
+            self.0.wait().ok();
+        }
+    }
+}
@@ -0,0 +1,131 @@
+#![cfg(unix)]
+
+use common::{run, start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    message::{client_message, server_message, AddRequest, ClientMessage, EchoMessage},
+    server::Server,
+};
+use prost::Message;
+use serial_test::serial;
+use server_process::ServerProcess;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+mod server_process;
+
+#[test]
+#[serial]
+fn test_sigterm_completes_in_flight_requests() {
+    let mut server = ServerProcess::spawn();
+
+    let mut clients = [
+        client::Client::new("localhost", 8080, 1000),
+        client::Client::new("localhost", 8080, 1000),
+        client::Client::new("localhost", 8080, 1000),
+    ];
+
+    // Make sure every connection has been accepted by the server
+    for client in clients.iter_mut() {
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+        let echo = EchoMessage {
+            content: "ready".to_string(),
+        };
+        assert!(client.send(client_message::Message::EchoMessage(echo)).is_ok());
+        assert!(client.receive().is_ok(), "Failed to receive warm-up response");
+    }
+
+    // Send a request on every connection and terminate the server right away
+    for (i, client) in clients.iter_mut().enumerate() {
+        let add_request = AddRequest { a: i as i32, b: 100 };
+        assert!(
+            client.send(client_message::Message::AddRequest(add_request)).is_ok(),
+            "Failed to send message"
+        );
+    }
+    server.signal("TERM");
+
+    // Every in-flight request still gets its full response
+    for (i, client) in clients.iter_mut().enumerate() {
+        let response = client.receive();
+        assert!(response.is_ok(), "Failed to receive response after SIGTERM");
+        match response.unwrap().message {
+            Some(server_message::Message::AddResponse(add_response)) => {
+                assert_eq!(add_response.result, i as i32 + 100, "Wrong calculation result");
+            }
+            _ => panic!("Expected AddResponse"),
+        }
+    }
+
+    let status = server.wait();
+    assert!(status.success(), "Server did not exit cleanly: {}", status);
+
+    // The server is gone, so the connections are closed
+    for client in clients.iter_mut() {
+        assert!(client.receive().is_err(), "Connection still open after shutdown");
+    }
+}
+
+#[test]
+#[serial]
+fn test_server_runs_again_after_stop() {
+    let server = Arc::new(Server::new("localhost:8080").expect("Failed to start server"));
+
+    for round in 0..2 {
+        let handle = run(Arc::clone(&server));
+
+        // the same listener serves clients again once the server runs again
+        let mut client = client::Client::new("localhost", 8080, 1000);
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+        let add_request = AddRequest { a: round, b: 1 };
+        assert!(client.send(client_message::Message::AddRequest(add_request)).is_ok());
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::AddResponse(add_response)) => assert_eq!(add_response.result, round + 1),
+            other => panic!("Expected AddResponse, but received {:?}", other),
+        }
+        assert!(client.disconnect().is_ok());
+
+        server.stop();
+        assert!(handle.join().is_ok(), "Server thread panicked or failed to join");
+    }
+}
+
+#[test]
+#[serial]
+fn test_shutdown_is_not_held_up_by_a_busy_client() {
+    // the requests follow each other without pause, so they are length delimited
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    // a client that sends requests without pause, reading the responses on
+    // another thread, until its connection is closed
+    let stream = TcpStream::connect("localhost:8080").expect("Failed to connect to the server");
+    let mut reader = stream.try_clone().unwrap();
+    let mut writer = stream;
+    let request = ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 })),
+        ..ClientMessage::default()
+    }
+    .encode_length_delimited_to_vec();
+    let sender = thread::spawn(move || while writer.write_all(&request).is_ok() {});
+    let receiver = thread::spawn(move || {
+        let mut received = 0;
+        let mut buffer = [0; 1024];
+        while let Ok(1..) = reader.read(&mut buffer) {
+            received += 1;
+        }
+        received
+    });
+    thread::sleep(Duration::from_millis(200));
+
+    let stopped = Instant::now();
+    stop(server, handle);
+    assert!(stopped.elapsed() < Duration::from_secs(5), "Shutdown took {:?}", stopped.elapsed());
+    sender.join().expect("Sending thread panicked");
+    assert!(receiver.join().expect("Receiving thread panicked") > 0, "No request was answered");
+}
@@ -0,0 +1,262 @@
+use common::{start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    message::{
+        client_message, server_message, AddRequest, ClientMessage, EchoMessage, ErrorCode, ServerMessage, Signature,
+    },
+    metrics::Counter,
+    server::{FrameFormat, Server},
+    signing::{MessageSigner, ReplayGuard, SignatureError},
+};
+use hmac::{Hmac, Mac};
+use prost::Message;
+use serial_test::serial;
+use sha2::Sha256;
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+mod client;
+mod common;
+
+const KEY: &[u8] = b"pre-shared signing key";
+
+fn start_server(required: bool) -> (Arc<Server>, JoinHandle<()>) {
+    common::start_server(Server::builder().sign_messages(MessageSigner::new(KEY), required))
+}
+
+fn add_request(a: i32, b: i32) -> ClientMessage {
+    ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a, b })),
+        deadline_ms: 0,
+        signature: None,
+    }
+}
+
+fn signed(signer: &MessageSigner, mut message: ClientMessage, nonce: u64) -> ClientMessage {
+    signer.sign(&mut message, nonce);
+    message
+}
+
+fn exchange(client: &mut client::Client, message: &ClientMessage) -> ServerMessage {
+    assert!(client.send_envelope(message).is_ok(), "Failed to send message");
+    client.receive().expect("Failed to receive response")
+}
+
+fn assert_error(response: &ServerMessage, code: ErrorCode) {
+    match &response.message {
+        Some(server_message::Message::ErrorResponse(error)) => assert_eq!(error.code(), code, "Wrong error code"),
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+}
+
+fn assert_sum(response: &ServerMessage, expected: i32) {
+    match &response.message {
+        Some(server_message::Message::AddResponse(add_response)) => {
+            assert_eq!(add_response.result, expected, "Wrong calculation result")
+        }
+        other => panic!("Expected AddResponse, but received {:?}", other),
+    }
+}
+
+#[test]
+#[serial]
+fn test_signed_requests_and_responses() {
+    let (server, handle) = start_server(true);
+    let signer = MessageSigner::new(KEY);
+    let mut responses = ReplayGuard::new();
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let request = signed(&signer, add_request(1, 2), 1);
+    let response = exchange(&mut client, &request);
+    assert_sum(&response, 3);
+    let signature = signer.verify(&response).expect("Response is not signed");
+    assert!(responses.check(&signature).is_ok(), "Response signature is stale");
+
+    // the same request again is a replay
+    let response = exchange(&mut client, &request);
+    assert_error(&response, ErrorCode::ReplayDetected);
+    // responses are signed with increasing nonces too, errors included
+    let signature = signer.verify(&response).expect("Error response is not signed");
+    assert!(responses.check(&signature).is_ok(), "Response nonce did not increase");
+
+    // altered after signing
+    let mut tampered = signed(&signer, add_request(1, 2), 2);
+    tampered.message = Some(client_message::Message::AddRequest(AddRequest { a: 1000, b: 2 }));
+    assert_error(&exchange(&mut client, &tampered), ErrorCode::InvalidSignature);
+
+    assert_error(&exchange(&mut client, &add_request(1, 2)), ErrorCode::InvalidSignature);
+
+    // nonces may skip values but never go back
+    assert_sum(&exchange(&mut client, &signed(&signer, add_request(5, 5), 10)), 10);
+    assert_error(
+        &exchange(&mut client, &signed(&signer, add_request(5, 5), 9)),
+        ErrorCode::ReplayDetected,
+    );
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_optional_signatures() {
+    let (server, handle) = start_server(false);
+    let other_signer = MessageSigner::new("another key");
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // unsigned requests are accepted, but signed ones are still checked
+    assert_sum(&exchange(&mut client, &add_request(2, 2)), 4);
+    assert_error(
+        &exchange(&mut client, &signed(&other_signer, add_request(2, 2), 1)),
+        ErrorCode::InvalidSignature,
+    );
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_replay_on_a_new_connection() {
+    let (server, handle) = start_server(true);
+    let signer = MessageSigner::new(KEY);
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let request = signed(&signer, add_request(1, 2), 1);
+    assert_sum(&exchange(&mut client, &request), 3);
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    // the recorded request is rejected on another connection, while the
+    // connection's own nonces still start wherever it likes
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_error(&exchange(&mut client, &request), ErrorCode::ReplayDetected);
+    assert_sum(&exchange(&mut client, &signed(&signer, add_request(2, 2), 1)), 4);
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_reflected_messages_are_rejected() {
+    let (server, handle) = start_server(true);
+    let signer = MessageSigner::new(KEY);
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let echo = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "reflect me".to_string(),
+        })),
+        deadline_ms: 0,
+        signature: None,
+    };
+    let response = exchange(&mut client, &signed(&signer, echo, 1));
+    signer.verify(&response).expect("Response is not signed");
+
+    // a signed echo response is also a well formed echo request, with the
+    // same bytes, but it was signed for the other direction
+    let reflected = ClientMessage::decode(response.encode_to_vec().as_slice()).expect("Failed to decode response");
+    assert_eq!(reflected.encode_to_vec(), response.encode_to_vec());
+    assert_eq!(signer.verify(&reflected), Err(SignatureError::Invalid));
+    assert_error(&exchange(&mut client, &reflected), ErrorCode::InvalidSignature);
+
+    // and a signed request is no response
+    let request = signed(&signer, add_request(1, 2), 2);
+    let reflected = ServerMessage::decode(request.encode_to_vec().as_slice()).expect("Failed to decode request");
+    assert_eq!(signer.verify(&reflected), Err(SignatureError::Invalid));
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_signature_covers_the_bytes_sent() {
+    let (server, handle) = start_server(true);
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // a newer client's request: a field unknown to the server, and the
+    // deadline written out although prost leaves a 0 away
+    let mut unsigned = add_request(2, 3).encode_to_vec();
+    prost::encoding::uint64::encode(14, &0, &mut unsigned);
+    prost::encoding::string::encode(99, &"from a newer schema".to_string(), &mut unsigned);
+    let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let mut mac = Hmac::<Sha256>::new_from_slice(KEY).unwrap();
+    mac.update(b"c2s");
+    mac.update(&[10]);
+    mac.update(b"AddRequest");
+    mac.update(&1u64.to_be_bytes());
+    mac.update(&timestamp_ms.to_be_bytes());
+    mac.update(&unsigned);
+    let signature = Signature {
+        nonce: 1,
+        timestamp_ms,
+        mac: mac.finalize().into_bytes().to_vec(),
+    };
+    let mut payload = unsigned.clone();
+    prost::encoding::message::encode(15, &signature, &mut payload);
+    assert!(client.send_raw(&payload).is_ok(), "Failed to send message");
+    assert_sum(&client.receive().expect("Failed to receive response"), 5);
+
+    // the unknown field is covered as well
+    let tampered: Vec<u8> = payload.iter().map(|&byte| if byte == b'n' { b'N' } else { byte }).collect();
+    assert!(client.send_raw(&tampered).is_ok(), "Failed to send message");
+    assert_error(&client.receive().expect("Failed to receive response"), ErrorCode::InvalidSignature);
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_heartbeats_are_answered_signed() {
+    let builder = Server::builder()
+        .sign_messages(MessageSigner::new(KEY), true)
+        .heartbeat(Duration::from_millis(50), 3);
+    let (server, handle) = start_length_delimited_server(builder);
+
+    // the pongs carry signatures like the requests, the connection outlives many pings
+    let mut client = client::Client::new("localhost", 8080, 1000)
+        .with_frame_format(FrameFormat::LengthDelimited)
+        .with_signer(MessageSigner::new(KEY));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    for a in 0..6 {
+        thread::sleep(Duration::from_millis(60));
+        let message = client_message::Message::AddRequest(AddRequest { a, b: 1 });
+        assert!(client.send(message).is_ok(), "Failed to send message");
+        assert_sum(&client.receive().expect("Failed to receive response"), a + 1);
+    }
+    assert!(server.connections()[0].round_trip_time.is_some(), "No pong was accepted");
+    assert_eq!(server.metrics().get(Counter::HeartbeatTimeouts), 0);
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+fn test_replay_guard_rejects_stale_timestamps() {
+    let signer = MessageSigner::new(KEY);
+    let mut message = add_request(1, 1);
+    signer.sign(&mut message, 1);
+    let fresh = message.signature.clone().unwrap();
+
+    let mut guard = ReplayGuard::new();
+    let stale = Signature {
+        timestamp_ms: fresh.timestamp_ms - 60_000,
+        ..fresh.clone()
+    };
+    assert_eq!(guard.check(&stale), Err(SignatureError::Expired));
+    // a rejected message does not use up its nonce
+    assert_eq!(guard.check(&fresh), Ok(()));
+    assert_eq!(guard.check(&fresh), Err(SignatureError::Replayed));
+}
@@ -0,0 +1,153 @@
+use common::{start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    message::{
+        client_message, server_message, EchoMessage, ErrorCode, RangeSumRequest, StreamCredit, StreamEnd, StreamItem,
+        SubscribeCounter,
+    },
+    server::{FrameFormat, Server},
+};
+use serial_test::serial;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+
+fn receive(client: &mut client::Client) -> server_message::Message {
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Received an empty message")
+}
+
+fn receive_item(client: &mut client::Client) -> StreamItem {
+    match receive(client) {
+        server_message::Message::StreamItem(item) => item,
+        other => panic!("Expected StreamItem, but received {:?}", other),
+    }
+}
+
+fn receive_end(client: &mut client::Client) -> StreamEnd {
+    match receive(client) {
+        server_message::Message::StreamEnd(end) => end,
+        other => panic!("Expected StreamEnd, but received {:?}", other),
+    }
+}
+
+fn subscribe(request_id: u64, count: u64, interval_ms: u32, credits: u32) -> client_message::Message {
+    client_message::Message::SubscribeCounter(SubscribeCounter {
+        request_id,
+        count,
+        interval_ms,
+        credits,
+    })
+}
+
+#[test]
+#[serial]
+fn test_range_sum_waits_for_credit() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let request = RangeSumRequest {
+        request_id: 42,
+        start: 1,
+        end: 11,
+        credits: 4,
+    };
+    assert!(client.send(client_message::Message::RangeSumRequest(request)).is_ok());
+    for sum in [1, 3, 6, 10] {
+        assert_eq!(receive_item(&mut client), StreamItem { request_id: 42, value: sum });
+    }
+
+    // Out of credit, the stream waits while other requests are answered
+    thread::sleep(Duration::from_millis(100));
+    let echo = EchoMessage {
+        content: "still there".to_string(),
+    };
+    assert!(client.send(client_message::Message::EchoMessage(echo.clone())).is_ok());
+    assert_eq!(receive(&mut client), server_message::Message::EchoMessage(echo));
+
+    let credit = StreamCredit {
+        request_id: 42,
+        credits: 100,
+    };
+    assert!(client.send(client_message::Message::StreamCredit(credit)).is_ok());
+    for sum in [15, 21, 28, 36, 45, 55] {
+        assert_eq!(receive_item(&mut client), StreamItem { request_id: 42, value: sum });
+    }
+    assert_eq!(receive_end(&mut client), StreamEnd { request_id: 42, error: None });
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_counter_streams_interleave() {
+    let (server, handle) = start_length_delimited_server(Server::builder());
+
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let started = Instant::now();
+    assert!(client.send(subscribe(1, 3, 50, 10)).is_ok());
+    assert!(client.send(subscribe(2, 3, 50, 10)).is_ok());
+
+    let mut values = [Vec::new(), Vec::new()];
+    let mut ended = 0;
+    while ended < 2 {
+        match receive(&mut client) {
+            server_message::Message::StreamItem(item) => values[item.request_id as usize - 1].push(item.value),
+            server_message::Message::StreamEnd(end) => {
+                assert_eq!(end.error, None, "Stream {} failed", end.request_id);
+                assert_eq!(values[end.request_id as usize - 1].len(), 3, "Stream ended early");
+                ended += 1;
+            }
+            other => panic!("Expected a stream message, but received {:?}", other),
+        }
+    }
+    assert_eq!(values, [vec![1, 2, 3], vec![1, 2, 3]]);
+    assert!(started.elapsed() >= Duration::from_millis(100), "Counter ignored its interval");
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_stream_limits() {
+    let (server, handle) = start_length_delimited_server(Server::builder().max_streams(1));
+
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    // an endless counter without credit keeps its slot
+    assert!(client.send(subscribe(1, 0, 10, 0)).is_ok());
+
+    assert!(client.send(subscribe(1, 0, 10, 0)).is_ok());
+    let end = receive_end(&mut client);
+    assert_eq!(end.request_id, 1);
+    assert_eq!(end.error.expect("No error").code(), ErrorCode::InvalidRequest);
+
+    assert!(client.send(subscribe(2, 0, 10, 0)).is_ok());
+    let end = receive_end(&mut client);
+    assert_eq!(end.request_id, 2);
+    let error = end.error.expect("No error");
+    assert_eq!(error.code(), ErrorCode::TooManyStreams);
+    assert!(error.message.contains("no more than 1"), "Unclear error: {}", error.message);
+
+    let credit = StreamCredit {
+        request_id: 1,
+        credits: 2,
+    };
+    assert!(client.send(client_message::Message::StreamCredit(credit)).is_ok());
+    assert_eq!(receive_item(&mut client), StreamItem { request_id: 1, value: 1 });
+    assert_eq!(receive_item(&mut client), StreamItem { request_id: 1, value: 2 });
+
+    // Open streams are ended when the server shuts down
+    let stop_thread = thread::spawn(move || stop(server, handle));
+    let end = receive_end(&mut client);
+    assert_eq!(end.request_id, 1);
+    assert_eq!(end.error.expect("No error").code(), ErrorCode::Unavailable);
+    assert!(stop_thread.join().is_ok());
+}
@@ -0,0 +1,133 @@
+#![cfg(unix)]
+
+use common::{start_length_delimited_server, stop};
+use embedded_recruitment_task::{
+    message::{client_message, server_message, AddRequest, ClientMessage, EchoMessage},
+    metrics::Counter,
+    server::{FrameFormat, Server, Timeouts},
+};
+use prost::Message;
+use serial_test::serial;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    os::unix::io::AsRawFd,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+
+/// Waits until `counter` of `server` reached `value`
+fn wait_for(server: &Server, counter: Counter, value: u64) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.metrics().get(counter) < value {
+        assert!(Instant::now() < deadline, "{} did not reach {}", counter.name(), value);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn send_add(client: &mut client::Client) -> std::io::Result<server_message::Message> {
+    client.send(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 }))?;
+    let response = client.receive()?;
+    Ok(response.message.expect("Received an empty message"))
+}
+
+#[test]
+#[serial]
+fn test_idle_timeout() {
+    let timeouts = Timeouts::new().idle(Duration::from_millis(300));
+    let (server, handle) = start_length_delimited_server(Server::builder().timeouts(timeouts));
+
+    // Requests keep the connection open past the idle timeout
+    let mut active = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(active.connect().is_ok(), "Failed to connect to the server");
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(100));
+        assert!(
+            matches!(send_add(&mut active), Ok(server_message::Message::AddResponse(_))),
+            "Active connection was closed"
+        );
+    }
+
+    let mut idle = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(idle.connect().is_ok(), "Failed to connect to the server");
+    wait_for(&server, Counter::IdleTimeouts, 1);
+    assert!(idle.receive().is_err(), "Idle connection was not closed");
+
+    assert!(active.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_write_timeout() {
+    let timeouts = Timeouts::new().write(Duration::from_millis(200));
+    let (server, handle) = start_length_delimited_server(Server::builder().timeouts(timeouts));
+
+    // A client that sends requests but never reads the responses
+    let mut stream = TcpStream::connect("localhost:8080").expect("Failed to connect to the server");
+    let receive_buffer: libc::c_int = 4096;
+    unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &receive_buffer as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+    let request = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "x".repeat(1000),
+        })),
+        deadline_ms: 0,
+        signature: None,
+    }
+    .encode_length_delimited_to_vec();
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while server.metrics().get(Counter::WriteTimeouts) == 0 {
+        assert!(Instant::now() < deadline, "Write timeout was not hit");
+        if stream.write_all(&request).is_err() {
+            // closed by the server
+            break;
+        }
+    }
+    wait_for(&server, Counter::WriteTimeouts, 1);
+
+    // Other clients are still served
+    let mut client = client::Client::new("localhost", 8080, 1000).with_frame_format(FrameFormat::LengthDelimited);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(matches!(send_add(&mut client), Ok(server_message::Message::AddResponse(_))));
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_read_timeout() {
+    let timeouts = Timeouts::new()
+        .read(Duration::from_millis(200))
+        .idle(Duration::from_secs(60));
+    let (server, handle) = start_length_delimited_server(Server::builder().timeouts(timeouts));
+
+    // A request cut off in the middle of the frame
+    let mut stream = TcpStream::connect("localhost:8080").expect("Failed to connect to the server");
+    let request = ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a: 1, b: 2 })),
+        deadline_ms: 0,
+        signature: None,
+    }
+    .encode_length_delimited_to_vec();
+    stream.write_all(&request[..request.len() - 1]).unwrap();
+
+    wait_for(&server, Counter::ReadTimeouts, 1);
+    let mut buffer = [0u8; 16];
+    assert_eq!(stream.read(&mut buffer).unwrap_or(0), 0, "Connection was not closed");
+    assert_eq!(server.metrics().get(Counter::IdleTimeouts), 0);
+
+    stop(server, handle);
+}
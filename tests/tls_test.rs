@@ -0,0 +1,147 @@
+#![cfg(feature = "tls")]
+
+use common::{run, stop};
+use certs::{TempDir, TestCa};
+use embedded_recruitment_task::{
+    message::{client_message, server_message, AddRequest, EchoMessage},
+    server::Server,
+};
+use serial_test::serial;
+use std::{path::PathBuf, sync::Arc};
+
+mod certs;
+mod client;
+mod common;
+
+/// Writes a server certificate issued by `ca` and its key, returning their paths
+fn write_server_cert(dir: &TempDir, ca: &TestCa) -> (PathBuf, PathBuf) {
+    let (cert, key) = ca.issue("server", &["localhost"]);
+    (dir.write("server.pem", &cert), dir.write("server.key", &key))
+}
+
+fn create_tls_server(cert_path: PathBuf, key_path: PathBuf) -> Arc<Server> {
+    let server = Server::builder()
+        .tls(cert_path, key_path)
+        .bind("localhost:8080")
+        .expect("Failed to start server");
+    Arc::new(server)
+}
+
+fn assert_echo(client: &mut client::Client, content: &str) {
+    let echo_message = EchoMessage {
+        content: content.to_string(),
+    };
+    let message = client_message::Message::EchoMessage(echo_message);
+    assert!(client.send(message).is_ok(), "Failed to send message");
+
+    let response = client.receive();
+    assert!(response.is_ok(), "Failed to receive response for EchoMessage");
+    match response.unwrap().message {
+        Some(server_message::Message::EchoMessage(echo)) => {
+            assert_eq!(echo.content, content, "Echoed message content does not match");
+        }
+        _ => panic!("Expected EchoMessage, but received a different message"),
+    }
+}
+
+#[test]
+#[serial]
+fn test_tls_echo_and_add() {
+    let dir = TempDir::new("tls-echo");
+    let ca = TestCa::new("Test CA");
+    let ca_path = dir.write("ca.pem", &ca.pem());
+    let (cert_path, key_path) = write_server_cert(&dir, &ca);
+
+    let server = create_tls_server(cert_path, key_path);
+    let handle = run(server.clone());
+
+    let mut client = client::Client::new("localhost", 8080, 1000)
+        .with_tls(&ca_path)
+        .expect("Failed to load CA bundle");
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    assert_echo(&mut client, "Hello, TLS!");
+    let message = client_message::Message::AddRequest(AddRequest { a: 10, b: 20 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::AddResponse(add_response)) => {
+            assert_eq!(add_response.result, 30, "Wrong calculation result");
+        }
+        _ => panic!("Expected AddResponse"),
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_tls_rejects_untrusted_and_plaintext_clients() {
+    let dir = TempDir::new("tls-untrusted");
+    let ca = TestCa::new("Test CA");
+    let (cert_path, key_path) = write_server_cert(&dir, &ca);
+    let other_ca_path = dir.write("other-ca.pem", &TestCa::new("Other CA").pem());
+
+    let server = create_tls_server(cert_path, key_path);
+    let handle = run(server.clone());
+
+    // The server certificate is not signed by a CA the client trusts
+    let mut client = client::Client::new("localhost", 8080, 1000)
+        .with_tls(&other_ca_path)
+        .expect("Failed to load CA bundle");
+    assert!(client.connect().is_err(), "Connected to an untrusted server");
+
+    // Plaintext requests are not answered
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let message = client_message::Message::AddRequest(AddRequest { a: 1, b: 2 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    assert!(client.receive().is_err(), "Plaintext request was answered");
+
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_tls_certificate_reload() {
+    let dir = TempDir::new("tls-reload");
+    let old_ca = TestCa::new("Old CA");
+    let new_ca = TestCa::new("New CA");
+    let old_ca_path = dir.write("old-ca.pem", &old_ca.pem());
+    let new_ca_path = dir.write("new-ca.pem", &new_ca.pem());
+    let (cert_path, key_path) = write_server_cert(&dir, &old_ca);
+
+    let server = create_tls_server(cert_path.clone(), key_path.clone());
+    let handle = run(server.clone());
+
+    let mut established = client::Client::new("localhost", 8080, 1000)
+        .with_tls(&old_ca_path)
+        .expect("Failed to load CA bundle");
+    assert!(established.connect().is_ok(), "Failed to connect to the server");
+    assert_echo(&mut established, "before reload");
+
+    // A broken certificate file is rejected and the current one stays in use
+    dir.write("server.pem", "not a certificate");
+    assert!(server.reload_tls().is_err(), "Reloaded an invalid certificate");
+
+    write_server_cert(&dir, &new_ca);
+    assert!(server.reload_tls().is_ok(), "Failed to reload the certificate");
+
+    let mut old_client = client::Client::new("localhost", 8080, 1000)
+        .with_tls(&old_ca_path)
+        .expect("Failed to load CA bundle");
+    assert!(old_client.connect().is_err(), "Old certificate still served after reload");
+
+    let mut new_client = client::Client::new("localhost", 8080, 1000)
+        .with_tls(&new_ca_path)
+        .expect("Failed to load CA bundle");
+    assert!(new_client.connect().is_ok(), "New certificate not served after reload");
+    assert_echo(&mut new_client, "after reload");
+
+    // Established sessions are not affected by the reload
+    assert_echo(&mut established, "still connected");
+
+    assert!(new_client.disconnect().is_ok());
+    assert!(established.disconnect().is_ok());
+    stop(server, handle);
+}
@@ -0,0 +1,219 @@
+use common::{start_server, stop};
+use embedded_recruitment_task::{
+    auth::{AccessPolicy, MessageKind, TokenStore, CHALLENGE_LEN},
+    message::{client_message, server_message, AddRequest, AuthRequest, AuthResponse, ErrorCode},
+    metrics::Counter,
+    server::{Server, MAX_FAILED_AUTHS},
+};
+use hmac::{Hmac, Mac};
+use serial_test::serial;
+use sha2::Sha256;
+use std::{env, fs, io::ErrorKind};
+
+mod client;
+mod common;
+
+fn tokens() -> TokenStore {
+    let mut tokens = TokenStore::new();
+    tokens.insert("sensor-1", "s3cret");
+    tokens.insert("sensor-2", "other");
+    tokens
+}
+
+fn authenticate(client: &mut client::Client, request: AuthRequest) -> server_message::Message {
+    assert!(
+        client.send(client_message::Message::AuthRequest(request)).is_ok(),
+        "Failed to send message"
+    );
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Received an empty message")
+}
+
+fn token_request(token: &str) -> AuthRequest {
+    AuthRequest {
+        token: token.to_string(),
+        ..Default::default()
+    }
+}
+
+fn send_add(client: &mut client::Client) -> server_message::Message {
+    let message = client_message::Message::AddRequest(AddRequest { a: 4, b: 5 });
+    assert!(client.send(message).is_ok(), "Failed to send message");
+    let response = client.receive().expect("Failed to receive response");
+    response.message.expect("Received an empty message")
+}
+
+fn assert_error(message: server_message::Message, code: ErrorCode) {
+    match message {
+        server_message::Message::ErrorResponse(error) => assert_eq!(error.code(), code, "Wrong error code"),
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+}
+
+fn assert_authenticated(message: server_message::Message) {
+    assert_eq!(
+        message,
+        server_message::Message::AuthResponse(AuthResponse {
+            authenticated: true,
+            challenge: vec![],
+        }),
+        "Authentication failed"
+    );
+}
+
+fn hmac(secret: &str, challenge: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(challenge);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[test]
+#[serial]
+fn test_messages_rejected_until_authenticated() {
+    let (server, handle) = start_server(Server::builder().require_auth(tokens()));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    assert_error(send_add(&mut client), ErrorCode::Unauthenticated);
+    assert_error(authenticate(&mut client, token_request("wrong")), ErrorCode::Unauthenticated);
+    assert_error(send_add(&mut client), ErrorCode::Unauthenticated);
+
+    assert_authenticated(authenticate(&mut client, token_request("s3cret")));
+    match send_add(&mut client) {
+        server_message::Message::AddResponse(add_response) => assert_eq!(add_response.result, 9),
+        other => panic!("Expected AddResponse, but received {:?}", other),
+    }
+
+    // authentication is per connection
+    let mut other = client::Client::new("localhost", 8080, 1000);
+    assert!(other.connect().is_ok(), "Failed to connect to the server");
+    assert_error(send_add(&mut other), ErrorCode::Unauthenticated);
+
+    assert!(client.disconnect().is_ok());
+    assert!(other.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_challenge_response() {
+    let (server, handle) = start_server(Server::builder().require_auth(tokens()));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let challenge_request = AuthRequest {
+        client_id: "sensor-1".to_string(),
+        ..Default::default()
+    };
+    let challenge = match authenticate(&mut client, challenge_request.clone()) {
+        server_message::Message::AuthResponse(response) => {
+            assert!(!response.authenticated, "Authenticated without a challenge response");
+            response.challenge
+        }
+        other => panic!("Expected AuthResponse, but received {:?}", other),
+    };
+    assert_eq!(challenge.len(), CHALLENGE_LEN, "Wrong challenge length");
+
+    // keyed with another client's secret
+    let wrong = AuthRequest {
+        client_id: "sensor-1".to_string(),
+        challenge_response: hmac("other", &challenge),
+        ..Default::default()
+    };
+    assert_error(authenticate(&mut client, wrong), ErrorCode::Unauthenticated);
+
+    // the challenge is spent after one answer
+    let replayed = AuthRequest {
+        client_id: "sensor-1".to_string(),
+        challenge_response: hmac("s3cret", &challenge),
+        ..Default::default()
+    };
+    assert_error(authenticate(&mut client, replayed), ErrorCode::Unauthenticated);
+
+    let challenge = match authenticate(&mut client, challenge_request) {
+        server_message::Message::AuthResponse(response) => response.challenge,
+        other => panic!("Expected AuthResponse, but received {:?}", other),
+    };
+    let answer = AuthRequest {
+        client_id: "sensor-1".to_string(),
+        challenge_response: hmac("s3cret", &challenge),
+        ..Default::default()
+    };
+    assert_authenticated(authenticate(&mut client, answer));
+    assert!(matches!(send_add(&mut client), server_message::Message::AddResponse(_)));
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_guessing_tokens_closes_connection() {
+    let (server, handle) = start_server(Server::builder().require_auth(tokens()));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    for guess in 1..MAX_FAILED_AUTHS {
+        assert_error(authenticate(&mut client, token_request(&format!("guess-{}", guess))), ErrorCode::Unauthenticated);
+    }
+    match authenticate(&mut client, token_request("last-guess")) {
+        server_message::Message::ErrorResponse(error) => {
+            assert_eq!(error.code(), ErrorCode::Unauthenticated);
+            assert!(error.message.contains("closing the connection"), "Unexpected error: {}", error.message);
+        }
+        other => panic!("Expected ErrorResponse, but received {:?}", other),
+    }
+    assert!(client.receive().is_err(), "Connection still open after the last failed attempt");
+    assert_eq!(server.metrics().get(Counter::AuthFailureDisconnects), 1);
+
+    // a new connection may try again
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_authenticated(authenticate(&mut client, token_request("s3cret")));
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+#[serial]
+fn test_token_identity_is_authorized() {
+    let policy = AccessPolicy::new()
+        .allow("sensor-1", &[MessageKind::Echo, MessageKind::Add])
+        .allow("sensor-2", &[MessageKind::Echo]);
+    let (server, handle) = start_server(Server::builder().require_auth(tokens()).authorize(policy));
+
+    let mut client = client::Client::new("localhost", 8080, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_authenticated(authenticate(&mut client, token_request("other")));
+    assert_error(send_add(&mut client), ErrorCode::PermissionDenied);
+
+    assert!(client.disconnect().is_ok());
+    stop(server, handle);
+}
+
+#[test]
+fn test_token_store_sources() {
+    let path = env::temp_dir().join(format!("server-{}-tokens", std::process::id()));
+    fs::write(&path, "# clients\nsensor-1:s3cret\n\n  sensor-2:with:colon  \n").unwrap();
+    let tokens = TokenStore::from_file(&path).expect("Failed to read token file");
+    assert_eq!(tokens.authenticate_token("s3cret"), Some("sensor-1"));
+    assert_eq!(tokens.authenticate_token("with:colon"), Some("sensor-2"));
+    assert_eq!(tokens.authenticate_token("sensor-1"), None);
+
+    fs::write(&path, "sensor-1:s3cret\nmissing-secret\n").unwrap();
+    let error = TokenStore::from_file(&path).expect_err("Accepted a malformed token file");
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains(":2:"), "Error does not name the line: {}", error);
+    fs::remove_file(&path).ok();
+
+    env::set_var("TEST_SERVER_TOKENS", "sensor-1:s3cret, sensor-2:other");
+    let tokens = TokenStore::from_env("TEST_SERVER_TOKENS").expect("Failed to read tokens from env");
+    assert_eq!(tokens.authenticate_token("other"), Some("sensor-2"));
+    assert_eq!(
+        TokenStore::from_env("TEST_SERVER_TOKENS_UNSET").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+}
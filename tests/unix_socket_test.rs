@@ -0,0 +1,165 @@
+#![cfg(unix)]
+
+use common::{run, stop};
+use embedded_recruitment_task::{
+    message::{client_message, server_message, AddRequest, EchoMessage},
+    server::Server,
+};
+use std::{
+    fs,
+    io::ErrorKind,
+    os::unix::{fs::PermissionsExt, net::UnixListener},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod common;
+
+/// Socket path unique to this test run
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("server-{}-{}.sock", std::process::id(), name));
+    fs::remove_file(&path).ok();
+    path
+}
+
+#[test]
+fn test_unix_socket_echo_and_add() {
+    let path = socket_path("echo");
+    let server = Arc::new(Server::bind_unix(&path, None).expect("Failed to start server"));
+    let handle = run(server.clone());
+
+    let mut clients = [
+        client::Client::new_unix(&path, 1000),
+        client::Client::new_unix(&path, 1000),
+    ];
+    for client in clients.iter_mut() {
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+    }
+
+    for (i, client) in clients.iter_mut().enumerate() {
+        let echo_message = EchoMessage {
+            content: format!("Hello from client {}", i),
+        };
+        let message = client_message::Message::EchoMessage(echo_message.clone());
+        assert!(client.send(message).is_ok(), "Failed to send message");
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::EchoMessage(echo)) => {
+                assert_eq!(echo.content, echo_message.content, "Echoed message content does not match");
+            }
+            _ => panic!("Expected EchoMessage, but received a different message"),
+        }
+
+        let message = client_message::Message::AddRequest(AddRequest { a: 10, b: i as i32 });
+        assert!(client.send(message).is_ok(), "Failed to send message");
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::AddResponse(add_response)) => {
+                assert_eq!(add_response.result, 10 + i as i32, "Wrong calculation result");
+            }
+            _ => panic!("Expected AddResponse"),
+        }
+    }
+
+    for client in clients.iter_mut() {
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    }
+    stop(server, handle);
+}
+
+#[test]
+fn test_unix_socket_permissions_and_cleanup() {
+    let path = socket_path("permissions");
+    let server = Arc::new(Server::bind_unix(&path, Some(0o600)).expect("Failed to start server"));
+
+    let mode = fs::metadata(&path).expect("Socket file missing").permissions().mode();
+    assert_eq!(mode & 0o777, 0o600, "Socket permissions were not applied");
+    // the socket is bound in a private directory first, which is gone again
+    let siblings = fs::read_dir(path.parent().unwrap()).expect("Failed to read socket directory");
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let private = siblings.filter_map(Result::ok).any(|entry| {
+        let sibling = entry.file_name();
+        let sibling = sibling.to_string_lossy();
+        sibling.starts_with('.') && sibling.contains(name)
+    });
+    assert!(!private, "Private directory was left behind");
+    // connecting through the renamed file reaches the server
+    let mut client = client::Client::new_unix(&path, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect through the renamed socket");
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    let handle = run(server.clone());
+    stop(server, handle);
+
+    // Removed on shutdown even though the server value is still alive
+    assert!(!path.exists(), "Socket file was not removed on shutdown");
+}
+
+#[test]
+fn test_unix_socket_runs_again_after_stop() {
+    let path = socket_path("again");
+    let server = Arc::new(Server::bind_unix(&path, Some(0o600)).expect("Failed to start server"));
+
+    for round in 0..2 {
+        let handle = run(server.clone());
+        // the file removed by the last shutdown is back once the server runs,
+        // with its permissions
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !path.exists() {
+            assert!(Instant::now() < deadline, "Socket file was not bound again");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut client = client::Client::new_unix(&path, 1000);
+        assert!(client.connect().is_ok(), "Failed to connect in round {}", round);
+        let mode = fs::metadata(&path).expect("Socket file missing").permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "Socket permissions were not applied");
+        let message = client_message::Message::AddRequest(AddRequest { a: round, b: 1 });
+        assert!(client.send(message).is_ok(), "Failed to send message");
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::AddResponse(add_response)) => assert_eq!(add_response.result, round + 1),
+            other => panic!("Expected AddResponse, but received {:?}", other),
+        }
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+        stop(server.clone(), handle);
+        assert!(!path.exists(), "Socket file was not removed on shutdown");
+    }
+}
+
+#[test]
+fn test_unix_socket_replaces_stale_file() {
+    let path = socket_path("stale");
+    // A listener dropped without unlinking leaves its socket file behind
+    drop(UnixListener::bind(&path).expect("Failed to create stale socket"));
+    assert!(path.exists());
+
+    let server = Arc::new(Server::bind_unix(&path, None).expect("Failed to replace stale socket"));
+    let handle = run(server.clone());
+
+    let mut client = client::Client::new_unix(&path, 1000);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+
+    stop(server, handle);
+}
+
+#[test]
+fn test_unix_socket_in_use_or_not_a_socket() {
+    let path = socket_path("in-use");
+    let server = Server::bind_unix(&path, None).expect("Failed to start server");
+
+    // A live socket belongs to a running server and must be left alone
+    let error = Server::bind_unix(&path, None).err().expect("Bound a socket in use");
+    assert_eq!(error.kind(), ErrorKind::AddrInUse);
+    assert!(path.exists(), "Socket of the running server was removed");
+    drop(server);
+    assert!(!path.exists(), "Socket file was not removed on drop");
+
+    // Never delete something that is not a socket
+    fs::write(&path, b"data").unwrap();
+    let error = Server::bind_unix(&path, None).err().expect("Replaced a regular file");
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(fs::read(&path).unwrap(), b"data");
+    fs::remove_file(&path).unwrap();
+}